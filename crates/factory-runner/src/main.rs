@@ -0,0 +1,80 @@
+//! Runs a factory scenario without a window, as fast as it simulates, and
+//! prints what it made. Mods in the directory given to `--mods` are loaded
+//! on top of the scenario's prototypes.
+//!
+//! Usage: `factory-runner <scenario> [--ticks <n>] [--threads <n>] [--format json|csv] [--mods <dir>]`
+
+mod report;
+mod scenario;
+
+use std::path::Path;
+
+use open_factory::mods::{read_mods, LoadOrder};
+use report::Report;
+use scenario::Scenario;
+
+/// Ten minutes of game time.
+const DEFAULT_TICKS: u64 = open_factory::TICKS_PER_SECOND as u64 * 60 * 10;
+
+enum Format {
+    Json,
+    Csv,
+}
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("factory-runner: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+    let (mut path, mut ticks, mut threads, mut format, mut mods) = (None, DEFAULT_TICKS, 1, Format::Json, None);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ticks" => {
+                let value = args.next().ok_or("--ticks needs a number")?;
+                ticks = value.parse().map_err(|_| format!("--ticks needs a number, found `{}`", value))?;
+            },
+            "--threads" => {
+                let value = args.next().ok_or("--threads needs a number")?;
+                threads = value.parse().map_err(|_| format!("--threads needs a number, found `{}`", value))?;
+            },
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("json") => Format::Json,
+                    Some("csv") => Format::Csv,
+                    _ => return Err("--format needs `json` or `csv`".to_string()),
+                };
+            },
+            "--mods" => mods = Some(args.next().ok_or("--mods needs a directory")?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(format!("unexpected argument `{}`", arg)),
+        }
+    }
+
+    let path = path.ok_or("usage: factory-runner <scenario> [--ticks <n>] [--threads <n>] [--format json|csv] [--mods <dir>]")?;
+    let source = std::fs::read_to_string(&path).map_err(|error| format!("{}: {}", path, error))?;
+    let mut draft = Scenario::read(&source).map_err(|error| format!("{}: {}", path, error))?;
+
+    if let Some(dir) = mods {
+        let order = read_mods(Path::new(&dir)).and_then(LoadOrder::new).map_err(|error| error.to_string())?;
+        draft.load_mods(&order).map_err(|error| error.to_string())?;
+    }
+
+    let mut scenario = draft.place().map_err(|error| format!("{}: {}", path, error))?;
+
+    scenario.factory.set_threads(threads);
+    let stats = scenario.run(ticks);
+    let report = Report::new(&scenario, &stats);
+
+    match format {
+        Format::Json => print!("{}", report.to_json()),
+        Format::Csv => print!("{}", report.to_csv()),
+    }
+
+    Ok(())
+}
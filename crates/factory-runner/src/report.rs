@@ -0,0 +1,134 @@
+//! What a run made, as JSON or CSV.
+
+use open_factory::{stats::ProductionStats, TICKS_PER_SECOND};
+
+use crate::scenario::Scenario;
+
+/// A named count and its rate over the run.
+pub struct Row {
+    pub name: String,
+    pub count: u64,
+    pub per_minute: f64,
+}
+
+pub struct Report {
+    pub ticks: u64,
+    pub recipes: Vec<Row>,
+    pub produced: Vec<Row>,
+    pub consumed: Vec<Row>,
+    pub producers: Vec<Row>,
+}
+
+impl Report {
+    pub fn new(scenario: &Scenario, stats: &ProductionStats) -> Self {
+        let minutes = stats.ticks as f64 / (TICKS_PER_SECOND as f64 * 60.0);
+        let row = |name: String, count: u64| Row {
+            name,
+            count,
+            per_minute: if minutes > 0.0 { count as f64 / minutes } else { 0.0 },
+        };
+
+        Self {
+            ticks: stats.ticks,
+            recipes: stats
+            .recipes()
+            .map(|(recipe, crafts)| row(scenario.recipes.name(&recipe).to_string(), crafts))
+            .collect(),
+            produced: stats
+            .produced(&scenario.recipes)
+            .into_iter()
+            .map(|(item, count)| row(scenario.items.name(&item).to_string(), count))
+            .collect(),
+            consumed: stats
+            .consumed(&scenario.recipes)
+            .into_iter()
+            .map(|(filter, count)| row(scenario.filter_name(filter), count))
+            .collect(),
+            producers: scenario
+            .producers
+            .iter()
+            .map(|(name, id)| row(name.clone(), stats.producer_crafts(*id)))
+            .collect(),
+        }
+    }
+
+    fn sections(&self) -> [(&'static str, &[Row]); 4] {
+        [("recipes", &self.recipes), ("produced", &self.produced), ("consumed", &self.consumed), ("producers", &self.producers)]
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = format!("{{\n  \"ticks\": {},\n  \"seconds\": {}", self.ticks, self.ticks as f64 / TICKS_PER_SECOND as f64);
+
+        for (section, rows) in self.sections().iter() {
+            out.push_str(&format!(",\n  \"{}\": {{", section));
+            for (index, row) in rows.iter().enumerate() {
+                let separator = if index == 0 { "" } else { "," };
+                out.push_str(&format!("{}\n    {}: {{ \"count\": {}, \"per_minute\": {:.3} }}", separator, json_string(&row.name), row.count, row.per_minute));
+            }
+            out.push_str(if rows.is_empty() { "}" } else { "\n  }" });
+        }
+
+        out.push_str("\n}\n");
+        out
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("section,name,count,per_minute\n");
+
+        for (section, rows) in self.sections().iter() {
+            for row in rows.iter() {
+                out.push_str(&format!("{},{},{},{:.3}\n", section, csv_field(&row.name), row.count, row.per_minute));
+            }
+        }
+
+        out
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+
+    for character in text.chars() {
+        match character {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            character if character.is_control() => out.push_str(&format!("\\u{:04x}", character as u32)),
+            character => out.push(character),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rates_are_per_minute_of_game_time() {
+        let mut scenario = Scenario::parse(include_str!("../scenarios/bronze.scenario")).unwrap();
+        let stats = scenario.run(TICKS_PER_SECOND as u64 * 30);
+        let report = Report::new(&scenario, &stats);
+
+        let copper = report.recipes.iter().find(|row| row.name == "mine-copper").unwrap();
+        assert_eq!(copper.count as f64 * 2.0, copper.per_minute);
+
+        let csv = report.to_csv();
+        assert!(csv.starts_with("section,name,count,per_minute\n"));
+        assert!(csv.contains("\nconsumed,#metal,"));
+        assert!(report.to_json().contains("\"seconds\": 30"));
+
+        assert_eq!("\"say \\\"hi\\\"\"", json_string("say \"hi\""));
+        assert_eq!("\"a,b\"", csv_field("a,b"));
+    }
+}
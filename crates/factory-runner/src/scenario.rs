@@ -0,0 +1,494 @@
+//! Scenario files: prototypes and a layout to simulate.
+//!
+//! A scenario is plain text with one declaration per line. Words are separated
+//! by whitespace, and lines starting with `#` are comments. Prototypes have to
+//! be declared before they are used, but the layout can be in any order.
+//!
+//! ```text
+//! item copper
+//! tag metal copper tin
+//! recipe bronze time 40 in copper 3 in #metal 1 wear hammer 5 out bronze 4
+//! recipe mine-copper time 20 out copper 1 mining
+//! connector stack-arm hand 4 timeout 20
+//! deposit copper at 0 0 size 4 1 amount 300
+//! producer smelter bronze at 180 45 facing east
+//! connect miner smelter stack-arm
+//! ```
+//!
+//! Deposits are placed in tiles, producers in world units. A producer without
+//! a recipe has the recipe `none`.
+//!
+//! The prototypes are the game's own content to mods, which are loaded into a
+//! [`Draft`] before the layout is placed, so producers and connections can
+//! use what mods declare.
+
+use std::str::{FromStr, SplitWhitespace};
+
+use open_factory::{
+    command::Command,
+    deposit::{Deposit, Deposits},
+    factory::{EntityId, Factory, Position, Prototypes},
+    item_stack::{ItemData, ItemFilter},
+    kinds::{ConnectorKind, ConnectorKindBuilder, ItemKind, ItemKindBuilder, ItemTag, ItemTagBuilder, QualityRule, RecipeInput, RecipeKind, RecipeOutput, TechnologyKind},
+    local_string::LocalString,
+    mods::LoadOrder,
+    registry::{Handle, Table},
+    scripting::{Content, ScriptError, ScriptHost},
+    stats::ProductionStats,
+    tile::{Rotation, TilePosition},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScenarioError {
+    /// Line the error is on, counting from one.
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+pub struct Scenario {
+    pub items: Table<ItemKind>,
+    pub tags: Table<ItemTag>,
+    pub recipes: Table<RecipeKind>,
+    pub connectors: Table<ConnectorKind>,
+    pub technologies: Table<TechnologyKind>,
+    pub factory: Factory,
+
+    /// Producers in the order they were declared, with their names.
+    pub producers: Vec<(String, EntityId)>,
+}
+
+/// A line of the layout, kept until all deposits and prototypes are known.
+enum Placement<'a> {
+    Producer { name: &'a str, recipe: Option<&'a str>, position: Position, rotation: Rotation },
+    Connector { giver: &'a str, taker: &'a str, kind: &'a str },
+}
+
+/// A scenario read but not placed yet, for mods to add their content to.
+pub struct Draft<'a> {
+    pub items: Table<ItemKind>,
+    pub tags: Table<ItemTag>,
+    pub recipes: Table<RecipeKind>,
+    pub connectors: Table<ConnectorKind>,
+    deposits: Deposits,
+    placements: Vec<(usize, Placement<'a>)>,
+}
+
+/// The words of a line, with errors pointing at it.
+struct Words<'a> {
+    line: usize,
+    words: SplitWhitespace<'a>,
+}
+
+impl<'a> Words<'a> {
+    fn error(&self, message: impl Into<String>) -> ScenarioError {
+        ScenarioError { line: self.line, message: message.into() }
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.words.next()
+    }
+
+    /// The next word, which has to be there.
+    fn expect(&mut self, what: &str) -> Result<&'a str, ScenarioError> {
+        match self.words.next() {
+            Some(word) => Ok(word),
+            None => Err(self.error(format!("expected {}", what))),
+        }
+    }
+
+    fn number<T: FromStr>(&mut self, what: &str) -> Result<T, ScenarioError> {
+        let word = self.expect(what)?;
+        word.parse().map_err(|_| self.error(format!("expected {}, found `{}`", what, word)))
+    }
+
+    fn handle<T>(&mut self, table: &Table<T>, what: &str) -> Result<Handle<T>, ScenarioError> {
+        let name = self.expect(what)?;
+        table.find_handle_from_name(name).ok_or_else(|| self.error(format!("unknown {} `{}`", what, name)))
+    }
+
+    /// Put a value in its table under a name that mustn't be taken.
+    fn declare<T>(&self, table: &mut Table<T>, value: T, name: &str, what: &str) -> Result<Handle<T>, ScenarioError> {
+        table
+        .try_insert(value, name.to_string())
+        .map_err(|_| self.error(format!("{} `{}` is declared twice", what, name)))
+    }
+
+    fn position(&mut self) -> Result<Position, ScenarioError> {
+        Ok(Position::from_f32(self.number("an x coordinate")?, self.number("a y coordinate")?))
+    }
+
+    fn rotation(&mut self) -> Result<Rotation, ScenarioError> {
+        match self.expect("a direction")? {
+            "north" => Ok(Rotation::North),
+            "east" => Ok(Rotation::East),
+            "south" => Ok(Rotation::South),
+            "west" => Ok(Rotation::West),
+            other => Err(self.error(format!("expected north, east, south or west, found `{}`", other))),
+        }
+    }
+}
+
+impl Scenario {
+    /// Read and place a scenario without mods.
+    #[cfg(test)]
+    pub fn parse(source: &str) -> Result<Self, ScenarioError> {
+        Self::read(source)?.place()
+    }
+
+    /// Read the prototypes and layout without placing anything.
+    pub fn read(source: &str) -> Result<Draft<'_>, ScenarioError> {
+        let mut items = Table::new();
+        let mut tags = Table::new();
+        let mut recipes = Table::new();
+        let mut connectors = Table::new();
+        let mut deposits = Deposits::new();
+        let mut placements = vec![];
+
+        for (index, text) in source.lines().enumerate() {
+            let mut words = Words { line: index + 1, words: text.split_whitespace() };
+
+            let keyword = match words.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+
+            match keyword {
+                "item" => {
+                    let name = words.expect("an item name")?;
+                    let item = ItemKindBuilder::new().with_name(LocalString::from_str(name)).build();
+                    words.declare(&mut items, item, name, "item")?;
+                },
+
+                "tag" => {
+                    let name = words.expect("a tag name")?;
+                    let mut tag = ItemTagBuilder::new().with_name(LocalString::from_str(name));
+                    while words.words.clone().next().is_some() {
+                        tag = tag.with_item(words.handle(&items, "item")?);
+                    }
+                    words.declare(&mut tags, tag.build(), name, "tag")?;
+                },
+
+                "recipe" => {
+                    let name = words.expect("a recipe name")?;
+                    let recipe = parse_recipe(&mut words, name, &items, &tags)?;
+                    words.declare(&mut recipes, recipe, name, "recipe")?;
+                },
+
+                "connector" => {
+                    let name = words.expect("a connector name")?;
+                    let mut connector = ConnectorKindBuilder::new().with_name(LocalString::from_str(name));
+
+                    while let Some(option) = words.next() {
+                        connector = match option {
+                            "hand" => match words.number("a hand size")? {
+                                0 => return Err(words.error("hand size has to be at least one")),
+                                hand_size => connector.with_hand_size(hand_size),
+                            },
+                            "timeout" => connector.with_pickup_timeout(words.number("a timeout in ticks")?),
+                            other => return Err(words.error(format!("unknown connector option `{}`", other))),
+                        };
+                    }
+
+                    words.declare(&mut connectors, connector.build(), name, "connector")?;
+                },
+
+                "deposit" => {
+                    let item = words.handle(&items, "item")?;
+                    let (mut corner, mut size, mut amount) = (None, (1, 1), None);
+
+                    while let Some(option) = words.next() {
+                        match option {
+                            "at" => corner = Some(TilePosition::new(words.number("a tile x")?, words.number("a tile y")?)),
+                            "size" => size = (words.number::<i32>("a width in tiles")?, words.number::<i32>("a height in tiles")?),
+                            "amount" => amount = Some(words.number::<u32>("an amount per tile")?),
+                            other => return Err(words.error(format!("unknown deposit option `{}`", other))),
+                        }
+                    }
+
+                    let corner = corner.ok_or_else(|| words.error("deposit needs `at <x> <y>`"))?;
+                    let amount = amount.ok_or_else(|| words.error("deposit needs `amount <n>`"))?;
+
+                    for y in corner.y..corner.y + size.1 {
+                        for x in corner.x..corner.x + size.0 {
+                            deposits.insert(TilePosition::new(x, y), Deposit::new(item, amount));
+                        }
+                    }
+                },
+
+                "producer" => {
+                    let name = words.expect("a producer name")?;
+                    let recipe = match words.expect("a recipe")? {
+                        "none" => None,
+                        recipe => Some(recipe),
+                    };
+                    let (mut position, mut rotation) = (None, Rotation::North);
+
+                    while let Some(option) = words.next() {
+                        match option {
+                            "at" => position = Some(words.position()?),
+                            "facing" => rotation = words.rotation()?,
+                            other => return Err(words.error(format!("unknown producer option `{}`", other))),
+                        }
+                    }
+
+                    let position = position.ok_or_else(|| words.error("producer needs `at <x> <y>`"))?;
+                    placements.push((words.line, Placement::Producer { name, recipe, position, rotation }));
+                },
+
+                "connect" => {
+                    let giver = words.expect("the giving producer")?;
+                    let taker = words.expect("the taking producer")?;
+                    let kind = words.expect("a connector")?;
+                    placements.push((words.line, Placement::Connector { giver, taker, kind }));
+                },
+
+                other => return Err(words.error(format!("unknown declaration `{}`", other))),
+            }
+
+            if let Some(extra) = words.next() {
+                return Err(words.error(format!("unexpected `{}`", extra)));
+            }
+        }
+
+        Ok(Draft { items, tags, recipes, connectors, deposits, placements })
+    }
+
+    /// The id of the producer with the name.
+    pub fn producer(&self, name: &str) -> Option<EntityId> {
+        self.producers
+        .iter()
+        .find(|(producer, _)| producer == name)
+        .map(|&(_, id)| id)
+    }
+
+    /// Simulate as fast as possible, counting what gets made.
+    pub fn run(&mut self, ticks: u64) -> ProductionStats {
+        let prototypes = Prototypes { recipes: &self.recipes, connectors: &self.connectors, tags: &self.tags, technologies: &self.technologies };
+        let mut stats = ProductionStats::new();
+
+        for _ in 0..ticks {
+            self.factory.tick_with_stats(&prototypes, &mut stats);
+        }
+
+        // Nobody is watching the events.
+        self.factory.drain_events().for_each(drop);
+        stats
+    }
+
+    /// Name of an item or of a recipe input's tag, as written in the scenario.
+    pub fn filter_name(&self, filter: ItemFilter) -> String {
+        match filter {
+            ItemFilter::Item(item) => self.items.name(&item).to_string(),
+            ItemFilter::Tag(tag) => format!("#{}", self.tags.name(&tag)),
+        }
+    }
+}
+
+impl Draft<'_> {
+    /// Run the mods' scripts, declaring their content next to the scenario's.
+    pub fn load_mods(&mut self, order: &LoadOrder) -> Result<(), ScriptError> {
+        let mut content = Content { items: &mut self.items, tags: &mut self.tags, recipes: &mut self.recipes, connectors: &mut self.connectors };
+        ScriptHost::new().load_mods(order, &mut content)
+    }
+
+    /// Place the layout in a new factory.
+    pub fn place(self) -> Result<Scenario, ScenarioError> {
+        let Draft { items, tags, recipes, connectors, deposits, mut placements } = self;
+        let mut scenario = Scenario {
+            items,
+            tags,
+            recipes,
+            connectors,
+            technologies: Table::new(),
+            factory: Factory::with_deposits(deposits),
+            producers: vec![],
+        };
+
+        // Producers first, so connections can refer to producers declared after them.
+        placements.sort_by_key(|(_, placement)| matches!(placement, Placement::Connector { .. }));
+
+        for (line, placement) in placements {
+            let error = |message: String| ScenarioError { line, message };
+
+            let prototypes = Prototypes { recipes: &scenario.recipes, connectors: &scenario.connectors, tags: &scenario.tags, technologies: &scenario.technologies };
+
+            match placement {
+                Placement::Producer { name, recipe, position, rotation } => {
+                    if scenario.producer(name).is_some() {
+                        return Err(error(format!("producer `{}` is declared twice", name)));
+                    }
+
+                    let recipe = match recipe {
+                        Some(recipe) => Some(scenario.recipes.find_handle_from_name(recipe).ok_or_else(|| error(format!("unknown recipe `{}`", recipe)))?),
+                        None => None,
+                    };
+
+                    match scenario.factory.apply(&Command::PlaceProducer { id: None, position, rotation, recipe }, &prototypes) {
+                        Ok(Command::RemoveEntity { entity }) => scenario.producers.push((name.to_string(), entity)),
+                        Ok(_) => unreachable!("placing a producer is undone by removing it"),
+                        Err(command_error) => return Err(error(command_error.to_string())),
+                    }
+                },
+                Placement::Connector { giver, taker, kind } => {
+                    let giver = scenario.producer(giver).ok_or_else(|| error(format!("unknown producer `{}`", giver)))?;
+                    let taker = scenario.producer(taker).ok_or_else(|| error(format!("unknown producer `{}`", taker)))?;
+                    let kind = scenario.connectors.find_handle_from_name(kind).ok_or_else(|| error(format!("unknown connector `{}`", kind)))?;
+
+                    if let Err(command_error) = scenario.factory.apply(&Command::Connect { id: None, giver, taker, kind }, &prototypes) {
+                        return Err(error(command_error.to_string()));
+                    }
+                },
+            }
+        }
+
+        scenario.factory.drain_events().for_each(drop);
+        Ok(scenario)
+    }
+}
+
+fn parse_recipe(words: &mut Words, name: &str, items: &Table<ItemKind>, tags: &Table<ItemTag>) -> Result<RecipeKind, ScenarioError> {
+    let mut recipe = RecipeKind {
+        name: LocalString::from_str(name),
+        input_items: vec![],
+        output: vec![],
+        time: 0,
+        quality: QualityRule::default(),
+        mining: false,
+    };
+
+    let input_filter = |words: &mut Words| -> Result<ItemFilter, ScenarioError> {
+        let name = words.expect("an item or #tag")?;
+        let found = match name.strip_prefix('#') {
+            Some(tag) => tags.find_handle_from_name(tag).map(ItemFilter::from),
+            None => items.find_handle_from_name(name).map(ItemFilter::from),
+        };
+        found.ok_or_else(|| words.error(format!("unknown item or tag `{}`", name)))
+    };
+
+    while let Some(option) = words.next() {
+        match option {
+            "time" => recipe.time = words.number("a time in ticks")?,
+            "in" => {
+                let item = input_filter(words)?;
+                recipe.input_items.push(RecipeInput { item, quantity: words.number("a quantity")?, wear: None });
+            },
+            "wear" => {
+                let item = input_filter(words)?;
+                recipe.input_items.push(RecipeInput { item, quantity: 1, wear: Some(words.number("an amount of wear")?) });
+            },
+            "out" => {
+                let item = words.handle(items, "item")?;
+                recipe.output.push(RecipeOutput { item, quantity: words.number("a quantity")?, data: ItemData::default() });
+            },
+            "mining" => recipe.mining = true,
+            "upgrade" => recipe.quality.upgrade_chance = words.number("a chance per mille")?,
+            other => return Err(words.error(format!("unknown recipe option `{}`", other))),
+        }
+    }
+
+    if recipe.time == 0 {
+        return Err(words.error("recipe needs `time <ticks>`"));
+    }
+
+    Ok(recipe)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use open_factory::{checksum::checksum, mods::{Mod, ModManifest}};
+
+    #[test]
+    fn reports_errors_with_their_line() {
+        let error = |source: &str| Scenario::parse(source).err().map(|error| error.to_string());
+
+        assert_eq!(Some("line 2: unknown item or tag `tin`".to_string()), error("item copper\nrecipe bronze time 4 in tin 1"));
+        assert_eq!(Some("line 1: recipe needs `time <ticks>`".to_string()), error("recipe nothing"));
+        assert_eq!(Some("line 3: unknown producer `b`".to_string()), error("connector arm\nproducer a none at 60 15\nconnect a b arm"));
+        assert_eq!(Some("line 3: producer #0 is in the way".to_string()), error("# Overlapping\nproducer a none at 60 15\nproducer b none at 90 15"));
+        assert_eq!(Some("line 3: recipe `r` is declared twice".to_string()), error("item a\nrecipe r time 1 in a 1\nrecipe r time 2 in a 1"));
+        assert_eq!(Some("line 2: connector `arm` is declared twice".to_string()), error("connector arm\nconnector arm hand 2"));
+    }
+
+    #[test]
+    fn layouts_use_what_mods_declare() {
+        let faster_bronze = Mod {
+            manifest: ModManifest::parse("faster-bronze", "id faster-bronze\nversion 1").unwrap(),
+            scripts: vec![("content.rhai".to_string(), r#"
+                recipe("base:bronze", #{ time: 10, inputs: [["copper", 3], ["tin", 1]], outputs: [["bronze", 4]] });
+                connector("long-arm", #{ hand: 8 });
+            "#.to_string())],
+        };
+
+        let source = include_str!("../scenarios/bronze.scenario").replace("connect smelter trash stack-arm", "connect smelter trash faster-bronze:long-arm");
+        let mut draft = Scenario::read(&source).unwrap();
+        draft.load_mods(&LoadOrder::new(vec![faster_bronze]).unwrap()).unwrap();
+        let mut scenario = draft.place().unwrap();
+
+        let bronze = scenario.recipes.get_handle_from_name("bronze");
+        assert_eq!(10, scenario.recipes[bronze].time);
+        assert_eq!(3, scenario.connectors.len());
+
+        let long_arm = scenario.connectors.get_handle_from_name("faster-bronze:long-arm");
+        assert_eq!(8, scenario.connectors[long_arm].hand_size);
+
+        // Bronze still reaches the trash over the mod's connector.
+        let stats = scenario.run(20 * 60);
+        assert!(stats.crafts(bronze) > 0);
+        assert!(stats.producer_crafts(scenario.producer("trash").unwrap()) > 0);
+
+        let unknown = Scenario::read("producer a faster-bronze:smelt at 60 15").unwrap().place().err().map(|error| error.to_string());
+        assert_eq!(Some("line 1: unknown recipe `faster-bronze:smelt`".to_string()), unknown);
+    }
+
+    #[test]
+    fn bronze_scenario_makes_bronze_until_the_ore_runs_out() {
+        let mut scenario = Scenario::parse(include_str!("../scenarios/bronze.scenario")).unwrap();
+        let stats = scenario.run(20 * 60 * 10);
+
+        let crafts = |recipe: &str| stats.crafts(scenario.recipes.get_handle_from_name(recipe));
+        assert_eq!(240, crafts("mine-copper"));
+        assert_eq!(80, crafts("bronze"));
+        assert_eq!(320, crafts("destroy-metal"));
+
+        let trash = scenario.producer("trash").unwrap();
+        assert_eq!(320, stats.producer_crafts(trash));
+    }
+
+    #[test]
+    fn ticking_on_threads_changes_nothing() {
+        let source = include_str!("../scenarios/bronze.scenario");
+        let mut serial = Scenario::parse(source).unwrap();
+        let mut parallel = Scenario::parse(source).unwrap();
+        parallel.factory.set_threads(4);
+
+        for _ in 0..60 {
+            assert_eq!(serial.run(50), parallel.run(50));
+            assert_eq!(checksum(&serial.factory), checksum(&parallel.factory));
+        }
+    }
+
+    #[test]
+    fn sleeping_changes_nothing() {
+        let source = include_str!("../scenarios/bronze.scenario");
+        let mut sleeping = Scenario::parse(source).unwrap();
+        let mut awake = Scenario::parse(source).unwrap();
+        let prototypes = Prototypes { recipes: &awake.recipes, connectors: &awake.connectors, tags: &awake.tags, technologies: &awake.technologies };
+
+        // Past the ore running out, when everything falls asleep.
+        for _ in 0..20 * 60 * 10 {
+            sleeping.run(1);
+            awake.factory.wake_all();
+            awake.factory.tick(&prototypes);
+            assert_eq!(checksum(&awake.factory), checksum(&sleeping.factory));
+        }
+    }
+}
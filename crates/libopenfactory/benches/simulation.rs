@@ -0,0 +1,75 @@
+//! How the simulation scales. Run with `cargo bench -p open_factory`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use open_factory::{
+    item_stack::{ItemSlotBuilder, ItemStack, ItemStackQuanity},
+    producer::Producer,
+    stats::ProductionStats,
+    synthetic::SyntheticFactoryBuilder,
+};
+
+fn producer_tick(c: &mut Criterion) {
+    let synthetic = SyntheticFactoryBuilder::new().build();
+    let recipes = &synthetic.recipes;
+    let recipe = recipes.get_handle_from_name("step-1");
+    let input = synthetic.items.get_handle_from_name("item-1");
+    let mut producer = Producer::new_with_recipe(recipe, &synthetic.prototypes(), synthetic.factory.research()).unwrap();
+
+    c.bench_function("producer tick", |b| b.iter(|| {
+        // Keep it crafting: top up the input and empty the output.
+        let _ = producer.try_insert_ingredient(ItemStack::new(input, 1), &synthetic.tags);
+        producer.attempt_to_start_production(recipes);
+
+        if producer.tick(recipes) {
+            producer.take_items(None, ItemStackQuanity::MAX);
+        }
+    }));
+}
+
+fn item_slot_insert(c: &mut Criterion) {
+    let synthetic = SyntheticFactoryBuilder::new().build();
+    let item = synthetic.items.get_handle_from_name("item-1");
+    let mut slot = ItemSlotBuilder::new().with_capacity(100).with_filter(item).build();
+
+    c.bench_function("item slot insert", |b| b.iter(|| {
+        let result = slot.insert_item_stack(black_box(ItemStack::new(item, 3)), &synthetic.tags);
+
+        if slot.available_capacity() < 3 {
+            slot.take_items(ItemStackQuanity::MAX);
+        }
+
+        result
+    }));
+}
+
+fn factory_tick(c: &mut Criterion) {
+    let mut group = c.benchmark_group("factory tick");
+
+    for &threads in &[1, 4] {
+        for &chains in &[10, 100, 1000] {
+            let mut synthetic = SyntheticFactoryBuilder::new()
+            .with_chains(chains)
+            .with_chain_length(5)
+            .with_connectors_per_link(2)
+            .build();
+            synthetic.factory.set_threads(threads);
+
+            // Measure the factory running, not filling up.
+            let mut stats = ProductionStats::new();
+            for _ in 0..200 {
+                synthetic.tick(&mut stats);
+            }
+
+            let entities = synthetic.factory.producers().count() + synthetic.factory.connectors().count();
+            group.throughput(Throughput::Elements(entities as u64));
+            group.bench_function(BenchmarkId::new(format!("{} threads", threads), chains), |b| b.iter(|| synthetic.tick(&mut stats)));
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, producer_tick, item_slot_insert, factory_tick);
+criterion_main!(benches);
@@ -0,0 +1,58 @@
+//! Base64 with the standard alphabet and padding, for binary data shared as text.
+//!
+//! Decoding is strict: every piece of data has exactly one text, so padding
+//! has to be there and the bits it covers have to be zero.
+
+use ::base64::{engine::general_purpose::STANDARD, DecodeError as Base64Error, Engine};
+
+use crate::codec::DecodeError;
+
+pub fn encode(input: &[u8]) -> String {
+    STANDARD.encode(input)
+}
+
+/// Decode text from [`encode`]. Whitespace is skipped, so text wrapped over several lines decodes too.
+pub fn decode(text: &str) -> Result<Vec<u8>, DecodeError> {
+    let text: Vec<u8> = text.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    if !text.len().is_multiple_of(4) {
+        return Err(DecodeError::Invalid("base64 length is not a multiple of four"));
+    }
+
+    STANDARD.decode(text).map_err(|error| match error {
+        Base64Error::InvalidByte(_, b'=') | Base64Error::InvalidPadding => DecodeError::Invalid("misplaced base64 padding"),
+        Base64Error::InvalidByte(..) => DecodeError::Invalid("not a base64 character"),
+        Base64Error::InvalidLength(_) => DecodeError::Invalid("base64 length is not a multiple of four"),
+        Base64Error::InvalidLastSymbol(..) => DecodeError::Invalid("base64 padding bits are not zero"),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_with_padding() {
+        assert_eq!("", encode(b""));
+        assert_eq!("Zg==", encode(b"f"));
+        assert_eq!("Zm9vYg==", encode(b"foob"));
+        assert_eq!("Zm9vYmFy", encode(b"foobar"));
+
+        for length in 0..8 {
+            let bytes: Vec<u8> = (0..length).map(|byte: u32| (byte * 37 + 250) as u8).collect();
+            assert_eq!(Ok(bytes.clone()), decode(&encode(&bytes)));
+        }
+
+        assert_eq!(Ok(b"foobar".to_vec()), decode("Zm9v\nYmFy"));
+        assert!(decode("Zg=").is_err());
+        assert!(decode("Zg==Zg==").is_err());
+        assert!(decode("Z!==").is_err());
+    }
+
+    #[test]
+    fn every_data_has_one_text() {
+        assert_eq!(Err(DecodeError::Invalid("base64 padding bits are not zero")), decode("Zh=="));
+        assert_eq!(Err(DecodeError::Invalid("base64 padding bits are not zero")), decode("Zm9="));
+        assert_eq!(Err(DecodeError::Invalid("base64 length is not a multiple of four")), decode("Zg"));
+        assert_eq!(Ok(b"f".to_vec()), decode("Zg=="));
+    }
+}
@@ -0,0 +1,271 @@
+//! Copying sections of a factory to paste elsewhere.
+//!
+//! A [`Blueprint`] holds producers by their offset from its center, and the
+//! connectors between them. [`Command::PlaceBlueprint`](crate::command::Command::PlaceBlueprint)
+//! pastes one, turned any way.
+//!
+//! Blueprints are shared as text: a version character followed by the
+//! blueprint in base64, deflated. Recipes and connector kinds are written by
+//! name, so the text works no matter the order the prototype tables were built in.
+
+use crate::{
+    base64,
+    codec::{Decode, DecodeError, Encode},
+    compress,
+    factory::{EntityId, Factory, Position},
+    fixed::Fixed,
+    kinds::{ConnectorKind, RecipeKind},
+    registry::{Handle, Table},
+    spatial::Rect,
+    tile::{Rotation, TilePosition},
+};
+
+/// Marks blueprint text in this version of the format.
+const TEXT_VERSION: char = '1';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlueprintProducer {
+    /// Offset from the center of the blueprint.
+    pub offset: Position,
+    pub rotation: Rotation,
+    pub recipe: Option<Handle<RecipeKind>>,
+}
+
+impl BlueprintProducer {
+    /// Where the producer goes and which way it faces when the blueprint is placed at `position`, turned by `rotation`.
+    pub fn placed_at(&self, position: Position, rotation: Rotation) -> (Position, Rotation) {
+        (position + rotation.rotate(self.offset), self.rotation.turned(rotation))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlueprintConnector {
+    /// Index of the producer the connector takes items from.
+    pub giver: u32,
+
+    /// Index of the producer the connector gives items to.
+    pub taker: u32,
+
+    pub kind: Handle<ConnectorKind>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Blueprint {
+    pub producers: Vec<BlueprintProducer>,
+    pub connectors: Vec<BlueprintConnector>,
+}
+
+impl Blueprint {
+    /// Copy the producers overlapping the area, and the connectors between them.
+    ///
+    /// Offsets are from the center of the tile in the middle of the area, so
+    /// placing the blueprint on the center of a tile lines the producers up with
+    /// the tiles again.
+    pub fn copy(factory: &Factory, area: &Rect) -> Self {
+        let middle = |min: Fixed, max: Fixed| Fixed::from_bits(min.to_bits() + (max.to_bits() - min.to_bits()) / 2);
+        let origin = TilePosition::containing(Position::new(middle(area.min.x, area.max.x), middle(area.min.y, area.max.y))).center();
+
+        // Ordered by id, so the copy doesn't depend on how the area was dragged.
+        let ids = factory.producers_in(area);
+        let index_of = |id: EntityId| ids.binary_search(&id).ok().map(|index| index as u32);
+
+        let producers = ids
+        .iter()
+        .map(|&id| {
+            let placed = factory.producer(id).expect("indexed producer exists");
+            BlueprintProducer { offset: placed.position - origin, rotation: placed.rotation, recipe: placed.producer.recipe() }
+        })
+        .collect();
+
+        let connectors = factory
+        .connectors()
+        .filter_map(|(_, placed)| Some(BlueprintConnector { giver: index_of(placed.giver)?, taker: index_of(placed.taker)?, kind: placed.kind }))
+        .collect();
+
+        Self { producers, connectors }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.producers.is_empty()
+    }
+
+    /// Whether every connector connects two different producers of the blueprint.
+    pub fn is_valid(&self) -> bool {
+        let count = self.producers.len() as u32;
+        self.connectors
+        .iter()
+        .all(|connector| connector.giver < count && connector.taker < count && connector.giver != connector.taker)
+    }
+
+    /// The blueprint as text to share.
+    pub fn to_text(&self, recipes: &Table<RecipeKind>, connectors: &Table<ConnectorKind>) -> String {
+        let mut out = vec![];
+
+        (self.producers.len() as u32).encode(&mut out);
+        for producer in &self.producers {
+            producer.offset.encode(&mut out);
+            producer.rotation.encode(&mut out);
+            producer.recipe.map(|recipe| recipes.name(&recipe).to_string()).encode(&mut out);
+        }
+
+        (self.connectors.len() as u32).encode(&mut out);
+        for connector in &self.connectors {
+            connector.giver.encode(&mut out);
+            connector.taker.encode(&mut out);
+            connectors.name(&connector.kind).encode(&mut out);
+        }
+
+        let mut text = TEXT_VERSION.to_string();
+        text.push_str(&base64::encode(&compress::compress(&out)));
+        text
+    }
+
+    /// Read a blueprint from [`Blueprint::to_text`]. Fails on recipes or connector kinds missing from the tables.
+    pub fn from_text(text: &str, recipes: &Table<RecipeKind>, connectors: &Table<ConnectorKind>) -> Result<Self, DecodeError> {
+        let text = text.trim();
+        if !text.starts_with(TEXT_VERSION) {
+            return Err(DecodeError::Invalid("not a blueprint, or one from another version"));
+        }
+
+        let bytes = compress::decompress(&base64::decode(&text[TEXT_VERSION.len_utf8()..])?)?;
+        let input = &mut &bytes[..];
+        let mut blueprint = Blueprint::default();
+
+        for _ in 0..u32::decode(input)? {
+            let offset = Position::decode(input)?;
+            let rotation = Rotation::decode(input)?;
+            let recipe = match Option::<String>::decode(input)? {
+                Some(name) => Some(recipes.find_handle_from_name(&name).ok_or(DecodeError::Invalid("unknown recipe"))?),
+                None => None,
+            };
+
+            blueprint.producers.push(BlueprintProducer { offset, rotation, recipe });
+        }
+
+        for _ in 0..u32::decode(input)? {
+            let giver = u32::decode(input)?;
+            let taker = u32::decode(input)?;
+            let kind = connectors.find_handle_from_name(&String::decode(input)?).ok_or(DecodeError::Invalid("unknown connector kind"))?;
+
+            blueprint.connectors.push(BlueprintConnector { giver, taker, kind });
+        }
+
+        if !input.is_empty() {
+            return Err(DecodeError::Invalid("trailing bytes"));
+        }
+
+        if !blueprint.is_valid() {
+            return Err(DecodeError::Invalid("connector is attached to a missing producer"));
+        }
+
+        Ok(blueprint)
+    }
+}
+
+impl Encode for BlueprintProducer {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.offset.encode(out);
+        self.rotation.encode(out);
+        self.recipe.encode(out);
+    }
+}
+
+impl Decode for BlueprintProducer {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(BlueprintProducer {
+            offset: Decode::decode(input)?,
+            rotation: Decode::decode(input)?,
+            recipe: Decode::decode(input)?,
+        })
+    }
+}
+
+impl Encode for BlueprintConnector {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.giver.encode(out);
+        self.taker.encode(out);
+        self.kind.encode(out);
+    }
+}
+
+impl Decode for BlueprintConnector {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(BlueprintConnector {
+            giver: Decode::decode(input)?,
+            taker: Decode::decode(input)?,
+            kind: Decode::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Blueprint {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.producers.encode(out);
+        self.connectors.encode(out);
+    }
+}
+
+impl Decode for Blueprint {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let blueprint = Blueprint {
+            producers: Decode::decode(input)?,
+            connectors: Decode::decode(input)?,
+        };
+
+        if !blueprint.is_valid() {
+            return Err(DecodeError::Invalid("connector is attached to a missing producer"));
+        }
+
+        Ok(blueprint)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        command::{Command, CommandError},
+        testing::Fixture,
+    };
+
+    #[test]
+    fn copies_and_pastes_turned() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::new();
+
+        factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(60.0, 15.0), rotation: Rotation::North, recipe: Some(fixture.generate) }, &prototypes).unwrap();
+        factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(180.0, 15.0), rotation: Rotation::North, recipe: Some(fixture.consume) }, &prototypes).unwrap();
+        factory.apply(&Command::Connect { id: None, giver: EntityId::from_raw(0), taker: EntityId::from_raw(1), kind: fixture.connector }, &prototypes).unwrap();
+
+        let blueprint = Blueprint::copy(&factory, &Rect::from_corners(Position::from_f32(240.0, 30.0), Position::from_f32(0.0, 0.0)));
+        assert_eq!(Position::from_f32(-75.0, 0.0), blueprint.producers[0].offset);
+        assert_eq!(vec![BlueprintConnector { giver: 0, taker: 1, kind: fixture.connector }], blueprint.connectors);
+
+        let text = blueprint.to_text(&fixture.recipes, &fixture.connectors);
+        assert_eq!(Ok(blueprint.clone()), Blueprint::from_text(&text, &fixture.recipes, &fixture.connectors));
+        assert!(Blueprint::from_text(&text[..text.len() - 4], &fixture.recipes, &fixture.connectors).is_err());
+        assert!(Blueprint::from_text(&text, &fixture.recipes, &Table::new()).is_err());
+
+        // Pasting on top of the original is all or nothing.
+        factory.drain_events().for_each(drop);
+        let on_top = Command::PlaceBlueprint { blueprint: blueprint.clone(), position: Position::from_f32(135.0, 15.0), rotation: Rotation::North };
+        assert_eq!(Err(CommandError::Blocked(EntityId::from_raw(0))), factory.apply(&on_top, &prototypes));
+        assert_eq!(2, factory.producers().count());
+        assert_eq!(0, factory.drain_events().count());
+
+        // Turned a quarter, the row becomes a column with a connector of the same length.
+        let paste = Command::PlaceBlueprint { blueprint, position: Position::from_f32(135.0, 315.0), rotation: Rotation::East };
+        let undo = factory.apply(&paste, &prototypes).unwrap();
+        assert_eq!(4, factory.producers().count());
+
+        let pasted: Vec<_> = factory.producers().skip(2).map(|(_, placed)| (placed.position, placed.rotation)).collect();
+        assert_eq!(vec![(Position::from_f32(135.0, 390.0), Rotation::East), (Position::from_f32(135.0, 270.0), Rotation::East)], pasted);
+        assert!(factory.connectors().all(|(_, placed)| placed.connector.length() == Fixed::from_int(30)));
+        assert_eq!(2, factory.connectors().count());
+
+        factory.apply(&undo, &prototypes).unwrap();
+        assert_eq!(2, factory.producers().count());
+        assert_eq!(1, factory.connectors().count());
+    }
+}
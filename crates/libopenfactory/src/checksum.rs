@@ -0,0 +1,239 @@
+//! Checksums over simulation state.
+//!
+//! Two simulations that started from the same state and received the same
+//! inputs must produce the same checksum on every platform. Lockstep peers
+//! compare checksums to detect desyncs.
+//!
+//! `std`'s hashers are not used because their output is not guaranteed to
+//! be stable between Rust releases.
+
+use crate::{fixed::Fixed, item_stack::{Durability, ItemData, ItemFilter, ItemSlot, ItemStack, Quality}, registry::Handle};
+
+/// 64-bit FNV-1a hasher.
+#[derive(Debug, Clone)]
+pub struct StateHasher(u64);
+
+impl StateHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> Self {
+        StateHasher(Self::OFFSET_BASIS)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.write(&[value]);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Simulation state that can be fed into a [`StateHasher`].
+pub trait Checksum {
+    fn checksum(&self, hasher: &mut StateHasher);
+}
+
+/// Checksum of a piece of simulation state.
+///
+/// To checksum the whole simulation, pass everything that is ticked,
+/// e.g. `checksum(&(&producers[..], &connectors[..]))`.
+pub fn checksum<T: Checksum + ?Sized>(state: &T) -> u64 {
+    let mut hasher = StateHasher::new();
+    state.checksum(&mut hasher);
+    hasher.finish()
+}
+
+impl<T: Checksum + ?Sized> Checksum for &'_ T {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        (**self).checksum(hasher);
+    }
+}
+
+impl<T: Checksum> Checksum for [T] {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.len() as u64);
+        for value in self {
+            value.checksum(hasher);
+        }
+    }
+}
+
+impl<T: Checksum> Checksum for Vec<T> {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        self[..].checksum(hasher);
+    }
+}
+
+impl<T: Checksum> Checksum for Option<T> {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        match self {
+            None => hasher.write_u8(0),
+            Some(value) => {
+                hasher.write_u8(1);
+                value.checksum(hasher);
+            }
+        }
+    }
+}
+
+impl<A: Checksum, B: Checksum> Checksum for (A, B) {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        self.0.checksum(hasher);
+        self.1.checksum(hasher);
+    }
+}
+
+impl Checksum for u16 {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u16(*self);
+    }
+}
+
+impl Checksum for u32 {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u32(*self);
+    }
+}
+
+impl Checksum for u64 {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(*self);
+    }
+}
+
+impl Checksum for i64 {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_i64(*self);
+    }
+}
+
+impl Checksum for str {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.len() as u64);
+        hasher.write(self.as_bytes());
+    }
+}
+
+impl Checksum for Fixed {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_i64(self.to_bits());
+    }
+}
+
+impl<T> Checksum for Handle<T> {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.index() as u64);
+    }
+}
+
+impl Checksum for ItemStack {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        self.item.checksum(hasher);
+        self.quantity.checksum(hasher);
+        self.data.checksum(hasher);
+    }
+}
+
+impl Checksum for Durability {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        self.remaining.checksum(hasher);
+        self.max.checksum(hasher);
+    }
+}
+
+impl Checksum for Quality {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u8(*self as u8);
+    }
+}
+
+impl Checksum for ItemData {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        self.quality.checksum(hasher);
+        self.durability.checksum(hasher);
+        self.charge.checksum(hasher);
+
+        hasher.write_u64(self.metadata.len() as u64);
+        for (key, value) in &self.metadata {
+            key.as_str().checksum(hasher);
+            value.checksum(hasher);
+        }
+    }
+}
+
+impl Checksum for ItemFilter {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        match self {
+            ItemFilter::Item(item) => {
+                hasher.write_u8(0);
+                item.checksum(hasher);
+            },
+            ItemFilter::Tag(tag) => {
+                hasher.write_u8(1);
+                tag.checksum(hasher);
+            },
+        }
+    }
+}
+
+impl Checksum for ItemSlot {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        self.stack.checksum(hasher);
+        self.capacity.checksum(hasher);
+        self.filter.checksum(hasher);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fnv1a_known_values() {
+        assert_eq!(checksum(&[] as &[u16]), {
+            let mut hasher = StateHasher::new();
+            hasher.write_u64(0);
+            hasher.finish()
+        });
+
+        let mut hasher = StateHasher::new();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn order_matters() {
+        assert_ne!(checksum(&(1u16, 2u16)), checksum(&(2u16, 1u16)));
+    }
+}
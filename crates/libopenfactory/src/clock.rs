@@ -0,0 +1,143 @@
+//! Turning real time into logic ticks.
+//!
+//! A [`TickClock`] keeps the time the simulation is owed. Every update it runs
+//! as many whole ticks as that time covers, so a slow frame is made up for by
+//! running more ticks instead of losing them. A cap on ticks per update keeps a
+//! slow machine from spending ever longer frames catching up, and a cap on the
+//! time owed forgets stalls too long to be worth catching up on.
+
+use std::time::Duration;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ClockUpdate {
+    /// Ticks to run now.
+    pub ticks: u32,
+
+    /// Whole ticks still owed after these, left for later updates.
+    pub behind: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TickClock {
+    /// Ticks per second.
+    rate: u32,
+    max_ticks_per_update: u32,
+    max_behind: u32,
+
+    /// Time owed, in nanoseconds times the rate, so that a tick is a whole second of it.
+    owed: u128,
+}
+
+pub struct TickClockBuilder {
+    rate: u32,
+    max_ticks_per_update: u32,
+    max_behind: Option<u32>,
+}
+
+impl TickClockBuilder {
+    pub fn new() -> Self {
+        Self {
+            rate: crate::TICKS_PER_SECOND,
+            max_ticks_per_update: 10,
+            max_behind: None,
+        }
+    }
+
+    pub fn with_rate(mut self, rate: u32) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// Ticks run at most per update.
+    pub fn with_max_ticks_per_update(mut self, max_ticks_per_update: u32) -> Self {
+        self.max_ticks_per_update = max_ticks_per_update;
+        self
+    }
+
+    /// Ticks owed at most, beyond those run. Defaults to five seconds' worth.
+    pub fn with_max_behind(mut self, max_behind: u32) -> Self {
+        self.max_behind = Some(max_behind);
+        self
+    }
+
+    pub fn build(self) -> TickClock {
+        match self {
+            TickClockBuilder { rate, max_ticks_per_update, max_behind } if rate > 0 && max_ticks_per_update > 0 => {
+                TickClock { rate, max_ticks_per_update, max_behind: max_behind.unwrap_or(rate * 5), owed: 0 }
+            },
+
+            _ => panic!("Tick Clock Builder built with a rate or tick cap of zero")
+        }
+    }
+}
+
+impl Default for TickClockBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for TickClock {
+    fn default() -> Self {
+        TickClockBuilder::new().build()
+    }
+}
+
+impl TickClock {
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Duration of one tick.
+    pub fn tick_duration(&self) -> Duration {
+        Duration::from_nanos((NANOS_PER_SECOND / self.rate as u128) as u64)
+    }
+
+    /// Add the time since the last update and take the ticks it owes.
+    pub fn update(&mut self, elapsed: Duration) -> ClockUpdate {
+        self.owed += elapsed.as_nanos() * self.rate as u128;
+
+        let owed_ticks = self.owed / NANOS_PER_SECOND;
+        let ticks = owed_ticks.min(self.max_ticks_per_update as u128) as u32;
+        let behind = (owed_ticks - ticks as u128).min(self.max_behind as u128) as u32;
+
+        // Keep the fraction of a tick, and the ticks left behind.
+        self.owed = self.owed % NANOS_PER_SECOND + behind as u128 * NANOS_PER_SECOND;
+
+        ClockUpdate { ticks, behind }
+    }
+
+    /// Forget the time owed, e.g. after unpausing.
+    pub fn reset(&mut self) {
+        self.owed = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn catches_up_on_slow_updates() {
+        let mut clock = TickClockBuilder::new().with_rate(20).with_max_ticks_per_update(4).with_max_behind(6).build();
+        let millis = Duration::from_millis;
+
+        // Fractions of a tick add up.
+        assert_eq!(ClockUpdate { ticks: 0, behind: 0 }, clock.update(millis(30)));
+        assert_eq!(ClockUpdate { ticks: 1, behind: 0 }, clock.update(millis(30)));
+        assert_eq!(ClockUpdate { ticks: 0, behind: 0 }, clock.update(millis(30)));
+
+        // A 200ms frame owes four ticks and change, a 500ms one more than the caps allow.
+        assert_eq!(ClockUpdate { ticks: 4, behind: 0 }, clock.update(millis(200)));
+        assert_eq!(ClockUpdate { ticks: 4, behind: 6 }, clock.update(millis(500)));
+        assert_eq!(ClockUpdate { ticks: 4, behind: 2 }, clock.update(millis(0)));
+        assert_eq!(ClockUpdate { ticks: 2, behind: 0 }, clock.update(millis(0)));
+
+        // Rates that don't divide a second don't drift.
+        let mut clock = TickClockBuilder::new().with_rate(3).build();
+        let ticks: u32 = (0..300).map(|_| clock.update(millis(10)).ticks).sum();
+        assert_eq!(9, ticks);
+    }
+}
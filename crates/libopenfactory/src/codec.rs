@@ -0,0 +1,371 @@
+//! Compact binary encoding for commands, messages and saved state.
+//!
+//! All integers are little-endian and fixed width so that the encoding is the
+//! same on every platform. Handles are encoded as their table index, so both
+//! sides must build their prototype tables in the same order.
+
+use std::collections::BTreeMap;
+
+use crate::{fixed::Fixed, item_stack::{Durability, ItemData, ItemFilter, ItemSlot, ItemStack, ItemStackQuanity, Quality}, registry::Handle};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ended in the middle of a value.
+    UnexpectedEnd,
+
+    /// An enum tag that doesn't name a variant.
+    InvalidTag { tag: u8, type_name: &'static str },
+
+    /// A string that isn't valid UTF-8.
+    InvalidString,
+
+    /// Input that doesn't fit what was expected, e.g. a bad magic number.
+    Invalid(&'static str),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::InvalidTag { tag, type_name } => write!(f, "invalid tag {} for {}", tag, type_name),
+            DecodeError::InvalidString => write!(f, "string is not valid UTF-8"),
+            DecodeError::Invalid(reason) => write!(f, "invalid input: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+pub trait Decode: Sized {
+    /// Decode a value from the front of `input`, advancing it past the value.
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+/// Encode a value into a new buffer.
+pub fn encode_to_vec<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut out = vec![];
+    value.encode(&mut out);
+    out
+}
+
+/// Decode a value that must take up the entire input.
+pub fn decode_from_slice<T: Decode>(mut input: &[u8]) -> Result<T, DecodeError> {
+    let value = T::decode(&mut input)?;
+
+    if input.is_empty() {
+        Ok(value)
+    } else {
+        Err(DecodeError::Invalid("trailing bytes"))
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < len {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+    Ok(taken)
+}
+
+macro_rules! impl_codec_for_int {
+    ($($int:ty),*) => {$(
+        impl Encode for $int {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+        }
+
+        impl Decode for $int {
+            fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                let mut bytes = [0; std::mem::size_of::<$int>()];
+                bytes.copy_from_slice(take(input, std::mem::size_of::<$int>())?);
+                Ok(<$int>::from_le_bytes(bytes))
+            }
+        }
+    )*};
+}
+
+impl_codec_for_int!(u8, u16, u32, u64, i32, i64);
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u8).encode(out);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag { tag, type_name: "bool" }),
+        }
+    }
+}
+
+impl Encode for Fixed {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.to_bits().encode(out);
+    }
+}
+
+impl Decode for Fixed {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Fixed::from_bits(i64::decode(input)?))
+    }
+}
+
+impl<T> Encode for Handle<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.index() as u32).encode(out);
+    }
+}
+
+impl<T> Decode for Handle<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Handle::new(u32::decode(input)? as usize))
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = u32::decode(input)? as usize;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidString)
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        for value in self {
+            value.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self[..].encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = u32::decode(input)? as usize;
+
+        // Every value takes at least a byte, so don't trust lengths longer than the input.
+        let mut values = Vec::with_capacity(std::cmp::min(len, input.len()));
+        for _ in 0..len {
+            values.push(T::decode(input)?);
+        }
+
+        Ok(values)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => 0u8.encode(out),
+            Some(value) => {
+                1u8.encode(out);
+                value.encode(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag { tag, type_name: "Option" }),
+        }
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+impl Encode for ItemStack {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.item.encode(out);
+        self.quantity.encode(out);
+        self.data.encode(out);
+    }
+}
+
+impl Decode for ItemStack {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(ItemStack {
+            item: Handle::decode(input)?,
+            quantity: ItemStackQuanity::decode(input)?,
+            data: ItemData::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Durability {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.remaining.encode(out);
+        self.max.encode(out);
+    }
+}
+
+impl Decode for Durability {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let durability = Durability { remaining: u16::decode(input)?, max: u16::decode(input)? };
+
+        if durability.remaining > durability.max {
+            return Err(DecodeError::Invalid("durability above its maximum"));
+        }
+
+        Ok(durability)
+    }
+}
+
+impl Encode for Quality {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u8).encode(out);
+    }
+}
+
+impl Decode for Quality {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Quality::Normal),
+            1 => Ok(Quality::Good),
+            2 => Ok(Quality::Excellent),
+            tag => Err(DecodeError::InvalidTag { tag, type_name: "Quality" }),
+        }
+    }
+}
+
+impl Encode for ItemData {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.quality.encode(out);
+        self.durability.encode(out);
+        self.charge.encode(out);
+
+        (self.metadata.len() as u32).encode(out);
+        for (key, value) in &self.metadata {
+            key.encode(out);
+            value.encode(out);
+        }
+    }
+}
+
+impl Decode for ItemData {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let quality = Quality::decode(input)?;
+        let durability = Option::decode(input)?;
+        let charge = Option::decode(input)?;
+
+        let mut metadata = BTreeMap::new();
+        for (key, value) in Vec::<(String, i64)>::decode(input)? {
+            if metadata.insert(key, value).is_some() {
+                return Err(DecodeError::Invalid("duplicate item metadata key"));
+            }
+        }
+
+        Ok(ItemData { quality, durability, charge, metadata })
+    }
+}
+
+impl Encode for ItemFilter {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            ItemFilter::Item(item) => {
+                0u8.encode(out);
+                item.encode(out);
+            },
+            ItemFilter::Tag(tag) => {
+                1u8.encode(out);
+                tag.encode(out);
+            },
+        }
+    }
+}
+
+impl Decode for ItemFilter {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(ItemFilter::Item(Handle::decode(input)?)),
+            1 => Ok(ItemFilter::Tag(Handle::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag { tag, type_name: "ItemFilter" }),
+        }
+    }
+}
+
+impl Encode for ItemSlot {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.stack.encode(out);
+        self.capacity.encode(out);
+        self.filter.encode(out);
+    }
+}
+
+impl Decode for ItemSlot {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let slot = ItemSlot {
+            stack: Option::decode(input)?,
+            capacity: ItemStackQuanity::decode(input)?,
+            filter: Option::decode(input)?,
+        };
+
+        if slot.quantity() > slot.capacity {
+            return Err(DecodeError::Invalid("item slot holds more than its capacity"));
+        }
+
+        Ok(slot)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let value = (vec![Some(3u16), None], (String::from("copper"), Fixed::from_f32(-1.25)));
+        let bytes = encode_to_vec(&value);
+        assert_eq!(decode_from_slice::<(Vec<Option<u16>>, (String, Fixed))>(&bytes), Ok(value));
+    }
+
+    #[test]
+    fn truncated_input() {
+        let bytes = encode_to_vec(&0x1234_5678u32);
+        assert_eq!(decode_from_slice::<u32>(&bytes[..3]), Err(DecodeError::UnexpectedEnd));
+    }
+}
@@ -0,0 +1,829 @@
+//! Player actions as data.
+//!
+//! Every edit to a [`Factory`] goes through a [`Command`]. Commands are
+//! validated before they change anything, so a rejected command leaves the
+//! factory untouched on every peer.
+
+use crate::{
+    blueprint::Blueprint,
+    codec::{Decode, DecodeError, Encode},
+    connector::Connector,
+    factory::{EntityId, Factory, FactoryEvent, Position, Prototypes},
+    kinds::{ConnectorKind, RecipeKind, TechnologyKind},
+    producer::Producer,
+    registry::Handle,
+    tile::Rotation,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Place a new producer, optionally already set to a recipe.
+    ///
+    /// Without an `id`, the factory picks one. An `id` is only given to put
+    /// back a producer that was removed.
+    PlaceProducer {
+        id: Option<EntityId>,
+        position: Position,
+        rotation: Rotation,
+        recipe: Option<Handle<RecipeKind>>,
+    },
+
+    /// Remove a producer or connector.
+    ///
+    /// Removing a producer also removes the connectors attached to it.
+    /// Items in them are returned, see [`Factory::returned_items`].
+    RemoveEntity {
+        entity: EntityId,
+    },
+
+    /// Place a connector moving items from the giver's output to the taker's input.
+    ///
+    /// Without an `id`, the factory picks one. An `id` is only given to put
+    /// back a connector that was removed.
+    Connect {
+        id: Option<EntityId>,
+        giver: EntityId,
+        taker: EntityId,
+        kind: Handle<ConnectorKind>,
+    },
+
+    /// Remove a connector. Items in its hand are returned.
+    Disconnect {
+        connector: EntityId,
+    },
+
+    /// Change or clear the recipe of a producer. Items in the producer are returned.
+    SetRecipe {
+        producer: EntityId,
+        recipe: Option<Handle<RecipeKind>>,
+    },
+
+    /// Move or turn a producer. Connectors attached to it follow.
+    MoveEntity {
+        entity: EntityId,
+        position: Position,
+        rotation: Rotation,
+    },
+
+    /// Research another technology, or none. Progress on the previous one is kept.
+    SetResearch {
+        technology: Option<Handle<TechnologyKind>>,
+    },
+
+    /// Several commands applied in order as one.
+    Batch(Vec<Command>),
+
+    /// Place the producers of a blueprint centered on `position` and turned by
+    /// `rotation`, then connect them. Either all of it is placed or none.
+    PlaceBlueprint {
+        blueprint: Blueprint,
+        position: Position,
+        rotation: Rotation,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    UnknownEntity(EntityId),
+    UnknownRecipe,
+    UnknownConnectorKind,
+    UnknownTechnology,
+
+    /// The recipe is unlocked by a technology that isn't researched yet.
+    RecipeLocked,
+
+    /// The technology is researched already, or its prerequisites aren't.
+    TechnologyUnavailable,
+
+    /// The id is either in use or was never handed out by the factory.
+    EntityIdUnavailable(EntityId),
+
+    /// The command only works on producers.
+    NotAProducer(EntityId),
+
+    /// The command only works on connectors.
+    NotAConnector(EntityId),
+
+    /// The giver of a connection has no output slots.
+    NoOutput(EntityId),
+
+    /// The taker of a connection has no input slots.
+    NoInput(EntityId),
+
+    /// A producer can't be connected to itself.
+    SelfConnection(EntityId),
+
+    /// A mining recipe needs deposits of its outputs under the producer.
+    NoDeposit,
+
+    /// The producer would cover tiles another one covers already.
+    Blocked(EntityId),
+
+    /// A connector of the blueprint is attached to a producer the blueprint doesn't have.
+    InvalidBlueprint,
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::UnknownEntity(id) => write!(f, "no entity with id {}", id),
+            CommandError::UnknownRecipe => write!(f, "unknown recipe"),
+            CommandError::UnknownConnectorKind => write!(f, "unknown connector kind"),
+            CommandError::UnknownTechnology => write!(f, "unknown technology"),
+            CommandError::RecipeLocked => write!(f, "recipe is not researched yet"),
+            CommandError::TechnologyUnavailable => write!(f, "technology can't be researched"),
+            CommandError::EntityIdUnavailable(id) => write!(f, "entity id {} is not available", id),
+            CommandError::NotAProducer(id) => write!(f, "entity {} is not a producer", id),
+            CommandError::NotAConnector(id) => write!(f, "entity {} is not a connector", id),
+            CommandError::NoOutput(id) => write!(f, "producer {} has no output to connect from", id),
+            CommandError::NoInput(id) => write!(f, "producer {} has no input to connect to", id),
+            CommandError::SelfConnection(id) => write!(f, "producer {} can't be connected to itself", id),
+            CommandError::NoDeposit => write!(f, "nothing to mine there"),
+            CommandError::Blocked(id) => write!(f, "producer {} is in the way", id),
+            CommandError::InvalidBlueprint => write!(f, "blueprint connects producers it doesn't have"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl Factory {
+    /// Apply a command to the factory.
+    ///
+    /// Returns the command that undoes it. Undoing puts back what was placed,
+    /// moved or connected. The items that were in removed entities stay
+    /// in the returned items.
+    ///
+    /// On error, the factory is left unchanged. For a [`Command::Batch`], the
+    /// commands before the one that failed are undone.
+    pub fn apply(&mut self, command: &Command, prototypes: &Prototypes) -> Result<Command, CommandError> {
+        self.apply_as(command, prototypes, false)
+    }
+
+    /// Apply a command, or undo one while rolling back with `undoing`.
+    ///
+    /// Undoing doesn't check deposits, as a miner put back where it was may
+    /// have mined the tiles under it out since.
+    fn apply_as(&mut self, command: &Command, prototypes: &Prototypes, undoing: bool) -> Result<Command, CommandError> {
+        match *command {
+            Command::PlaceProducer { id, position, rotation, recipe } => {
+                self.check_id_available(id)?;
+
+                let producer = match recipe {
+                    Some(handle) => {
+                        let recipe = self.research().unlocked_recipe(handle, prototypes)?;
+                        if !undoing {
+                            self.check_deposits(recipe, position, rotation)?;
+                        }
+                        Producer::new_with_recipe(handle, prototypes, self.research())?
+                    },
+                    None => Producer::new(),
+                };
+
+                if let Some(blocker) = self.producer_blocked_by(position, rotation, None) {
+                    return Err(CommandError::Blocked(blocker));
+                }
+
+                let id = self.insert_producer(id, position, rotation, producer);
+                Ok(Command::RemoveEntity { entity: id })
+            },
+
+            Command::RemoveEntity { entity } => {
+                if self.connector(entity).is_some() {
+                    return self.apply_as(&Command::Disconnect { connector: entity }, prototypes, undoing);
+                }
+
+                if self.producer(entity).is_none() {
+                    return Err(CommandError::UnknownEntity(entity));
+                }
+
+                // The connectors have to go first, but have to be put back last.
+                let mut reconnect = vec![];
+                for connector in self.connectors_attached_to(entity) {
+                    let placed = self.remove_connector(connector).expect("attached connector exists");
+                    reconnect.push(Command::Connect { id: Some(connector), giver: placed.giver, taker: placed.taker, kind: placed.kind });
+                }
+
+                let placed = self.remove_producer(entity).expect("producer exists");
+                let mut inverse = vec![Command::PlaceProducer { id: Some(entity), position: placed.position, rotation: placed.rotation, recipe: placed.producer.recipe() }];
+                inverse.extend(reconnect);
+
+                Ok(Command::Batch(inverse))
+            },
+
+            Command::Connect { id, giver, taker, kind } => {
+                self.check_id_available(id)?;
+                let connector_kind = prototypes.connectors.get(kind).ok_or(CommandError::UnknownConnectorKind)?;
+
+                if giver == taker {
+                    return Err(CommandError::SelfConnection(giver));
+                }
+
+                let giver_producer = self.producer(giver).ok_or(CommandError::UnknownEntity(giver))?;
+                if !giver_producer.producer.gives_output() {
+                    return Err(CommandError::NoOutput(giver));
+                }
+
+                let taker_producer = self.producer(taker).ok_or(CommandError::UnknownEntity(taker))?;
+                if !taker_producer.producer.takes_input() {
+                    return Err(CommandError::NoInput(taker));
+                }
+
+                let length = self.connector_length(giver, taker).expect("both producers exist");
+                let id = self.insert_connector(id, kind, giver, taker, Connector::new_with_kind(connector_kind, length));
+                Ok(Command::Disconnect { connector: id })
+            },
+
+            Command::Disconnect { connector } => {
+                if self.producer(connector).is_some() {
+                    return Err(CommandError::NotAConnector(connector));
+                }
+
+                let placed = self.remove_connector(connector).ok_or(CommandError::UnknownEntity(connector))?;
+                Ok(Command::Connect { id: Some(connector), giver: placed.giver, taker: placed.taker, kind: placed.kind })
+            },
+
+            Command::SetRecipe { producer, recipe: handle } => {
+                let recipe = match handle {
+                    Some(handle) => Some(self.research().unlocked_recipe(handle, prototypes)?),
+                    None => None,
+                };
+
+                if self.connector(producer).is_some() {
+                    return Err(CommandError::NotAProducer(producer));
+                }
+
+                let placed = self.producer(producer).ok_or(CommandError::UnknownEntity(producer))?;
+                match recipe {
+                    Some(recipe) if !undoing => self.check_deposits(recipe, placed.position, placed.rotation)?,
+                    _ => (),
+                }
+
+                self.stash_producer(producer);
+                let (placed, research) = self.producer_mut_with_research(producer).expect("producer exists");
+                let previous = placed.producer.recipe();
+                let contents = placed.producer.take_contents();
+
+                match handle {
+                    Some(handle) => placed.producer.set_recipe(handle, prototypes, research).expect("recipe is unlocked"),
+                    None => placed.producer.clear_recipe(),
+                }
+
+                self.push_event(FactoryEvent::RecipeChanged(producer));
+                self.return_items(producer, contents);
+                Ok(Command::SetRecipe { producer, recipe: previous })
+            },
+
+            Command::MoveEntity { entity, position, rotation } => {
+                if self.connector(entity).is_some() {
+                    return Err(CommandError::NotAProducer(entity));
+                }
+
+                let placed = self.producer(entity).ok_or(CommandError::UnknownEntity(entity))?;
+                match placed.producer.recipe() {
+                    Some(recipe) if !undoing => self.check_deposits(&prototypes.recipes[recipe], position, rotation)?,
+                    _ => (),
+                }
+
+                if let Some(blocker) = self.producer_blocked_by(position, rotation, Some(entity)) {
+                    return Err(CommandError::Blocked(blocker));
+                }
+
+                let (previous_position, previous_rotation) = self.move_producer(entity, position, rotation).expect("producer exists");
+                Ok(Command::MoveEntity { entity, position: previous_position, rotation: previous_rotation })
+            },
+
+            Command::SetResearch { technology } => {
+                if let Some(technology) = technology {
+                    prototypes.technologies.get(technology).ok_or(CommandError::UnknownTechnology)?;
+
+                    if !self.research().can_research(technology, prototypes.technologies) {
+                        return Err(CommandError::TechnologyUnavailable);
+                    }
+                }
+
+                let previous = self.research_mut().set_current(technology);
+                self.push_event(FactoryEvent::ResearchChanged);
+                Ok(Command::SetResearch { technology: previous })
+            },
+
+            Command::Batch(ref commands) => {
+                let checkpoint = self.checkpoint();
+                let mut inverses = vec![];
+
+                for command in commands {
+                    match self.apply_as(command, prototypes, undoing) {
+                        Ok(inverse) => inverses.push(inverse),
+
+                        Err(error) => {
+                            for inverse in inverses.iter().rev() {
+                                self.apply_as(inverse, prototypes, true).expect("Inverse of an applied command applies");
+                            }
+
+                            // Nothing happened, as far as anybody can tell.
+                            self.roll_back(checkpoint);
+
+                            return Err(error);
+                        },
+                    }
+                }
+
+                self.commit(checkpoint);
+                inverses.reverse();
+                Ok(Command::Batch(inverses))
+            },
+
+            Command::PlaceBlueprint { ref blueprint, position, rotation } => {
+                if !blueprint.is_valid() {
+                    return Err(CommandError::InvalidBlueprint);
+                }
+
+                let checkpoint = self.checkpoint();
+                let mut placed = vec![];
+
+                if let Err(error) = self.place_blueprint(blueprint, position, rotation, prototypes, &mut placed) {
+                    for &entity in placed.iter().rev() {
+                        self.apply_as(&Command::RemoveEntity { entity }, prototypes, true).expect("placed producer can be removed");
+                    }
+
+                    self.roll_back(checkpoint);
+                    return Err(error);
+                }
+
+                self.commit(checkpoint);
+
+                // Removing the producers takes the connectors with them.
+                Ok(Command::Batch(placed.into_iter().rev().map(|entity| Command::RemoveEntity { entity }).collect()))
+            },
+        }
+    }
+
+    /// Place the producers of a blueprint, then connect them. The producers placed are pushed to `placed`, even if it fails part way.
+    fn place_blueprint(&mut self, blueprint: &Blueprint, position: Position, rotation: Rotation, prototypes: &Prototypes, placed: &mut Vec<EntityId>) -> Result<(), CommandError> {
+        for producer in &blueprint.producers {
+            let (position, rotation) = producer.placed_at(position, rotation);
+
+            match self.apply(&Command::PlaceProducer { id: None, position, rotation, recipe: producer.recipe }, prototypes)? {
+                Command::RemoveEntity { entity } => placed.push(entity),
+                inverse => unreachable!("placing a producer is undone by removing it, not {:?}", inverse),
+            }
+        }
+
+        for connector in &blueprint.connectors {
+            let (giver, taker) = (placed[connector.giver as usize], placed[connector.taker as usize]);
+            self.apply(&Command::Connect { id: None, giver, taker, kind: connector.kind }, prototypes)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_deposits(&self, recipe: &RecipeKind, position: Position, rotation: Rotation) -> Result<(), CommandError> {
+        if self.has_deposits_for(recipe, position, rotation) {
+            Ok(())
+        } else {
+            Err(CommandError::NoDeposit)
+        }
+    }
+
+    fn check_id_available(&self, id: Option<EntityId>) -> Result<(), CommandError> {
+        match id {
+            Some(id) if !self.is_id_available(id) => Err(CommandError::EntityIdUnavailable(id)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Encode for Command {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Command::PlaceProducer { id, position, rotation, recipe } => {
+                0u8.encode(out);
+                id.encode(out);
+                position.encode(out);
+                rotation.encode(out);
+                recipe.encode(out);
+            },
+
+            Command::RemoveEntity { entity } => {
+                1u8.encode(out);
+                entity.encode(out);
+            },
+
+            Command::Connect { id, giver, taker, kind } => {
+                2u8.encode(out);
+                id.encode(out);
+                giver.encode(out);
+                taker.encode(out);
+                kind.encode(out);
+            },
+
+            Command::Disconnect { connector } => {
+                3u8.encode(out);
+                connector.encode(out);
+            },
+
+            Command::SetRecipe { producer, recipe } => {
+                4u8.encode(out);
+                producer.encode(out);
+                recipe.encode(out);
+            },
+
+            Command::MoveEntity { entity, position, rotation } => {
+                5u8.encode(out);
+                entity.encode(out);
+                position.encode(out);
+                rotation.encode(out);
+            },
+
+            Command::Batch(commands) => {
+                6u8.encode(out);
+                commands.encode(out);
+            },
+
+            Command::SetResearch { technology } => {
+                7u8.encode(out);
+                technology.encode(out);
+            },
+
+            Command::PlaceBlueprint { blueprint, position, rotation } => {
+                8u8.encode(out);
+                blueprint.encode(out);
+                position.encode(out);
+                rotation.encode(out);
+            },
+        }
+    }
+}
+
+/// How deep [`Command::Batch`]es may be nested in decoded commands, so made up input can't overflow the stack.
+pub const MAX_BATCH_DEPTH: usize = 32;
+
+impl Command {
+    fn decode_nested(input: &mut &[u8], depth: usize) -> Result<Self, DecodeError> {
+        Ok(match u8::decode(input)? {
+            0 => Command::PlaceProducer {
+                id: Decode::decode(input)?,
+                position: Decode::decode(input)?,
+                rotation: Decode::decode(input)?,
+                recipe: Decode::decode(input)?,
+            },
+
+            1 => Command::RemoveEntity {
+                entity: Decode::decode(input)?,
+            },
+
+            2 => Command::Connect {
+                id: Decode::decode(input)?,
+                giver: Decode::decode(input)?,
+                taker: Decode::decode(input)?,
+                kind: Decode::decode(input)?,
+            },
+
+            3 => Command::Disconnect {
+                connector: Decode::decode(input)?,
+            },
+
+            4 => Command::SetRecipe {
+                producer: Decode::decode(input)?,
+                recipe: Decode::decode(input)?,
+            },
+
+            5 => Command::MoveEntity {
+                entity: Decode::decode(input)?,
+                position: Decode::decode(input)?,
+                rotation: Decode::decode(input)?,
+            },
+
+            6 if depth == MAX_BATCH_DEPTH => return Err(DecodeError::Invalid("batches nested too deep")),
+            6 => {
+                let len = u32::decode(input)? as usize;
+                let mut commands = Vec::with_capacity(std::cmp::min(len, input.len()));
+                for _ in 0..len {
+                    commands.push(Self::decode_nested(input, depth + 1)?);
+                }
+                Command::Batch(commands)
+            },
+
+            7 => Command::SetResearch {
+                technology: Decode::decode(input)?,
+            },
+
+            8 => Command::PlaceBlueprint {
+                blueprint: Decode::decode(input)?,
+                position: Decode::decode(input)?,
+                rotation: Decode::decode(input)?,
+            },
+
+            tag => return Err(DecodeError::InvalidTag { tag, type_name: "Command" }),
+        })
+    }
+}
+
+impl Decode for Command {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Self::decode_nested(input, 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        blueprint::{BlueprintConnector, BlueprintProducer},
+        codec::{decode_from_slice, encode_to_vec},
+        fixed::Fixed,
+        spatial::Rect,
+        testing::Fixture,
+        tile::TilePosition,
+    };
+
+    #[test]
+    fn connect_two_producers() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::new();
+
+        factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(0.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.generate) }, &prototypes).unwrap();
+        factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(120.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.consume) }, &prototypes).unwrap();
+
+        let events = factory.drain_events().collect::<Vec<_>>();
+        let (generator, consumer) = match events[..] {
+            [FactoryEvent::ProducerPlaced(generator), FactoryEvent::ProducerPlaced(consumer)] => (generator, consumer),
+            _ => panic!("Expected two producers to be placed, got {:?}", events),
+        };
+
+        assert_eq!(
+            factory.apply(&Command::Connect { id: None, giver: consumer, taker: generator, kind: fixture.connector }, &prototypes),
+            Err(CommandError::NoOutput(consumer)),
+        );
+
+        let inverse = factory.apply(&Command::Connect { id: None, giver: generator, taker: consumer, kind: fixture.connector }, &prototypes).unwrap();
+        assert!(matches!(inverse, Command::Disconnect { .. }));
+
+        let connector = factory.connectors().next().expect("connector was placed").1;
+        assert_eq!(connector.connector.length(), crate::fixed::Fixed::from_int(30));
+
+        for _ in 0..20 {
+            factory.tick(&prototypes);
+        }
+
+        assert!(factory.producer(consumer).unwrap().producer.is_producing());
+    }
+
+    #[test]
+    fn rejected_commands_do_not_change_the_factory() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::new();
+        let before = crate::checksum::checksum(&factory);
+
+        let missing = EntityId::from_raw(7);
+        assert_eq!(
+            factory.apply(&Command::SetRecipe { producer: missing, recipe: Some(fixture.generate) }, &prototypes),
+            Err(CommandError::UnknownEntity(missing)),
+        );
+        assert_eq!(
+            factory.apply(&Command::PlaceProducer { id: None, position: Position::default(), rotation: Rotation::North, recipe: Some(Handle::new(99)) }, &prototypes),
+            Err(CommandError::UnknownRecipe),
+        );
+
+        assert_eq!(before, crate::checksum::checksum(&factory));
+        assert_eq!(factory.drain_events().count(), 0);
+    }
+
+    #[test]
+    fn inverses_undo_and_redo() {
+        use crate::checksum::checksum;
+
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::new();
+
+        let place_generator = Command::PlaceProducer { id: None, position: Position::from_f32(0.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.generate) };
+        let remove_generator = factory.apply(&place_generator, &prototypes).unwrap();
+        let generator = match remove_generator {
+            Command::RemoveEntity { entity } => entity,
+            ref inverse => panic!("Expected a removal, got {:?}", inverse),
+        };
+
+        let place_consumer = Command::PlaceProducer { id: None, position: Position::from_f32(120.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.consume) };
+        let consumer = match factory.apply(&place_consumer, &prototypes).unwrap() {
+            Command::RemoveEntity { entity } => entity,
+            inverse => panic!("Expected a removal, got {:?}", inverse),
+        };
+
+        factory.apply(&Command::Connect { id: None, giver: generator, taker: consumer, kind: fixture.connector }, &prototypes).unwrap();
+        let connected = checksum(&factory);
+
+        // Moving changes the connector's length and moving back changes it back.
+        let move_back = factory.apply(&Command::MoveEntity { entity: consumer, position: Position::from_f32(200.0, 0.0), rotation: Rotation::North }, &prototypes).unwrap();
+        assert_eq!(factory.connectors().next().unwrap().1.connector.length(), crate::fixed::Fixed::from_int(110));
+        factory.apply(&move_back, &prototypes).unwrap();
+        assert_eq!(checksum(&factory), connected);
+
+        // Turning around moves the input port to the far side, so the connector gets longer.
+        let turn_back = factory.apply(&Command::MoveEntity { entity: consumer, position: Position::from_f32(120.0, 0.0), rotation: Rotation::South }, &prototypes).unwrap();
+        assert_eq!(factory.connectors().next().unwrap().1.connector.length(), crate::fixed::Fixed::from_int(120));
+        assert_eq!(turn_back, Command::MoveEntity { entity: consumer, position: Position::from_f32(120.0, 0.0), rotation: Rotation::North });
+        factory.apply(&turn_back, &prototypes).unwrap();
+        assert_eq!(checksum(&factory), connected);
+
+        // Removing the generator takes its connector with it.
+        let put_back = factory.apply(&Command::RemoveEntity { entity: generator }, &prototypes).unwrap();
+        assert_eq!(factory.producers().count(), 1);
+        assert_eq!(factory.connectors().count(), 0);
+
+        let remove_again = factory.apply(&put_back, &prototypes).unwrap();
+        assert_eq!(checksum(&factory), connected);
+
+        factory.apply(&remove_again, &prototypes).unwrap();
+        assert_eq!(factory.producers().count(), 1);
+        assert_eq!(factory.connectors().count(), 0);
+
+        // Clearing a recipe undoes back to the recipe.
+        let restore_recipe = factory.apply(&Command::SetRecipe { producer: consumer, recipe: None }, &prototypes).unwrap();
+        assert_eq!(restore_recipe, Command::SetRecipe { producer: consumer, recipe: Some(fixture.consume) });
+    }
+
+    #[test]
+    fn failed_batch_is_rolled_back() {
+        use crate::checksum::checksum;
+
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::new();
+        let before = checksum(&factory);
+
+        let batch = Command::Batch(vec![
+            Command::PlaceProducer { id: None, position: Position::default(), rotation: Rotation::North, recipe: Some(fixture.generate) },
+            Command::SetRecipe { producer: EntityId::from_raw(5), recipe: None },
+        ]);
+
+        assert_eq!(factory.apply(&batch, &prototypes), Err(CommandError::UnknownEntity(EntityId::from_raw(5))));
+        assert_eq!(factory.producers().count(), 0);
+        assert_eq!(factory.drain_events().count(), 0);
+
+        // The id that was handed out is taken back.
+        assert_eq!(checksum(&factory), before);
+    }
+
+    #[test]
+    fn failed_batch_puts_back_what_was_in_removed_entities() {
+        use crate::checksum::checksum;
+
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let running = || {
+            let mut factory = Factory::new();
+            factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(0.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.generate) }, &prototypes).unwrap();
+            factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(120.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.consume) }, &prototypes).unwrap();
+            factory.apply(&Command::Connect { id: None, giver: EntityId::from_raw(0), taker: EntityId::from_raw(1), kind: fixture.connector }, &prototypes).unwrap();
+
+            for _ in 0..30 {
+                factory.tick(&prototypes);
+            }
+            factory.drain_events().for_each(drop);
+            factory
+        };
+
+        let mut factory = running();
+        let untouched = running();
+        assert!(factory.producer(EntityId::from_raw(1)).unwrap().producer.is_producing());
+
+        let batch = Command::Batch(vec![
+            Command::RemoveEntity { entity: EntityId::from_raw(1) },
+            Command::SetRecipe { producer: EntityId::from_raw(0), recipe: None },
+            Command::PlaceProducer { id: None, position: Position::from_f32(0.0, 120.0), rotation: Rotation::North, recipe: Some(fixture.generate) },
+            Command::Batch(vec![
+                Command::RemoveEntity { entity: EntityId::from_raw(0) },
+                Command::SetRecipe { producer: EntityId::from_raw(9), recipe: None },
+            ]),
+        ]);
+        assert_eq!(factory.apply(&batch, &prototypes), Err(CommandError::UnknownEntity(EntityId::from_raw(9))));
+
+        assert!(factory.returned_items().is_empty());
+        assert_eq!(factory.drain_events().count(), 0);
+        assert_eq!(checksum(&untouched), checksum(&factory));
+
+        // Including the rolls for quality, so they keep crafting the same.
+        let mut untouched = untouched;
+        for _ in 0..200 {
+            factory.tick(&prototypes);
+            untouched.tick(&prototypes);
+        }
+        assert_eq!(checksum(&untouched), checksum(&factory));
+    }
+
+    #[test]
+    fn producers_cannot_overlap() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::new();
+
+        let place = |x, y, rotation| Command::PlaceProducer { id: None, position: Position::from_f32(x, y), rotation, recipe: None };
+        let first = match factory.apply(&place(60.0, 15.0, Rotation::North), &prototypes).unwrap() {
+            Command::RemoveEntity { entity } => entity,
+            inverse => panic!("Expected a removal, got {:?}", inverse),
+        };
+
+        assert_eq!(factory.apply(&place(100.0, 15.0, Rotation::North), &prototypes), Err(CommandError::Blocked(first)));
+
+        // Turned on its side, it covers a column instead of a row.
+        assert_eq!(factory.apply(&place(105.0, 15.0, Rotation::East), &prototypes), Err(CommandError::Blocked(first)));
+        let second = match factory.apply(&place(105.0, 75.0, Rotation::East), &prototypes).unwrap() {
+            Command::RemoveEntity { entity } => entity,
+            inverse => panic!("Expected a removal, got {:?}", inverse),
+        };
+
+        assert_eq!(factory.apply(&Command::MoveEntity { entity: first, position: Position::from_f32(90.0, 45.0), rotation: Rotation::North }, &prototypes), Err(CommandError::Blocked(second)));
+        assert!(factory.apply(&Command::MoveEntity { entity: first, position: Position::from_f32(30.0, 15.0), rotation: Rotation::North }, &prototypes).is_ok());
+        assert_eq!(Some(first), factory.grid().occupant(TilePosition::new(-1, 0)));
+        assert_eq!(None, factory.grid().occupant(TilePosition::new(3, 0)));
+
+        // The spatial index follows along.
+        assert_eq!(Some(first), factory.producer_at(Position::from_f32(-25.0, 20.0)));
+        assert_eq!(Some(first), factory.nearest_producer(Position::from_f32(30.0, -100.0), Fixed::from_int(100)));
+        factory.apply(&Command::RemoveEntity { entity: second }, &prototypes).unwrap();
+        assert!(factory.producers_in(&Rect::from_corners(Position::from_f32(100.0, 0.0), Position::from_f32(200.0, 200.0))).is_empty());
+    }
+
+    #[test]
+    fn removing_returns_the_items_inside() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::new();
+
+        factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(0.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.generate) }, &prototypes).unwrap();
+        factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(120.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.consume) }, &prototypes).unwrap();
+        let (generator, consumer) = (EntityId::from_raw(0), EntityId::from_raw(1));
+        factory.apply(&Command::Connect { id: None, giver: generator, taker: consumer, kind: fixture.connector }, &prototypes).unwrap();
+
+        for _ in 0..30 {
+            factory.tick(&prototypes);
+        }
+        factory.drain_events().for_each(drop);
+
+        // The consumer is halfway through a craft, so the input it used up is in there too.
+        assert!(factory.producer(consumer).unwrap().producer.is_producing());
+        let slot_total = |factory: &Factory, id| {
+            let (inputs, outputs) = factory.producer(id).unwrap().producer.item_counts();
+            inputs.iter().chain(&outputs).map(|&(quantity, _)| quantity as u32).sum::<u32>()
+        };
+        let held = factory.connectors().map(|(_, placed)| placed.connector.held_stack().map(|stack| stack.quantity as u32).unwrap_or(0)).sum::<u32>();
+        let inside = slot_total(&factory, generator) + slot_total(&factory, consumer) + 1 + held;
+
+        factory.apply(&Command::RemoveEntity { entity: consumer }, &prototypes).unwrap();
+        factory.apply(&Command::RemoveEntity { entity: generator }, &prototypes).unwrap();
+
+        assert_eq!(inside, factory.returned_items().iter().map(|stack| stack.quantity as u32).sum::<u32>());
+        assert_eq!(1, factory.returned_items().len(), "items of one kind share a stack");
+
+        let events: Vec<_> = factory.drain_events().collect();
+        assert!(events.contains(&FactoryEvent::ItemsReturned(consumer)));
+        assert!(events.contains(&FactoryEvent::ItemsReturned(generator)));
+
+        // Returned items are part of the state.
+        let bytes = encode_to_vec(&factory);
+        let decoded: Factory = decode_from_slice(&bytes).unwrap();
+        assert_eq!(crate::checksum::checksum(&factory), crate::checksum::checksum(&decoded));
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        let fixture = Fixture::new();
+        let commands = vec![
+            Command::PlaceProducer { id: None, position: Position::from_f32(-1.5, 3.0), rotation: Rotation::West, recipe: None },
+            Command::Connect { id: None, giver: EntityId::from_raw(1), taker: EntityId::from_raw(2), kind: fixture.connector },
+            Command::SetRecipe { producer: EntityId::from_raw(1), recipe: Some(fixture.consume) },
+            Command::Batch(vec![
+                Command::RemoveEntity { entity: EntityId::from_raw(3) },
+                Command::Disconnect { connector: EntityId::from_raw(4) },
+                Command::MoveEntity { entity: EntityId::from_raw(1), position: Position::from_f32(7.0, 8.0), rotation: Rotation::South },
+            ]),
+            Command::PlaceBlueprint {
+                blueprint: Blueprint {
+                    producers: vec![
+                        BlueprintProducer { offset: Position::from_f32(-45.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.generate) },
+                        BlueprintProducer { offset: Position::from_f32(45.0, 30.0), rotation: Rotation::East, recipe: None },
+                    ],
+                    connectors: vec![BlueprintConnector { giver: 0, taker: 1, kind: fixture.connector }],
+                },
+                position: Position::from_f32(15.0, 15.0),
+                rotation: Rotation::South,
+            },
+        ];
+
+        assert_eq!(decode_from_slice::<Vec<Command>>(&encode_to_vec(&commands)), Ok(commands));
+    }
+
+    #[test]
+    fn deeply_nested_batches_dont_decode() {
+        let nested = |depth: usize| (1..depth).fold(Command::Batch(vec![]), |command, _| Command::Batch(vec![command]));
+        assert_eq!(decode_from_slice(&encode_to_vec(&nested(MAX_BATCH_DEPTH))), Ok(nested(MAX_BATCH_DEPTH)));
+        assert_eq!(decode_from_slice::<Command>(&encode_to_vec(&nested(MAX_BATCH_DEPTH + 1))), Err(DecodeError::Invalid("batches nested too deep")));
+
+        // Far deeper than the stack would take.
+        let bytes = [6, 1, 0, 0, 0].repeat(1_000_000);
+        assert_eq!(decode_from_slice::<Command>(&bytes), Err(DecodeError::Invalid("batches nested too deep")));
+    }
+}
@@ -0,0 +1,60 @@
+//! Compression for data shared as text, e.g. blueprints.
+//!
+//! Data is compressed with deflate in a zlib stream, whose checksum catches
+//! text that was cut short or mistyped.
+
+use miniz_oxide::inflate::TINFLStatus;
+
+use crate::codec::DecodeError;
+
+/// Best compression, as shared data is written once and kept small.
+const LEVEL: u8 = 9;
+
+/// Most bytes data may decompress to, so pasted text can't fill the memory.
+pub const MAX_SIZE: usize = 16 * 1024 * 1024;
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    miniz_oxide::deflate::compress_to_vec_zlib(input, LEVEL)
+}
+
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(input, MAX_SIZE).map_err(|error| match error.status {
+        TINFLStatus::FailedCannotMakeProgress => DecodeError::UnexpectedEnd,
+        TINFLStatus::HasMoreOutput => DecodeError::Invalid("compressed data is too large"),
+        TINFLStatus::Adler32Mismatch => DecodeError::Invalid("compressed data is corrupt"),
+        _ => DecodeError::Invalid("not compressed data"),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_shrinks_repetition() {
+        let repetitive: Vec<u8> = b"producer producer producer ".iter().copied().cycle().take(500).collect();
+        let compressed = compress(&repetitive);
+        assert!(compressed.len() < repetitive.len() / 4);
+        assert_eq!(Ok(repetitive), decompress(&compressed));
+
+        let varied: Vec<u8> = (0..=255u8).chain(0..7).collect();
+        assert_eq!(Ok(varied.clone()), decompress(&compress(&varied)));
+        assert_eq!(Ok(vec![]), decompress(&compress(&[])));
+
+        assert!(decompress(&[0b1, 0x05, 0x00]).is_err());
+    }
+
+    #[test]
+    fn rejects_cut_corrupt_and_oversized_data() {
+        let compressed = compress(b"producer producer producer producer");
+        assert_eq!(Err(DecodeError::UnexpectedEnd), decompress(&compressed[..compressed.len() - 5]));
+
+        let mut corrupt = compressed.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert_eq!(Err(DecodeError::Invalid("compressed data is corrupt")), decompress(&corrupt));
+
+        let bomb = compress(&vec![0; MAX_SIZE + 1]);
+        assert!(bomb.len() < 64 * 1024);
+        assert_eq!(Err(DecodeError::Invalid("compressed data is too large")), decompress(&bomb));
+    }
+}
@@ -1,6 +1,6 @@
 //! Connection point between output slots and input slots of things.
 
-use crate::item_stack::{InsertItemStackResult, ItemSlot, ItemSlotBuilder, ItemStack};
+use crate::{Time, checksum::{Checksum, StateHasher}, codec::{Decode, DecodeError, Encode}, fixed::Fixed, item_stack::{InsertItemStackResult, ItemSlot, ItemSlotBuilder, ItemStack, ItemStackQuanity}, kinds::{ConnectorKind, ItemKind}, registry::Handle};
 
 /// Whether the connector is traveling towards its input slot or output slot.
 #[derive(Debug)]
@@ -21,55 +21,124 @@ pub struct Connector {
     direction: ConnectorDirection,
 
     /// How long the connector is between the input and output.
-    length: f32,
-    position: f32,
+    length: Fixed,
+    position: Fixed,
+
+    /// How many ticks the connector has been holding a partial hand at its input.
+    waiting: Time,
+
+    /// How many ticks to wait for a full hand before leaving with a partial one.
+    pickup_timeout: Time,
 
     item: ItemSlot,
 }
 
 impl Connector {
     /// How far the connector moves in one frame.
-    const SPEED: f32 = 5.0;
+    const SPEED: Fixed = Fixed::from_int(5);
 
-    pub fn new(length: f32) -> Self {
+    /// A connector that carries a single item per trip.
+    pub fn new(length: Fixed) -> Self {
         Self {
             direction: ConnectorDirection::Input,
             length,
-            position: 0.0,
+            position: Fixed::ZERO,
+            waiting: 0,
+            pickup_timeout: 0,
             item: ItemSlotBuilder::new().with_capacity(1).build(),
         }
     }
 
+    pub fn new_with_kind(kind: &ConnectorKind, length: Fixed) -> Self {
+        Self {
+            pickup_timeout: kind.pickup_timeout,
+            item: ItemSlotBuilder::new().with_capacity(kind.hand_size).build(),
+            ..Self::new(length)
+        }
+    }
+
     pub fn status(&self) -> ConnectorStatus {
-        if self.position == 0.0 && self.item.is_empty() {
-            ConnectorStatus::WaitingOnInput
-        } else if self.position == self.length && !self.item.is_empty() {
+        if self.position == self.length && !self.item.is_empty() {
             ConnectorStatus::WaitingOnOutput
+        } else if self.position == Fixed::ZERO && matches!(self.direction, ConnectorDirection::Input) {
+            ConnectorStatus::WaitingOnInput
         } else {
             ConnectorStatus::Traveling
         }
     }
 
-    pub fn length(&self) -> f32 {
+    /// Maximum number of items carried per trip.
+    pub fn hand_size(&self) -> ItemStackQuanity {
+        self.item.capacity
+    }
+
+    /// Amount of items that can still be picked up before the hand is full.
+    pub fn available_capacity(&self) -> ItemStackQuanity {
+        self.item.available_capacity()
+    }
+
+    /// The kind of item currently in the connector's hand, if any.
+    ///
+    /// A connector only carries one kind of item per trip.
+    pub fn held_item(&self) -> Option<Handle<ItemKind>> {
+        self.item.stack.as_ref().map(|stack| stack.item)
+    }
+
+    /// The items currently in the connector's hand, if any.
+    pub fn held_stack(&self) -> Option<&ItemStack> {
+        self.item.stack.as_ref()
+    }
+
+    pub fn length(&self) -> Fixed {
         self.length
     }
 
-    pub fn position(&self) -> f32 {
+    pub fn position(&self) -> Fixed {
         self.position
     }
 
+    /// Change the length, e.g. because what it connects moved.
+    ///
+    /// A connector past the new end is moved back to it.
+    pub fn set_length(&mut self, length: Fixed) {
+        self.length = length;
+        self.position = Fixed::min(self.position, length);
+    }
+
+    /// What ticking changes besides the items in hand. A tick that leaves this
+    /// as it was, without items changing hands, will do the same until the
+    /// producers it connects change.
+    pub(crate) fn motion(&self) -> (bool, Fixed, Time) {
+        (matches!(self.direction, ConnectorDirection::Output), self.position, self.waiting)
+    }
+
     pub fn tick(&mut self) {
-        if self.status() != ConnectorStatus::Traveling {
-            return;
+        match self.status() {
+            ConnectorStatus::WaitingOnInput => {
+                // Holding a partial hand. Wait for more items until timing out.
+                if !self.item.is_empty() {
+                    self.waiting += 1;
+
+                    if self.waiting >= self.pickup_timeout {
+                        self.depart();
+                    }
+                }
+
+                return;
+            },
+
+            ConnectorStatus::WaitingOnOutput => return,
+
+            ConnectorStatus::Traveling => {},
         }
 
         match self.direction {
             ConnectorDirection::Input => {
-                self.position = f32::max(0.0, self.position - Self::SPEED);
+                self.position = Fixed::max(Fixed::ZERO, self.position - Self::SPEED);
             },
 
             ConnectorDirection::Output => {
-                self.position = f32::min(self.length, self.position + Self::SPEED);
+                self.position = Fixed::min(self.length, self.position + Self::SPEED);
             }
         }
     }
@@ -79,37 +148,103 @@ impl Connector {
         // As such,this debug_assert! is bad.
         // debug_assert!(matches!(self.status(), ConnectorStatus::WaitingOnInput));
 
-        let res = self.item.insert_item_stack(stack);
+        // The hand has no filter.
+        let res = self.item.insert_ignoring_filter(stack);
 
-        if res.is_change() {
-            self.direction = ConnectorDirection::Output;
+        // At the input, only leave once the hand is full. Partial hands leave
+        // after waiting `pickup_timeout` ticks. See `tick`.
+        if res.is_change() && (self.position != Fixed::ZERO || self.item.available_capacity() == 0) {
+            self.depart();
         }
 
         res
     }
 
+    fn depart(&mut self) {
+        self.direction = ConnectorDirection::Output;
+        self.waiting = 0;
+    }
+
+    /// Empty the hand for deconstruction.
+    pub(crate) fn take_contents(&mut self) -> Option<ItemStack> {
+        self.item.stack.take()
+    }
+
     pub fn take_stack(&mut self) -> ItemStack {
         self.direction = ConnectorDirection::Input;
         self.item.stack.take().unwrap()
     }
 }
 
+impl Checksum for Connector {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u8(match self.direction {
+            ConnectorDirection::Input => 0,
+            ConnectorDirection::Output => 1,
+        });
+        self.length.checksum(hasher);
+        self.position.checksum(hasher);
+        self.waiting.checksum(hasher);
+        self.pickup_timeout.checksum(hasher);
+        self.item.checksum(hasher);
+    }
+}
+
+impl Encode for Connector {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self.direction {
+            ConnectorDirection::Input => 0u8.encode(out),
+            ConnectorDirection::Output => 1u8.encode(out),
+        }
+        self.length.encode(out);
+        self.position.encode(out);
+        self.waiting.encode(out);
+        self.pickup_timeout.encode(out);
+        self.item.encode(out);
+    }
+}
+
+impl Decode for Connector {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let direction = match u8::decode(input)? {
+            0 => ConnectorDirection::Input,
+            1 => ConnectorDirection::Output,
+            tag => return Err(DecodeError::InvalidTag { tag, type_name: "ConnectorDirection" }),
+        };
+
+        let connector = Connector {
+            direction,
+            length: Fixed::decode(input)?,
+            position: Fixed::decode(input)?,
+            waiting: Time::decode(input)?,
+            pickup_timeout: Time::decode(input)?,
+            item: ItemSlot::decode(input)?,
+        };
+
+        if connector.position < Fixed::ZERO || connector.position > connector.length {
+            return Err(DecodeError::Invalid("connector position is outside of its length"));
+        }
+
+        Ok(connector)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::{kinds::ItemKindBuilder, local_string::LocalString, registry::Table};
+    use crate::{kinds::{ConnectorKindBuilder, ItemKindBuilder}, local_string::LocalString, registry::Table};
 
     use super::*;
 
     #[test]
     fn test_connector() {
-        let mut connector: Connector = Connector::new(50.0);
+        let mut connector: Connector = Connector::new(Fixed::from_int(50));
 
         assert_eq!(connector.status(), ConnectorStatus::WaitingOnInput);
 
         let mut items = Table::new();
         let item = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("item")).build(), "item".to_string());
 
-        let stack = ItemStack { item, quantity: 1 };
+        let stack = ItemStack::new(item, 1);
 
         let stack_insert_result = connector.insert_stack(stack);
         assert!(matches!(stack_insert_result, InsertItemStackResult::StackConsumed));
@@ -134,4 +269,70 @@ mod test {
         // And to complete the trip!
         assert_eq!(connector.status(), ConnectorStatus::WaitingOnInput);
     }
+
+    #[test]
+    fn stack_connector_waits_for_full_hand() {
+        let kind = ConnectorKindBuilder::new()
+        .with_name(LocalString::from_str("stack-connector"))
+        .with_hand_size(4)
+        .with_pickup_timeout(100)
+        .build();
+
+        let mut connector = Connector::new_with_kind(&kind, Fixed::from_int(50));
+        assert_eq!(connector.hand_size(), 4);
+
+        let mut items = Table::new();
+        let item = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("item")).build(), "item".to_string());
+
+        let stack_insert_result = connector.insert_stack(ItemStack::new(item, 3));
+        assert!(matches!(stack_insert_result, InsertItemStackResult::StackConsumed));
+
+        // Partial hand, so it keeps waiting.
+        connector.tick();
+        assert_eq!(connector.status(), ConnectorStatus::WaitingOnInput);
+        assert_eq!(connector.available_capacity(), 1);
+        assert_eq!(connector.held_item(), Some(item));
+
+        let stack_insert_result = connector.insert_stack(ItemStack::new(item, 2));
+        assert!(matches!(stack_insert_result, InsertItemStackResult::StackPartiallyConsumed(ItemStack { quantity: 1, .. })));
+        assert_eq!(connector.status(), ConnectorStatus::Traveling);
+
+        for _ in 0..10 {
+            connector.tick();
+        }
+
+        assert_eq!(connector.status(), ConnectorStatus::WaitingOnOutput);
+        assert_eq!(connector.take_stack().quantity, 4);
+    }
+
+    #[test]
+    fn stack_connector_leaves_with_partial_hand_after_timeout() {
+        let kind = ConnectorKindBuilder::new()
+        .with_name(LocalString::from_str("stack-connector"))
+        .with_hand_size(4)
+        .with_pickup_timeout(5)
+        .build();
+
+        let mut connector = Connector::new_with_kind(&kind, Fixed::from_int(50));
+
+        let mut items = Table::new();
+        let item = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("item")).build(), "item".to_string());
+
+        let _ = connector.insert_stack(ItemStack::new(item, 1));
+
+        for _ in 0..4 {
+            connector.tick();
+            assert_eq!(connector.status(), ConnectorStatus::WaitingOnInput);
+        }
+
+        connector.tick();
+        assert_eq!(connector.status(), ConnectorStatus::Traveling);
+
+        for _ in 0..10 {
+            connector.tick();
+        }
+
+        assert_eq!(connector.status(), ConnectorStatus::WaitingOnOutput);
+        assert_eq!(connector.take_stack().quantity, 1);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,242 @@
+//! Resources lying on the map.
+//!
+//! Producers with a mining recipe dig their outputs out of the deposits under
+//! them. Every tile holds a finite amount of one item and is gone once it is
+//! mined out.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    checksum::{Checksum, StateHasher},
+    codec::{Decode, DecodeError, Encode},
+    kinds::ItemKind,
+    registry::Handle,
+    tile::TilePosition,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deposit {
+    pub item: Handle<ItemKind>,
+
+    /// Items left to mine.
+    pub amount: u32,
+}
+
+impl Deposit {
+    pub fn new(item: Handle<ItemKind>, amount: u32) -> Self {
+        Self { item, amount }
+    }
+}
+
+/// The deposits on the map, by tile.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Deposits {
+    tiles: BTreeMap<TilePosition, Deposit>,
+}
+
+impl Deposits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put a deposit on a tile, replacing the one that was there. Empty deposits are left out.
+    pub fn insert(&mut self, tile: TilePosition, deposit: Deposit) {
+        if deposit.amount > 0 {
+            self.tiles.insert(tile, deposit);
+        } else {
+            self.tiles.remove(&tile);
+        }
+    }
+
+    pub fn get(&self, tile: TilePosition) -> Option<&Deposit> {
+        self.tiles.get(&tile)
+    }
+
+    /// All deposits, ordered by tile.
+    pub fn iter(&self) -> impl Iterator<Item = (TilePosition, &Deposit)> {
+        self.tiles.iter().map(|(&tile, deposit)| (tile, deposit))
+    }
+
+    /// Amount of the item left on the tiles.
+    pub fn remaining(&self, tiles: impl IntoIterator<Item = TilePosition>, item: Handle<ItemKind>) -> u32 {
+        tiles
+        .into_iter()
+        .filter_map(|tile| self.get(tile))
+        .filter(|deposit| deposit.item == item)
+        .fold(0, |remaining: u32, deposit| remaining.saturating_add(deposit.amount))
+    }
+
+    /// Number of the tiles that still hold the item.
+    pub fn tiles_with(&self, tiles: impl IntoIterator<Item = TilePosition>, item: Handle<ItemKind>) -> u32 {
+        tiles
+        .into_iter()
+        .filter_map(|tile| self.get(tile))
+        .filter(|deposit| deposit.item == item)
+        .count() as u32
+    }
+
+    /// Mine up to `amount` of the item from the tiles, emptying them in order. Returns the amount mined.
+    pub(crate) fn mine(&mut self, tiles: impl IntoIterator<Item = TilePosition>, item: Handle<ItemKind>, amount: u32) -> u32 {
+        let mut mined = 0;
+
+        for tile in tiles {
+            if mined == amount {
+                break;
+            }
+
+            if let Some(deposit) = self.tiles.get_mut(&tile).filter(|deposit| deposit.item == item) {
+                let taken = deposit.amount.min(amount - mined);
+                deposit.amount -= taken;
+                mined += taken;
+
+                if deposit.amount == 0 {
+                    self.tiles.remove(&tile);
+                }
+            }
+        }
+
+        mined
+    }
+}
+
+impl Checksum for Deposits {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.tiles.len() as u64);
+        for (tile, deposit) in &self.tiles {
+            tile.checksum(hasher);
+            deposit.item.checksum(hasher);
+            deposit.amount.checksum(hasher);
+        }
+    }
+}
+
+impl Encode for Deposits {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.tiles.len() as u32).encode(out);
+        for (tile, deposit) in &self.tiles {
+            tile.encode(out);
+            deposit.item.encode(out);
+            deposit.amount.encode(out);
+        }
+    }
+}
+
+impl Decode for Deposits {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut deposits = Deposits::new();
+
+        for _ in 0..u32::decode(input)? {
+            let tile = TilePosition::decode(input)?;
+            let deposit = Deposit::new(Handle::decode(input)?, u32::decode(input)?);
+
+            if deposit.amount == 0 || deposits.tiles.insert(tile, deposit).is_some() {
+                return Err(DecodeError::Invalid("deposit is empty or on a tile twice"));
+            }
+        }
+
+        Ok(deposits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        checksum::checksum,
+        codec::{decode_from_slice, encode_to_vec},
+        command::{Command, CommandError},
+        factory::{EntityId, Factory, Position},
+        kinds::{QualityRule, RecipeKind, RecipeOutput},
+        item_stack::ItemData,
+        local_string::LocalString,
+        testing::Fixture,
+        tile::{Footprint, Rotation},
+    };
+
+    #[test]
+    fn miners_deplete_deposits_under_them() {
+        let mut fixture = Fixture::new();
+        let ore = fixture.recipes[fixture.generate].output[0].item;
+
+        let mine = fixture.recipes.insert(RecipeKind {
+            name: LocalString::from_str("mine"),
+            input_items: vec![],
+            output: vec![RecipeOutput { item: ore, quantity: 1, data: ItemData::default() }],
+            time: 2,
+            quality: QualityRule::default(),
+            mining: true,
+        }, "mine".to_string());
+
+        // Ore on two of the miner's four tiles.
+        let position = Position::from_f32(60.0, 15.0);
+        let mut deposits = Deposits::new();
+        deposits.insert(TilePosition::new(0, 0), Deposit::new(ore, 3));
+        deposits.insert(TilePosition::new(3, 0), Deposit::new(ore, 2));
+        assert_eq!(2, deposits.tiles_with(Footprint::new(4, 1).tiles(position), ore));
+
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::with_deposits(deposits);
+
+        let elsewhere = Command::PlaceProducer { id: None, position: Position::from_f32(300.0, 15.0), rotation: Rotation::North, recipe: Some(mine) };
+        assert_eq!(factory.apply(&elsewhere, &prototypes), Err(CommandError::NoDeposit));
+
+        factory.apply(&Command::PlaceProducer { id: None, position, rotation: Rotation::North, recipe: Some(mine) }, &prototypes).unwrap();
+        let miner = factory.producers().map(|(id, _)| id).next().unwrap();
+        assert_eq!(Some(5), factory.deposit_remaining(miner, &fixture.recipes));
+
+        let mut mined = 0;
+        for _ in 0..200 {
+            factory.tick(&prototypes);
+
+            if factory.producer(miner).unwrap().producer.has_output() {
+                mined += factory.producer_mut_with_research(miner).unwrap().0.producer.take_single_item().quantity as u32;
+            }
+        }
+
+        assert_eq!(5, mined);
+        assert_eq!(Some(0), factory.deposit_remaining(miner, &fixture.recipes));
+        assert!(factory.deposits().iter().next().is_none());
+        assert!(!factory.producer(miner).unwrap().producer.is_producing());
+
+        let bytes = encode_to_vec(factory.deposits());
+        assert_eq!(Ok(factory.deposits().clone()), decode_from_slice(&bytes));
+
+        // A failed batch puts the mined out miner back, though there is no ore under it.
+        let before = checksum(&factory);
+        let unknown = Command::RemoveEntity { entity: EntityId::from_raw(99) };
+        for command in [Command::RemoveEntity { entity: miner }, Command::SetRecipe { producer: miner, recipe: None }] {
+            assert_eq!(factory.apply(&Command::Batch(vec![command, unknown.clone()]), &prototypes), Err(CommandError::UnknownEntity(EntityId::from_raw(99))));
+            assert_eq!(before, checksum(&factory));
+        }
+    }
+
+    #[test]
+    fn mining_outputs_of_no_items_dont_limit_crafts() {
+        let mut fixture = Fixture::new();
+        let ore = fixture.recipes[fixture.generate].output[0].item;
+
+        let mine = fixture.recipes.insert(RecipeKind {
+            name: LocalString::from_str("mine"),
+            input_items: vec![],
+            output: vec![RecipeOutput { item: ore, quantity: 0, data: ItemData::default() }, RecipeOutput { item: ore, quantity: 1, data: ItemData::default() }],
+            time: 2,
+            quality: QualityRule::default(),
+            mining: true,
+        }, "mine".to_string());
+
+        let position = Position::from_f32(60.0, 15.0);
+        let mut deposits = Deposits::new();
+        deposits.insert(TilePosition::new(0, 0), Deposit::new(ore, 2));
+
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::with_deposits(deposits);
+        factory.apply(&Command::PlaceProducer { id: None, position, rotation: Rotation::North, recipe: Some(mine) }, &prototypes).unwrap();
+
+        // The output slot holds both crafts.
+        for _ in 0..200 {
+            factory.tick(&prototypes);
+        }
+
+        assert!(factory.deposits().iter().next().is_none());
+    }
+}
@@ -0,0 +1,1224 @@
+//! The factory model: placed producers, the connectors between them, and
+//! the logic tick that moves items through them.
+//!
+//! Frontends don't edit the factory directly. They issue [`Command`]s
+//! (see [`crate::command`]) and react to the [`FactoryEvent`]s that applying
+//! them fires, so that the same edits can be sent over the network or recorded.
+//!
+//! [`Command`]: crate::command::Command
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::{
+    checksum::{Checksum, StateHasher},
+    codec::{Decode, DecodeError, Encode},
+    connector::{Connector, ConnectorStatus},
+    deposit::Deposits,
+    fixed::Fixed,
+    item_stack::ItemStack,
+    kinds::{ConnectorKind, ItemTag, RecipeKind, TechnologyKind},
+    parallel,
+    producer::Producer,
+    registry::{Handle, Table},
+    research::ResearchState,
+    schedule::Scheduler,
+    spatial::{Rect, SpatialIndex},
+    stats::ProductionStats,
+    tile::{Footprint, Rotation, TileGrid, TilePosition},
+};
+
+/// Identifies a producer or connector placed in a [`Factory`].
+///
+/// Ids are handed out in order, so every peer that applies the same commands
+/// assigns the same ids. An id is only used again to put back an entity that
+/// was removed, e.g. when undoing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntityId(u32);
+
+impl EntityId {
+    pub fn to_raw(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_raw(raw: u32) -> Self {
+        EntityId(raw)
+    }
+}
+
+impl std::fmt::Display for EntityId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// A point in the factory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub x: Fixed,
+    pub y: Fixed,
+}
+
+impl Position {
+    pub fn new(x: Fixed, y: Fixed) -> Self {
+        Self { x, y }
+    }
+
+    pub fn from_f32(x: f32, y: f32) -> Self {
+        Self::new(Fixed::from_f32(x), Fixed::from_f32(y))
+    }
+
+    pub fn distance(self, other: Position) -> Fixed {
+        Fixed::hypot(other.x - self.x, other.y - self.y)
+    }
+}
+
+impl std::ops::Add for Position {
+    type Output = Position;
+
+    fn add(self, other: Position) -> Position {
+        Position::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl std::ops::Sub for Position {
+    type Output = Position;
+
+    fn sub(self, other: Position) -> Position {
+        Position::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+/// The prototype tables a factory needs to apply commands and tick.
+///
+/// Every peer must build these tables in the same order.
+#[derive(Clone, Copy)]
+pub struct Prototypes<'a> {
+    pub recipes: &'a Table<RecipeKind>,
+    pub connectors: &'a Table<ConnectorKind>,
+    pub tags: &'a Table<ItemTag>,
+    pub technologies: &'a Table<TechnologyKind>,
+}
+
+pub struct PlacedProducer {
+    pub position: Position,
+    pub rotation: Rotation,
+    pub producer: Producer,
+}
+
+impl PlacedProducer {
+    /// Where connectors taking items out of this producer start.
+    pub fn output_port(&self) -> Position {
+        self.position + self.rotation.rotate(Factory::OUTPUT_PORT_OFFSET)
+    }
+
+    /// Where connectors putting items into this producer end.
+    pub fn input_port(&self) -> Position {
+        self.position + self.rotation.rotate(Factory::INPUT_PORT_OFFSET)
+    }
+
+    /// The tiles the producer covers.
+    pub fn tiles(&self) -> impl Iterator<Item = TilePosition> {
+        Factory::producer_tiles(self.position, self.rotation)
+    }
+
+    /// The area the producer covers.
+    pub fn bounds(&self) -> Rect {
+        Rect::around(self.position, Factory::PRODUCER_FOOTPRINT.rotated(self.rotation).half_extents())
+    }
+}
+
+pub struct PlacedConnector {
+    pub kind: Handle<ConnectorKind>,
+
+    /// Producer the connector takes items from.
+    pub giver: EntityId,
+
+    /// Producer the connector gives items to.
+    pub taker: EntityId,
+
+    pub connector: Connector,
+}
+
+/// Something that changed in the factory because of a command or a tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FactoryEvent {
+    ProducerPlaced(EntityId),
+    ConnectorPlaced(EntityId),
+    RecipeChanged(EntityId),
+    ProducerRemoved(EntityId),
+    ConnectorRemoved(EntityId),
+
+    /// A producer moved, or a connector changed because a producer it is attached to moved.
+    EntityMoved(EntityId),
+
+    /// Another technology is being researched, or none.
+    ResearchChanged,
+
+    ResearchCompleted(Handle<TechnologyKind>),
+
+    /// The items in a removed entity, or in a producer whose recipe changed, were added to the returned items.
+    ItemsReturned(EntityId),
+}
+
+/// A producer or connector as it was, encoded, with its items and rolls.
+enum Stashed {
+    Producer(Vec<u8>),
+    Connector(Vec<u8>),
+}
+
+/// The factory as a [`crate::command::Command::Batch`] found it, to put back exactly if part of the batch fails.
+///
+/// Undoing the commands of the batch puts back the entities, but not what
+/// was in them, so producers and connectors changed during the batch are
+/// stashed as they were.
+pub(crate) struct Checkpoint {
+    events: usize,
+    returned: Vec<u8>,
+    next_entity: u32,
+    stashed: usize,
+}
+
+#[derive(Default)]
+pub struct Factory {
+    /// Number of logic ticks simulated so far.
+    tick: u64,
+    next_entity: u32,
+    producers: BTreeMap<EntityId, PlacedProducer>,
+    connectors: BTreeMap<EntityId, PlacedConnector>,
+    research: ResearchState,
+    deposits: Deposits,
+
+    /// Items taken out of deconstructed entities.
+    returned: Vec<ItemStack>,
+
+    /// Tiles covered by producers. Kept in sync with `producers` rather than stored.
+    grid: TileGrid,
+
+    /// Bounds of producers. Kept in sync with `producers` rather than stored.
+    spatial: SpatialIndex,
+
+    events: Vec<FactoryEvent>,
+
+    /// Which entities tick next. Only decides what work is done, not its results.
+    scheduler: Scheduler,
+
+    /// Entities as they were before the batches being applied changed them, to put back if one fails. See [`Checkpoint`].
+    stashed: Vec<(EntityId, Stashed)>,
+
+    /// Batches being applied, counting nested ones.
+    batches: usize,
+
+    /// Threads to tick on, if more than one. Not part of the state, as it doesn't change the results.
+    pool: Option<ThreadPool>,
+}
+
+/// Constructors
+impl Factory {
+    /// Offset of a producer facing north's input port from its position.
+    pub const INPUT_PORT_OFFSET: Position = Position { x: Fixed::from_int(-45), y: Fixed::ZERO };
+
+    /// Offset of a producer facing north's output port from its position.
+    pub const OUTPUT_PORT_OFFSET: Position = Position { x: Fixed::from_int(45), y: Fixed::ZERO };
+
+    /// Tiles covered by a producer facing north.
+    pub const PRODUCER_FOOTPRINT: Footprint = Footprint::new(4, 1);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty factory on a map with resources to mine.
+    pub fn with_deposits(deposits: Deposits) -> Self {
+        Self { deposits, ..Self::default() }
+    }
+}
+
+/// Edits. These are crate-visible so that commands are the only way in.
+impl Factory {
+    fn allocate_entity(&mut self) -> EntityId {
+        let id = EntityId(self.next_entity);
+        self.next_entity += 1;
+        id
+    }
+
+    /// Whether an entity can be placed with the given id.
+    ///
+    /// Only ids that were handed out before and are no longer used can be
+    /// reused, e.g. when undoing a removal.
+    pub(crate) fn is_id_available(&self, id: EntityId) -> bool {
+        id.0 < self.next_entity
+        && !self.producers.contains_key(&id)
+        && !self.connectors.contains_key(&id)
+    }
+
+    /// Place a producer. Without an `id`, a new one is allocated. The tiles it covers must be free.
+    pub(crate) fn insert_producer(&mut self, id: Option<EntityId>, position: Position, rotation: Rotation, mut producer: Producer) -> EntityId {
+        let id = id.unwrap_or_else(|| self.allocate_entity());
+        let tiles: Vec<_> = Factory::producer_tiles(position, rotation).collect();
+        self.grid.occupy(&tiles, id).expect("tiles are free");
+
+        producer.seed(id.to_raw() as u64);
+        let placed = PlacedProducer { position, rotation, producer };
+        self.spatial.insert(id, placed.bounds());
+        self.producers.insert(id, placed);
+        self.scheduler.insert_producer(id);
+        self.events.push(FactoryEvent::ProducerPlaced(id));
+        id
+    }
+
+    /// Place a connector. Without an `id`, a new one is allocated.
+    pub(crate) fn insert_connector(&mut self, id: Option<EntityId>, kind: Handle<ConnectorKind>, giver: EntityId, taker: EntityId, connector: Connector) -> EntityId {
+        let id = id.unwrap_or_else(|| self.allocate_entity());
+        self.connectors.insert(id, PlacedConnector { kind, giver, taker, connector });
+        self.scheduler.insert_connector(id, giver, taker);
+        self.events.push(FactoryEvent::ConnectorPlaced(id));
+        id
+    }
+
+    /// Remove a producer. Connectors attached to it must be removed first.
+    pub(crate) fn remove_producer(&mut self, id: EntityId) -> Option<PlacedProducer> {
+        debug_assert!(self.connectors_attached_to(id).is_empty());
+
+        self.stash_producer(id);
+        let mut placed = self.producers.remove(&id)?;
+        self.grid.vacate(&placed.tiles().collect::<Vec<_>>(), id);
+        self.spatial.remove(id);
+        self.scheduler.remove_producer(id);
+        self.events.push(FactoryEvent::ProducerRemoved(id));
+        self.return_items(id, placed.producer.take_contents());
+        Some(placed)
+    }
+
+    pub(crate) fn remove_connector(&mut self, id: EntityId) -> Option<PlacedConnector> {
+        if self.batches > 0 {
+            if let Some(placed) = self.connectors.get(&id) {
+                let mut encoded = vec![];
+                placed.connector.encode(&mut encoded);
+                self.stashed.push((id, Stashed::Connector(encoded)));
+            }
+        }
+
+        let mut placed = self.connectors.remove(&id)?;
+        self.scheduler.remove_connector(id, placed.giver, placed.taker);
+        self.events.push(FactoryEvent::ConnectorRemoved(id));
+        self.return_items(id, placed.connector.take_contents());
+        Some(placed)
+    }
+
+    /// Add the items that were in an entity to the returned items, merging them into stacks where they fit.
+    pub(crate) fn return_items(&mut self, id: EntityId, stacks: impl IntoIterator<Item = ItemStack>) {
+        let mut returned_any = false;
+
+        for stack in stacks.into_iter().filter(|stack| stack.quantity > 0) {
+            returned_any = true;
+
+            let merged = self.returned
+            .iter_mut()
+            .find(|held| held.stacks_with(&stack) && held.quantity.checked_add(stack.quantity).is_some());
+
+            match merged {
+                Some(held) => held.quantity += stack.quantity,
+                None => self.returned.push(stack),
+            }
+        }
+
+        if returned_any {
+            self.events.push(FactoryEvent::ItemsReturned(id));
+        }
+    }
+
+    /// Move or turn a producer, changing the length of the connectors attached to it. The tiles it moves to must be free.
+    ///
+    /// Returns where it was and which way it faced.
+    pub(crate) fn move_producer(&mut self, id: EntityId, position: Position, rotation: Rotation) -> Option<(Position, Rotation)> {
+        // Miners dig under where they are, and connectors change length.
+        self.wake_producer(id, self.tick);
+        self.scheduler.wake_attached(id);
+
+        let placed = self.producers.get_mut(&id)?;
+        self.grid.vacate(&placed.tiles().collect::<Vec<_>>(), id);
+
+        let previous = (
+            std::mem::replace(&mut placed.position, position),
+            std::mem::replace(&mut placed.rotation, rotation),
+        );
+        self.grid.occupy(&placed.tiles().collect::<Vec<_>>(), id).expect("tiles are free");
+        self.spatial.insert(id, placed.bounds());
+        self.events.push(FactoryEvent::EntityMoved(id));
+
+        for connector_id in self.connectors_attached_to(id) {
+            let placed = &self.connectors[&connector_id];
+            let length = self.connector_length(placed.giver, placed.taker).expect("attached producers exist");
+
+            self.connectors.get_mut(&connector_id).expect("connector exists").connector.set_length(length);
+            self.events.push(FactoryEvent::EntityMoved(connector_id));
+        }
+
+        Some(previous)
+    }
+
+    /// Wakes every producer, as labs depend on what's researched.
+    pub(crate) fn research_mut(&mut self) -> &mut ResearchState {
+        self.wake_producers(self.tick);
+        &mut self.research
+    }
+
+    /// Wakes the producer and the connectors attached to it, as their slots may change.
+    /// The research comes along to check recipes against.
+    pub(crate) fn producer_mut_with_research(&mut self, id: EntityId) -> Option<(&mut PlacedProducer, &ResearchState)> {
+        self.wake_producer(id, self.tick);
+        self.scheduler.wake_attached(id);
+        Some((self.producers.get_mut(&id)?, &self.research))
+    }
+
+    pub(crate) fn push_event(&mut self, event: FactoryEvent) {
+        self.events.push(event);
+    }
+
+    /// Keep the producer as it is, if a batch being applied may have to put it back.
+    pub(crate) fn stash_producer(&mut self, id: EntityId) {
+        if self.batches == 0 {
+            return;
+        }
+
+        // Catch up on the ticks it slept through, as it is put back awake.
+        self.wake_producer(id, self.tick);
+        if let Some(placed) = self.producers.get(&id) {
+            let mut encoded = vec![];
+            placed.producer.encode(&mut encoded);
+            self.stashed.push((id, Stashed::Producer(encoded)));
+        }
+    }
+
+    /// Start applying a batch. Has to be followed by [`Factory::commit`] or [`Factory::roll_back`].
+    pub(crate) fn checkpoint(&mut self) -> Checkpoint {
+        let mut returned = vec![];
+        self.returned.encode(&mut returned);
+        self.batches += 1;
+
+        Checkpoint { events: self.events.len(), returned, next_entity: self.next_entity, stashed: self.stashed.len() }
+    }
+
+    /// Finish applying a batch that succeeded.
+    pub(crate) fn commit(&mut self, _: Checkpoint) {
+        self.end_batch();
+    }
+
+    /// Finish applying a batch that failed, once its commands are undone.
+    ///
+    /// Puts back what was in the entities changed since the checkpoint, and
+    /// forgets the items they returned, the ids they took and the events they fired.
+    pub(crate) fn roll_back(&mut self, checkpoint: Checkpoint) {
+        // The first stashed is how it was at the checkpoint, so that goes in last.
+        for (id, stashed) in self.stashed.drain(checkpoint.stashed..).rev() {
+            match stashed {
+                Stashed::Producer(encoded) => if let Some(placed) = self.producers.get_mut(&id) {
+                    placed.producer = Producer::decode(&mut &encoded[..]).expect("stashed producer decodes");
+                },
+
+                Stashed::Connector(encoded) => if let Some(placed) = self.connectors.get_mut(&id) {
+                    placed.connector = Connector::decode(&mut &encoded[..]).expect("stashed connector decodes");
+                    self.scheduler.wake_connector(id);
+                },
+            }
+        }
+
+        self.returned = Vec::decode(&mut &checkpoint.returned[..]).expect("returned items decode");
+        self.next_entity = checkpoint.next_entity;
+        self.events.truncate(checkpoint.events);
+        self.end_batch();
+    }
+
+    fn end_batch(&mut self) {
+        self.batches -= 1;
+        if self.batches == 0 {
+            self.stashed.clear();
+        }
+    }
+}
+
+/// Simulation
+impl Factory {
+    pub fn tick(&mut self, prototypes: &Prototypes) {
+        self.tick_recording(prototypes, None);
+    }
+
+    /// Simulate a tick like [`Factory::tick`], counting the crafts that finish.
+    pub fn tick_with_stats(&mut self, prototypes: &Prototypes, stats: &mut ProductionStats) {
+        self.tick_recording(prototypes, Some(stats));
+        stats.ticks += 1;
+    }
+
+    /// Tick on a pool of this many threads. The results are the same no matter the number of threads.
+    ///
+    /// Producers are split over the threads. Connectors tick by connected
+    /// parts of the factory, parts that share no producers ticking on
+    /// different threads. Handing out work costs more than ticking a small
+    /// factory, so only large ones gain from it.
+    ///
+    /// If the threads can't be started, it ticks on the calling thread.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.pool = match threads {
+            0 | 1 => None,
+            _ => ThreadPoolBuilder::new().num_threads(threads).build().ok(),
+        };
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.as_ref().map_or(1, ThreadPool::current_num_threads)
+    }
+
+    /// Tick every producer and connector on the next tick, as if none had slept.
+    ///
+    /// Producers and connectors with nothing to do sleep until something wakes
+    /// them, see [`crate::schedule`]. That never changes the results, which this
+    /// is for checking.
+    pub fn wake_all(&mut self) {
+        self.wake_producers(self.tick);
+
+        for &id in self.connectors.keys() {
+            self.scheduler.wake_connector(id);
+        }
+    }
+
+    /// Tick a sleeping producer on tick `next`, catching up on the crafting it slept through.
+    fn wake_producer(&mut self, id: EntityId, next: u64) {
+        if let Some(skipped) = self.scheduler.wake_producer(id, next) {
+            if let Some(placed) = self.producers.get_mut(&id) {
+                placed.producer.skip_ticks(skipped);
+            }
+        }
+    }
+
+    fn wake_producers(&mut self, next: u64) {
+        for (&id, placed) in self.producers.iter_mut() {
+            if let Some(skipped) = self.scheduler.wake_producer(id, next) {
+                placed.producer.skip_ticks(skipped);
+            }
+        }
+    }
+
+    fn tick_recording(&mut self, prototypes: &Prototypes, mut stats: Option<&mut ProductionStats>) {
+        let tick = self.tick;
+
+        for id in self.scheduler.take_due(tick) {
+            self.wake_producer(id, tick);
+        }
+
+        let context = ProducerTickContext {
+            prototypes,
+            speed: 1000 + self.research.production_speed_bonus(prototypes.technologies) as u32,
+            current_lab_recipe: self.research.current().and_then(|technology| prototypes.technologies.get(technology)).map(|kind| kind.lab_recipe),
+            lab_recipes: prototypes.technologies
+            .iter()
+            .map(|(_, technology)| technology.lab_recipe)
+            .collect(),
+            deposits: &self.deposits,
+        };
+
+        // Producers only read the deposits under themselves, and don't overlap, so
+        // digging out the crafts they started afterwards is the same as right away.
+        let awake = self.scheduler.awake_producers();
+        let outcomes = match &self.pool {
+            Some(pool) => parallel::map_in_order(pool, entries_mut(&mut self.producers, awake), |(id, placed)| tick_producer(id, placed, &context)),
+            None => {
+                let producers = &mut self.producers;
+                awake
+                .iter()
+                .map(|&id| tick_producer(id, producers.get_mut(&id).expect("awake producers exist"), &context))
+                .collect::<Vec<_>>()
+            },
+        };
+
+        let lab_recipes = context.lab_recipes;
+        let mut research_units = BTreeMap::new();
+
+        for outcome in outcomes {
+            if let Some(recipe) = outcome.finished {
+                if let Some(stats) = stats.as_deref_mut() {
+                    stats.record_craft(outcome.id, recipe);
+                }
+
+                // Labs that finish after their technology did still count, towards the next one.
+                if lab_recipes.contains(&recipe) {
+                    *research_units.entry(recipe).or_default() += 1;
+                }
+            }
+
+            if let Some((recipe, started)) = outcome.started_mining {
+                let tiles: Vec<_> = self.producers[&outcome.id].tiles().collect();
+                for output in &prototypes.recipes[recipe].output {
+                    self.deposits.mine(tiles.iter().copied(), output.item, started * output.quantity as u32);
+                }
+            }
+
+            // Crafting fills the outputs, and starting a craft empties the inputs.
+            if outcome.finished.is_some() || outcome.started {
+                self.scheduler.wake_attached(outcome.id);
+            }
+
+            let until = outcome.ticks_to_finish.map(|ticks| tick + ticks as u64);
+            self.scheduler.sleep_producer(outcome.id, tick, until);
+        }
+
+        for (lab_recipe, units) in research_units {
+            self.research.bank(lab_recipe, units);
+        }
+
+        if let Some(technology) = self.research.use_banked(prototypes.technologies) {
+            self.events.push(FactoryEvent::ResearchCompleted(technology));
+
+            // Speed bonuses and labs change.
+            self.wake_producers(tick + 1);
+        }
+
+        let awake = self.scheduler.awake_connectors();
+        let outcomes = match &self.pool {
+            Some(pool) => {
+                let parts = connected_parts(&mut self.producers, entries_mut(&mut self.connectors, awake));
+                let weight = |part: &ConnectedPart| part.producers.len() + part.connectors.len();
+
+                let mut outcomes: Vec<_> = parallel::map_balanced(pool, parts, weight, |part| {
+                    let ConnectedPart { mut producers, connectors } = part;
+                    connectors
+                    .into_iter()
+                    .map(|(id, placed)| tick_connector(id, placed, &mut producers, prototypes.tags))
+                    .collect::<Vec<_>>()
+                })
+                .into_iter()
+                .flatten()
+                .collect();
+
+                outcomes.sort_unstable_by_key(|outcome| outcome.id);
+                outcomes
+            },
+            None => {
+                let (producers, connectors) = (&mut self.producers, &mut self.connectors);
+                awake
+                .iter()
+                .map(|&id| tick_connector(id, connectors.get_mut(&id).expect("awake connectors exist"), producers, prototypes.tags))
+                .collect::<Vec<_>>()
+            },
+        };
+
+        for outcome in outcomes {
+            if outcome.took {
+                self.wake_producer(outcome.giver, tick + 1);
+            }
+
+            if outcome.gave {
+                self.wake_producer(outcome.taker, tick + 1);
+            }
+
+            if outcome.stalled {
+                self.scheduler.sleep_connector(outcome.id);
+            }
+        }
+
+        self.tick += 1;
+    }
+}
+
+/// The entries with the given ids, in order.
+fn entries_mut<'a, T>(map: &'a mut BTreeMap<EntityId, T>, ids: &BTreeSet<EntityId>) -> Vec<(EntityId, &'a mut T)> {
+    let mut ids = ids.iter().copied().peekable();
+    let mut entries = vec![];
+
+    for (&id, value) in map.iter_mut() {
+        while ids.next_if(|&next| next < id).is_some() {}
+
+        match ids.peek() {
+            Some(&next) if next == id => {
+                ids.next();
+                entries.push((id, value));
+            },
+            Some(_) => {},
+            None => break,
+        }
+    }
+
+    entries
+}
+
+/// What ticking a producer reads besides the producer itself.
+struct ProducerTickContext<'a> {
+    prototypes: &'a Prototypes<'a>,
+
+    /// Production speed before miners slow down, per mille.
+    speed: u32,
+    current_lab_recipe: Option<Handle<RecipeKind>>,
+    lab_recipes: Vec<Handle<RecipeKind>>,
+    deposits: &'a Deposits,
+}
+
+/// What ticking a producer changes outside of it, applied once every producer has ticked.
+struct ProducerTickOutcome {
+    id: EntityId,
+
+    /// Recipe of the craft that finished.
+    finished: Option<Handle<RecipeKind>>,
+
+    /// Whether a craft started, using up inputs.
+    started: bool,
+
+    /// Mining recipe and the number of crafts started, to dig out of the deposits.
+    started_mining: Option<(Handle<RecipeKind>, u32)>,
+
+    /// Ticks until the craft in progress finishes, to sleep until then.
+    ticks_to_finish: Option<crate::Time>,
+}
+
+fn tick_producer(id: EntityId, placed: &mut PlacedProducer, context: &ProducerTickContext) -> ProducerTickOutcome {
+    let recipes = context.prototypes.recipes;
+    let mining = placed.producer.recipe().filter(|&recipe| recipes[recipe].mining);
+
+    let mut speed = context.speed;
+    let mut craft_limit = None;
+
+    // Miners slow down as the tiles under them run out, and stop once they can't dig out a whole craft.
+    if let Some(recipe) = mining {
+        let tiles: Vec<_> = placed.tiles().collect();
+
+        // Outputs of no items don't dig anything out.
+        let crafts_left = recipes[recipe].output
+        .iter()
+        .filter_map(|output| context.deposits.remaining(tiles.iter().copied(), output.item).checked_div(output.quantity as u32))
+        .min()
+        .unwrap_or_default();
+
+        let tiles_with_ore = recipes[recipe].output
+        .iter()
+        .map(|output| context.deposits.tiles_with(tiles.iter().copied(), output.item))
+        .min()
+        .unwrap_or_default();
+
+        speed = speed * tiles_with_ore / Factory::PRODUCER_FOOTPRINT.area();
+        craft_limit = Some(crafts_left);
+    }
+
+    let producer = &mut placed.producer;
+    producer.set_speed(speed);
+    producer.set_craft_limit(craft_limit);
+
+    // Labs only use up science items while their technology is being researched.
+    let idle_lab = producer.recipe()
+    .map(|recipe| context.lab_recipes.contains(&recipe) && Some(recipe) != context.current_lab_recipe)
+    .unwrap_or(false);
+
+    let was_producing = producer.is_producing();
+    if !idle_lab {
+        producer.attempt_to_start_production(recipes);
+    }
+    let started = !was_producing && producer.is_producing();
+
+    let finished = if producer.tick(recipes) {
+        Some(producer.recipe().expect("only producers with a recipe finish crafts"))
+    } else {
+        None
+    };
+
+    // Crafts are dug out of the deposits as they start.
+    let started_mining = match (mining, craft_limit, producer.craft_limit()) {
+        (Some(recipe), Some(before), Some(after)) if before > after => Some((recipe, before - after)),
+        _ => None,
+    };
+
+    ProducerTickOutcome { id, finished, started, started_mining, ticks_to_finish: producer.ticks_to_finish() }
+}
+
+/// Producers a connector can reach: all of them, or those of one connected part.
+trait ProducerLookup {
+    fn producer_mut(&mut self, id: EntityId) -> Option<&mut Producer>;
+}
+
+impl ProducerLookup for BTreeMap<EntityId, PlacedProducer> {
+    fn producer_mut(&mut self, id: EntityId) -> Option<&mut Producer> {
+        self.get_mut(&id).map(|placed| &mut placed.producer)
+    }
+}
+
+impl ProducerLookup for BTreeMap<EntityId, &mut PlacedProducer> {
+    fn producer_mut(&mut self, id: EntityId) -> Option<&mut Producer> {
+        self.get_mut(&id).map(|placed| &mut placed.producer)
+    }
+}
+
+/// What ticking a connector changes outside of it, applied once every connector has ticked.
+struct ConnectorTickOutcome {
+    id: EntityId,
+    giver: EntityId,
+    taker: EntityId,
+
+    /// Whether items were taken from the giver.
+    took: bool,
+
+    /// Whether items were given to the taker.
+    gave: bool,
+
+    /// Whether the tick changed nothing, so the connector can sleep until its producers change.
+    stalled: bool,
+}
+
+fn tick_connector(id: EntityId, placed: &mut PlacedConnector, producers: &mut impl ProducerLookup, tags: &Table<ItemTag>) -> ConnectorTickOutcome {
+    let connector = &mut placed.connector;
+    let motion = connector.motion();
+    let (mut took, mut gave) = (false, false);
+
+    match connector.status() {
+        ConnectorStatus::WaitingOnInput if connector.available_capacity() > 0 => {
+            if let Some(giver) = producers.producer_mut(placed.giver) {
+                // Only take items that stack with what's already in the connector's hand.
+                if let Some(stack) = giver.take_items(connector.held_stack(), connector.available_capacity()) {
+                    // The connector has room for the whole stack, so nothing is returned.
+                    let _ = connector.insert_stack(stack);
+                    took = true;
+                }
+            }
+        },
+
+        ConnectorStatus::WaitingOnOutput => {
+            if let Some(taker) = producers.producer_mut(placed.taker) {
+                let result = taker.try_insert_ingredient(connector.take_stack(), tags);
+                gave = result.is_change();
+
+                // Whatever didn't fit stays in the connector's hand.
+                if let Some(stack) = result.get_item_stack() {
+                    let _ = connector.insert_stack(stack);
+                }
+            }
+        },
+
+        _ => {},
+    }
+
+    connector.tick();
+
+    ConnectorTickOutcome {
+        id,
+        giver: placed.giver,
+        taker: placed.taker,
+        took,
+        gave,
+        stalled: !took && !gave && connector.motion() == motion,
+    }
+}
+
+/// Producers linked by connectors, directly or through other producers, and the connectors between them.
+#[derive(Default)]
+struct ConnectedPart<'a> {
+    producers: BTreeMap<EntityId, &'a mut PlacedProducer>,
+
+    /// In id order, the order they tick in.
+    connectors: Vec<(EntityId, &'a mut PlacedConnector)>,
+}
+
+/// Split the connectors, in id order, into parts that share no producers, so they can tick independently.
+fn connected_parts<'a>(producers: &'a mut BTreeMap<EntityId, PlacedProducer>, connectors: Vec<(EntityId, &'a mut PlacedConnector)>) -> Vec<ConnectedPart<'a>> {
+    let attached: BTreeSet<EntityId> = connectors
+    .iter()
+    .flat_map(|(_, placed)| [placed.giver, placed.taker])
+    .collect();
+
+    let producers = entries_mut(producers, &attached);
+    let ids: Vec<EntityId> = producers.iter().map(|&(id, _)| id).collect();
+    let index_of = |id: EntityId| ids.binary_search(&id).ok();
+
+    // Union-find over producer indices. The root of a set is its lowest index.
+    let mut parent: Vec<usize> = (0..ids.len()).collect();
+    fn root(parent: &mut [usize], mut index: usize) -> usize {
+        while parent[index] != index {
+            parent[index] = parent[parent[index]];
+            index = parent[index];
+        }
+        index
+    }
+
+    for (_, placed) in connectors.iter() {
+        if let (Some(giver), Some(taker)) = (index_of(placed.giver), index_of(placed.taker)) {
+            let (giver, taker) = (root(&mut parent, giver), root(&mut parent, taker));
+            parent[giver.max(taker)] = giver.min(taker);
+        }
+    }
+
+    // A root comes before the rest of its set, so its part is numbered by the time they are reached.
+    let mut part_of = vec![0; ids.len()];
+    let mut part_count = 0;
+    for index in 0..ids.len() {
+        let root = root(&mut parent, index);
+        if root == index {
+            part_of[index] = part_count;
+            part_count += 1;
+        } else {
+            part_of[index] = part_of[root];
+        }
+    }
+
+    let mut parts: Vec<ConnectedPart> = (0..part_count).map(|_| ConnectedPart::default()).collect();
+
+    for (index, (id, placed)) in producers.into_iter().enumerate() {
+        parts[part_of[index]].producers.insert(id, placed);
+    }
+
+    for (id, placed) in connectors {
+        match index_of(placed.giver).or_else(|| index_of(placed.taker)) {
+            Some(index) => parts[part_of[index]].connectors.push((id, placed)),
+            None => parts.push(ConnectedPart { producers: BTreeMap::new(), connectors: vec![(id, placed)] }),
+        }
+    }
+
+    parts
+}
+
+/// Queries
+impl Factory {
+    /// Number of logic ticks simulated so far.
+    pub fn current_tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn research(&self) -> &ResearchState {
+        &self.research
+    }
+
+    pub fn deposits(&self) -> &Deposits {
+        &self.deposits
+    }
+
+    /// Items taken out of deconstructed entities, in the order they were first returned.
+    pub fn returned_items(&self) -> &[ItemStack] {
+        &self.returned
+    }
+
+    /// Amount left in the deposits under a miner, or `None` if the producer isn't mining.
+    pub fn deposit_remaining(&self, id: EntityId, recipes: &Table<RecipeKind>) -> Option<u32> {
+        let placed = self.producers.get(&id)?;
+        let recipe = recipes.get(placed.producer.recipe()?).filter(|recipe| recipe.mining)?;
+
+        let remaining = recipe.output
+        .iter()
+        .map(|output| self.deposits.remaining(placed.tiles(), output.item))
+        .sum();
+
+        Some(remaining)
+    }
+
+    /// Whether a producer with the recipe can work at the position, i.e. the recipe isn't mining or there is something to mine.
+    pub fn has_deposits_for(&self, recipe: &RecipeKind, position: Position, rotation: Rotation) -> bool {
+        !recipe.mining
+        || recipe.output
+        .iter()
+        .all(|output| self.deposits.tiles_with(Factory::producer_tiles(position, rotation), output.item) > 0)
+    }
+
+    pub fn grid(&self) -> &TileGrid {
+        &self.grid
+    }
+
+    /// The tiles a producer would cover.
+    pub fn producer_tiles(position: Position, rotation: Rotation) -> impl Iterator<Item = TilePosition> {
+        Factory::PRODUCER_FOOTPRINT.rotated(rotation).tiles(position)
+    }
+
+    pub fn spatial(&self) -> &SpatialIndex {
+        &self.spatial
+    }
+
+    /// The producer covering the point.
+    pub fn producer_at(&self, point: Position) -> Option<EntityId> {
+        self.spatial.query_point(point).into_iter().next()
+    }
+
+    /// Producers overlapping the rectangle, ordered by id.
+    pub fn producers_in(&self, rect: &Rect) -> Vec<EntityId> {
+        self.spatial.query_rect(rect)
+    }
+
+    /// The producer closest to the point, if one is within `max_distance`.
+    pub fn nearest_producer(&self, point: Position, max_distance: Fixed) -> Option<EntityId> {
+        self.spatial.nearest(point, max_distance).map(|(id, _)| id)
+    }
+
+    /// The first producer in the way of placing one at the position, not counting the one being moved there.
+    pub fn producer_blocked_by(&self, position: Position, rotation: Rotation, moving: Option<EntityId>) -> Option<EntityId> {
+        self.grid.first_occupant(Factory::producer_tiles(position, rotation), moving)
+    }
+
+    pub fn producer(&self, id: EntityId) -> Option<&PlacedProducer> {
+        self.producers.get(&id)
+    }
+
+    pub fn connector(&self, id: EntityId) -> Option<&PlacedConnector> {
+        self.connectors.get(&id)
+    }
+
+    /// All producers, ordered by id.
+    pub fn producers(&self) -> impl Iterator<Item = (EntityId, &PlacedProducer)> {
+        self.producers.iter().map(|(&id, placed)| (id, placed))
+    }
+
+    /// All connectors, ordered by id.
+    pub fn connectors(&self) -> impl Iterator<Item = (EntityId, &PlacedConnector)> {
+        self.connectors.iter().map(|(&id, placed)| (id, placed))
+    }
+
+    /// Connectors giving to or taking from a producer, ordered by id.
+    pub fn connectors_attached_to(&self, producer: EntityId) -> Vec<EntityId> {
+        self.scheduler.attached(producer).to_vec()
+    }
+
+    /// Length a connector between the two producers would have.
+    pub fn connector_length(&self, giver: EntityId, taker: EntityId) -> Option<Fixed> {
+        let giver = self.producers.get(&giver)?;
+        let taker = self.producers.get(&taker)?;
+        Some(giver.output_port().distance(taker.input_port()))
+    }
+
+    /// Take the events fired since the last call.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, FactoryEvent> {
+        self.events.drain(..)
+    }
+}
+
+/// Snapshots
+impl Factory {
+    /// Replace this factory with `other`, e.g. one decoded from a snapshot.
+    ///
+    /// Fires events removing every current entity and placing every new one,
+    /// so views can be kept up to date the same way as for commands.
+    pub fn load(&mut self, other: Factory) {
+        let mut events = std::mem::take(&mut self.events);
+
+        events.extend(self.connectors.keys().map(|&id| FactoryEvent::ConnectorRemoved(id)));
+        events.extend(self.producers.keys().map(|&id| FactoryEvent::ProducerRemoved(id)));
+        events.extend(other.producers.keys().map(|&id| FactoryEvent::ProducerPlaced(id)));
+        events.extend(other.connectors.keys().map(|&id| FactoryEvent::ConnectorPlaced(id)));
+        events.push(FactoryEvent::ResearchChanged);
+
+        let pool = self.pool.take();
+        *self = other;
+        self.events = events;
+        self.pool = pool;
+    }
+}
+
+impl Checksum for EntityId {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u32(self.0);
+    }
+}
+
+impl Checksum for Position {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        self.x.checksum(hasher);
+        self.y.checksum(hasher);
+    }
+}
+
+impl Checksum for Factory {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.tick);
+        hasher.write_u32(self.next_entity);
+
+        hasher.write_u64(self.producers.len() as u64);
+        for (id, placed) in self.producers.iter() {
+            id.checksum(hasher);
+            placed.position.checksum(hasher);
+            placed.rotation.checksum(hasher);
+            placed.producer.checksum_ahead(self.scheduler.slept_through(*id, self.tick), hasher);
+        }
+
+        hasher.write_u64(self.connectors.len() as u64);
+        for (id, placed) in self.connectors.iter() {
+            id.checksum(hasher);
+            placed.kind.checksum(hasher);
+            placed.giver.checksum(hasher);
+            placed.taker.checksum(hasher);
+            placed.connector.checksum(hasher);
+        }
+
+        self.research.checksum(hasher);
+        self.deposits.checksum(hasher);
+        self.returned.checksum(hasher);
+    }
+}
+
+impl Encode for EntityId {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
+impl Decode for EntityId {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(EntityId(u32::decode(input)?))
+    }
+}
+
+impl Encode for Position {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.x.encode(out);
+        self.y.encode(out);
+    }
+}
+
+impl Decode for Position {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Position::new(Fixed::decode(input)?, Fixed::decode(input)?))
+    }
+}
+
+/// Encodes the simulation state only. Undrained events are not part of a snapshot.
+impl Encode for Factory {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.tick.encode(out);
+        self.next_entity.encode(out);
+
+        (self.producers.len() as u32).encode(out);
+        for (id, placed) in self.producers.iter() {
+            id.encode(out);
+            placed.position.encode(out);
+            placed.rotation.encode(out);
+            placed.producer.encode_ahead(self.scheduler.slept_through(*id, self.tick), out);
+        }
+
+        (self.connectors.len() as u32).encode(out);
+        for (id, placed) in self.connectors.iter() {
+            id.encode(out);
+            placed.kind.encode(out);
+            placed.giver.encode(out);
+            placed.taker.encode(out);
+            placed.connector.encode(out);
+        }
+
+        self.research.encode(out);
+        self.deposits.encode(out);
+        self.returned.encode(out);
+    }
+}
+
+impl Decode for Factory {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut factory = Factory {
+            tick: u64::decode(input)?,
+            next_entity: u32::decode(input)?,
+            ..Factory::default()
+        };
+
+        for _ in 0..u32::decode(input)? {
+            let id = EntityId::decode(input)?;
+            let placed = PlacedProducer { position: Position::decode(input)?, rotation: Rotation::decode(input)?, producer: Producer::decode(input)? };
+
+            if factory.grid.occupy(&placed.tiles().collect::<Vec<_>>(), id).is_err() {
+                return Err(DecodeError::Invalid("producers overlap"));
+            }
+            factory.spatial.insert(id, placed.bounds());
+
+            if id.0 >= factory.next_entity || factory.producers.insert(id, placed).is_some() {
+                return Err(DecodeError::Invalid("producer id is reused or was never handed out"));
+            }
+            factory.scheduler.insert_producer(id);
+        }
+
+        for _ in 0..u32::decode(input)? {
+            let id = EntityId::decode(input)?;
+            let placed = PlacedConnector {
+                kind: Handle::decode(input)?,
+                giver: EntityId::decode(input)?,
+                taker: EntityId::decode(input)?,
+                connector: Connector::decode(input)?,
+            };
+
+            if !factory.producers.contains_key(&placed.giver) || !factory.producers.contains_key(&placed.taker) {
+                return Err(DecodeError::Invalid("connector is attached to a missing producer"));
+            }
+
+            let (giver, taker) = (placed.giver, placed.taker);
+            if id.0 >= factory.next_entity || factory.producers.contains_key(&id) || factory.connectors.insert(id, placed).is_some() {
+                return Err(DecodeError::Invalid("connector id is reused or was never handed out"));
+            }
+            factory.scheduler.insert_connector(id, giver, taker);
+        }
+
+        factory.research = ResearchState::decode(input)?;
+        factory.deposits = Deposits::decode(input)?;
+        factory.returned = Vec::decode(input)?;
+
+        Ok(factory)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{checksum::checksum, command::Command, testing::Fixture};
+
+    #[test]
+    fn parallel_tick_matches_serial() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let mut serial = Factory::new();
+
+        // Generators each feeding the consumer to their right, and the last generator also feeding the first consumer.
+        for row in 0..6 {
+            let y = 15.0 + 30.0 * row as f32;
+            serial.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(60.0, y), rotation: Rotation::North, recipe: Some(fixture.generate) }, &prototypes).unwrap();
+            serial.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(180.0, y), rotation: Rotation::North, recipe: Some(fixture.consume) }, &prototypes).unwrap();
+            serial.apply(&Command::Connect { id: None, giver: EntityId(row * 3), taker: EntityId(row * 3 + 1), kind: fixture.connector }, &prototypes).unwrap();
+        }
+        serial.apply(&Command::Connect { id: None, giver: EntityId(15), taker: EntityId(1), kind: fixture.connector }, &prototypes).unwrap();
+
+        let connectors = serial.connectors.iter_mut().map(|(&id, placed)| (id, placed)).collect();
+        let parts = connected_parts(&mut serial.producers, connectors);
+        assert_eq!(vec![4, 2, 2, 2, 2], parts.iter().map(|part| part.producers.len()).collect::<Vec<_>>());
+
+        let mut parallel = Factory::decode(&mut &crate::codec::encode_to_vec(&serial)[..]).unwrap();
+        parallel.set_threads(3);
+
+        for _ in 0..300 {
+            serial.tick(&prototypes);
+            parallel.tick(&prototypes);
+            assert_eq!(checksum(&serial), checksum(&parallel));
+        }
+    }
+
+    #[test]
+    fn sleeping_changes_nothing() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let mut sleeping = Factory::new();
+
+        // A generator feeding a consumer, and one with nowhere to put its items.
+        let place = |factory: &mut Factory, x: f32, y: f32, recipe| {
+            factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(x, y), rotation: Rotation::North, recipe: Some(recipe) }, &prototypes).unwrap();
+        };
+        place(&mut sleeping, 60.0, 15.0, fixture.generate);
+        place(&mut sleeping, 180.0, 15.0, fixture.consume);
+        place(&mut sleeping, 60.0, 45.0, fixture.generate);
+        sleeping.apply(&Command::Connect { id: None, giver: EntityId(0), taker: EntityId(1), kind: fixture.connector }, &prototypes).unwrap();
+
+        let mut awake = Factory::decode(&mut &crate::codec::encode_to_vec(&sleeping)[..]).unwrap();
+
+        for tick in 0..1000 {
+            // Snapshots of sleeping producers catch up on the crafting they slept through.
+            if tick == 500 {
+                sleeping = Factory::decode(&mut &crate::codec::encode_to_vec(&sleeping)[..]).unwrap();
+            }
+
+            sleeping.tick(&prototypes);
+            awake.wake_all();
+            awake.tick(&prototypes);
+            assert_eq!(checksum(&awake), checksum(&sleeping));
+        }
+
+        // The consumer crafts on the wheel, and the full generator sleeps for good.
+        assert!(!sleeping.scheduler.awake_producers().contains(&EntityId(1)));
+        assert!(!sleeping.scheduler.awake_producers().contains(&EntityId(2)));
+
+        place(&mut sleeping, 180.0, 45.0, fixture.consume);
+        sleeping.apply(&Command::Connect { id: None, giver: EntityId(2), taker: EntityId(4), kind: fixture.connector }, &prototypes).unwrap();
+        sleeping.tick(&prototypes);
+        assert!(sleeping.scheduler.awake_producers().contains(&EntityId(2)));
+    }
+}
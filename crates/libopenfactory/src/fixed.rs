@@ -0,0 +1,151 @@
+//! Fixed-point numbers for simulation state that must be identical on every platform.
+//!
+//! Floating point results can differ between platforms, builds and optimization
+//! levels. Anything that the simulation stores between ticks uses [`Fixed`] instead
+//! so that lockstep peers and replays stay in sync. Convert to `f32` only at the
+//! edges, e.g. for rendering.
+
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+/// A signed 48.16 fixed-point number.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i64);
+
+impl Fixed {
+    pub const FRACTIONAL_BITS: u32 = 16;
+    pub const ZERO: Fixed = Fixed(0);
+    pub const ONE: Fixed = Fixed(1 << Self::FRACTIONAL_BITS);
+
+    pub const fn from_int(value: i32) -> Self {
+        Fixed((value as i64) << Self::FRACTIONAL_BITS)
+    }
+
+    /// Rounds to the nearest representable value.
+    pub fn from_f32(value: f32) -> Self {
+        Fixed((value as f64 * Self::ONE.0 as f64).round() as i64)
+    }
+
+    pub const fn from_bits(bits: i64) -> Self {
+        Fixed(bits)
+    }
+
+    pub const fn to_bits(self) -> i64 {
+        self.0
+    }
+
+    pub fn to_f32(self) -> f32 {
+        (self.0 as f64 / Self::ONE.0 as f64) as f32
+    }
+
+    /// Rounds towards negative infinity.
+    pub const fn floor_to_int(self) -> i32 {
+        (self.0 >> Self::FRACTIONAL_BITS) as i32
+    }
+
+    pub const fn abs(self) -> Self {
+        Fixed(self.0.abs())
+    }
+
+    /// Square root, rounded down. Negative values have a square root of zero.
+    pub fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Fixed::ZERO;
+        }
+
+        Fixed(isqrt((self.0 as u128) << Self::FRACTIONAL_BITS) as i64)
+    }
+
+    /// Length of the vector `(x, y)`, rounded down.
+    ///
+    /// Computed without going through `f32` so it is the same everywhere.
+    pub fn hypot(x: Fixed, y: Fixed) -> Fixed {
+        let x = x.0.unsigned_abs() as u128;
+        let y = y.0.unsigned_abs() as u128;
+        Fixed(isqrt(x * x + y * y) as i64)
+    }
+}
+
+/// Integer square root, rounded down.
+fn isqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+
+    // Newton's method, starting from a power of two above the root.
+    let mut root = 1u128 << ((128 - value.leading_zeros()) / 2 + 1);
+    loop {
+        let next = (root + value / root) / 2;
+        if next >= root {
+            return root;
+        }
+        root = next;
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, other: Fixed) -> Fixed {
+        Fixed(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Fixed {
+    fn add_assign(&mut self, other: Fixed) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, other: Fixed) -> Fixed {
+        Fixed(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Fixed {
+    fn sub_assign(&mut self, other: Fixed) {
+        self.0 -= other.0;
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+
+impl From<i32> for Fixed {
+    fn from(value: i32) -> Self {
+        Fixed::from_int(value)
+    }
+}
+
+impl std::fmt::Display for Fixed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_f32())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Fixed::from_int(3).to_f32(), 3.0);
+        assert_eq!(Fixed::from_f32(2.5) + Fixed::from_f32(0.5), Fixed::from_int(3));
+        assert_eq!(Fixed::from_f32(-1.5).floor_to_int(), -2);
+        assert_eq!(Fixed::from_f32(1.5).floor_to_int(), 1);
+    }
+
+    #[test]
+    fn roots() {
+        assert_eq!(Fixed::from_int(16).sqrt(), Fixed::from_int(4));
+        assert_eq!(Fixed::from_f32(2.25).sqrt(), Fixed::from_f32(1.5));
+        assert_eq!(Fixed::hypot(Fixed::from_int(-3), Fixed::from_int(4)), Fixed::from_int(5));
+    }
+}
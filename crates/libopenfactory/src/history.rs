@@ -0,0 +1,136 @@
+//! Undo and redo for a player's own commands.
+//!
+//! Commands don't take effect when they're issued, but some ticks later once a
+//! [`LockstepSession`](crate::lockstep::LockstepSession) applies them, and they
+//! may be rejected. [`History`] therefore only learns what to undo from the
+//! outcomes of the commands, which must be fed back in the order they were issued.
+
+use std::collections::VecDeque;
+
+use crate::command::{Command, CommandError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Origin {
+    Do,
+    Undo,
+    Redo,
+}
+
+#[derive(Debug, Default)]
+pub struct History {
+    /// Inverses of applied commands, most recent last.
+    undo: Vec<Command>,
+
+    /// Inverses of undone commands, most recent last.
+    redo: Vec<Command>,
+
+    /// Why each command whose outcome is still unknown was issued.
+    pending: VecDeque<Origin>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that a new command is being issued.
+    ///
+    /// Returns the command so that it can be passed straight on to the session.
+    pub fn issue(&mut self, command: Command) -> Command {
+        self.pending.push_back(Origin::Do);
+        command
+    }
+
+    /// The command to issue to undo the most recent command.
+    pub fn undo(&mut self) -> Option<Command> {
+        let command = self.undo.pop()?;
+        self.pending.push_back(Origin::Undo);
+        Some(command)
+    }
+
+    /// The command to issue to redo the most recently undone command.
+    pub fn redo(&mut self) -> Option<Command> {
+        let command = self.redo.pop()?;
+        self.pending.push_back(Origin::Redo);
+        Some(command)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Record the outcome of the oldest issued command whose outcome isn't known yet.
+    pub fn record_outcome(&mut self, result: &Result<Command, CommandError>) {
+        let origin = match self.pending.pop_front() {
+            Some(origin) => origin,
+            None => return,
+        };
+
+        let inverse = match result {
+            Ok(inverse) => inverse.clone(),
+            Err(_) => return,
+        };
+
+        match origin {
+            Origin::Do => {
+                self.undo.push(inverse);
+                self.redo.clear();
+            },
+            Origin::Undo => self.redo.push(inverse),
+            Origin::Redo => self.undo.push(inverse),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{factory::{EntityId, Position}, tile::Rotation};
+
+    fn place(x: f32) -> Command {
+        Command::PlaceProducer { id: None, position: Position::from_f32(x, 0.0), rotation: Rotation::North, recipe: None }
+    }
+
+    fn remove(id: u32) -> Command {
+        Command::RemoveEntity { entity: EntityId::from_raw(id) }
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut history = History::new();
+        assert_eq!(history.undo(), None);
+
+        history.issue(place(0.0));
+        history.issue(place(1.0));
+        history.record_outcome(&Ok(remove(0)));
+        history.record_outcome(&Err(CommandError::UnknownEntity(EntityId::from_raw(7))));
+
+        // Only the applied command can be undone.
+        assert_eq!(history.undo(), Some(remove(0)));
+        assert!(!history.can_undo());
+
+        history.record_outcome(&Ok(place(0.0)));
+        assert_eq!(history.redo(), Some(place(0.0)));
+        history.record_outcome(&Ok(remove(1)));
+        assert_eq!(history.undo(), Some(remove(1)));
+    }
+
+    #[test]
+    fn new_command_clears_redo() {
+        let mut history = History::new();
+
+        history.issue(place(0.0));
+        history.record_outcome(&Ok(remove(0)));
+        history.undo();
+        history.record_outcome(&Ok(place(0.0)));
+        assert!(history.can_redo());
+
+        history.issue(place(2.0));
+        history.record_outcome(&Ok(remove(1)));
+        assert!(!history.can_redo());
+    }
+}
@@ -0,0 +1,287 @@
+//! Random factories, run and edited at random, keep their invariants.
+//!
+//! - Items are only made and used up by crafts. Everything else moves them,
+//!   e.g. connectors, or removing entities returning their items.
+//! - No slot or hand holds more than its capacity, and no stack is empty.
+//! - Sleeping producers and connectors don't change the results.
+//!
+//! Failing cases are shrunk to the fewest recipes and actions that still fail.
+//! Set `PROPTEST_CASES` to try more factories than the default 256.
+
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+
+use crate::{
+    checksum::checksum,
+    codec::{encode_to_vec, Decode},
+    command::Command,
+    factory::{EntityId, Factory, Position, Prototypes},
+    item_stack::{ItemData, ItemFilter},
+    kinds::{ConnectorKind, ConnectorKindBuilder, ItemKind, ItemKindBuilder, QualityRule, RecipeInput, RecipeKind, RecipeOutput},
+    local_string::LocalString,
+    registry::{Handle, Table},
+    stats::ProductionStats,
+    tile::Rotation,
+};
+
+const ITEMS: usize = 3;
+
+#[derive(Debug, Clone)]
+struct RecipeSpec {
+    /// Item indices and quantities.
+    inputs: BTreeMap<usize, u16>,
+    outputs: BTreeMap<usize, u16>,
+    time: u16,
+    quality: QualityRule,
+}
+
+#[derive(Debug, Clone)]
+struct ConnectorSpec {
+    hand_size: u16,
+    pickup_timeout: u16,
+}
+
+/// Entities are picked by index into those placed so far, wrapping around.
+#[derive(Debug, Clone)]
+enum Action {
+    Place { column: u8, row: u8, recipe: Option<usize> },
+    Connect { giver: usize, taker: usize, kind: usize },
+    Remove { entity: usize },
+    SetRecipe { producer: usize, recipe: Option<usize> },
+    Tick(u16),
+
+    /// Carry on from a snapshot.
+    Reload,
+}
+
+fn recipe_spec() -> impl Strategy<Value = RecipeSpec> {
+    (
+        prop::collection::btree_map(0..ITEMS, 1..=3u16, 0..=ITEMS),
+        prop::collection::btree_map(0..ITEMS, 1..=3u16, 0..=2),
+        1..=20u16,
+        any::<bool>(),
+        prop_oneof![Just(0u16), 0..=1000u16],
+    )
+    .prop_map(|(inputs, outputs, time, from_inputs, upgrade_chance)| RecipeSpec {
+        inputs,
+        outputs,
+        time,
+        quality: QualityRule { from_inputs, upgrade_chance },
+    })
+}
+
+fn connector_spec() -> impl Strategy<Value = ConnectorSpec> {
+    (1..=4u16, 0..=10u16).prop_map(|(hand_size, pickup_timeout)| ConnectorSpec { hand_size, pickup_timeout })
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    prop_oneof![
+        2 => (0..3u8, 0..3u8, prop::option::weighted(0.9, 0..8usize)).prop_map(|(column, row, recipe)| Action::Place { column, row, recipe }),
+        4 => (0..9usize, 0..9usize, 0..2usize).prop_map(|(giver, taker, kind)| Action::Connect { giver, taker, kind }),
+        1 => (0..32usize).prop_map(|entity| Action::Remove { entity }),
+        1 => (0..9usize, prop::option::of(0..8usize)).prop_map(|(producer, recipe)| Action::SetRecipe { producer, recipe }),
+        4 => (1..=100u16).prop_map(Action::Tick),
+        1 => Just(Action::Reload),
+    ]
+}
+
+/// Handles are picked by index too, wrapping around.
+fn nth_handle<T>(table: &Table<T>, index: usize) -> Handle<T> {
+    table
+    .iter()
+    .nth(index % table.len())
+    .map(|(handle, _)| handle)
+    .expect("tables aren't empty")
+}
+
+struct World {
+    items: Table<ItemKind>,
+    recipes: Table<RecipeKind>,
+    connectors: Table<ConnectorKind>,
+
+    /// Sleeps as usual.
+    factory: Factory,
+
+    /// Ticks everything on every tick.
+    awake: Factory,
+    stats: ProductionStats,
+}
+
+impl World {
+    fn new(recipe_specs: &[RecipeSpec], connector_specs: &[ConnectorSpec]) -> Self {
+        let mut items = Table::new();
+        let item_handles: Vec<Handle<ItemKind>> = (0..ITEMS)
+        .map(|index| {
+            let name = format!("item-{}", index);
+            items.insert(ItemKindBuilder::new().with_name(LocalString::from_str(&name)).build(), name)
+        })
+        .collect();
+
+        let mut recipes = Table::new();
+        for (index, spec) in recipe_specs.iter().enumerate() {
+            let name = format!("recipe-{}", index);
+            recipes.insert(RecipeKind {
+                name: LocalString::from_str(&name),
+                input_items: spec.inputs
+                .iter()
+                .map(|(&item, &quantity)| RecipeInput { item: item_handles[item].into(), quantity, wear: None })
+                .collect(),
+                output: spec.outputs
+                .iter()
+                .map(|(&item, &quantity)| RecipeOutput { item: item_handles[item], quantity, data: ItemData::default() })
+                .collect(),
+                time: spec.time,
+                quality: spec.quality,
+                mining: false,
+            }, name);
+        }
+
+        let mut connectors = Table::new();
+        for (index, spec) in connector_specs.iter().enumerate() {
+            let name = format!("connector-{}", index);
+            let kind = ConnectorKindBuilder::new()
+            .with_name(LocalString::from_str(&name))
+            .with_hand_size(spec.hand_size)
+            .with_pickup_timeout(spec.pickup_timeout)
+            .build();
+            connectors.insert(kind, name);
+        }
+
+        Self { items, recipes, connectors, factory: Factory::new(), awake: Factory::new(), stats: ProductionStats::new() }
+    }
+
+    fn entity(&self, index: usize, producers_only: bool) -> Option<EntityId> {
+        let producers = self.factory.producers().map(|(id, _)| id);
+        let entities: Vec<EntityId> = if producers_only {
+            producers.collect()
+        } else {
+            producers.chain(self.factory.connectors().map(|(id, _)| id)).collect()
+        };
+
+        entities.get(index % entities.len().max(1)).copied()
+    }
+
+    fn recipe(&self, index: usize) -> Handle<RecipeKind> {
+        nth_handle(&self.recipes, index)
+    }
+
+    /// Run the action on both factories.
+    fn act(&mut self, action: &Action) -> Result<(), TestCaseError> {
+        let command = match *action {
+            Action::Place { column, row, recipe } => Command::PlaceProducer {
+                id: None,
+                position: Position::from_f32(60.0 + 120.0 * column as f32, 15.0 + 30.0 * row as f32),
+                rotation: Rotation::North,
+                recipe: recipe.map(|recipe| self.recipe(recipe)),
+            },
+
+            Action::Connect { giver, taker, kind } => match (self.entity(giver, true), self.entity(taker, true)) {
+                (Some(giver), Some(taker)) => Command::Connect { id: None, giver, taker, kind: nth_handle(&self.connectors, kind) },
+                _ => return Ok(()),
+            },
+
+            Action::Remove { entity } => match self.entity(entity, false) {
+                Some(entity) => Command::RemoveEntity { entity },
+                None => return Ok(()),
+            },
+
+            Action::SetRecipe { producer, recipe } => match self.entity(producer, true) {
+                Some(producer) => Command::SetRecipe { producer, recipe: recipe.map(|recipe| self.recipe(recipe)) },
+                None => return Ok(()),
+            },
+
+            Action::Tick(ticks) => {
+                for _ in 0..ticks {
+                    self.tick()?;
+                }
+                return Ok(());
+            },
+
+            Action::Reload => {
+                self.factory = Factory::decode(&mut &encode_to_vec(&self.factory)[..]).expect("snapshots decode");
+                return self.check();
+            },
+        };
+
+        let tags = Table::new();
+        let technologies = Table::new();
+        let prototypes = Prototypes { recipes: &self.recipes, connectors: &self.connectors, tags: &tags, technologies: &technologies };
+
+        let applied = self.factory.apply(&command, &prototypes).is_ok();
+        prop_assert_eq!(applied, self.awake.apply(&command, &prototypes).is_ok());
+        self.check()
+    }
+
+    fn tick(&mut self) -> Result<(), TestCaseError> {
+        let tags = Table::new();
+        let technologies = Table::new();
+        let prototypes = Prototypes { recipes: &self.recipes, connectors: &self.connectors, tags: &tags, technologies: &technologies };
+
+        self.factory.tick_with_stats(&prototypes, &mut self.stats);
+        self.awake.wake_all();
+        self.awake.tick(&prototypes);
+        self.check()
+    }
+
+    fn check(&self) -> Result<(), TestCaseError> {
+        prop_assert_eq!(checksum(&self.factory), checksum(&self.awake), "sleeping changed the results");
+
+        // Items made minus items used up by every craft so far.
+        let mut expected: BTreeMap<Handle<ItemKind>, i64> = BTreeMap::new();
+        for (recipe, crafts) in self.stats.recipes() {
+            for output in &self.recipes[recipe].output {
+                *expected.entry(output.item).or_default() += crafts as i64 * output.quantity as i64;
+            }
+            for input in &self.recipes[recipe].input_items {
+                if let ItemFilter::Item(item) = input.item {
+                    *expected.entry(item).or_default() -= crafts as i64 * input.quantity as i64;
+                }
+            }
+        }
+
+        let mut counted: BTreeMap<Handle<ItemKind>, i64> = BTreeMap::new();
+        let stacks = self.factory
+        .producers()
+        .flat_map(|(_, placed)| placed.producer.contents())
+        .chain(self.factory.connectors().filter_map(|(_, placed)| placed.connector.held_stack()))
+        .chain(self.factory.returned_items());
+
+        for stack in stacks {
+            prop_assert!(stack.quantity > 0, "empty stack of {}", self.items.name(&stack.item));
+            *counted.entry(stack.item).or_default() += stack.quantity as i64;
+        }
+
+        expected.retain(|_, count| *count != 0);
+        prop_assert_eq!(expected, counted, "items were made or lost outside of crafts");
+
+        for (id, placed) in self.factory.producers() {
+            let (inputs, outputs) = placed.producer.item_counts();
+            for (quantity, capacity) in inputs.into_iter().chain(outputs) {
+                prop_assert!(quantity <= capacity, "producer {} holds {} of {}", id, quantity, capacity);
+            }
+        }
+
+        for (id, placed) in self.factory.connectors() {
+            let held = placed.connector.held_stack().map(|stack| stack.quantity).unwrap_or_default();
+            prop_assert!(held <= placed.connector.hand_size(), "connector {} holds {} of {}", id, held, placed.connector.hand_size());
+        }
+
+        Ok(())
+    }
+}
+
+proptest! {
+    #[test]
+    fn random_factories_keep_their_invariants(
+        recipes in prop::collection::vec(recipe_spec(), 1..=4),
+        connectors in prop::collection::vec(connector_spec(), 1..=2),
+        actions in prop::collection::vec(action(), 0..60),
+    ) {
+        let mut world = World::new(&recipes, &connectors);
+
+        for action in &actions {
+            world.act(action)?;
+        }
+    }
+}
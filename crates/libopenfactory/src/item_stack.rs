@@ -1,11 +1,45 @@
-use crate::{kinds::{ItemKind, RecipeOutput}, registry::Handle};
+use std::collections::BTreeMap;
+
+use crate::{kinds::{ItemKind, ItemTag, RecipeOutput}, registry::{Handle, Table}};
 
 /// Maximum number of items allowed in a single item stack.
 pub type ItemStackQuanity = u16;
 
+/// Which items a slot or recipe input accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ItemFilter {
+    /// Exactly this kind of item.
+    Item(Handle<ItemKind>),
+
+    /// Any item with this tag.
+    Tag(Handle<ItemTag>),
+}
+
+impl ItemFilter {
+    /// A tag that isn't in the table, e.g. one decoded from a peer, matches nothing.
+    pub fn matches(&self, item: Handle<ItemKind>, tags: &Table<ItemTag>) -> bool {
+        match *self {
+            ItemFilter::Item(filter) => filter == item,
+            ItemFilter::Tag(tag) => tags.get(tag).is_some_and(|tag| tag.contains(item)),
+        }
+    }
+}
+
+impl From<Handle<ItemKind>> for ItemFilter {
+    fn from(item: Handle<ItemKind>) -> Self {
+        ItemFilter::Item(item)
+    }
+}
+
+impl From<Handle<ItemTag>> for ItemFilter {
+    fn from(tag: Handle<ItemTag>) -> Self {
+        ItemFilter::Tag(tag)
+    }
+}
+
 pub struct ItemSlotBuilder {
     capacity: ItemStackQuanity,
-    filter: Option<Handle<ItemKind>>,
+    filter: Option<ItemFilter>,
 }
 
 impl ItemSlotBuilder {
@@ -21,8 +55,8 @@ impl ItemSlotBuilder {
         self
     }
 
-    pub fn with_filter(mut self, filter: Handle<ItemKind>) -> Self {
-        self.filter = Some(filter);
+    pub fn with_filter(mut self, filter: impl Into<ItemFilter>) -> Self {
+        self.filter = Some(filter.into());
         self
     }
 
@@ -39,11 +73,11 @@ impl ItemSlotBuilder {
 pub struct ItemSlot {
     pub stack: Option<ItemStack>,
     pub(crate) capacity: ItemStackQuanity,
-    pub(crate) filter: Option<Handle<ItemKind>>
+    pub(crate) filter: Option<ItemFilter>
 }
 
 impl ItemSlot {
-    pub fn new(filter: Option<Handle<ItemKind>>) -> Self {
+    pub fn new(filter: Option<ItemFilter>) -> Self {
         Self {
             stack: None,
             capacity: ItemStackQuanity::MAX,
@@ -66,28 +100,49 @@ impl ItemSlot {
     }
 
     pub fn take_single_item(&mut self) -> Option<ItemStack> {
+        self.take_items(1)
+    }
+
+    /// Take up to `max` items out of the slot as a single stack.
+    pub fn take_items(&mut self, max: ItemStackQuanity) -> Option<ItemStack> {
+        if max == 0 {
+            return None;
+        }
+
         if let Some(stack) = self.stack.take() {
-            let (new_stack, single_stack) = stack.split_single();
+            let (new_stack, taken_stack) = stack.split(max);
             self.stack = new_stack;
-            Some(single_stack)
+            Some(taken_stack)
         } else {
             None
         }
     }
 
-    pub fn insert_item_stack(&mut self, mut stack: ItemStack) -> InsertItemStackResult {
-        use InsertItemStackResult::*;
+    pub fn filter(&self) -> Option<ItemFilter> {
+        self.filter
+    }
 
-        // If the item slot is filtered, the item stack must actually be same item.
-        if let Some(filter) = self.filter {
-            if stack.item != filter {
-                return FilterError(stack);
-            }
+    /// Whether the slot's filter lets in the item. The slot may still be full or hold another item.
+    pub fn accepts(&self, item: Handle<ItemKind>, tags: &Table<ItemTag>) -> bool {
+        self.filter.map(|filter| filter.matches(item, tags)).unwrap_or(true)
+    }
+
+    pub fn insert_item_stack(&mut self, stack: ItemStack, tags: &Table<ItemTag>) -> InsertItemStackResult {
+        // If the item slot is filtered, the item stack must pass the filter.
+        if !self.accepts(stack.item, tags) {
+            return InsertItemStackResult::FilterError(stack);
         }
 
-        // If the item slot has a stack already, the item type must match.
+        self.insert_ignoring_filter(stack)
+    }
+
+    /// Insert into a slot that is known to accept the stack's item, e.g. an unfiltered one.
+    pub(crate) fn insert_ignoring_filter(&mut self, mut stack: ItemStack) -> InsertItemStackResult {
+        use InsertItemStackResult::*;
+
+        // If the item slot has a stack already, the item type and data must match.
         if let Some(slot_stack) = &self.stack {
-            if slot_stack.item != stack.item {
+            if !slot_stack.stacks_with(&stack) {
                 return ItemSlotTaken(stack);
             }
         }
@@ -99,9 +154,12 @@ impl ItemSlot {
         }
 
         stack.quantity -= stack_consumption;
-        let slot_stack = self.stack.get_or_insert(ItemStack { item: stack.item, quantity: 0 });
+        let slot_stack = self.stack.get_or_insert(ItemStack { item: stack.item, quantity: 0, data: stack.data.clone() });
         slot_stack.quantity += stack_consumption;
 
+        // The slot took the top item.
+        stack.data = stack.data.unused();
+
         if stack.quantity == 0 {
             StackConsumed
         } else {
@@ -118,6 +176,24 @@ impl ItemSlot {
             self.stack = None;
         } else {
             slot_stack.quantity -= quantity;
+            slot_stack.data = slot_stack.data.unused();
+        }
+    }
+
+    /// Wear the top item by `amount`, destroying it once it's used up.
+    ///
+    /// Items without durability are destroyed right away.
+    pub fn wear(&mut self, amount: u16) {
+        let durability = self.stack
+        .as_mut()
+        .expect("ItemSlot must have a stack in it.")
+        .data
+        .durability
+        .as_mut();
+
+        match durability {
+            Some(durability) if durability.remaining > amount => durability.remaining -= amount,
+            _ => self.destroy_quantity(1),
         }
     }
 }
@@ -156,32 +232,215 @@ impl InsertItemStackResult {
     }
 }
 
+/// How much use an item has left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Durability {
+    pub remaining: u16,
+    pub max: u16,
+}
+
+impl Durability {
+    /// An unused item.
+    pub fn new(max: u16) -> Self {
+        Self { remaining: max, max }
+    }
+}
+
+/// How good an item is. Items of different quality don't stack.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Quality {
+    #[default]
+    Normal,
+    Good,
+    Excellent,
+}
+
+impl Quality {
+    pub const ALL: [Quality; 3] = [Quality::Normal, Quality::Good, Quality::Excellent];
+
+    /// The next tier up, if any.
+    pub fn upgraded(self) -> Self {
+        match self {
+            Quality::Normal => Quality::Good,
+            Quality::Good | Quality::Excellent => Quality::Excellent,
+        }
+    }
+
+    /// Key of the quality's name, for localization.
+    pub fn name(self) -> &'static str {
+        match self {
+            Quality::Normal => "quality-normal",
+            Quality::Good => "quality-good",
+            Quality::Excellent => "quality-excellent",
+        }
+    }
+}
+
+/// Data carried by the items in a stack, e.g. a tool's durability or a battery's charge.
+///
+/// Stacks only merge when their data is equal. Most items carry the default, empty data.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ItemData {
+    pub quality: Quality,
+
+    /// Durability of the top item of the stack. The items below it are unused.
+    pub durability: Option<Durability>,
+    pub charge: Option<u32>,
+    pub metadata: BTreeMap<String, i64>,
+}
+
+impl ItemData {
+    pub fn with_quality(mut self, quality: Quality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    pub fn with_durability(mut self, max: u16) -> Self {
+        self.durability = Some(Durability::new(max));
+        self
+    }
+
+    pub fn with_charge(mut self, charge: u32) -> Self {
+        self.charge = Some(charge);
+        self
+    }
+
+    pub fn with_metadata(mut self, key: &str, value: i64) -> Self {
+        self.metadata.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn is_worn(&self) -> bool {
+        self.durability.map(|durability| durability.remaining < durability.max).unwrap_or(false)
+    }
+
+    /// The data of an unused item of the same stack.
+    fn unused(&self) -> Self {
+        Self {
+            durability: self.durability.map(|durability| Durability::new(durability.max)),
+            ..self.clone()
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ItemStack {
     pub item: Handle<ItemKind>,
     pub quantity: ItemStackQuanity,
+    pub data: ItemData,
 }
 
 impl ItemStack {
+    pub fn new(item: Handle<ItemKind>, quantity: ItemStackQuanity) -> Self {
+        Self {
+            item,
+            quantity,
+            data: ItemData::default(),
+        }
+    }
+
+    pub fn with_data(mut self, data: ItemData) -> Self {
+        self.data = data;
+        self
+    }
+
     pub fn from_recipe_output(recipe_output: &RecipeOutput) -> Self {
         Self {
             item: recipe_output.item,
             quantity: recipe_output.quantity,
+            data: recipe_output.data.clone(),
         }
     }
 
-    pub fn split_single(mut self) -> (Option<Self>, Self) {
-        if self.quantity == 1 {
+    /// Whether the stacks can merge. Worn items don't stack, as only the top item of a stack can be worn.
+    pub fn stacks_with(&self, other: &ItemStack) -> bool {
+        self.item == other.item && self.data == other.data && !self.data.is_worn()
+    }
+
+    pub fn split_single(self) -> (Option<Self>, Self) {
+        self.split(1)
+    }
+
+    /// Split off up to `quantity` items into their own stack.
+    ///
+    /// Returns the remainder of this stack (if any is left) and the split off stack.
+    /// The split off stack takes the top item, so the remainder only holds unused items.
+    pub fn split(mut self, quantity: ItemStackQuanity) -> (Option<Self>, Self) {
+        if self.quantity <= quantity {
             return (None, self);
         }
 
-        let single_stack = ItemStack {
+        let split_stack = ItemStack {
             item: self.item,
-            quantity: 1,
+            quantity,
+            data: self.data.clone(),
         };
 
-        self.quantity -= 1;
+        self.quantity -= quantity;
+        self.data = self.data.unused();
+
+        (Some(self), split_stack)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{kinds::ItemKindBuilder, local_string::LocalString};
+
+    fn make_item() -> Handle<ItemKind> {
+        Table::new().insert(ItemKindBuilder::new().with_name(LocalString::from_str("item")).build(), "item".to_string())
+    }
+
+    #[test]
+    fn stacks_with_different_data_do_not_merge() {
+        let battery = make_item();
+        let charged = ItemData::default().with_charge(100);
+        let mut slot = ItemSlotBuilder::new().build();
+
+        let result = slot.insert_item_stack(ItemStack::new(battery, 2).with_data(charged.clone()), &Table::new());
+        assert!(matches!(result, InsertItemStackResult::StackConsumed));
+
+        let result = slot.insert_item_stack(ItemStack::new(battery, 1), &Table::new());
+        assert!(matches!(result, InsertItemStackResult::ItemSlotTaken(_)));
+
+        let result = slot.insert_item_stack(ItemStack::new(battery, 1).with_data(charged), &Table::new());
+        assert!(matches!(result, InsertItemStackResult::StackConsumed));
+        assert_eq!(3, slot.quantity());
+    }
+
+    #[test]
+    fn unknown_tags_match_nothing() {
+        let item = make_item();
+        let decoded: ItemFilter = crate::codec::decode_from_slice(&crate::codec::encode_to_vec(&ItemFilter::Tag(Handle::new(5)))).unwrap();
 
-        (Some(self), single_stack)
+        assert!(!decoded.matches(item, &Table::new()));
+        let mut slot = ItemSlotBuilder::new().with_filter(decoded).build();
+        assert!(matches!(slot.insert_item_stack(ItemStack::new(item, 1), &Table::new()), InsertItemStackResult::FilterError(_)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn only_the_top_item_wears() {
+        let pickaxe = make_item();
+        let mut slot = ItemSlotBuilder::new().build();
+        let _ = slot.insert_item_stack(ItemStack::new(pickaxe, 2).with_data(ItemData::default().with_durability(10)), &Table::new());
+
+        slot.wear(4);
+        slot.wear(4);
+        assert_eq!(2, slot.quantity());
+        assert_eq!(Some(Durability { remaining: 2, max: 10 }), slot.stack.as_ref().unwrap().data.durability);
+
+        // The worn top item is taken first and can't go back onto the unused ones.
+        let worn = slot.take_single_item().unwrap();
+        assert_eq!(Some(Durability::new(10)), slot.stack.as_ref().unwrap().data.durability);
+        assert!(matches!(slot.insert_item_stack(worn, &Table::new()), InsertItemStackResult::ItemSlotTaken(_)));
+
+        // Wearing out the last of its durability destroys the item.
+        slot.wear(10);
+        assert!(slot.is_empty());
+    }
+}
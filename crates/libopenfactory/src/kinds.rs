@@ -1,4 +1,4 @@
-use crate::{Time, item_stack::{ItemSlot, ItemStackQuanity}, local_string::LocalString, registry::Handle};
+use crate::{Time, item_stack::{ItemData, ItemFilter, ItemSlot, ItemStackQuanity}, local_string::LocalString, random::PerMille, registry::Handle};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct ItemKind {
@@ -35,21 +35,162 @@ impl ItemKindBuilder {
     }
 }
 
+impl ItemKind {
+    pub fn name(&self) -> &LocalString {
+        &self.name
+    }
+}
+
+/// A group of items, e.g. `#fuel` or `#ore`, that recipe inputs and slot filters can accept as a whole.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ItemTag {
+    pub name: LocalString,
+    items: Vec<Handle<ItemKind>>,
+}
+
+impl ItemTag {
+    pub fn contains(&self, item: Handle<ItemKind>) -> bool {
+        self.items.contains(&item)
+    }
+
+    pub fn items(&self) -> &[Handle<ItemKind>] {
+        &self.items
+    }
+}
+
+pub struct ItemTagBuilder {
+    name: Option<LocalString>,
+    items: Vec<Handle<ItemKind>>,
+}
+
+impl ItemTagBuilder {
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            items: vec![],
+        }
+    }
+
+    pub fn with_name(mut self, name: LocalString) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn with_item(mut self, item: Handle<ItemKind>) -> Self {
+        if !self.items.contains(&item) {
+            self.items.push(item);
+        }
+        self
+    }
+
+    pub fn build(self) -> ItemTag {
+        match self {
+            ItemTagBuilder { name: Some(name), items } => ItemTag { name, items },
+            _ => panic!("Item Tag Builder built without all required fields"),
+        }
+    }
+}
+
+impl Default for ItemTagBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConnectorKind {
+    pub name: LocalString,
+
+    /// Maximum number of items carried per trip.
+    pub hand_size: ItemStackQuanity,
+
+    /// Ticks to wait for a full hand before leaving with a partial one.
+    pub pickup_timeout: Time,
+}
+
+pub struct ConnectorKindBuilder {
+    name: Option<LocalString>,
+    hand_size: ItemStackQuanity,
+    pickup_timeout: Time,
+}
+
+impl ConnectorKindBuilder {
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            hand_size: 1,
+            pickup_timeout: 0,
+        }
+    }
+
+    pub fn with_name(mut self, name: LocalString) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn with_hand_size(mut self, hand_size: ItemStackQuanity) -> Self {
+        self.hand_size = hand_size;
+        self
+    }
+
+    pub fn with_pickup_timeout(mut self, pickup_timeout: Time) -> Self {
+        self.pickup_timeout = pickup_timeout;
+        self
+    }
+
+    pub fn build(self) -> ConnectorKind {
+        match self {
+            ConnectorKindBuilder { name: Some(name), hand_size, pickup_timeout } if hand_size > 0 => {
+                ConnectorKind { name, hand_size, pickup_timeout }
+            },
+
+            ConnectorKindBuilder { name: Some(_), .. } => panic!("Connector Kind Builder built with a hand size of zero"),
+
+            _ => panic!("Connector Kind Builder built without all required fields")
+        }
+    }
+}
+
+impl Default for ConnectorKindBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct RecipeKind {
     pub name: LocalString,
     pub input_items: Vec<RecipeInput>,
     pub output: Vec<RecipeOutput>,
     pub time: Time,
+    pub quality: QualityRule,
+
+    /// Dig the outputs out of the deposits under the producer instead of making them from nothing.
+    pub mining: bool,
+}
+
+/// How a recipe picks the quality of its outputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QualityRule {
+    /// Start from the lowest quality among the inputs instead of normal.
+    pub from_inputs: bool,
+
+    /// Chance to upgrade the outputs one tier.
+    pub upgrade_chance: PerMille,
 }
 
 pub struct RecipeInput {
-    pub item: Handle<ItemKind>,
+    /// A single kind of item, or any item with a tag.
+    pub item: ItemFilter,
     pub quantity: ItemStackQuanity,
+
+    /// Wear the top item by this much instead of consuming `quantity` items.
+    pub wear: Option<u16>,
 }
 
 pub struct RecipeOutput {
     pub item: Handle<ItemKind>,
     pub quantity: ItemStackQuanity,
+    pub data: ItemData,
 }
 
 impl From<RecipeInput> for ItemSlot {
@@ -67,7 +208,7 @@ impl From<RecipeOutput> for ItemSlot {
         ItemSlot {
             stack: None,
             capacity: output.quantity * 2,
-            filter: Some(output.item),
+            filter: Some(ItemFilter::Item(output.item)),
         }
     }
 }
@@ -87,11 +228,102 @@ impl From<&RecipeOutput> for ItemSlot {
         ItemSlot {
             stack: None,
             capacity: output.quantity * 2,
-            filter: Some(output.item),
+            filter: Some(ItemFilter::Item(output.item)),
+        }
+    }
+}
+
+/// Something researching a technology unlocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TechnologyEffect {
+    /// The recipe can be used. Recipes no technology unlocks can be used from the start.
+    UnlockRecipe(Handle<RecipeKind>),
+
+    /// Producers work faster. Bonuses of several technologies add up.
+    ProductionSpeed(PerMille),
+}
+
+pub struct TechnologyKind {
+    pub name: LocalString,
+    pub prerequisites: Vec<Handle<TechnologyKind>>,
+
+    /// Recipe labs run for the technology. Each craft is one unit of research,
+    /// so its inputs are the science items a unit costs.
+    pub lab_recipe: Handle<RecipeKind>,
+
+    /// Units of research needed.
+    pub units: u32,
+
+    pub effects: Vec<TechnologyEffect>,
+}
+
+impl TechnologyKind {
+    pub fn unlocks(&self, recipe: Handle<RecipeKind>) -> bool {
+        self.effects.contains(&TechnologyEffect::UnlockRecipe(recipe))
+    }
+}
+
+pub struct TechnologyKindBuilder {
+    name: Option<LocalString>,
+    prerequisites: Vec<Handle<TechnologyKind>>,
+    lab_recipe: Option<Handle<RecipeKind>>,
+    units: u32,
+    effects: Vec<TechnologyEffect>,
+}
+
+impl TechnologyKindBuilder {
+    pub fn new() -> Self {
+        Self {
+            name: None,
+            prerequisites: vec![],
+            lab_recipe: None,
+            units: 1,
+            effects: vec![],
+        }
+    }
+
+    pub fn with_name(mut self, name: LocalString) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    pub fn with_prerequisite(mut self, technology: Handle<TechnologyKind>) -> Self {
+        self.prerequisites.push(technology);
+        self
+    }
+
+    pub fn with_lab_recipe(mut self, recipe: Handle<RecipeKind>) -> Self {
+        self.lab_recipe = Some(recipe);
+        self
+    }
+
+    pub fn with_units(mut self, units: u32) -> Self {
+        self.units = units;
+        self
+    }
+
+    pub fn with_effect(mut self, effect: TechnologyEffect) -> Self {
+        self.effects.push(effect);
+        self
+    }
+
+    pub fn build(self) -> TechnologyKind {
+        match self {
+            TechnologyKindBuilder { name: Some(name), prerequisites, lab_recipe: Some(lab_recipe), units, effects } if units > 0 => {
+                TechnologyKind { name, prerequisites, lab_recipe, units, effects }
+            },
+
+            _ => panic!("Technology Kind Builder built without all required fields"),
         }
     }
 }
 
+impl Default for TechnologyKindBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::registry::Table;
@@ -105,9 +337,11 @@ mod test {
 
         let _recipe = RecipeKind {
             name: LocalString::from_str("generic-recipe"),
-            input_items: vec![RecipeInput { item: input_kind, quantity: 1 }],
-            output: vec![RecipeOutput { item: output_kind, quantity: 1 }],
+            input_items: vec![RecipeInput { item: input_kind.into(), quantity: 1, wear: None }],
+            output: vec![RecipeOutput { item: output_kind, quantity: 1, data: ItemData::default() }],
             time: 20,
+            quality: QualityRule::default(),
+            mining: false,
         };
     }
 }
\ No newline at end of file
@@ -1,10 +1,46 @@
 pub mod registry;
 pub mod local_string;
+pub mod localization;
 pub mod item_stack;
 pub mod kinds;
+pub mod fixed;
+pub mod random;
+pub mod clock;
+pub mod checksum;
+pub mod codec;
+pub mod compress;
+pub mod base64;
+pub mod tile;
+pub mod spatial;
 
 pub mod producer;
 pub mod connector;
+pub mod factory;
+pub mod research;
+pub mod deposit;
+pub mod blueprint;
+pub mod command;
+pub mod history;
+pub mod lockstep;
+pub mod replay;
+pub mod stats;
+pub mod synthetic;
+pub mod mods;
+
+#[cfg(feature = "scripting")]
+pub mod scripting;
+
+mod parallel;
+mod schedule;
+
+#[cfg(test)]
+mod testing;
+
+#[cfg(test)]
+mod invariants;
 
 /// Number of ticks (20 ticks = 1 second)
-pub type Time = u16;
\ No newline at end of file
+pub type Time = u16;
+
+/// Logic ticks simulated per second of game time.
+pub const TICKS_PER_SECOND: u32 = 20;
\ No newline at end of file
@@ -1,8 +1,91 @@
-#[derive(Debug, PartialEq, Eq)]
-pub struct LocalString(String);
+//! Text shown to players.
+//!
+//! A [`LocalString`] doesn't hold the text itself but the key of a message and
+//! the arguments to fill it in with. A [`Localizer`](crate::localization::Localizer)
+//! turns it into text in the player's language.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalString {
+    key: String,
+    args: Vec<(String, LocalArg)>,
+}
+
+/// A value filled into a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocalArg {
+    Text(String),
+
+    /// Numbers also pick plural forms.
+    Number(i64),
+}
 
 impl LocalString {
+    /// The message with the key `s`, without arguments.
     pub fn from_str(s: &str) -> Self {
-        LocalString(s.to_string())
+        LocalString {
+            key: s.to_string(),
+            args: vec![],
+        }
+    }
+
+    /// Set the argument `name`, replacing any previous value.
+    pub fn with_arg(mut self, name: &str, value: impl Into<LocalArg>) -> Self {
+        let value = value.into();
+
+        match self.args.iter_mut().find(|(arg_name, _)| arg_name == name) {
+            Some((_, arg)) => *arg = value,
+            None => self.args.push((name.to_string(), value)),
+        }
+
+        self
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The arguments and their names, in the order they were first set.
+    pub fn args(&self) -> impl Iterator<Item = (&str, &LocalArg)> {
+        self.args.iter().map(|(name, value)| (name.as_str(), value))
     }
-}
\ No newline at end of file
+
+    pub fn arg(&self, name: &str) -> Option<&LocalArg> {
+        self.args
+        .iter()
+        .find(|(arg_name, _)| arg_name == name)
+        .map(|(_, value)| value)
+    }
+}
+
+impl std::fmt::Display for LocalArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LocalArg::Text(text) => write!(f, "{}", text),
+            LocalArg::Number(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+impl From<&str> for LocalArg {
+    fn from(text: &str) -> Self {
+        LocalArg::Text(text.to_string())
+    }
+}
+
+impl From<String> for LocalArg {
+    fn from(text: String) -> Self {
+        LocalArg::Text(text)
+    }
+}
+
+macro_rules! impl_from_int_for_local_arg {
+    ($($int:ty),*) => {$(
+        impl From<$int> for LocalArg {
+            fn from(number: $int) -> Self {
+                LocalArg::Number(number as i64)
+            }
+        }
+    )*};
+}
+
+impl_from_int_for_local_arg!(u8, u16, u32, i32, i64);
@@ -0,0 +1,306 @@
+//! Turning [`LocalString`]s into text in the player's language.
+//!
+//! Each locale has a [`Catalog`] of messages written in
+//! [Fluent](https://projectfluent.org/), which [`fluent_bundle`] formats,
+//! picking plural forms by the locale's plural rules.
+//!
+//! ```text
+//! # Comments start with a hash.
+//! -brand = Open Factory
+//! welcome = Welcome to { -brand }!
+//! items = { $count ->
+//!     [0] No items
+//!     [one] One item
+//!    *[other] { $count } items
+//! }
+//! ```
+
+use std::sync::Arc;
+
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource, FluentValue};
+use fluent_syntax::ast;
+use unic_langid::LanguageIdentifier;
+
+use crate::local_string::{LocalArg, LocalString};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    /// Line the error is on, counting from one.
+    pub line: usize,
+    pub reason: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The messages of one locale.
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    locale: String,
+    language: LanguageIdentifier,
+    resource: Arc<FluentResource>,
+}
+
+impl Catalog {
+    /// Parse a catalog for `locale`, e.g. `en` or `de-AT`.
+    pub fn parse(locale: &str, source: &str) -> Result<Self, ParseError> {
+        let language = locale
+        .parse()
+        .map_err(|_| ParseError { line: 1, reason: format!("`{}` isn't a locale", locale) })?;
+
+        let resource = FluentResource::try_new(source.to_string()).map_err(|(_, errors)| {
+            let error = &errors[0];
+            ParseError { line: source[..error.pos.start].matches('\n').count() + 1, reason: error.kind.to_string() }
+        })?;
+
+        Ok(Catalog { locale: locale.to_string(), language, resource: Arc::new(resource) })
+    }
+
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// The language part of the locale, e.g. `de` for `de-AT`.
+    pub fn language(&self) -> &str {
+        self.language.language.as_str()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.resource
+        .entries()
+        .any(|entry| matches!(entry, ast::Entry::Message(message) if message.id.name == key))
+    }
+}
+
+/// Resolves [`LocalString`]s using the catalogs of the current locale and its fallbacks.
+pub struct Localizer {
+    /// Each locale with a catalog and the messages of all its catalogs, in the order they were added.
+    bundles: Vec<(String, FluentBundle<Arc<FluentResource>>)>,
+    default_locale: String,
+    locale: String,
+}
+
+impl Localizer {
+    /// Messages missing from other locales fall back to `default_locale`.
+    pub fn new(default_locale: &str) -> Self {
+        Self {
+            bundles: vec![],
+            default_locale: default_locale.to_string(),
+            locale: default_locale.to_string(),
+        }
+    }
+
+    /// Add a catalog. Messages in a catalog for a locale that already has one replace those in it.
+    pub fn add_catalog(&mut self, catalog: Catalog) {
+        let index = match self.bundles.iter().position(|(locale, _)| *locale == catalog.locale) {
+            Some(index) => index,
+            None => {
+                let mut bundle = FluentBundle::new_concurrent(vec![catalog.language.clone()]);
+
+                // Text is laid out by the frontend, which has no use for bidi isolation marks.
+                bundle.set_use_isolating(false);
+                self.bundles.push((catalog.locale.clone(), bundle));
+                self.bundles.len() - 1
+            },
+        };
+
+        self.bundles[index].1.add_resource_overriding(catalog.resource);
+    }
+
+    /// Locales with a catalog, in the order they were added.
+    pub fn locales(&self) -> impl Iterator<Item = &str> {
+        self.bundles.iter().map(|(locale, _)| locale.as_str())
+    }
+
+    pub fn locale(&self) -> &str {
+        &self.locale
+    }
+
+    /// Switch to a locale, e.g. `de-AT`.
+    ///
+    /// Messages it doesn't have are looked up in its language (`de`) and then the default locale.
+    pub fn set_locale(&mut self, locale: &str) {
+        self.locale = locale.to_string();
+    }
+
+    /// The locales searched for messages, most specific first.
+    pub fn fallback_chain(&self) -> Vec<&str> {
+        let mut chain = vec![self.locale.as_str()];
+
+        if let Some(language) = self.locale.split('-').next() {
+            chain.push(language);
+        }
+        chain.push(&self.default_locale);
+
+        chain.dedup();
+        chain
+    }
+
+    /// The text of a string. Strings without a message anywhere come out as their key.
+    pub fn localize(&self, string: &LocalString) -> String {
+        let mut args = FluentArgs::new();
+        for (name, value) in string.args() {
+            match value {
+                LocalArg::Text(text) => args.set(name, FluentValue::from(text.as_str())),
+                LocalArg::Number(number) => args.set(name, FluentValue::from(*number)),
+            }
+        }
+
+        for locale in self.fallback_chain() {
+            let bundle = match self.bundles.iter().find(|(bundle_locale, _)| bundle_locale == locale) {
+                Some((_, bundle)) => bundle,
+                None => continue,
+            };
+
+            if let Some(pattern) = bundle.get_message(string.key()).and_then(|message| message.value()) {
+                // Missing arguments and references come out as their names, which is all there is to do about them.
+                let mut errors = vec![];
+                return bundle.format_pattern(pattern, Some(&args), &mut errors).into_owned();
+            }
+        }
+
+        string.key().to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EN: &str = "
+# Things for English speakers.
+-brand = Open Factory
+welcome = Welcome to { -brand }!
+items = { $count ->
+    [0] No items
+    [one] One item
+   *[other] { $count } items
+}
+bronze = Bronze
+help =
+    First line
+      second line
+quoted = { \"{\" }braces{ \"}\" }
+";
+
+    const RU: &str = "
+items = { $count ->
+    [one] { $count } предмет
+    [few] { $count } предмета
+   *[many] { $count } предметов
+}
+";
+
+    const CY: &str = "
+items = { $count ->
+    [zero] dim eitemau
+    [two] dwy eitem
+   *[other] { $count } eitem
+}
+";
+
+    const DE: &str = "welcome = Willkommen!";
+
+    /// Names the plural category of `$count`.
+    const CATEGORY: &str = "
+category = { $count ->
+    [zero] zero
+    [one] one
+    [two] two
+    [few] few
+    [many] many
+   *[other] other
+}
+";
+
+    fn localizer() -> Localizer {
+        let mut localizer = Localizer::new("en");
+        localizer.add_catalog(Catalog::parse("en", EN).unwrap());
+        localizer.add_catalog(Catalog::parse("ru", RU).unwrap());
+        localizer.add_catalog(Catalog::parse("de", DE).unwrap());
+        localizer
+    }
+
+    fn items(count: i64) -> LocalString {
+        LocalString::from_str("items").with_arg("count", count)
+    }
+
+    #[test]
+    fn messages_and_references() {
+        let localizer = localizer();
+
+        assert_eq!(localizer.localize(&LocalString::from_str("welcome")), "Welcome to Open Factory!");
+        assert_eq!(localizer.localize(&LocalString::from_str("help")), "First line\n  second line");
+        assert_eq!(localizer.localize(&LocalString::from_str("quoted")), "{braces}");
+        assert_eq!(localizer.localize(&LocalString::from_str("missing")), "missing");
+    }
+
+    #[test]
+    fn plurals() {
+        let mut localizer = localizer();
+
+        assert_eq!(localizer.localize(&items(0)), "No items");
+        assert_eq!(localizer.localize(&items(1)), "One item");
+        assert_eq!(localizer.localize(&items(21)), "21 items");
+        assert_eq!(localizer.localize(&LocalString::from_str("items")), "{$count} items");
+
+        localizer.set_locale("ru");
+        assert_eq!(localizer.localize(&items(21)), "21 предмет");
+        assert_eq!(localizer.localize(&items(3)), "3 предмета");
+        assert_eq!(localizer.localize(&items(12)), "12 предметов");
+
+        localizer.add_catalog(Catalog::parse("cy", CY).unwrap());
+        localizer.set_locale("cy");
+        assert_eq!(localizer.localize(&items(0)), "dim eitemau");
+        assert_eq!(localizer.localize(&items(2)), "dwy eitem");
+        assert_eq!(localizer.localize(&items(4)), "4 eitem");
+    }
+
+    #[test]
+    fn plural_categories() {
+        let categories = |language: &str, numbers: &[i64]| -> Vec<String> {
+            let mut localizer = Localizer::new(language);
+            localizer.add_catalog(Catalog::parse(language, CATEGORY).unwrap());
+            numbers.iter().map(|&number| localizer.localize(&LocalString::from_str("category").with_arg("count", number))).collect()
+        };
+
+        assert_eq!(categories("ar", &[0, 1, 2, 3, 11, 100, 102]), ["zero", "one", "two", "few", "many", "other", "other"]);
+        assert_eq!(categories("sl", &[1, 2, 3, 5, 101, 102]), ["one", "two", "few", "other", "one", "two"]);
+        assert_eq!(categories("lv", &[0, 1, 11, 21, 22]), ["zero", "one", "zero", "one", "other"]);
+        assert_eq!(categories("he", &[1, 2, 3]), ["one", "two", "other"]);
+    }
+
+    #[test]
+    fn fallbacks() {
+        let mut localizer = localizer();
+        localizer.set_locale("de-AT");
+
+        assert_eq!(localizer.fallback_chain(), vec!["de-AT", "de", "en"]);
+        assert_eq!(localizer.localize(&LocalString::from_str("welcome")), "Willkommen!");
+        assert_eq!(localizer.localize(&LocalString::from_str("bronze")), "Bronze");
+
+        // Later catalogs of a locale replace its messages.
+        localizer.add_catalog(Catalog::parse("de", "welcome = Hallo!").unwrap());
+        assert_eq!(localizer.localize(&LocalString::from_str("welcome")), "Hallo!");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Catalog::parse("en", "a = ok\nb { $x }").unwrap_err().line, 2);
+        assert_eq!(Catalog::parse("en", "\n\na = { $x").unwrap_err().line, 3);
+        assert!(Catalog::parse("en", "a = { $x ->\n  [one] One\n}").is_err());
+        assert!(Catalog::parse("en", "a = oops }").is_err());
+        assert!(Catalog::parse("not a locale", "a = ok").is_err());
+    }
+
+    #[test]
+    fn localizers_can_be_shared_between_threads() {
+        fn shared<T: Send + Sync>() {}
+        shared::<Localizer>();
+    }
+}
@@ -0,0 +1,813 @@
+//! Lockstep multiplayer.
+//!
+//! Every peer runs the whole simulation. Instead of sending state, peers send
+//! the [`Command`]s their player issued, scheduled `input_delay` ticks in the
+//! future. A tick is only simulated once the commands of every player for that
+//! tick have arrived, so every peer applies the same commands in the same order.
+//! After each tick, peers exchange a checksum of their factory to detect desyncs.
+//!
+//! How messages get between peers is up to the [`Transport`].
+//! [`LoopbackTransport`] connects sessions in the same process and
+//! [`TcpTransport`] connects them over TCP.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    checksum::checksum,
+    codec::{Decode, DecodeError, Encode},
+    command::{Command, CommandError},
+    factory::{Factory, Prototypes},
+};
+
+pub type PlayerId = u8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// The commands a player issued for a tick.
+    ///
+    /// Sent for every tick, even without commands, so that peers know
+    /// when it's safe to simulate the tick.
+    Commands {
+        player: PlayerId,
+        tick: u64,
+        commands: Vec<Command>,
+    },
+
+    /// Checksum of a player's factory after simulating a tick.
+    Checksum {
+        player: PlayerId,
+        tick: u64,
+        checksum: u64,
+    },
+
+    /// Sent by a host to a peer that just joined.
+    Welcome {
+        player: PlayerId,
+        player_count: u8,
+    },
+}
+
+impl Message {
+    /// The player a message is from, or `None` for a host's welcome.
+    pub fn sender(&self) -> Option<PlayerId> {
+        match *self {
+            Message::Commands { player, .. } | Message::Checksum { player, .. } => Some(player),
+            Message::Welcome { .. } => None,
+        }
+    }
+}
+
+/// Gets messages between the peers of a session.
+pub trait Transport {
+    /// Send a message to every other peer.
+    fn send(&mut self, message: &Message) -> io::Result<()>;
+
+    /// Receive the next message from any peer without blocking.
+    fn receive(&mut self) -> io::Result<Option<Message>>;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        (**self).send(message)
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        (**self).receive()
+    }
+}
+
+#[derive(Debug)]
+pub enum LockstepError {
+    Transport(io::Error),
+
+    /// Peers disagree about the state of the factory after `tick`.
+    Desync {
+        tick: u64,
+        checksums: Vec<(PlayerId, u64)>,
+    },
+}
+
+impl std::fmt::Display for LockstepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockstepError::Transport(error) => write!(f, "transport error: {}", error),
+            LockstepError::Desync { tick, checksums } => write!(f, "desync after tick {}: {:x?}", tick, checksums),
+        }
+    }
+}
+
+impl std::error::Error for LockstepError {}
+
+impl From<io::Error> for LockstepError {
+    fn from(error: io::Error) -> Self {
+        LockstepError::Transport(error)
+    }
+}
+
+pub struct LockstepSession<T> {
+    transport: T,
+    local_player: PlayerId,
+    player_count: u8,
+
+    /// How many ticks in the future local commands are scheduled.
+    input_delay: u64,
+
+    /// The next tick to simulate.
+    next_tick: u64,
+
+    /// The next tick to send local commands for.
+    next_scheduled_tick: u64,
+
+    /// Local commands that haven't been scheduled yet.
+    pending: Vec<Command>,
+
+    inputs: BTreeMap<u64, BTreeMap<PlayerId, Vec<Command>>>,
+    checksums: BTreeMap<u64, BTreeMap<PlayerId, u64>>,
+    outcomes: Vec<CommandOutcome>,
+}
+
+/// What happened when a player's command was applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutcome {
+    pub player: PlayerId,
+    pub command: Command,
+
+    /// The command that undoes it, or why it was rejected.
+    pub result: Result<Command, CommandError>,
+}
+
+impl<T: Transport> LockstepSession<T> {
+    /// Start a session for a factory that is at tick `0`.
+    ///
+    /// Players are numbered `0..player_count`.
+    pub fn new(transport: T, local_player: PlayerId, player_count: u8, input_delay: u64) -> Self {
+        assert!(local_player < player_count, "Local player must be one of the players");
+        assert!(input_delay > 0, "Commands can't be scheduled for the current tick");
+
+        // Nobody can have issued commands for the ticks before the input delay.
+        let inputs = (0..input_delay)
+        .map(|tick| (tick, (0..player_count).map(|player| (player, vec![])).collect()))
+        .collect();
+
+        Self {
+            transport,
+            local_player,
+            player_count,
+            input_delay,
+            next_tick: 0,
+            next_scheduled_tick: input_delay,
+            pending: vec![],
+            inputs,
+            checksums: BTreeMap::new(),
+            outcomes: vec![],
+        }
+    }
+
+    pub fn local_player(&self) -> PlayerId {
+        self.local_player
+    }
+
+    pub fn player_count(&self) -> u8 {
+        self.player_count
+    }
+
+    /// The next tick to simulate.
+    pub fn next_tick(&self) -> u64 {
+        self.next_tick
+    }
+
+    /// Issue a command as the local player.
+    ///
+    /// It is applied `input_delay` ticks from now on every peer.
+    pub fn issue(&mut self, command: Command) {
+        self.pending.push(command);
+    }
+
+    /// The outcomes of the commands applied since the last call, in the order they were applied.
+    ///
+    /// Every peer has the same outcomes.
+    pub fn drain_outcomes(&mut self) -> std::vec::Drain<'_, CommandOutcome> {
+        self.outcomes.drain(..)
+    }
+
+    /// Simulate the next tick if the commands of every player have arrived.
+    ///
+    /// Returns whether a tick was simulated.
+    pub fn advance(&mut self, factory: &mut Factory, prototypes: &Prototypes) -> Result<bool, LockstepError> {
+        self.schedule_local_commands()?;
+        self.receive_messages()?;
+
+        let ready = self.inputs
+        .get(&self.next_tick)
+        .map(|inputs| inputs.len() == self.player_count as usize)
+        .unwrap_or(false);
+
+        if !ready {
+            return Ok(false);
+        }
+
+        let tick = self.next_tick;
+        let inputs = self.inputs.remove(&tick).expect("inputs are ready");
+
+        for (player, commands) in inputs {
+            for command in commands {
+                let result = factory.apply(&command, prototypes);
+                self.outcomes.push(CommandOutcome { player, command, result });
+            }
+        }
+
+        factory.tick(prototypes);
+        self.next_tick += 1;
+
+        let local_checksum = checksum(factory);
+        self.transport.send(&Message::Checksum { player: self.local_player, tick, checksum: local_checksum })?;
+        self.record_checksum(self.local_player, tick, local_checksum)?;
+
+        Ok(true)
+    }
+
+    fn schedule_local_commands(&mut self) -> Result<(), LockstepError> {
+        while self.next_scheduled_tick <= self.next_tick + self.input_delay {
+            let tick = self.next_scheduled_tick;
+            let commands = std::mem::take(&mut self.pending);
+
+            self.transport.send(&Message::Commands { player: self.local_player, tick, commands: commands.clone() })?;
+            self.inputs.entry(tick).or_default().insert(self.local_player, commands);
+            self.next_scheduled_tick += 1;
+        }
+
+        Ok(())
+    }
+
+    fn receive_messages(&mut self) -> Result<(), LockstepError> {
+        while let Some(message) = self.transport.receive()? {
+            // Only other players speak for themselves; anything else is made up.
+            match message.sender() {
+                Some(player) if player < self.player_count && player != self.local_player => {},
+                _ => continue,
+            }
+
+            match message {
+                Message::Commands { player, tick, commands } => {
+                    if tick >= self.next_tick {
+                        self.inputs.entry(tick).or_default().insert(player, commands);
+                    }
+                },
+
+                Message::Checksum { player, tick, checksum } => {
+                    self.record_checksum(player, tick, checksum)?;
+                },
+
+                Message::Welcome { .. } => unreachable!("welcomes have no sender"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Once every player's checksum for a tick is in, compare them.
+    fn record_checksum(&mut self, player: PlayerId, tick: u64, checksum: u64) -> Result<(), LockstepError> {
+        let checksums = self.checksums.entry(tick).or_default();
+        checksums.insert(player, checksum);
+
+        if checksums.len() < self.player_count as usize {
+            return Ok(());
+        }
+
+        let checksums = self.checksums.remove(&tick).expect("checksums exist");
+        let first = *checksums.values().next().expect("there is at least one player");
+
+        if checksums.values().all(|&checksum| checksum == first) {
+            Ok(())
+        } else {
+            Err(LockstepError::Desync { tick, checksums: checksums.into_iter().collect() })
+        }
+    }
+}
+
+/// Connects sessions in the same process.
+pub struct LoopbackTransport {
+    peer: usize,
+    inboxes: Arc<Mutex<Vec<VecDeque<Message>>>>,
+}
+
+impl LoopbackTransport {
+    /// Create one transport per peer, all connected to each other.
+    pub fn connect(peers: usize) -> Vec<LoopbackTransport> {
+        let inboxes = Arc::new(Mutex::new(vec![VecDeque::new(); peers]));
+
+        (0..peers)
+        .map(|peer| LoopbackTransport { peer, inboxes: inboxes.clone() })
+        .collect()
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        let mut inboxes = self.inboxes.lock().expect("loopback mutex is not poisoned");
+
+        for (peer, inbox) in inboxes.iter_mut().enumerate() {
+            if peer != self.peer {
+                inbox.push_back(message.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        let mut inboxes = self.inboxes.lock().expect("loopback mutex is not poisoned");
+        Ok(inboxes[self.peer].pop_front())
+    }
+}
+
+/// Connects sessions over TCP.
+///
+/// One peer hosts and the others join it. The host relays messages between
+/// the peers that joined, so every peer only needs to reach the host.
+/// Messages are sent as a little-endian `u32` length followed by the encoded message.
+///
+/// A peer that sends a frame over [`MAX_FRAME`] bytes, one that doesn't
+/// decode, or, to a host, a message from a player other than its own, is
+/// dropped.
+pub struct TcpTransport {
+    peers: Vec<TcpPeer>,
+    local_player: PlayerId,
+    player_count: u8,
+    is_host: bool,
+}
+
+/// Most bytes an encoded message may take.
+pub const MAX_FRAME: usize = 8 * 1024 * 1024;
+
+struct TcpPeer {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+
+    /// The player on the other end, for the peers of a host. A host relays every player's messages.
+    player: Option<PlayerId>,
+}
+
+/// A host waiting for peers to join.
+pub struct TcpHost {
+    listener: TcpListener,
+}
+
+impl TcpHost {
+    pub fn local_addr(&self) -> io::Result<std::net::SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Block until `peers` peers have joined.
+    ///
+    /// The host is player `0` and the peers are numbered in the order they joined.
+    pub fn accept(self, peers: u8) -> io::Result<TcpTransport> {
+        let player_count = peers + 1;
+        let mut streams = vec![];
+
+        for player in 1..player_count {
+            let (stream, _) = self.listener.accept()?;
+            stream.set_nodelay(true)?;
+
+            let mut peer = TcpPeer { stream, buffer: vec![], player: Some(player) };
+            peer.send(&Message::Welcome { player, player_count })?;
+            peer.stream.set_nonblocking(true)?;
+            streams.push(peer);
+        }
+
+        Ok(TcpTransport { peers: streams, local_player: 0, player_count, is_host: true })
+    }
+}
+
+impl TcpTransport {
+    /// Start hosting a session.
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> io::Result<TcpHost> {
+        Ok(TcpHost { listener: TcpListener::bind(addr)? })
+    }
+
+    /// Join a hosted session, blocking until the host welcomes us.
+    pub fn join<A: ToSocketAddrs>(addr: A) -> io::Result<TcpTransport> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+
+        let mut peer = TcpPeer { stream, buffer: vec![], player: None };
+
+        let (local_player, player_count) = loop {
+            match peer.receive_blocking()? {
+                Message::Welcome { player, player_count } => break (player, player_count),
+                _ => continue,
+            }
+        };
+
+        peer.stream.set_nonblocking(true)?;
+
+        Ok(TcpTransport { peers: vec![peer], local_player, player_count, is_host: false })
+    }
+
+    pub fn local_player(&self) -> PlayerId {
+        self.local_player
+    }
+
+    pub fn player_count(&self) -> u8 {
+        self.player_count
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        for peer in self.peers.iter_mut() {
+            peer.send(message)?;
+        }
+
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        for ix in 0..self.peers.len() {
+            let message = match self.peers[ix].receive() {
+                Ok(message) => message,
+                Err(error) => {
+                    self.peers.remove(ix);
+                    return Err(error);
+                },
+            };
+
+            if let Some(message) = message {
+                if self.peers[ix].player.is_some() && message.sender() != self.peers[ix].player {
+                    self.peers.remove(ix);
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "peer sent a message for another player"));
+                }
+
+                if self.is_host {
+                    for (other, peer) in self.peers.iter_mut().enumerate() {
+                        if other != ix {
+                            peer.send(&message)?;
+                        }
+                    }
+                }
+
+                return Ok(Some(message));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl TcpPeer {
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        let mut frame = vec![0; 4];
+        message.encode(&mut frame);
+        let len = (frame.len() - 4) as u32;
+        frame[..4].copy_from_slice(&len.to_le_bytes());
+
+        let mut written = 0;
+        while written < frame.len() {
+            match self.stream.write(&frame[written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read whatever is available and return the next complete message, if any.
+    fn receive(&mut self) -> io::Result<Option<Message>> {
+        let mut chunk = [0; 4096];
+        let mut closed = false;
+
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                },
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+                Err(error) => return Err(error),
+            }
+        }
+
+        // Messages sent right before the peer closed the connection still count.
+        match self.take_frame()? {
+            None if closed => Err(io::ErrorKind::UnexpectedEof.into()),
+            message => Ok(message),
+        }
+    }
+
+    fn receive_blocking(&mut self) -> io::Result<Message> {
+        let mut chunk = [0; 4096];
+
+        loop {
+            if let Some(message) = self.take_frame()? {
+                return Ok(message);
+            }
+
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn take_frame(&mut self) -> io::Result<Option<Message>> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+
+        let mut len = [0; 4];
+        len.copy_from_slice(&self.buffer[..4]);
+        let len = u32::from_le_bytes(len) as usize;
+
+        if len > MAX_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message is too large"));
+        }
+
+        if self.buffer.len() < 4 + len {
+            return Ok(None);
+        }
+
+        let message = crate::codec::decode_from_slice(&self.buffer[4..4 + len])
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        self.buffer.drain(..4 + len);
+
+        Ok(Some(message))
+    }
+}
+
+impl Encode for Message {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Commands { player, tick, commands } => {
+                0u8.encode(out);
+                player.encode(out);
+                tick.encode(out);
+                commands.encode(out);
+            },
+
+            Message::Checksum { player, tick, checksum } => {
+                1u8.encode(out);
+                player.encode(out);
+                tick.encode(out);
+                checksum.encode(out);
+            },
+
+            Message::Welcome { player, player_count } => {
+                2u8.encode(out);
+                player.encode(out);
+                player_count.encode(out);
+            },
+        }
+    }
+}
+
+impl Decode for Message {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(match u8::decode(input)? {
+            0 => Message::Commands {
+                player: Decode::decode(input)?,
+                tick: Decode::decode(input)?,
+                commands: Decode::decode(input)?,
+            },
+
+            1 => Message::Checksum {
+                player: Decode::decode(input)?,
+                tick: Decode::decode(input)?,
+                checksum: Decode::decode(input)?,
+            },
+
+            2 => Message::Welcome {
+                player: Decode::decode(input)?,
+                player_count: Decode::decode(input)?,
+            },
+
+            tag => return Err(DecodeError::InvalidTag { tag, type_name: "Message" }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        factory::{FactoryEvent, Position},
+        testing::Fixture,
+        tile::Rotation,
+    };
+
+    /// Advance every session by one tick, looping until they all have.
+    fn advance_all<T: Transport>(sessions: &mut [(LockstepSession<T>, Factory)], prototypes: &Prototypes) -> Result<(), LockstepError> {
+        let mut advanced = vec![false; sessions.len()];
+
+        while advanced.iter().any(|advanced| !advanced) {
+            for ((session, factory), advanced) in sessions.iter_mut().zip(advanced.iter_mut()) {
+                if !*advanced {
+                    *advanced = session.advance(factory, prototypes)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn loopback_peers_stay_in_sync() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+
+        let mut sessions = LoopbackTransport::connect(2)
+        .into_iter()
+        .enumerate()
+        .map(|(player, transport)| (LockstepSession::new(transport, player as PlayerId, 2, 2), Factory::new()))
+        .collect::<Vec<_>>();
+
+        sessions[0].0.issue(Command::PlaceProducer { id: None, position: Position::from_f32(0.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.generate) });
+        sessions[1].0.issue(Command::PlaceProducer { id: None, position: Position::from_f32(200.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.consume) });
+
+        for _ in 0..5 {
+            advance_all(&mut sessions, &prototypes).unwrap();
+        }
+
+        // Player 0's commands are applied first, so they get the first id on both peers.
+        for (_, factory) in sessions.iter_mut() {
+            let events = factory.drain_events().collect::<Vec<_>>();
+            assert!(matches!(events[..], [FactoryEvent::ProducerPlaced(_), FactoryEvent::ProducerPlaced(_)]));
+        }
+
+        let ids = sessions[0].1.producers().map(|(id, _)| id).collect::<Vec<_>>();
+        sessions[1].0.issue(Command::Connect { id: None, giver: ids[0], taker: ids[1], kind: fixture.connector });
+
+        for _ in 0..100 {
+            advance_all(&mut sessions, &prototypes).unwrap();
+        }
+
+        assert_eq!(sessions[0].1.connectors().count(), 1);
+        assert_eq!(checksum(&sessions[0].1), checksum(&sessions[1].1));
+        assert_eq!(sessions[0].1.current_tick(), 105);
+
+        let outcomes = sessions[0].0.drain_outcomes().collect::<Vec<_>>();
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes, sessions[1].0.drain_outcomes().collect::<Vec<_>>());
+        assert!(outcomes.iter().all(|outcome| outcome.result.is_ok()));
+    }
+
+    #[test]
+    fn session_waits_for_other_players() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+
+        let mut transports = LoopbackTransport::connect(2);
+        let mut session = LockstepSession::new(transports.remove(0), 0, 2, 2);
+        let mut factory = Factory::new();
+
+        // The first ticks are free, but then the other player's commands are needed.
+        assert!(session.advance(&mut factory, &prototypes).unwrap());
+        assert!(session.advance(&mut factory, &prototypes).unwrap());
+        assert!(!session.advance(&mut factory, &prototypes).unwrap());
+        assert_eq!(factory.current_tick(), 2);
+    }
+
+    #[test]
+    fn desync_is_detected() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+
+        let mut sessions = LoopbackTransport::connect(2)
+        .into_iter()
+        .enumerate()
+        .map(|(player, transport)| (LockstepSession::new(transport, player as PlayerId, 2, 1), Factory::new()))
+        .collect::<Vec<_>>();
+
+        advance_all(&mut sessions, &prototypes).unwrap();
+
+        // Edit one peer's factory behind the session's back.
+        sessions[1].1.apply(&Command::PlaceProducer { id: None, position: Position::default(), rotation: Rotation::North, recipe: None }, &prototypes).unwrap();
+
+        let error = advance_all(&mut sessions, &prototypes).unwrap_err();
+        assert!(matches!(error, LockstepError::Desync { tick: 1, .. }));
+    }
+
+    #[test]
+    fn messages_for_other_players_are_ignored() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+
+        let mut transports = LoopbackTransport::connect(2);
+        let mut session = LockstepSession::new(transports.remove(0), 0, 2, 1);
+        let mut factory = Factory::new();
+        let mut peer = transports.remove(0);
+
+        // Made up players and the local player can't issue commands or checksums.
+        let place = Command::PlaceProducer { id: None, position: Position::default(), rotation: Rotation::North, recipe: None };
+        for player in [0, 2, 200] {
+            peer.send(&Message::Commands { player, tick: 1, commands: vec![place.clone()] }).unwrap();
+            peer.send(&Message::Checksum { player, tick: 0, checksum: 7 }).unwrap();
+        }
+
+        assert!(session.advance(&mut factory, &prototypes).unwrap());
+        assert!(!session.advance(&mut factory, &prototypes).unwrap());
+        assert!(session.inputs.values().all(|inputs| inputs.keys().all(|&player| player < 2)));
+        assert!(session.checksums.values().all(|checksums| checksums.keys().all(|&player| player < 2)));
+
+        peer.send(&Message::Commands { player: 1, tick: 1, commands: vec![] }).unwrap();
+        assert!(session.advance(&mut factory, &prototypes).unwrap());
+        assert_eq!(0, factory.producers().count());
+    }
+
+    #[test]
+    fn tcp_peers_stay_in_sync() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+
+        let host = TcpTransport::listen("127.0.0.1:0").unwrap();
+        let addr = host.local_addr().unwrap();
+        let joiner = std::thread::spawn(move || TcpTransport::join(addr).unwrap());
+        let host = host.accept(1).unwrap();
+        let joiner = joiner.join().unwrap();
+
+        assert_eq!(joiner.local_player(), 1);
+        assert_eq!(joiner.player_count(), 2);
+
+        let mut sessions = vec![
+            (LockstepSession::new(host, 0, 2, 2), Factory::new()),
+            (LockstepSession::new(joiner, 1, 2, 2), Factory::new()),
+        ];
+
+        sessions[1].0.issue(Command::PlaceProducer { id: None, position: Position::default(), rotation: Rotation::North, recipe: Some(fixture.generate) });
+
+        for _ in 0..20 {
+            advance_all(&mut sessions, &prototypes).unwrap();
+        }
+
+        assert_eq!(sessions[0].1.producers().count(), 1);
+        assert_eq!(checksum(&sessions[0].1), checksum(&sessions[1].1));
+    }
+
+    #[test]
+    fn messages_sent_before_closing_are_received() {
+        let host = TcpTransport::listen("127.0.0.1:0").unwrap();
+        let addr = host.local_addr().unwrap();
+        let joiner = std::thread::spawn(move || {
+            let mut joiner = TcpTransport::join(addr).unwrap();
+            for tick in 0..2 {
+                joiner.send(&Message::Checksum { player: 1, tick, checksum: 7 }).unwrap();
+            }
+        });
+        let mut host = host.accept(1).unwrap();
+        joiner.join().unwrap();
+
+        let mut received = vec![];
+        let error = loop {
+            match host.receive() {
+                Ok(Some(message)) => received.push(message),
+                Ok(None) => std::thread::yield_now(),
+                Err(error) => break error,
+            }
+        };
+
+        assert_eq!(vec![Message::Checksum { player: 1, tick: 0, checksum: 7 }, Message::Checksum { player: 1, tick: 1, checksum: 7 }], received);
+        assert_eq!(io::ErrorKind::UnexpectedEof, error.kind());
+    }
+
+    #[test]
+    fn host_drops_peers_that_misbehave() {
+        let host = TcpTransport::listen("127.0.0.1:0").unwrap();
+        let addr = host.local_addr().unwrap();
+        let joiners = std::thread::spawn(move || {
+            let mut spoofer = TcpTransport::join(addr).unwrap();
+            let mut hog = TcpStream::connect(addr).unwrap();
+            spoofer.send(&Message::Checksum { player: 2, tick: 0, checksum: 7 }).unwrap();
+
+            // Only the length of a huge frame, which the host mustn't wait for.
+            hog.write_all(&(MAX_FRAME as u32 + 1).to_le_bytes()).unwrap();
+            (spoofer, hog)
+        });
+        let mut host = host.accept(2).unwrap();
+        let _joiners = joiners.join().unwrap();
+
+        let mut errors = vec![];
+        while errors.len() < 2 {
+            match host.receive() {
+                Ok(None) => std::thread::yield_now(),
+                Ok(Some(message)) => panic!("received {:?}", message),
+                Err(error) => errors.push(error.to_string()),
+            }
+        }
+
+        errors.sort();
+        assert_eq!(vec!["message is too large", "peer sent a message for another player"], errors);
+        assert!(host.peers.is_empty());
+    }
+}
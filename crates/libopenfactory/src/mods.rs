@@ -0,0 +1,397 @@
+//! Mods: directories of scripts adding to and changing the game's content.
+//!
+//! A mod is a directory with a `mod.txt` manifest and any number of `.rhai`
+//! scripts, run in the order of their file names, see [`crate::scripting`].
+//! The manifest has one declaration per line, like scenarios:
+//!
+//! ```text
+//! # Lines starting with a hash are comments.
+//! id bronze-age
+//! version 1.2.0
+//! depends metals 1.0
+//! depends tools
+//! ```
+//!
+//! A dependency may give the oldest version it works with. Mods load after
+//! the mods they depend on, and otherwise in the order of their ids.
+//!
+//! Content of a mod is named `<mod id>:<name>`. The game's own content is
+//! that of the `base` mod, which every mod depends on without saying so, but
+//! keeps its name without `base:`. In its scripts, a name without a mod id
+//! is the mod's own content or, failing that, the game's. Content of other
+//! mods has to be named in full, and only of mods it depends on. Declaring
+//! content with the full name of another mod's content, the game's
+//! included, overrides it, keeping its handle. Two mods overriding the same
+//! content is a conflict, unless one of them depends on the other, and then
+//! it wins.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+};
+
+/// Separates the mod id from the name in the names of a mod's content.
+pub const SEPARATOR: char = ':';
+
+/// Id of the game's own content. Every mod depends on it.
+pub const BASE: &str = "base";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModError {
+    /// The mod's id, or its directory if its manifest couldn't be read.
+    pub id: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ModError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.id, self.message)
+    }
+}
+
+impl std::error::Error for ModError {}
+
+/// `major.minor.patch`, with missing parts being zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self { major, minor, patch }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.split('.').map(|part| part.parse::<u32>().ok());
+        let version = Self {
+            major: parts.next()??,
+            minor: parts.next().unwrap_or(Some(0))?,
+            patch: parts.next().unwrap_or(Some(0))?,
+        };
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(version),
+        }
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub id: String,
+
+    /// Oldest version that will do.
+    pub version: Option<Version>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModManifest {
+    pub id: String,
+    pub version: Version,
+    pub dependencies: Vec<Dependency>,
+}
+
+/// Mod ids are lowercase letters, digits and dashes, so they can't hold the separator.
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+impl ModManifest {
+    /// Parse a manifest. Errors are reported for `source_name`, e.g. the mod's directory.
+    pub fn parse(source_name: &str, source: &str) -> Result<Self, ModError> {
+        let error = |line: usize, message: String| ModError { id: source_name.to_string(), message: format!("line {}: {}", line, message) };
+
+        let mut id = None;
+        let mut version = None;
+        let mut dependencies: Vec<Dependency> = vec![];
+
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let mut words = text.split_whitespace();
+
+            let keyword = match words.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+
+            let value = words.next().ok_or_else(|| error(line, format!("expected a value after `{}`", keyword)))?;
+            let version_of = |word: &str| Version::parse(word).ok_or_else(|| error(line, format!("expected a version like 1.2.0, found `{}`", word)));
+
+            match keyword {
+                "id" if id.is_some() => return Err(error(line, "`id` is given twice".to_string())),
+                "id" if !valid_id(value) => return Err(error(line, format!("`{}` isn't lowercase letters, digits and dashes", value))),
+                "id" if value == BASE => return Err(error(line, format!("`{}` is the game's own content", BASE))),
+                "id" => id = Some(value.to_string()),
+                "version" if version.is_some() => return Err(error(line, "`version` is given twice".to_string())),
+                "version" => version = Some(version_of(value)?),
+
+                "depends" if value == BASE => return Err(error(line, format!("every mod depends on `{}` already", BASE))),
+                "depends" => {
+                    if dependencies.iter().any(|dependency| dependency.id == value) {
+                        return Err(error(line, format!("depends on `{}` twice", value)));
+                    }
+                    let version = words.next().map(version_of).transpose()?;
+                    dependencies.push(Dependency { id: value.to_string(), version });
+                },
+
+                other => return Err(error(line, format!("unknown declaration `{}`", other))),
+            }
+
+            if let Some(extra) = words.next() {
+                return Err(error(line, format!("unexpected `{}`", extra)));
+            }
+        }
+
+        let id = id.ok_or_else(|| ModError { id: source_name.to_string(), message: "manifest has no `id`".to_string() })?;
+        let version = version.ok_or_else(|| ModError { id: id.clone(), message: "manifest has no `version`".to_string() })?;
+
+        if dependencies.iter().any(|dependency| dependency.id == id) {
+            return Err(ModError { id, message: "depends on itself".to_string() });
+        }
+
+        Ok(Self { id, version, dependencies })
+    }
+}
+
+pub struct Mod {
+    pub manifest: ModManifest,
+
+    /// File names and sources, ordered by file name.
+    pub scripts: Vec<(String, String)>,
+}
+
+impl Mod {
+    /// Read a mod's manifest and scripts from its directory.
+    pub fn read(dir: &Path) -> Result<Self, ModError> {
+        let error = |message: String| ModError { id: dir.display().to_string(), message };
+
+        let manifest = std::fs::read_to_string(dir.join("mod.txt")).map_err(|io_error| error(format!("mod.txt: {}", io_error)))?;
+        let manifest = ModManifest::parse(&dir.display().to_string(), &manifest)?;
+
+        let mut paths = std::fs::read_dir(dir)
+        .map_err(|io_error| error(io_error.to_string()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "rhai"))
+        .collect::<Vec<_>>();
+        paths.sort();
+
+        let scripts = paths
+        .into_iter()
+        .map(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            std::fs::read_to_string(&path)
+            .map(|source| (name.clone(), source))
+            .map_err(|io_error| ModError { id: manifest.id.clone(), message: format!("{}: {}", name, io_error) })
+        })
+        .collect::<Result<_, _>>()?;
+
+        Ok(Self { manifest, scripts })
+    }
+}
+
+/// Read every mod in a directory, one per subdirectory.
+pub fn read_mods(dir: &Path) -> Result<Vec<Mod>, ModError> {
+    let mut dirs = std::fs::read_dir(dir)
+    .map_err(|io_error| ModError { id: dir.display().to_string(), message: io_error.to_string() })?
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.is_dir())
+    .collect::<Vec<_>>();
+    dirs.sort();
+
+    dirs.iter().map(|dir| Mod::read(dir)).collect()
+}
+
+/// Mods sorted so each loads after its dependencies.
+pub struct LoadOrder {
+    mods: Vec<Mod>,
+
+    /// Every mod each mod depends on, directly or not.
+    requires: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl LoadOrder {
+    /// Sort the mods, checking their dependencies are there and new enough.
+    pub fn new(mods: Vec<Mod>) -> Result<Self, ModError> {
+        let mut by_id: BTreeMap<String, Mod> = BTreeMap::new();
+        for loaded in mods {
+            let id = loaded.manifest.id.clone();
+            if by_id.insert(id.clone(), loaded).is_some() {
+                return Err(ModError { id, message: "is installed twice".to_string() });
+            }
+        }
+
+        for (id, loaded) in &by_id {
+            for dependency in &loaded.manifest.dependencies {
+                let error = |message: String| ModError { id: id.clone(), message };
+                let installed = by_id.get(&dependency.id).ok_or_else(|| error(format!("needs `{}`, which isn't installed", dependency.id)))?;
+
+                match dependency.version {
+                    Some(version) if installed.manifest.version < version => {
+                        return Err(error(format!("needs `{}` {} or newer, found {}", dependency.id, version, installed.manifest.version)));
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        // Take the first mod in id order whose dependencies have all loaded.
+        let mut mods = vec![];
+        let mut requires: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+        while !by_id.is_empty() {
+            let next = by_id
+            .iter()
+            .find(|(_, waiting)| waiting.manifest.dependencies.iter().all(|dependency| requires.contains_key(&dependency.id)))
+            .map(|(id, _)| id.clone());
+
+            let id = match next {
+                Some(id) => id,
+                None => {
+                    let cycle = cycle(&by_id, &requires);
+                    return Err(ModError { id: cycle[0].to_string(), message: format!("dependencies go in a circle between {}", cycle.join(", ")) });
+                },
+            };
+
+            let loaded = by_id.remove(&id).expect("id was just found");
+            let mut required = BTreeSet::new();
+            for dependency in &loaded.manifest.dependencies {
+                required.insert(dependency.id.clone());
+                required.extend(requires[&dependency.id].iter().cloned());
+            }
+
+            requires.insert(id, required);
+            mods.push(loaded);
+        }
+
+        Ok(Self { mods, requires })
+    }
+
+    /// The mods in the order they load.
+    pub fn mods(&self) -> &[Mod] {
+        &self.mods
+    }
+
+    /// Whether `id` depends on `other`, directly or not.
+    pub fn requires(&self, id: &str, other: &str) -> bool {
+        self.requires.get(id).is_some_and(|required| required.contains(other))
+    }
+}
+
+/// Mods whose dependencies go in a circle, when every mod left is waiting on another.
+///
+/// Waiting mods that only depend on the circle aren't part of it, so it is
+/// found by following dependencies that haven't loaded from the first mod.
+fn cycle<'a>(waiting: &'a BTreeMap<String, Mod>, loaded: &BTreeMap<String, BTreeSet<String>>) -> Vec<&'a str> {
+    let mut path: Vec<&str> = vec![];
+    let mut id = waiting.keys().next().expect("some mods are waiting").as_str();
+
+    while !path.contains(&id) {
+        path.push(id);
+        id = waiting[id].manifest.dependencies
+        .iter()
+        .find(|dependency| !loaded.contains_key(&dependency.id))
+        .map(|dependency| dependency.id.as_str())
+        .expect("waiting mods wait on a dependency");
+    }
+
+    let start = path.iter().position(|&on_path| on_path == id).expect("the path came back to it");
+    path.split_off(start)
+}
+
+/// The full name of a mod's content.
+pub fn qualified(id: &str, name: &str) -> String {
+    format!("{}{}{}", id, SEPARATOR, name)
+}
+
+/// The name content is kept under in its table, which for the game's own content is without `base:`.
+pub fn stored_name(name: &str) -> &str {
+    match split(name) {
+        (Some(BASE), rest) => rest,
+        _ => name,
+    }
+}
+
+/// Split a name into its mod id, if any, and the rest.
+pub fn split(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(SEPARATOR) {
+        Some((id, rest)) => (Some(id), rest),
+        None => (None, name),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn manifest(source: &str) -> ModManifest {
+        ModManifest::parse("test", source).unwrap()
+    }
+
+    fn installed(source: &str) -> Mod {
+        Mod { manifest: manifest(source), scripts: vec![] }
+    }
+
+    fn ids(order: &LoadOrder) -> Vec<&str> {
+        order.mods().iter().map(|loaded| loaded.manifest.id.as_str()).collect()
+    }
+
+    #[test]
+    fn manifests() {
+        let parsed = manifest("# Bronze\nid bronze-age\nversion 1.2\ndepends metals 1.0.3\ndepends tools\n");
+        assert_eq!("bronze-age", parsed.id);
+        assert_eq!(Version::new(1, 2, 0), parsed.version);
+        assert_eq!(vec![
+            Dependency { id: "metals".to_string(), version: Some(Version::new(1, 0, 3)) },
+            Dependency { id: "tools".to_string(), version: None },
+        ], parsed.dependencies);
+
+        let error = |source: &str| ModManifest::parse("dir", source).unwrap_err().to_string();
+        assert_eq!("dir: line 1: `Bronze:Age` isn't lowercase letters, digits and dashes", error("id Bronze:Age"));
+        assert_eq!("dir: line 2: expected a version like 1.2.0, found `1.x`", error("id a\nversion 1.x"));
+        assert_eq!("a: manifest has no `version`", error("id a"));
+        assert_eq!("a: depends on itself", error("id a\nversion 1\ndepends a"));
+        assert_eq!("dir: line 1: `base` is the game's own content", error("id base"));
+        assert_eq!("dir: line 2: `id` is given twice", error("id a\nid b\nversion 1"));
+        assert_eq!("dir: line 3: `version` is given twice", error("id a\nversion 1\nversion 2"));
+        assert_eq!("dir: line 3: every mod depends on `base` already", error("id a\nversion 1\ndepends base"));
+    }
+
+    #[test]
+    fn mods_load_after_their_dependencies() {
+        let order = LoadOrder::new(vec![
+            installed("id a\nversion 1\ndepends c\n"),
+            installed("id b\nversion 1.5\n"),
+            installed("id c\nversion 2\ndepends b 1.5\n"),
+            installed("id d\nversion 1\n"),
+        ]).unwrap();
+
+        assert_eq!(vec!["b", "c", "a", "d"], ids(&order));
+        assert!(order.requires("a", "b"));
+        assert!(!order.requires("b", "a"));
+        assert!(!order.requires("d", "b"));
+
+        let error = |mods: Vec<Mod>| LoadOrder::new(mods).err().unwrap().to_string();
+        assert_eq!("a: needs `b`, which isn't installed", error(vec![installed("id a\nversion 1\ndepends b")]));
+        assert_eq!("a: needs `b` 2.0.0 or newer, found 1.9.0", error(vec![installed("id a\nversion 1\ndepends b 2"), installed("id b\nversion 1.9")]));
+        assert_eq!("a: is installed twice", error(vec![installed("id a\nversion 1"), installed("id a\nversion 2")]));
+        assert_eq!("a: dependencies go in a circle between a, b", error(vec![installed("id a\nversion 1\ndepends b"), installed("id b\nversion 1\ndepends a"), installed("id c\nversion 1")]));
+
+        // Mods waiting on the circle aren't in it.
+        assert_eq!("c: dependencies go in a circle between c, e, d", error(vec![
+            installed("id a\nversion 1\ndepends c"),
+            installed("id b\nversion 1\ndepends a"),
+            installed("id c\nversion 1\ndepends e"),
+            installed("id d\nversion 1\ndepends c"),
+            installed("id e\nversion 1\ndepends d"),
+        ]));
+    }
+}
@@ -0,0 +1,42 @@
+//! Spreading independent work over a thread pool.
+//!
+//! Each call returns once all the work is done, so work can borrow from the
+//! caller. Results never depend on the number of threads.
+
+use rayon::{prelude::*, ThreadPool};
+
+/// Map the items on the pool's threads. Results keep the order of the items.
+pub(crate) fn map_in_order<T: Send, R: Send>(pool: &ThreadPool, items: Vec<T>, f: impl Fn(T) -> R + Sync + Send) -> Vec<R> {
+    pool.install(|| items.into_par_iter().map(f).collect())
+}
+
+/// Map the items on the pool's threads, heaviest first, each on its own so idle threads can take it.
+///
+/// Results come in no particular order.
+pub(crate) fn map_balanced<T: Send, R: Send>(pool: &ThreadPool, mut items: Vec<T>, weight: impl Fn(&T) -> usize, f: impl Fn(T) -> R + Sync + Send) -> Vec<R> {
+    items.sort_by_key(|item| std::cmp::Reverse(weight(item)));
+    pool.install(|| items.into_par_iter().with_max_len(1).map(f).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn keeps_order_and_visits_everything() {
+        for threads in 0..5 {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            assert_eq!((0..10).map(|n| n * n).collect::<Vec<_>>(), map_in_order(&pool, (0..10).collect(), |n| n * n));
+            assert!(map_in_order(&pool, Vec::<u32>::new(), |n| n).is_empty());
+        }
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(3).build().unwrap();
+        let sum = AtomicUsize::new(0);
+        let mut doubled = map_balanced(&pool, (1..=100).collect(), |&n| n, |n| { sum.fetch_add(n, Ordering::Relaxed); n * 2 });
+        assert_eq!(5050, sum.into_inner());
+
+        doubled.sort_unstable();
+        assert_eq!((1..=100).map(|n| n * 2).collect::<Vec<_>>(), doubled);
+    }
+}
@@ -1,20 +1,40 @@
-use crate::{item_stack::{InsertItemStackResult, ItemSlot, ItemStack, ItemStackQuanity}, kinds::{RecipeInput, RecipeKind, RecipeOutput}, registry::{Handle, Table}};
+use smallvec::SmallVec;
+
+use crate::{checksum::{Checksum, StateHasher}, codec::{Decode, DecodeError, Encode}, command::CommandError, factory::Prototypes, research::ResearchState, item_stack::{InsertItemStackResult, ItemSlot, ItemStack, ItemStackQuanity, Quality}, kinds::{ItemTag, RecipeInput, RecipeKind, RecipeOutput}, random::Rng, registry::{Handle, Table}};
 
 pub struct Producer {
     recipe: Option<Handle<RecipeKind>>,
     input_slots: Vec<ItemSlot>,
     pub output_slots: Vec<ItemSlot>,
     production: ProductionState,
+
+    /// Rolls for quality upgrades.
+    rng: Rng,
+
+    /// Speed relative to the recipe's time, 1000 being as fast as the recipe. Set by the factory every tick.
+    speed: u32,
+
+    /// Crafts that may still be started, or no limit. Set by the factory every tick, e.g. for miners.
+    craft_limit: Option<u32>,
 }
 
+#[allow(clippy::large_enum_variant)]
 enum ProductionState {
     /// Waiting on the required inputs.
     Idle,
 
     /// Currently producing the output.
     Producing {
-        progress: f32, 
+        /// Ticks spent producing so far.
+        elapsed: crate::Time,
         time: crate::Time,
+
+        /// Quality of the outputs.
+        quality: Quality,
+
+        /// Inputs used up by the craft, given back if the producer is removed before it finishes.
+        /// Kept inline, as crafts start all the time and recipes take few inputs.
+        consumed: SmallVec<[ItemStack; 4]>,
     },
 
     #[allow(dead_code)]
@@ -53,13 +73,17 @@ impl Producer {
             production: Default::default(),
             input_slots: vec![],
             output_slots: vec![],
+            rng: Rng::default(),
+            speed: 1000,
+            craft_limit: None,
         }
     }
 
-    pub fn new_with_recipe(recipe: &RecipeKind, handle: Handle<RecipeKind>) -> Self {
+    /// Fails if the recipe doesn't exist or isn't researched yet.
+    pub fn new_with_recipe(handle: Handle<RecipeKind>, prototypes: &Prototypes, research: &ResearchState) -> Result<Self, CommandError> {
         let mut producer = Self::new();
-        producer.set_recipe(recipe, handle);
-        producer
+        producer.set_recipe(handle, prototypes, research)?;
+        Ok(producer)
     }
 }
 
@@ -70,7 +94,11 @@ impl Default for Producer {
 }
 
 impl Producer {
-    pub fn set_recipe(&mut self, recipe: &RecipeKind, handle: Handle<RecipeKind>) {
+    /// Switch to the recipe, dropping the items in the producer. Fails, leaving
+    /// the producer as it was, if the recipe doesn't exist or isn't researched yet.
+    pub fn set_recipe(&mut self, handle: Handle<RecipeKind>, prototypes: &Prototypes, research: &ResearchState) -> Result<(), CommandError> {
+        let recipe = research.unlocked_recipe(handle, prototypes)?;
+
         self.input_slots = recipe.input_items
         .iter()
         .map(<ItemSlot as From<&RecipeInput>>::from)
@@ -82,29 +110,74 @@ impl Producer {
         .collect();
 
         self.recipe = Some(handle);
+        self.production = ProductionState::Idle;
+        Ok(())
     }
 
-    pub fn tick(&mut self, recipes: &Table<RecipeKind>) {
-        let mut reset_production = false;
+    /// Remove the recipe, along with the items in the producer.
+    pub fn clear_recipe(&mut self) {
+        self.input_slots = vec![];
+        self.output_slots = vec![];
+        self.recipe = None;
+        self.production = ProductionState::Idle;
+    }
 
-        if let ProductionState::Producing { progress, time } = &mut self.production {
-            let production_completion = {
-                const PROGRESS_DIFF: f32 = 1.0 / (24.0 * 3600.0 * 20.0);
+    /// Seed the rolls for quality upgrades. Placing a producer seeds it with its entity id.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+    }
 
-                *progress += 1.0 / *time as f32;
-                *progress >= 1.0 - PROGRESS_DIFF
-            };
+    pub(crate) fn set_speed(&mut self, speed: u32) {
+        self.speed = speed;
+    }
 
-            if production_completion {
-                Iterator::zip(
-                    recipes[self.recipe.unwrap()].output.iter(),
-                    &mut self.output_slots
-                )
-                .for_each(|(recipe_output, output_item_slot) | {
-                    let _ = output_item_slot.insert_item_stack(ItemStack::from_recipe_output(recipe_output));
-                });
+    pub(crate) fn set_craft_limit(&mut self, craft_limit: Option<u32>) {
+        self.craft_limit = craft_limit;
+    }
 
-                reset_production = true;
+    pub(crate) fn craft_limit(&self) -> Option<u32> {
+        self.craft_limit
+    }
+
+    /// Count ticks slept through while crafting, as if it had ticked. See [`crate::schedule`].
+    pub(crate) fn skip_ticks(&mut self, ticks: u64) {
+        if let ProductionState::Producing { elapsed, time, .. } = &mut self.production {
+            *elapsed = elapsed_after(*elapsed, *time, ticks);
+        }
+    }
+
+    /// Advance production. Returns whether a craft finished.
+    pub fn tick(&mut self, recipes: &Table<RecipeKind>) -> bool {
+        let mut reset_production = false;
+
+        if let ProductionState::Producing { elapsed, time, quality, .. } = &mut self.production {
+            if *elapsed < *time {
+                *elapsed += 1;
+            }
+
+            if *elapsed >= *time {
+                let outputs: Vec<ItemStack> = recipes[self.recipe.unwrap()].output
+                .iter()
+                .map(|recipe_output| {
+                    let mut stack = ItemStack::from_recipe_output(recipe_output);
+                    stack.data.quality = *quality;
+                    stack
+                })
+                .collect();
+
+                // Outputs of another quality have to be taken out first. Until then the producer is full.
+                let outputs_fit = Iterator::zip(self.output_slots.iter(), &outputs)
+                .all(|(slot, output)| slot.stack.as_ref().map(|held| held.stacks_with(output)).unwrap_or(true));
+
+                if outputs_fit {
+                    Iterator::zip(outputs.into_iter(), &mut self.output_slots)
+                    .for_each(|(output, output_item_slot)| {
+                        // Output slots are filtered to their recipe output.
+                        let _ = output_item_slot.insert_ignoring_filter(output);
+                    });
+
+                    reset_production = true;
+                }
             }
         }
 
@@ -112,13 +185,24 @@ impl Producer {
             self.production = ProductionState::Idle;
             self.attempt_to_start_production(recipes);
         }
+
+        reset_production
     }
 
-    pub fn try_insert_ingredient(&mut self, stack: ItemStack) -> InsertItemStackResult {
-        let ingredient_slot = self.input_slots.iter_mut().find(|slot| slot.filter == Some(stack.item));
+    /// Insert into the first input slot accepting the item that is empty or already holds it.
+    ///
+    /// Slots filtered by tag hold one kind of item at a time.
+    pub fn try_insert_ingredient(&mut self, stack: ItemStack, tags: &Table<ItemTag>) -> InsertItemStackResult {
+        let holds_other_item = |slot: &ItemSlot| slot.stack.as_ref().map(|held| !held.stacks_with(&stack)).unwrap_or(false);
+
+        // Without a free slot, the first accepting one reports why the stack didn't fit.
+        let index = self.input_slots
+        .iter()
+        .position(|slot| slot.accepts(stack.item, tags) && !holds_other_item(slot))
+        .or_else(|| self.input_slots.iter().position(|slot| slot.accepts(stack.item, tags)));
 
-        if let Some(ingredient_slot) = ingredient_slot {
-            ingredient_slot.insert_item_stack(stack)
+        if let Some(index) = index {
+            self.input_slots[index].insert_item_stack(stack, tags)
         } else {
             InsertItemStackResult::FilterError(stack)
         }
@@ -140,6 +224,23 @@ impl Producer {
         .expect("At least one non-empty item slot exists.")
     }
 
+    /// Take up to `max` items from the first output slot that has an item in it.
+    ///
+    /// If `like` is given, only output slots holding items that stack with it
+    /// are considered. This lets connectors top up a partially filled hand.
+    pub fn take_items(&mut self, like: Option<&ItemStack>, max: ItemStackQuanity) -> Option<ItemStack> {
+        self.output_slots
+        .iter_mut()
+        .filter(|slot| match (&slot.stack, like) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(stack), Some(like)) => like.stacks_with(stack),
+        })
+        .map(|slot| slot.take_items(max))
+        .next()
+        .flatten()
+    }
+
     pub fn attempt_to_start_production(&mut self, recipes: &Table<RecipeKind>) {
         if !self.can_start_production(recipes) {
             if let Some(recipe_handle) = self.recipe {
@@ -151,29 +252,100 @@ impl Producer {
         }
 
         let recipe = self.recipe.expect("Recipe must exist if can start production is true");
+        let rule = recipes[recipe].quality;
+
+        // Pick the quality before the inputs are used up.
+        let mut quality = if rule.from_inputs {
+            self.input_slots
+            .iter()
+            .filter_map(|slot| slot.stack.as_ref())
+            .map(|stack| stack.data.quality)
+            .min()
+            .unwrap_or_default()
+        } else {
+            Quality::Normal
+        };
+
+        if self.rng.roll(rule.upgrade_chance) {
+            quality = quality.upgraded();
+        }
 
         // Consume inputs.
-        Iterator::zip(
+        let consumed = Iterator::zip(
             self.input_slots.iter_mut(), 
             &recipes[recipe].input_items
         )
-        .for_each(|(item_slot, recipe_input)| {
-            item_slot.destroy_quantity(recipe_input.quantity);
-        });
+        .filter_map(|(item_slot, recipe_input)| {
+            match recipe_input.wear {
+                Some(wear) => {
+                    item_slot.wear(wear);
+                    None
+                },
+                None => item_slot.take_items(recipe_input.quantity),
+            }
+        })
+        .collect();
+
+        if let Some(limit) = &mut self.craft_limit {
+            *limit -= 1;
+        }
+
+        // Round up, so that bonuses never make a recipe instant.
+        let speed = self.speed.max(1);
+        let work = recipes[recipe].time as u32 * 1000;
+        let time = match work / speed {
+            time if time * speed < work => time + 1,
+            time => time,
+        };
 
         self.production = ProductionState::Producing {
-            progress: 0.0,
-            time: recipes[recipe].time,
+            elapsed: 0,
+            time: time.min(crate::Time::MAX as u32) as crate::Time,
+            quality,
+            consumed,
+        };
+    }
+
+    /// Empty the producer for deconstruction: the items in its slots and the inputs of an unfinished craft.
+    pub(crate) fn take_contents(&mut self) -> Vec<ItemStack> {
+        let consumed = match std::mem::take(&mut self.production) {
+            ProductionState::Producing { consumed, .. } => consumed,
+            _ => SmallVec::new(),
         };
+
+        self.input_slots
+        .iter_mut()
+        .chain(&mut self.output_slots)
+        .filter_map(|slot| slot.stack.take())
+        .chain(consumed)
+        .collect()
     }
 }
 
 /// Queries
 impl Producer {
+    pub fn recipe(&self) -> Option<Handle<RecipeKind>> {
+        self.recipe
+    }
+
     pub fn is_producing(&self) -> bool {
         matches!(self.production, ProductionState::Producing{ .. })
     }
 
+    /// The items in the producer: those in its slots, and the inputs used up by the craft in progress.
+    pub fn contents(&self) -> impl Iterator<Item = &ItemStack> {
+        let consumed = match &self.production {
+            ProductionState::Producing { consumed, .. } => consumed.as_slice(),
+            _ => &[],
+        };
+
+        self.input_slots
+        .iter()
+        .chain(&self.output_slots)
+        .filter_map(|slot| slot.stack.as_ref())
+        .chain(consumed)
+    }
+
     pub fn has_output(&self) -> bool {
         self.output_slots.iter().any(|slot| {
             !slot.is_empty()
@@ -197,8 +369,20 @@ impl Producer {
         !self.output_slots.is_empty()
     }
 
+    /// Ticks until the craft in progress finishes, if one is.
+    pub(crate) fn ticks_to_finish(&self) -> Option<crate::Time> {
+        match self.production {
+            ProductionState::Producing { elapsed, time, .. } if elapsed < time => Some(time - elapsed),
+            _ => None,
+        }
+    }
+
     pub fn status(&self) -> ProductionStatus {
-        (&self.production).into()
+        match self.production {
+            // Done, but waiting for outputs of another quality to be taken out.
+            ProductionState::Producing { elapsed, time, .. } if elapsed >= time => ProductionStatus::Full,
+            ref production => production.into(),
+        }
     }
 
     /// Returns if the output of the recipe would not fit in the
@@ -234,6 +418,10 @@ impl Producer {
             return false;
         }
 
+        if self.craft_limit == Some(0) {
+            return false;
+        }
+
         // Production must have enough ingredients to start.
         if
             Iterator::zip(
@@ -255,10 +443,100 @@ impl Producer {
     }
 }
 
+fn elapsed_after(elapsed: crate::Time, time: crate::Time, ticks: u64) -> crate::Time {
+    (elapsed as u64 + ticks).min(time as u64) as crate::Time
+}
+
+/// Snapshots of producers asleep in the factory, see [`Producer::skip_ticks`].
+impl Producer {
+    /// Checksum as if it had skipped the ticks.
+    pub(crate) fn checksum_ahead(&self, ticks: u64, hasher: &mut StateHasher) {
+        self.recipe.checksum(hasher);
+        self.input_slots.checksum(hasher);
+        self.output_slots.checksum(hasher);
+
+        match &self.production {
+            ProductionState::Idle => hasher.write_u8(0),
+            ProductionState::Producing { elapsed, time, quality, consumed } => {
+                hasher.write_u8(1);
+                hasher.write_u16(elapsed_after(*elapsed, *time, ticks));
+                hasher.write_u16(*time);
+                quality.checksum(hasher);
+                consumed.checksum(hasher);
+            },
+            ProductionState::Full => hasher.write_u8(2),
+        }
+
+        self.rng.checksum(hasher);
+    }
+
+    /// Encode as if it had skipped the ticks.
+    pub(crate) fn encode_ahead(&self, ticks: u64, out: &mut Vec<u8>) {
+        self.recipe.encode(out);
+        self.input_slots.encode(out);
+        self.output_slots.encode(out);
+
+        match &self.production {
+            ProductionState::Idle => 0u8.encode(out),
+            ProductionState::Producing { elapsed, time, quality, consumed } => {
+                1u8.encode(out);
+                elapsed_after(*elapsed, *time, ticks).encode(out);
+                time.encode(out);
+                quality.encode(out);
+                consumed.encode(out);
+            },
+            ProductionState::Full => 2u8.encode(out),
+        }
+
+        self.rng.encode(out);
+    }
+}
+
+impl Checksum for Producer {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        self.checksum_ahead(0, hasher);
+    }
+}
+
+impl Encode for Producer {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.encode_ahead(0, out);
+    }
+}
+
+impl Decode for Producer {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let recipe = Option::decode(input)?;
+        let input_slots = Vec::decode(input)?;
+        let output_slots = Vec::decode(input)?;
+
+        let production = match u8::decode(input)? {
+            0 => ProductionState::Idle,
+            1 => ProductionState::Producing {
+                elapsed: crate::Time::decode(input)?,
+                time: crate::Time::decode(input)?,
+                quality: Quality::decode(input)?,
+                consumed: SmallVec::from_vec(Vec::decode(input)?),
+            },
+            2 => ProductionState::Full,
+            tag => return Err(DecodeError::InvalidTag { tag, type_name: "ProductionState" }),
+        };
+
+        let rng = Rng::decode(input)?;
+
+        Ok(Producer { recipe, input_slots, output_slots, production, rng, speed: 1000, craft_limit: None })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{kinds::{ItemKind, ItemKindBuilder}, local_string::LocalString};
+    use crate::{item_stack::ItemData, kinds::{ItemKind, ItemKindBuilder, QualityRule}, local_string::LocalString};
+
+    fn with_recipe(recipes: &Table<RecipeKind>, handle: Handle<RecipeKind>) -> Producer {
+        let prototypes = Prototypes { recipes, connectors: &Table::new(), tags: &Table::new(), technologies: &Table::new() };
+        Producer::new_with_recipe(handle, &prototypes, &ResearchState::new()).unwrap()
+    }
 
     fn make_item(items: &mut Table<ItemKind>, name: &str) -> Handle<ItemKind> {
         items
@@ -281,20 +559,18 @@ mod tests {
 
         let make_iron_pipe = recipes.insert(RecipeKind {
             name: LocalString::from_str("iron-pipe"),
-            input_items: vec![RecipeInput { item: iron_plate, quantity: 1 }],
-            output: vec![RecipeOutput { item: iron_pipe, quantity: 1 }],
+            input_items: vec![RecipeInput { item: iron_plate.into(), quantity: 1, wear: None }],
+            output: vec![RecipeOutput { item: iron_pipe, quantity: 1, data: ItemData::default() }],
             time: 20,
+            quality: QualityRule::default(),
+            mining: false,
         }, "iron-pipe".to_string());
 
-        let mut producer = Producer::new();
-        producer.set_recipe(&recipes[make_iron_pipe.clone()], make_iron_pipe);
+        let mut producer = with_recipe(&recipes, make_iron_pipe);
 
-        let iron_plate_stack = ItemStack {
-            item: iron_plate,
-            quantity: 1,
-        };
+        let iron_plate_stack = ItemStack::new(iron_plate, 1);
 
-        let insert_result = producer.try_insert_ingredient(iron_plate_stack);
+        let insert_result = producer.try_insert_ingredient(iron_plate_stack, &Table::new());
         assert!(matches!(insert_result, InsertItemStackResult::StackConsumed));
 
         producer.attempt_to_start_production(&recipes);
@@ -312,12 +588,9 @@ mod tests {
 
         assert_eq!(ProductionStatus::Idle, producer.status());
 
-        let iron_plate_stack = ItemStack {
-            item: iron_plate,
-            quantity: 1,
-        };
+        let iron_plate_stack = ItemStack::new(iron_plate, 1);
 
-        let insert_result = producer.try_insert_ingredient(iron_plate_stack);
+        let insert_result = producer.try_insert_ingredient(iron_plate_stack, &Table::new());
         assert!(matches!(insert_result, InsertItemStackResult::StackConsumed));
 
         producer.attempt_to_start_production(&recipes);
@@ -331,6 +604,170 @@ mod tests {
         assert_eq!(ProductionStatus::Full, producer.status());
     }
 
+    #[test]
+    fn tagged_input_accepts_any_item_with_the_tag() {
+        use crate::kinds::ItemTagBuilder;
+
+        let mut items = Table::new();
+        let coal = make_item(&mut items, "coal");
+        let wood = make_item(&mut items, "wood");
+        let stone = make_item(&mut items, "stone");
+
+        let mut tags = Table::new();
+        let fuel = tags.insert(
+            ItemTagBuilder::new()
+            .with_name(LocalString::from_str("tag-fuel"))
+            .with_item(coal)
+            .with_item(wood)
+            .build(),
+
+            "fuel".to_string(),
+        );
+
+        let mut recipes = Table::new();
+        let burn = recipes.insert(RecipeKind {
+            name: LocalString::from_str("burn"),
+            input_items: vec![RecipeInput { item: fuel.into(), quantity: 1, wear: None }],
+            output: vec![],
+            time: 5,
+            quality: QualityRule::default(),
+            mining: false,
+        }, "burn".to_string());
+
+        let mut producer = with_recipe(&recipes, burn);
+
+        let result = producer.try_insert_ingredient(ItemStack::new(stone, 1), &tags);
+        assert!(matches!(result, InsertItemStackResult::FilterError(_)));
+
+        let result = producer.try_insert_ingredient(ItemStack::new(wood, 1), &tags);
+        assert!(matches!(result, InsertItemStackResult::StackConsumed));
+
+        let result = producer.try_insert_ingredient(ItemStack::new(coal, 1), &tags);
+        assert!(matches!(result, InsertItemStackResult::ItemSlotTaken(_)));
+
+        producer.attempt_to_start_production(&recipes);
+        assert!(producer.is_producing());
+
+        for _ in 0..5 {
+            producer.tick(&recipes);
+        }
+
+        let result = producer.try_insert_ingredient(ItemStack::new(coal, 1), &tags);
+        assert!(matches!(result, InsertItemStackResult::StackConsumed));
+    }
+
+    #[test]
+    fn recipes_can_wear_tools_instead_of_consuming_them() {
+        let mut items = Table::new();
+        let saw = make_item(&mut items, "saw");
+        let log = make_item(&mut items, "log");
+        let plank = make_item(&mut items, "plank");
+
+        let mut recipes = Table::new();
+        let cut = recipes.insert(RecipeKind {
+            name: LocalString::from_str("plank"),
+            input_items: vec![
+                RecipeInput { item: saw.into(), quantity: 1, wear: Some(3) },
+                RecipeInput { item: log.into(), quantity: 1, wear: None },
+            ],
+            output: vec![RecipeOutput { item: plank, quantity: 1, data: ItemData::default() }],
+            time: 1,
+            quality: QualityRule::default(),
+            mining: false,
+        }, "plank".to_string());
+
+        let mut producer = with_recipe(&recipes, cut);
+        let _ = producer.try_insert_ingredient(ItemStack::new(saw, 1).with_data(ItemData::default().with_durability(5)), &Table::new());
+        let _ = producer.try_insert_ingredient(ItemStack::new(log, 2), &Table::new());
+
+        producer.attempt_to_start_production(&recipes);
+        assert!(producer.is_producing());
+        assert_eq!(1, producer.input_slots[0].quantity());
+        assert_eq!(1, producer.input_slots[1].quantity());
+
+        producer.tick(&recipes);
+
+        // The second use wears out the saw.
+        assert!(producer.is_producing());
+        assert!(producer.input_slots[0].is_empty());
+        assert!(producer.input_slots[1].is_empty());
+    }
+
+    #[test]
+    fn quality_flows_from_inputs_to_outputs() {
+        use crate::item_stack::Quality;
+
+        let mut items = Table::new();
+        let ore = make_item(&mut items, "ore");
+        let plate = make_item(&mut items, "plate");
+
+        let mut recipes = Table::new();
+        let smelt = recipes.insert(RecipeKind {
+            name: LocalString::from_str("plate"),
+            input_items: vec![RecipeInput { item: ore.into(), quantity: 1, wear: None }],
+            output: vec![RecipeOutput { item: plate, quantity: 1, data: ItemData::default() }],
+            time: 1,
+            quality: QualityRule { from_inputs: true, upgrade_chance: 0 },
+            mining: false,
+        }, "plate".to_string());
+
+        let mut producer = with_recipe(&recipes, smelt);
+        let _ = producer.try_insert_ingredient(ItemStack::new(ore, 1).with_data(ItemData::default().with_quality(Quality::Good)), &Table::new());
+        producer.attempt_to_start_production(&recipes);
+        producer.tick(&recipes);
+
+        let output = producer.output_slots[0].stack.as_ref().unwrap();
+        assert_eq!(Quality::Good, output.data.quality);
+
+        // A normal plate can't join the good one, so the producer waits until it's taken out.
+        let _ = producer.try_insert_ingredient(ItemStack::new(ore, 1), &Table::new());
+        producer.attempt_to_start_production(&recipes);
+        producer.tick(&recipes);
+        producer.tick(&recipes);
+        assert_eq!(ProductionStatus::Full, producer.status());
+
+        let taken = producer.take_single_item();
+        assert_eq!(Quality::Good, taken.data.quality);
+
+        producer.tick(&recipes);
+        assert_eq!(Quality::Normal, producer.output_slots[0].stack.as_ref().unwrap().data.quality);
+    }
+
+    #[test]
+    fn upgrades_are_rolled_deterministically() {
+        use crate::item_stack::Quality;
+
+        let mut items = Table::new();
+        let gem = make_item(&mut items, "gem");
+
+        let mut recipes = Table::new();
+        let dig = recipes.insert(RecipeKind {
+            name: LocalString::from_str("gem"),
+            input_items: vec![],
+            output: vec![RecipeOutput { item: gem, quantity: 1, data: ItemData::default() }],
+            time: 1,
+            quality: QualityRule { from_inputs: false, upgrade_chance: 500 },
+            mining: false,
+        }, "gem".to_string());
+
+        let qualities = |seed| {
+            let mut producer = with_recipe(&recipes, dig);
+            producer.seed(seed);
+
+            (0..32)
+            .map(|_| {
+                producer.attempt_to_start_production(&recipes);
+                producer.tick(&recipes);
+                producer.take_single_item().data.quality
+            })
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(qualities(1), qualities(1));
+        assert!(qualities(1).contains(&Quality::Good));
+        assert!(qualities(1).contains(&Quality::Normal));
+    }
+
     #[test]
     fn producer_without_inputs_does_not_take_input() {
         let mut items = Table::new();
@@ -341,17 +778,52 @@ mod tests {
         let generate_test_item = recipes.insert(RecipeKind {
             name: LocalString::from_str("generate-test-item"),
             input_items: vec![],
-            output: vec![RecipeOutput { item: test_item, quantity: 1}],
+            output: vec![RecipeOutput { item: test_item, quantity: 1, data: ItemData::default() }],
             time: 20,
+            quality: QualityRule::default(),
+            mining: false,
         }, "generate-test-item".to_string());
 
-        let producer = Producer::new_with_recipe(&recipes[generate_test_item], generate_test_item);
+        let producer = with_recipe(&recipes, generate_test_item);
         assert!(!producer.takes_input());
     }
 
     #[test]
-    #[ignore] // was trying to test why connectors aren't working after placing a second one,
-    // and thought it was in the item slots. But it's not.
+    fn identical_producers_have_identical_checksums() {
+        use crate::checksum::checksum;
+
+        let mut items = Table::new();
+        let test_item = make_item(&mut items, "test-item");
+
+        let mut recipes = Table::new();
+
+        let generate_test_item = recipes.insert(RecipeKind {
+            name: LocalString::from_str("generate-test-item"),
+            input_items: vec![],
+            output: vec![RecipeOutput { item: test_item, quantity: 1, data: ItemData::default() }],
+            time: 7,
+            quality: QualityRule::default(),
+            mining: false,
+        }, "generate-test-item".to_string());
+
+        let mut a = with_recipe(&recipes, generate_test_item);
+        let mut b = with_recipe(&recipes, generate_test_item);
+
+        for _ in 0..10 {
+            a.attempt_to_start_production(&recipes);
+            a.tick(&recipes);
+            b.attempt_to_start_production(&recipes);
+            b.tick(&recipes);
+        }
+
+        assert_eq!(checksum(&a), checksum(&b));
+        assert_eq!(1, a.output_slots[0].quantity());
+
+        a.tick(&recipes);
+        assert_ne!(checksum(&a), checksum(&b));
+    }
+
+    #[test]
     fn producer_with_multiple_inputs() {
         let mut items = Table::new();
         let item_1 = make_item(&mut items, "1");
@@ -363,40 +835,53 @@ mod tests {
             name: LocalString::from_str("out"),
             input_items: vec![
                 RecipeInput {
-                    item: item_1,
-                    quantity: 1
+                    item: item_1.into(),
+                    quantity: 1,
+                    wear: None
                 },
                 RecipeInput {
-                    item: item_2,
-                    quantity: 2
+                    item: item_2.into(),
+                    quantity: 2,
+                    wear: None
                 },
             ],
             output: vec![RecipeOutput {
                 item: item_out,
-                quantity: 3
+                quantity: 3,
+                data: ItemData::default()
             }],
 
             time: 20,
+
+            quality: QualityRule::default(),
+
+            mining: false,
         }, "out".into());
 
-        let mut producer = Producer::new_with_recipe(&recipes[out_recipe], out_recipe);
+        let mut producer = with_recipe(&recipes, out_recipe);
+
+        let _ = producer.try_insert_ingredient(ItemStack::new(item_1, 1), &Table::new());
 
-        let _ = producer.try_insert_ingredient(ItemStack {
-            item: item_1,
-            quantity: 1,
-        });
+        let _ = producer.try_insert_ingredient(ItemStack::new(item_2, 1), &Table::new());
 
-        let _ = producer.try_insert_ingredient(ItemStack {
-            item: item_2,
-            quantity: 1,
-        });
+        // Each input goes to its own slot, which holds two crafts' worth.
+        let res = producer.try_insert_ingredient(ItemStack::new(item_1, 1), &Table::new());
+        assert!(matches!(res, InsertItemStackResult::StackConsumed));
 
-        let res = producer.try_insert_ingredient(ItemStack {
-            item: item_1,
-            quantity: 1,
-        });
+        let res = producer.try_insert_ingredient(ItemStack::new(item_1, 1), &Table::new());
+        assert!(matches!(res, InsertItemStackResult::ItemSlotFull(_)));
+
+        // Not enough of the second input yet.
+        producer.attempt_to_start_production(&recipes);
+        assert_eq!(ProductionStatus::Idle, producer.status());
+
+        let _ = producer.try_insert_ingredient(ItemStack::new(item_2, 1), &Table::new());
+        producer.attempt_to_start_production(&recipes);
+        assert_eq!(ProductionStatus::Producing, producer.status());
+        assert_eq!((vec![(1, 2), (0, 4)], vec![(0, 6)]), producer.item_counts());
 
-        println!("{:?}", res);
-        panic!();
+        assert!(!(0..19).any(|_| producer.tick(&recipes)));
+        assert!(producer.tick(&recipes));
+        assert_eq!((vec![(1, 2), (0, 4)], vec![(3, 6)]), producer.item_counts());
     }
 }
\ No newline at end of file
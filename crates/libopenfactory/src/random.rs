@@ -0,0 +1,82 @@
+//! Random numbers for the simulation.
+//!
+//! Every peer must roll the same numbers, so the generator is part of the
+//! simulation state: it is seeded deterministically, encoded with the rest of
+//! the factory and included in checksums.
+
+use crate::{checksum::{Checksum, StateHasher}, codec::{Decode, DecodeError, Encode}};
+
+/// A chance out of 1000.
+pub type PerMille = u16;
+
+/// A SplitMix64 generator.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Whether an event with the given chance happens.
+    pub fn roll(&mut self, chance: PerMille) -> bool {
+        chance > 0 && self.next_u64() % 1000 < chance as u64
+    }
+}
+
+impl Checksum for Rng {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.0);
+    }
+}
+
+impl Encode for Rng {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
+impl Decode for Rng {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Rng(u64::decode(input)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let mut c = Rng::new(8);
+
+        let a: Vec<_> = (0..4).map(|_| a.next_u64()).collect();
+        let b: Vec<_> = (0..4).map(|_| b.next_u64()).collect();
+        let c: Vec<_> = (0..4).map(|_| c.next_u64()).collect();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn roll_follows_the_chance() {
+        let mut rng = Rng::new(1);
+
+        assert!((0..100).all(|_| !rng.roll(0)));
+        assert!((0..100).all(|_| rng.roll(1000)));
+
+        let hits = (0..10_000).filter(|_| rng.roll(250)).count();
+        assert!((2_200..2_800).contains(&hits));
+    }
+}
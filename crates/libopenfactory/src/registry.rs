@@ -1,8 +1,14 @@
 use std::{collections::HashMap, marker::PhantomData, ops::Index};
 
-#[derive(Debug)]
 pub struct Handle<T>(usize, PhantomData<T>);
 
+// Cannot derive because derive requires that `T` be Debug as well.
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Handle").field(&self.0).finish()
+    }
+}
+
 // Cannot derive because derive requires that `T` be Clone as well.
 impl<T> Clone for Handle<T> {
     fn clone(&self) -> Self {
@@ -22,6 +28,18 @@ impl<T> Eq for Handle<T> {
     fn assert_receiver_is_total_eq(&self) {}
 }
 
+impl<T> PartialOrd for Handle<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Handle<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
 impl<T> ::std::hash::Hash for Handle<T> {
     fn hash<H: ::std::hash::Hasher>(&self, state: &mut H) {
         self.0.hash(state);
@@ -29,9 +47,16 @@ impl<T> ::std::hash::Hash for Handle<T> {
 }
 
 impl<T> Handle<T> {
-    fn new(ix: usize) -> Self {
+    pub(crate) fn new(ix: usize) -> Self {
         Self(ix, PhantomData)
     }
+
+    /// Position of the value in its table.
+    ///
+    /// Stable for as long as the table is built in the same order.
+    pub(crate) fn index(&self) -> usize {
+        self.0
+    }
 }
 
 impl<T> Index<Handle<T>> for Vec<T> {
@@ -50,6 +75,18 @@ impl<T> Index<&'_ Handle<T>> for Vec<T> {
     }
 }
 
+/// A name given to [`Table::try_insert`] is in the table already.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTaken(pub String);
+
+impl std::fmt::Display for NameTaken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` is in the table already", self.0)
+    }
+}
+
+impl std::error::Error for NameTaken {}
+
 pub struct Table<T> {
     list: Vec<T>,
     table: HashMap<String, Handle<T>>,
@@ -65,12 +102,52 @@ impl<T> Table<T> {
         }
     }
 
+    /// Insert under a name fixed in code. Panics if the name is taken;
+    /// names read from mods or files go through [`Table::try_insert`].
     pub fn insert(&mut self, item: T, name: String) -> Handle<T> {
+        match self.try_insert(item, name) {
+            Ok(handle) => handle,
+            Err(NameTaken(name)) => panic!("`{}` is in the table already", name),
+        }
+    }
+
+    /// Insert under a name that may be taken, e.g. one read from a mod.
+    pub fn try_insert(&mut self, item: T, name: String) -> Result<Handle<T>, NameTaken> {
+        if self.table.contains_key(&name) {
+            return Err(NameTaken(name));
+        }
+
         self.list.push(item);
         let handle = Handle::new(self.list.len() - 1);
         self.table.insert(name.clone(), handle.clone());
         self.inverse_table.insert(handle.clone(), name);
-        handle
+        Ok(handle)
+    }
+
+    /// Put a value in place of another, keeping its handle and name.
+    pub fn replace(&mut self, handle: Handle<T>, item: T) -> T {
+        std::mem::replace(&mut self.list[handle.0], item)
+    }
+
+    /// Look up a handle that may not be from this table, e.g. one decoded from a peer.
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.list.get(handle.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Every value with its handle, in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &T)> {
+        self.list
+        .iter()
+        .enumerate()
+        .map(|(index, value)| (Handle::new(index), value))
     }
 
     pub fn name(&self, handle: &Handle<T>) -> &str {
@@ -81,6 +158,11 @@ impl<T> Table<T> {
         self.table[name]
     }
 
+    /// Look up a name that may not be in this table, e.g. one read from a blueprint.
+    pub fn find_handle_from_name(&self, name: &str) -> Option<Handle<T>> {
+        self.table.get(name).copied()
+    }
+
     pub fn get_ref_and_handle_from_name(&self, name: &str) -> (&T, Handle<T>) {
         let handle = self.table[name];
         let value = &self.list[&handle];
@@ -0,0 +1,470 @@
+//! Recording and playing back sessions.
+//!
+//! A replay is a snapshot of the factory when recording started, followed by
+//! every command applied to it, stamped with the tick it was applied before.
+//! The simulation is deterministic, so applying the same commands to the same
+//! snapshot reproduces the session. Checksums recorded along the way tell
+//! whether it actually did.
+//!
+//! [`ReplayWriter`] appends records as the session runs, so the replay of a
+//! session that crashed can still be played up to the crash.
+
+use std::io::{self, Write};
+
+use crate::{
+    checksum::checksum,
+    codec::{decode_from_slice, encode_to_vec, Decode, DecodeError, Encode},
+    command::Command,
+    factory::{Factory, Prototypes},
+    lockstep::PlayerId,
+};
+
+const MAGIC: [u8; 4] = *b"OFRP";
+/// Bumped when the format changes after a release.
+const VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    /// A command applied before simulating `tick`, whether or not it was accepted.
+    Command {
+        tick: u64,
+        player: PlayerId,
+        command: Command,
+    },
+
+    /// Checksum of the factory after simulating `tick`.
+    Checksum {
+        tick: u64,
+        checksum: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    /// The encoded factory at the start of the recording.
+    snapshot: Vec<u8>,
+    start_tick: u64,
+    records: Vec<Record>,
+}
+
+impl Replay {
+    /// A replay starting from the current state of `factory`, without records yet.
+    pub fn new(factory: &Factory) -> Self {
+        Self {
+            snapshot: encode_to_vec(factory),
+            start_tick: factory.current_tick(),
+            records: vec![],
+        }
+    }
+
+    /// Records must be pushed in the order they happened.
+    pub fn push(&mut self, record: Record) {
+        self.records.push(record);
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    pub fn start_tick(&self) -> u64 {
+        self.start_tick
+    }
+
+    /// The tick after the last one anything was recorded for.
+    pub fn end_tick(&self) -> u64 {
+        self.records
+        .iter()
+        .map(|record| match *record {
+            Record::Command { tick, .. } => tick,
+            Record::Checksum { tick, .. } => tick + 1,
+        })
+        .max()
+        .unwrap_or(self.start_tick)
+    }
+
+    /// The factory at the start of the recording.
+    pub fn initial_factory(&self) -> Factory {
+        decode_from_slice(&self.snapshot).expect("snapshot was validated when the replay was made")
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![];
+        encode_header(&self.snapshot, &mut out);
+        for record in self.records.iter() {
+            record.encode(&mut out);
+        }
+        out
+    }
+
+    /// Read a replay written by [`Replay::to_bytes`] or a [`ReplayWriter`].
+    ///
+    /// A record cut off at the end, e.g. because the game crashed while writing it, is dropped.
+    pub fn from_bytes(mut input: &[u8]) -> Result<Self, DecodeError> {
+        let input = &mut input;
+
+        if input.len() < MAGIC.len() || input[..MAGIC.len()] != MAGIC {
+            return Err(DecodeError::Invalid("not a replay"));
+        }
+        *input = &input[MAGIC.len()..];
+
+        if u16::decode(input)? != VERSION {
+            return Err(DecodeError::Invalid("unsupported replay version"));
+        }
+
+        let snapshot = Vec::<u8>::decode(input)?;
+        let start_tick = decode_from_slice::<Factory>(&snapshot)?.current_tick();
+
+        let mut records = vec![];
+        while !input.is_empty() {
+            match Record::decode(input) {
+                Ok(record) => records.push(record),
+                Err(DecodeError::UnexpectedEnd) => break,
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(Self { snapshot, start_tick, records })
+    }
+}
+
+fn encode_header(snapshot: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&MAGIC);
+    VERSION.encode(out);
+    snapshot.encode(out);
+}
+
+/// Writes a replay as the session runs.
+pub struct ReplayWriter<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ReplayWriter<W> {
+    /// Start a replay from the current state of `factory`.
+    pub fn new(mut writer: W, factory: &Factory) -> io::Result<Self> {
+        let mut buffer = vec![];
+        encode_header(&encode_to_vec(factory), &mut buffer);
+        writer.write_all(&buffer)?;
+        buffer.clear();
+
+        Ok(Self { writer, buffer })
+    }
+
+    pub fn write(&mut self, record: &Record) -> io::Result<()> {
+        self.buffer.clear();
+        record.encode(&mut self.buffer);
+        self.writer.write_all(&self.buffer)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The factory doesn't match what was recorded after `tick`.
+    ///
+    /// Usually the prototypes or the simulation changed since recording.
+    Desync {
+        tick: u64,
+        recorded: u64,
+        actual: u64,
+    },
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Desync { tick, recorded, actual } => {
+                write!(f, "replay desynced after tick {}: recorded checksum {:x}, got {:x}", tick, recorded, actual)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+struct Keyframe {
+    tick: u64,
+    next_record: usize,
+    snapshot: Vec<u8>,
+}
+
+/// Plays a replay back into a factory.
+///
+/// Nothing else may edit the factory while it is being played into.
+/// Pausing and speed are up to the caller, which decides when to [`step`](Self::step).
+pub struct ReplayPlayer {
+    replay: Replay,
+    next_record: usize,
+
+    /// Snapshots taken while playing, so seeking backwards doesn't have to start over.
+    keyframes: Vec<Keyframe>,
+}
+
+impl ReplayPlayer {
+    /// Ticks between keyframes.
+    pub const KEYFRAME_INTERVAL: u64 = 600;
+
+    /// Loads the start of the replay into `factory`.
+    pub fn new(replay: Replay, factory: &mut Factory) -> Self {
+        factory.load(replay.initial_factory());
+
+        let keyframes = vec![Keyframe { tick: replay.start_tick, next_record: 0, snapshot: replay.snapshot.clone() }];
+
+        Self { replay, next_record: 0, keyframes }
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    /// Apply the recorded commands for the factory's current tick and simulate it.
+    ///
+    /// Returns whether a tick was simulated, which it isn't at the end of the replay.
+    pub fn step(&mut self, factory: &mut Factory, prototypes: &Prototypes) -> Result<bool, ReplayError> {
+        let tick = factory.current_tick();
+
+        if tick >= self.replay.end_tick() {
+            return Ok(false);
+        }
+
+        let keyframe_due = self.keyframes
+        .last()
+        .map(|keyframe| tick >= keyframe.tick + Self::KEYFRAME_INTERVAL)
+        .unwrap_or(true);
+
+        if keyframe_due {
+            self.keyframes.push(Keyframe { tick, next_record: self.next_record, snapshot: encode_to_vec(factory) });
+        }
+
+        while let Some(record) = self.replay.records.get(self.next_record) {
+            match *record {
+                Record::Command { tick: command_tick, ref command, .. } if command_tick == tick => {
+                    // Rejected commands were recorded too, and are rejected again.
+                    let _ = factory.apply(command, prototypes);
+                },
+
+                Record::Command { tick: record_tick, .. } | Record::Checksum { tick: record_tick, .. } if record_tick >= tick => break,
+
+                // Records for ticks before the replay started.
+                _ => {},
+            }
+
+            self.next_record += 1;
+        }
+
+        factory.tick(prototypes);
+
+        while let Some(&Record::Checksum { tick: checksum_tick, checksum: recorded }) = self.replay.records.get(self.next_record) {
+            if checksum_tick != tick {
+                break;
+            }
+
+            self.next_record += 1;
+
+            let actual = checksum(&*factory);
+            if actual != recorded {
+                return Err(ReplayError::Desync { tick, recorded, actual });
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Play forwards or backwards until the factory is at `tick`.
+    ///
+    /// Seeking is clamped to the start and end of the replay.
+    pub fn seek(&mut self, factory: &mut Factory, tick: u64, prototypes: &Prototypes) -> Result<(), ReplayError> {
+        let tick = tick.max(self.replay.start_tick).min(self.replay.end_tick());
+
+        if tick < factory.current_tick() {
+            let keyframe = self.keyframes
+            .iter()
+            .rev()
+            .find(|keyframe| keyframe.tick <= tick)
+            .expect("the start of the replay is a keyframe");
+
+            factory.load(decode_from_slice(&keyframe.snapshot).expect("keyframes are valid snapshots"));
+            self.next_record = keyframe.next_record;
+        }
+
+        while factory.current_tick() < tick {
+            self.step(factory, prototypes)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Encode for Record {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Record::Command { tick, player, command } => {
+                0u8.encode(out);
+                tick.encode(out);
+                player.encode(out);
+                command.encode(out);
+            },
+            Record::Checksum { tick, checksum } => {
+                1u8.encode(out);
+                tick.encode(out);
+                checksum.encode(out);
+            },
+        }
+    }
+}
+
+impl Decode for Record {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(match u8::decode(input)? {
+            0 => Record::Command { tick: u64::decode(input)?, player: PlayerId::decode(input)?, command: Command::decode(input)? },
+            1 => Record::Checksum { tick: u64::decode(input)?, checksum: u64::decode(input)? },
+            tag => return Err(DecodeError::InvalidTag { tag, type_name: "Record" }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{factory::{EntityId, FactoryEvent, Position}, testing::Fixture, tile::Rotation};
+
+    /// Runs a small factory for 1000 ticks, recording it.
+    fn record(fixture: &Fixture) -> (Vec<u8>, u64) {
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::new();
+        let mut writer = ReplayWriter::new(vec![], &factory).unwrap();
+
+        let commands = [
+            (0, Command::PlaceProducer { id: None, position: Position::from_f32(0.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.generate) }),
+            (3, Command::PlaceProducer { id: None, position: Position::from_f32(300.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.consume) }),
+            (10, Command::Connect { id: None, giver: EntityId::from_raw(0), taker: EntityId::from_raw(1), kind: fixture.connector }),
+        ];
+
+        for tick in 0..1000 {
+            for (_, command) in commands.iter().filter(|(command_tick, _)| *command_tick == tick) {
+                let _ = factory.apply(command, &prototypes);
+                writer.write(&Record::Command { tick, player: 0, command: command.clone() }).unwrap();
+            }
+
+            factory.tick(&prototypes);
+
+            if tick % 20 == 0 {
+                writer.write(&Record::Checksum { tick, checksum: checksum(&factory) }).unwrap();
+            }
+        }
+
+        (writer.into_inner(), checksum(&factory))
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let (bytes, _) = record(&fixture);
+
+        let mut factory = Factory::new();
+        let mut player = ReplayPlayer::new(Replay::from_bytes(&bytes).unwrap(), &mut factory);
+        player.seek(&mut factory, 500, &prototypes).unwrap();
+
+        let mut copy = decode_from_slice::<Factory>(&encode_to_vec(&factory)).unwrap();
+        assert_eq!(checksum(&copy), checksum(&factory));
+
+        for _ in 0..100 {
+            copy.tick(&prototypes);
+            factory.tick(&prototypes);
+        }
+
+        assert_eq!(checksum(&copy), checksum(&factory));
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let (bytes, final_checksum) = record(&fixture);
+
+        let replay = Replay::from_bytes(&bytes).unwrap();
+        assert_eq!(replay.to_bytes(), bytes);
+        assert_eq!(replay.end_tick(), 981);
+
+        let mut factory = Factory::new();
+        let mut player = ReplayPlayer::new(replay, &mut factory);
+        while player.step(&mut factory, &prototypes).unwrap() {}
+        assert_eq!(factory.current_tick(), 981);
+
+        let events = factory.drain_events().collect::<Vec<_>>();
+        assert!(events.contains(&FactoryEvent::ConnectorPlaced(EntityId::from_raw(2))));
+
+        // The recording ran for a little longer than the last record.
+        for _ in 981..1000 {
+            factory.tick(&prototypes);
+        }
+        assert_eq!(checksum(&factory), final_checksum);
+    }
+
+    #[test]
+    fn seeking_backwards_matches_playing_forwards() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let (bytes, _) = record(&fixture);
+
+        let mut forwards_factory = Factory::new();
+        let mut forwards = ReplayPlayer::new(Replay::from_bytes(&bytes).unwrap(), &mut forwards_factory);
+        forwards.seek(&mut forwards_factory, 700, &prototypes).unwrap();
+
+        let mut backwards_factory = Factory::new();
+        let mut backwards = ReplayPlayer::new(Replay::from_bytes(&bytes).unwrap(), &mut backwards_factory);
+        backwards.seek(&mut backwards_factory, 900, &prototypes).unwrap();
+        backwards.seek(&mut backwards_factory, 700, &prototypes).unwrap();
+        assert_eq!(checksum(&backwards_factory), checksum(&forwards_factory));
+
+        backwards.seek(&mut backwards_factory, 2, &prototypes).unwrap();
+        assert_eq!(backwards_factory.producers().count(), 1);
+    }
+
+    #[test]
+    fn truncated_replay_is_playable() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let (bytes, _) = record(&fixture);
+
+        let replay = Replay::from_bytes(&bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(replay.end_tick(), 961);
+
+        let mut factory = Factory::new();
+        let mut player = ReplayPlayer::new(replay, &mut factory);
+        player.seek(&mut factory, u64::MAX, &prototypes).unwrap();
+        assert_eq!(factory.current_tick(), 961);
+
+        assert_eq!(Replay::from_bytes(b"not a replay"), Err(DecodeError::Invalid("not a replay")));
+    }
+
+    #[test]
+    fn desync_is_detected() {
+        let fixture = Fixture::new();
+        let prototypes = fixture.prototypes();
+        let (bytes, _) = record(&fixture);
+        let recorded = Replay::from_bytes(&bytes).unwrap();
+
+        let mut tampered = Replay::new(&recorded.initial_factory());
+        for record in recorded.records() {
+            tampered.push(match *record {
+                Record::Checksum { tick: 100, checksum } => Record::Checksum { tick: 100, checksum: checksum + 1 },
+                ref record => record.clone(),
+            });
+        }
+
+        let mut factory = Factory::new();
+        let mut player = ReplayPlayer::new(tampered, &mut factory);
+        let error = player.seek(&mut factory, u64::MAX, &prototypes).unwrap_err();
+        assert!(matches!(error, ReplayError::Desync { tick: 100, .. }));
+        assert_eq!(factory.current_tick(), 101);
+    }
+}
@@ -0,0 +1,393 @@
+//! Research progress.
+//!
+//! Recipes unlocked by a [`TechnologyKind`] can't be used until it is
+//! researched. Labs are producers running the technology's lab recipe: every
+//! craft they finish while the technology is being researched is one unit.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    checksum::{Checksum, StateHasher},
+    codec::{Decode, DecodeError, Encode},
+    command::CommandError,
+    factory::Prototypes,
+    kinds::{RecipeKind, TechnologyEffect, TechnologyKind},
+    random::PerMille,
+    registry::{Handle, Table},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResearchState {
+    current: Option<Handle<TechnologyKind>>,
+
+    /// Units researched so far. Kept when switching to another technology.
+    progress: BTreeMap<Handle<TechnologyKind>, u32>,
+
+    researched: BTreeSet<Handle<TechnologyKind>>,
+
+    /// Units crafted when no technology needed them, e.g. by labs finishing on
+    /// the tick their technology completed. Used by the next technology with
+    /// the same lab recipe.
+    banked: BTreeMap<Handle<RecipeKind>, u32>,
+}
+
+impl ResearchState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The technology being researched.
+    pub fn current(&self) -> Option<Handle<TechnologyKind>> {
+        self.current
+    }
+
+    /// Units researched of the technology.
+    pub fn progress(&self, technology: Handle<TechnologyKind>) -> u32 {
+        self.progress.get(&technology).copied().unwrap_or_default()
+    }
+
+    pub fn is_researched(&self, technology: Handle<TechnologyKind>) -> bool {
+        self.researched.contains(&technology)
+    }
+
+    pub fn researched(&self) -> impl Iterator<Item = Handle<TechnologyKind>> + '_ {
+        self.researched.iter().copied()
+    }
+
+    /// Whether the technology exists and isn't researched yet but all its prerequisites are.
+    pub fn can_research(&self, technology: Handle<TechnologyKind>, technologies: &Table<TechnologyKind>) -> bool {
+        match technologies.get(technology) {
+            Some(kind) => !self.is_researched(technology) && kind.prerequisites.iter().all(|&prerequisite| self.is_researched(prerequisite)),
+            None => false,
+        }
+    }
+
+    /// Whether the recipe can be used: either no technology unlocks it, or one that does is researched.
+    pub fn is_recipe_unlocked(&self, recipe: Handle<RecipeKind>, technologies: &Table<TechnologyKind>) -> bool {
+        let mut unlocking = technologies
+        .iter()
+        .filter(|(_, technology)| technology.unlocks(recipe))
+        .peekable();
+
+        unlocking.peek().is_none() || unlocking.any(|(handle, _)| self.is_researched(handle))
+    }
+
+    /// The recipe, if it exists and is unlocked.
+    pub fn unlocked_recipe<'a>(&self, handle: Handle<RecipeKind>, prototypes: &Prototypes<'a>) -> Result<&'a RecipeKind, CommandError> {
+        let recipe = prototypes.recipes.get(handle).ok_or(CommandError::UnknownRecipe)?;
+
+        if !self.is_recipe_unlocked(handle, prototypes.technologies) {
+            return Err(CommandError::RecipeLocked);
+        }
+
+        Ok(recipe)
+    }
+
+    /// How much faster producers work. Technologies missing from the table give nothing.
+    pub fn production_speed_bonus(&self, technologies: &Table<TechnologyKind>) -> PerMille {
+        self.researched
+        .iter()
+        .filter_map(|&technology| technologies.get(technology))
+        .flat_map(|kind| &kind.effects)
+        .fold(0, |bonus: PerMille, effect| match *effect {
+            TechnologyEffect::ProductionSpeed(speed) => bonus.saturating_add(speed),
+            _ => bonus,
+        })
+    }
+
+    /// Switch research. Returns the technology researched before.
+    pub(crate) fn set_current(&mut self, technology: Option<Handle<TechnologyKind>>) -> Option<Handle<TechnologyKind>> {
+        std::mem::replace(&mut self.current, technology)
+    }
+
+    /// Units crafted with the lab recipe that no technology has used yet.
+    pub fn banked(&self, lab_recipe: Handle<RecipeKind>) -> u32 {
+        self.banked.get(&lab_recipe).copied().unwrap_or_default()
+    }
+
+    /// Bank units crafted with a lab recipe, for [`ResearchState::use_banked`].
+    pub(crate) fn bank(&mut self, lab_recipe: Handle<RecipeKind>, units: u32) {
+        if units > 0 {
+            *self.banked.entry(lab_recipe).or_default() += units;
+        }
+    }
+
+    /// Put the banked units of its lab recipe towards the current technology. Returns the technology if that finished it.
+    pub(crate) fn use_banked(&mut self, technologies: &Table<TechnologyKind>) -> Option<Handle<TechnologyKind>> {
+        let technology = self.current?;
+        let kind = technologies.get(technology)?;
+        let progress = self.progress(technology);
+
+        let banked = self.banked(kind.lab_recipe);
+        let used = banked.min(kind.units.saturating_sub(progress));
+        if used < banked {
+            self.banked.insert(kind.lab_recipe, banked - used);
+        } else {
+            self.banked.remove(&kind.lab_recipe);
+        }
+
+        if used > 0 {
+            self.progress.insert(technology, progress + used);
+        }
+
+        if progress + used < kind.units {
+            return None;
+        }
+
+        self.progress.remove(&technology);
+        self.researched.insert(technology);
+        self.current = None;
+        Some(technology)
+    }
+}
+
+impl Checksum for ResearchState {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        self.current.checksum(hasher);
+
+        hasher.write_u64(self.progress.len() as u64);
+        for (technology, units) in &self.progress {
+            technology.checksum(hasher);
+            units.checksum(hasher);
+        }
+
+        hasher.write_u64(self.researched.len() as u64);
+        for technology in &self.researched {
+            technology.checksum(hasher);
+        }
+
+        hasher.write_u64(self.banked.len() as u64);
+        for (lab_recipe, units) in &self.banked {
+            lab_recipe.checksum(hasher);
+            units.checksum(hasher);
+        }
+    }
+}
+
+impl Encode for ResearchState {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.current.encode(out);
+
+        (self.progress.len() as u32).encode(out);
+        for (technology, units) in &self.progress {
+            technology.encode(out);
+            units.encode(out);
+        }
+
+        (self.researched.len() as u32).encode(out);
+        for technology in &self.researched {
+            technology.encode(out);
+        }
+
+        (self.banked.len() as u32).encode(out);
+        for (lab_recipe, units) in &self.banked {
+            lab_recipe.encode(out);
+            units.encode(out);
+        }
+    }
+}
+
+impl Decode for ResearchState {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut research = ResearchState { current: Option::decode(input)?, ..ResearchState::default() };
+
+        for (technology, units) in Vec::<(Handle<TechnologyKind>, u32)>::decode(input)? {
+            if research.progress.insert(technology, units).is_some() {
+                return Err(DecodeError::Invalid("duplicate research progress"));
+            }
+        }
+
+        for technology in Vec::<Handle<TechnologyKind>>::decode(input)? {
+            if !research.researched.insert(technology) {
+                return Err(DecodeError::Invalid("technology researched twice"));
+            }
+        }
+
+        for (lab_recipe, units) in Vec::<(Handle<RecipeKind>, u32)>::decode(input)? {
+            if units == 0 || research.banked.insert(lab_recipe, units).is_some() {
+                return Err(DecodeError::Invalid("invalid banked research units"));
+            }
+        }
+
+        Ok(research)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        command::{Command, CommandError},
+        factory::{Factory, FactoryEvent, Position},
+        kinds::{QualityRule, RecipeInput, TechnologyKindBuilder},
+        local_string::LocalString,
+        producer::Producer,
+        testing::Fixture,
+        tile::Rotation,
+    };
+
+    #[test]
+    fn labs_research_technologies_that_unlock_recipes() {
+        let mut fixture = Fixture::new();
+        let item = fixture.recipes[fixture.generate].output[0].item;
+
+        let study = fixture.recipes.insert(RecipeKind {
+            name: LocalString::from_str("study"),
+            input_items: vec![RecipeInput { item: item.into(), quantity: 1, wear: None }],
+            output: vec![],
+            time: 1,
+            quality: QualityRule::default(),
+            mining: false,
+        }, "study".to_string());
+
+        let basics = fixture.technologies.insert(
+            TechnologyKindBuilder::new()
+            .with_name(LocalString::from_str("basics"))
+            .with_lab_recipe(study)
+            .with_units(3)
+            .with_effect(TechnologyEffect::UnlockRecipe(fixture.consume))
+            .with_effect(TechnologyEffect::ProductionSpeed(500))
+            .build(),
+
+            "basics".to_string(),
+        );
+
+        let advanced = fixture.technologies.insert(
+            TechnologyKindBuilder::new()
+            .with_name(LocalString::from_str("advanced"))
+            .with_prerequisite(basics)
+            .with_lab_recipe(study)
+            .build(),
+
+            "advanced".to_string(),
+        );
+
+        let prototypes = fixture.prototypes();
+        let mut factory = Factory::new();
+
+        let place_consumer = Command::PlaceProducer { id: None, position: Position::from_f32(0.0, 60.0), rotation: Rotation::North, recipe: Some(fixture.consume) };
+        assert_eq!(factory.apply(&place_consumer, &prototypes), Err(CommandError::RecipeLocked));
+        let mut producer = Producer::new();
+        assert_eq!(Err(CommandError::RecipeLocked), producer.set_recipe(fixture.consume, &prototypes, factory.research()));
+        assert_eq!(None, producer.recipe());
+        assert_eq!(factory.apply(&Command::SetResearch { technology: Some(advanced) }, &prototypes), Err(CommandError::TechnologyUnavailable));
+
+        let generator = Command::PlaceProducer { id: None, position: Position::from_f32(0.0, 0.0), rotation: Rotation::North, recipe: Some(fixture.generate) };
+        let lab = Command::PlaceProducer { id: None, position: Position::from_f32(120.0, 0.0), rotation: Rotation::North, recipe: Some(study) };
+        factory.apply(&generator, &prototypes).unwrap();
+        factory.apply(&lab, &prototypes).unwrap();
+
+        let (generator, lab) = {
+            let mut ids = factory.producers().map(|(id, _)| id);
+            (ids.next().unwrap(), ids.next().unwrap())
+        };
+        factory.apply(&Command::Connect { id: None, giver: generator, taker: lab, kind: fixture.connector }, &prototypes).unwrap();
+
+        // Without research, the lab keeps its science items.
+        for _ in 0..200 {
+            factory.tick(&prototypes);
+        }
+        assert_eq!(0, factory.research().progress(basics));
+
+        factory.apply(&Command::SetResearch { technology: Some(basics) }, &prototypes).unwrap();
+        factory.drain_events().for_each(drop);
+
+        let mut completed = false;
+        for _ in 0..1000 {
+            factory.tick(&prototypes);
+            completed |= factory.drain_events().any(|event| event == FactoryEvent::ResearchCompleted(basics));
+        }
+
+        assert!(completed);
+        assert!(factory.research().is_researched(basics));
+        assert_eq!(None, factory.research().current());
+        assert_eq!(500, factory.research().production_speed_bonus(&fixture.technologies));
+        assert!(factory.apply(&place_consumer, &prototypes).is_ok());
+        assert!(factory.apply(&Command::SetResearch { technology: Some(advanced) }, &prototypes).is_ok());
+    }
+
+    #[test]
+    fn switching_research_keeps_progress() {
+        let mut fixture = Fixture::new();
+        let basics = fixture.technologies.insert(
+            TechnologyKindBuilder::new()
+            .with_name(LocalString::from_str("basics"))
+            .with_lab_recipe(fixture.consume)
+            .with_units(2)
+            .build(),
+
+            "basics".to_string(),
+        );
+
+        let mut research = ResearchState::new();
+        research.set_current(Some(basics));
+        research.bank(fixture.consume, 1);
+        assert_eq!(None, research.use_banked(&fixture.technologies));
+
+        research.set_current(None);
+        assert_eq!(1, research.progress(basics));
+
+        research.set_current(Some(basics));
+        research.bank(fixture.consume, 1);
+        assert_eq!(Some(basics), research.use_banked(&fixture.technologies));
+        assert!(research.is_researched(basics));
+    }
+
+    #[test]
+    fn units_left_over_go_to_the_next_technology() {
+        let mut fixture = Fixture::new();
+        let mut technology = |name: &str, units| fixture.technologies.insert(
+            TechnologyKindBuilder::new()
+            .with_name(LocalString::from_str(name))
+            .with_lab_recipe(fixture.consume)
+            .with_units(units)
+            .build(),
+
+            name.to_string(),
+        );
+        let (basics, advanced) = (technology("basics", 2), technology("advanced", 4));
+
+        // Three labs finish on the same tick.
+        let mut research = ResearchState::new();
+        research.set_current(Some(basics));
+        research.bank(fixture.consume, 3);
+        assert_eq!(Some(basics), research.use_banked(&fixture.technologies));
+        assert_eq!(1, research.banked(fixture.consume));
+
+        // Nothing is being researched, so labs that were still crafting bank their units.
+        research.bank(fixture.consume, 2);
+        assert_eq!(None, research.use_banked(&fixture.technologies));
+        assert_eq!(3, research.banked(fixture.consume));
+
+        research.set_current(Some(advanced));
+        assert_eq!(None, research.use_banked(&fixture.technologies));
+        assert_eq!(3, research.progress(advanced));
+        assert_eq!(0, research.banked(fixture.consume));
+
+        let mut encoded = Vec::new();
+        research.bank(fixture.consume, 5);
+        research.encode(&mut encoded);
+        assert_eq!(Ok(research), ResearchState::decode(&mut &encoded[..]));
+    }
+
+    #[test]
+    fn technologies_missing_from_the_table_are_ignored() {
+        let fixture = Fixture::new();
+        let missing = Handle::new(fixture.technologies.len() + 7);
+
+        // As decoded from a peer with other prototypes.
+        let mut encoded = Vec::new();
+        Some(missing).encode(&mut encoded);
+        Vec::<(Handle<TechnologyKind>, u32)>::new().encode(&mut encoded);
+        vec![missing].encode(&mut encoded);
+        Vec::<(Handle<RecipeKind>, u32)>::new().encode(&mut encoded);
+        let mut research = ResearchState::decode(&mut &encoded[..]).unwrap();
+
+        assert!(!research.can_research(missing, &fixture.technologies));
+        assert_eq!(0, research.production_speed_bonus(&fixture.technologies));
+
+        research.bank(fixture.consume, 1);
+        assert_eq!(None, research.use_banked(&fixture.technologies));
+        assert_eq!(1, research.banked(fixture.consume));
+    }
+}
@@ -0,0 +1,201 @@
+//! Which producers and connectors have something to do on the next tick.
+//!
+//! After ticking, every producer goes to sleep. One that is crafting sleeps
+//! until the tick its craft finishes, kept on a [`TimerWheel`]. Any other waits
+//! for its slots to change, e.g. a connector bringing inputs or taking outputs.
+//! Connectors waiting at a producer that has nothing to give, or no room to
+//! take, sleep until that producer crafts. Only awake entities tick, so the
+//! cost of a tick follows how busy the factory is rather than how large it is.
+//!
+//! Waking an entity early is always safe: it ticks as if it had never slept.
+//! A crafting producer doesn't count the ticks it sleeps through, so they are
+//! added when it wakes. See [`Scheduler::wake_producer`].
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::factory::EntityId;
+
+/// Entities due on future ticks, in slots by tick.
+pub(crate) struct TimerWheel {
+    slots: Vec<Vec<(u64, EntityId)>>,
+}
+
+impl TimerWheel {
+    /// Ticks covered by one turn of the wheel. Later timers wait in their slot for more turns.
+    const SLOTS: usize = 256;
+
+    pub(crate) fn new() -> Self {
+        Self { slots: (0..Self::SLOTS).map(|_| vec![]).collect() }
+    }
+
+    pub(crate) fn schedule(&mut self, tick: u64, id: EntityId) {
+        self.slots[tick as usize % Self::SLOTS].push((tick, id));
+    }
+
+    /// Take the entities due on `tick`. Has to be called for every tick in turn.
+    pub(crate) fn take_due(&mut self, tick: u64) -> Vec<EntityId> {
+        let slot = &mut self.slots[tick as usize % Self::SLOTS];
+        let mut due = vec![];
+
+        slot.retain(|&(due_tick, id)| {
+            debug_assert!(due_tick >= tick, "timer wheel skipped a tick");
+            if due_tick == tick {
+                due.push(id);
+            }
+            due_tick != tick
+        });
+
+        due
+    }
+}
+
+impl Default for TimerWheel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sleep {
+    /// Tick the producer last ticked on.
+    since: u64,
+
+    /// Tick to wake on, if it doesn't wait for its slots to change.
+    until: Option<u64>,
+}
+
+#[derive(Default)]
+pub(crate) struct Scheduler {
+    awake_producers: BTreeSet<EntityId>,
+    sleeping_producers: BTreeMap<EntityId, Sleep>,
+    wheel: TimerWheel,
+    awake_connectors: BTreeSet<EntityId>,
+
+    /// Connectors giving to or taking from each producer, ordered by id.
+    attached: BTreeMap<EntityId, Vec<EntityId>>,
+}
+
+impl Scheduler {
+    pub(crate) fn insert_producer(&mut self, id: EntityId) {
+        self.sleeping_producers.remove(&id);
+        self.awake_producers.insert(id);
+    }
+
+    pub(crate) fn remove_producer(&mut self, id: EntityId) {
+        // A timer left on the wheel is ignored once it's due.
+        self.sleeping_producers.remove(&id);
+        self.awake_producers.remove(&id);
+        self.attached.remove(&id);
+    }
+
+    pub(crate) fn insert_connector(&mut self, id: EntityId, giver: EntityId, taker: EntityId) {
+        for producer in [giver, taker] {
+            let attached = self.attached.entry(producer).or_default();
+            if let Err(index) = attached.binary_search(&id) {
+                attached.insert(index, id);
+            }
+        }
+        self.awake_connectors.insert(id);
+    }
+
+    pub(crate) fn remove_connector(&mut self, id: EntityId, giver: EntityId, taker: EntityId) {
+        for producer in [giver, taker] {
+            if let Some(attached) = self.attached.get_mut(&producer) {
+                attached.retain(|&connector| connector != id);
+            }
+        }
+        self.awake_connectors.remove(&id);
+    }
+
+    /// Connectors giving to or taking from the producer, ordered by id.
+    pub(crate) fn attached(&self, producer: EntityId) -> &[EntityId] {
+        self.attached.get(&producer).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Tick the producer on tick `next`. Returns the ticks it slept through, which it has to skip ahead by.
+    pub(crate) fn wake_producer(&mut self, id: EntityId, next: u64) -> Option<u64> {
+        let sleep = self.sleeping_producers.remove(&id)?;
+        self.awake_producers.insert(id);
+        Some(next - sleep.since - 1)
+    }
+
+    /// Ticks a sleeping producer slept through before tick `next`.
+    pub(crate) fn slept_through(&self, id: EntityId, next: u64) -> u64 {
+        self.sleeping_producers
+        .get(&id)
+        .map(|sleep| next - sleep.since - 1)
+        .unwrap_or_default()
+    }
+
+    /// Put a producer that ticked on `tick` to sleep, until the tick `until` or until it's woken.
+    pub(crate) fn sleep_producer(&mut self, id: EntityId, tick: u64, until: Option<u64>) {
+        self.awake_producers.remove(&id);
+        self.sleeping_producers.insert(id, Sleep { since: tick, until });
+
+        if let Some(until) = until {
+            self.wheel.schedule(until, id);
+        }
+    }
+
+    /// Producers whose timers are due on `tick`. They still have to be woken.
+    pub(crate) fn take_due(&mut self, tick: u64) -> Vec<EntityId> {
+        let sleeping = &self.sleeping_producers;
+
+        // Timers of producers that were woken early and went back to sleep are stale.
+        self.wheel
+        .take_due(tick)
+        .into_iter()
+        .filter(|id| sleeping.get(id).map(|sleep| sleep.until == Some(tick)).unwrap_or(false))
+        .collect()
+    }
+
+    pub(crate) fn wake_connector(&mut self, id: EntityId) {
+        self.awake_connectors.insert(id);
+    }
+
+    pub(crate) fn wake_attached(&mut self, producer: EntityId) {
+        if let Some(attached) = self.attached.get(&producer) {
+            self.awake_connectors.extend(attached.iter().copied());
+        }
+    }
+
+    pub(crate) fn sleep_connector(&mut self, id: EntityId) {
+        self.awake_connectors.remove(&id);
+    }
+
+    pub(crate) fn awake_producers(&self) -> &BTreeSet<EntityId> {
+        &self.awake_producers
+    }
+
+    pub(crate) fn awake_connectors(&self) -> &BTreeSet<EntityId> {
+        &self.awake_connectors
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wheel_keeps_later_turns_and_ignores_stale_timers() {
+        let mut wheel = TimerWheel::new();
+        let (a, b) = (EntityId::from_raw(1), EntityId::from_raw(2));
+        wheel.schedule(3, a);
+        wheel.schedule(3 + TimerWheel::SLOTS as u64, b);
+
+        assert!(wheel.take_due(2).is_empty());
+        assert_eq!(vec![a], wheel.take_due(3));
+        assert_eq!(vec![b], wheel.take_due(3 + TimerWheel::SLOTS as u64));
+
+        let mut scheduler = Scheduler::default();
+        scheduler.insert_producer(a);
+        scheduler.sleep_producer(a, 0, Some(10));
+        assert_eq!(Some(4), scheduler.wake_producer(a, 5));
+        assert_eq!(None, scheduler.wake_producer(a, 5));
+        scheduler.sleep_producer(a, 5, Some(20));
+
+        assert!(scheduler.take_due(10).is_empty());
+        assert_eq!(9, scheduler.slept_through(a, 15));
+        assert_eq!(vec![a], scheduler.take_due(20));
+    }
+}
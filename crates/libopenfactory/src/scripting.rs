@@ -0,0 +1,856 @@
+//! Content and behaviour from Rhai scripts, behind the `scripting` feature.
+//!
+//! A script declares content at its top level:
+//!
+//! ```rhai
+//! item("bronze");
+//! tag("metal", ["copper", "tin", "bronze"]);
+//! recipe("bronze", #{ time: 40, inputs: [["copper", 3], ["tin", 1]], outputs: [["bronze", 4]] });
+//! recipe("destroy-metal", #{ time: 5, inputs: [["#metal", 1]] });
+//! connector("stack-arm", #{ hand: 4, timeout: 20 });
+//! ```
+//!
+//! Inputs starting with `#` name a tag, and `mining: true` makes a mining
+//! recipe. Names may refer to content declared before, by the game or by
+//! other scripts. Scripts of mods are loaded with [`ScriptHost::load_mods`],
+//! and name content as described in [`crate::mods`].
+//!
+//! Scripts react to the simulation by defining any of these functions:
+//!
+//! - `on_placed(entity)`: a producer or connector was placed.
+//! - `on_craft(producer, recipe)`: a producer finished a craft, of the recipe with this full name.
+//! - `on_tick(tick)`: a tick was simulated.
+//!
+//! Each script has its own object map, bound as `this` in those functions, to
+//! keep state in. Scripts don't edit the factory. They ask for commands with
+//! `set_recipe(producer, recipe)` (`()` for none), `remove(entity)`,
+//! `connect(giver, taker, connector)` and `place_producer(x, y, recipe)`,
+//! which the frontend issues like the player's own. Every call into a script
+//! may only run a limited number of operations, so a runaway script can't
+//! stall the game.
+
+use std::{cell::RefCell, collections::{BTreeMap, BTreeSet}, convert::TryFrom, rc::Rc};
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST, INT};
+
+use crate::{
+    command::Command,
+    factory::{EntityId, Factory, FactoryEvent, Position, Prototypes},
+    fixed::Fixed,
+    item_stack::{ItemData, ItemFilter},
+    kinds::{ConnectorKind, ConnectorKindBuilder, ItemKind, ItemKindBuilder, ItemTag, ItemTagBuilder, QualityRule, RecipeInput, RecipeKind, RecipeOutput},
+    local_string::LocalString,
+    mods::{self, LoadOrder},
+    registry::{Handle, NameTaken, Table},
+    stats::ProductionStats,
+    tile::Rotation,
+};
+
+/// Something a script failed at, e.g. a syntax error, an unknown name, or running out of operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    /// Name the script was loaded with.
+    pub script: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.script, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// What scripts asked for in reaction to a tick, and how the ones that failed failed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reactions {
+    pub commands: Vec<Command>,
+    pub errors: Vec<ScriptError>,
+}
+
+/// The tables scripts declare content into.
+pub struct Content<'a> {
+    pub items: &'a mut Table<ItemKind>,
+    pub tags: &'a mut Table<ItemTag>,
+    pub recipes: &'a mut Table<RecipeKind>,
+    pub connectors: &'a mut Table<ConnectorKind>,
+}
+
+enum Declaration {
+    Item { name: String },
+    Tag { name: String, items: Vec<String> },
+    Recipe { name: String, time: INT, inputs: Vec<(String, INT)>, outputs: Vec<(String, INT)>, mining: bool },
+    Connector { name: String, hand_size: INT, pickup_timeout: INT },
+}
+
+/// A command a script asked for, naming content instead of holding handles.
+enum Request {
+    SetRecipe { producer: INT, recipe: Option<String> },
+    Remove { entity: INT },
+    Connect { giver: INT, taker: INT, connector: String },
+    PlaceProducer { x: INT, y: INT, recipe: Option<String> },
+}
+
+struct Script {
+    name: String,
+    namespace: Option<Namespace>,
+    ast: AST,
+    scope: Scope<'static>,
+
+    /// Bound as `this` in the script's event functions.
+    state: Dynamic,
+}
+
+pub struct ScriptHost {
+    engine: Engine,
+    scripts: Vec<Script>,
+
+    /// Filled by the script running.
+    declarations: Rc<RefCell<Vec<Declaration>>>,
+    requests: Rc<RefCell<Vec<Request>>>,
+
+    /// Declarations of the scripts loaded since content was last declared, with the index of their script.
+    pending: Vec<(usize, Declaration)>,
+
+    overrides: Overrides,
+}
+
+/// The mod that last overrode each piece of content, by kind and name.
+type Overrides = BTreeMap<(&'static str, String), String>;
+
+pub struct ScriptHostBuilder {
+    max_operations: u64,
+}
+
+impl ScriptHostBuilder {
+    pub fn new() -> Self {
+        Self {
+            max_operations: 100_000,
+        }
+    }
+
+    /// Operations a single call into a script may run.
+    pub fn with_max_operations(mut self, max_operations: u64) -> Self {
+        self.max_operations = max_operations;
+        self
+    }
+
+    pub fn build(self) -> ScriptHost {
+        match self {
+            ScriptHostBuilder { max_operations } if max_operations > 0 => {
+                let mut engine = Engine::new();
+                engine.set_max_operations(max_operations);
+
+                let declarations = Rc::new(RefCell::new(vec![]));
+                let requests = Rc::new(RefCell::new(vec![]));
+                register_declarations(&mut engine, &declarations);
+                register_requests(&mut engine, &requests);
+
+                ScriptHost { engine, scripts: vec![], declarations, requests, pending: vec![], overrides: BTreeMap::new() }
+            },
+
+            _ => panic!("Script Host Builder built without any operations allowed"),
+        }
+    }
+}
+
+impl Default for ScriptHostBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for ScriptHost {
+    fn default() -> Self {
+        ScriptHostBuilder::new().build()
+    }
+}
+
+fn register_declarations(engine: &mut Engine, declarations: &Rc<RefCell<Vec<Declaration>>>) {
+    let declared = declarations.clone();
+    engine.register_fn("item", move |name: &str| {
+        declared.borrow_mut().push(Declaration::Item { name: name.to_string() });
+    });
+
+    let declared = declarations.clone();
+    engine.register_fn("tag", move |name: &str, items: Array| -> Result<(), Box<EvalAltResult>> {
+        let items = items
+        .into_iter()
+        .map(|item| item.into_string().map_err(|_| "tag items have to be names".into()))
+        .collect::<Result<_, Box<EvalAltResult>>>()?;
+
+        declared.borrow_mut().push(Declaration::Tag { name: name.to_string(), items });
+        Ok(())
+    });
+
+    let declared = declarations.clone();
+    engine.register_fn("recipe", move |name: &str, definition: Map| -> Result<(), Box<EvalAltResult>> {
+        let time = definition
+        .get("time")
+        .and_then(|time| time.as_int().ok())
+        .ok_or("recipe needs `time: <ticks>`")?;
+
+        let stacks = |key: &str| -> Result<Vec<(String, INT)>, Box<EvalAltResult>> {
+            let list = match definition.get(key) {
+                Some(list) => list.clone().into_array().map_err(|_| format!("recipe {} have to be a list", key))?,
+                None => return Ok(vec![]),
+            };
+
+            list
+            .into_iter()
+            .map(|stack| {
+                let pair = stack.into_array().unwrap_or_default();
+                match pair.as_slice() {
+                    [name, quantity] if name.is_string() && quantity.is_int() => Ok((name.clone().cast::<rhai::ImmutableString>().to_string(), quantity.as_int().unwrap_or_default())),
+                    _ => Err(format!("recipe {} are `[name, quantity]` pairs", key).into()),
+                }
+            })
+            .collect()
+        };
+
+        let mining = definition
+        .get("mining")
+        .map(|mining| mining.as_bool().map_err(|_| "`mining` is true or false"))
+        .transpose()?
+        .unwrap_or(false);
+
+        declared.borrow_mut().push(Declaration::Recipe { name: name.to_string(), time, inputs: stacks("inputs")?, outputs: stacks("outputs")?, mining });
+        Ok(())
+    });
+
+    let declared = declarations.clone();
+    engine.register_fn("connector", move |name: &str, definition: Map| -> Result<(), Box<EvalAltResult>> {
+        let field = |key: &str, default: INT| -> Result<INT, Box<EvalAltResult>> {
+            match definition.get(key) {
+                Some(value) => value.as_int().map_err(|_| format!("connector `{}` has to be a number", key).into()),
+                None => Ok(default),
+            }
+        };
+
+        declared.borrow_mut().push(Declaration::Connector { name: name.to_string(), hand_size: field("hand", 1)?, pickup_timeout: field("timeout", 0)? });
+        Ok(())
+    });
+}
+
+/// A recipe name, or `()` for none.
+fn optional_name(recipe: Dynamic) -> Result<Option<String>, Box<EvalAltResult>> {
+    if recipe.is_unit() {
+        Ok(None)
+    } else {
+        recipe.into_string().map(Some).map_err(|_| "recipes are given by name, or `()` for none".into())
+    }
+}
+
+fn register_requests(engine: &mut Engine, requests: &Rc<RefCell<Vec<Request>>>) {
+    let requested = requests.clone();
+    engine.register_fn("set_recipe", move |producer: INT, recipe: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        requested.borrow_mut().push(Request::SetRecipe { producer, recipe: optional_name(recipe)? });
+        Ok(())
+    });
+
+    let requested = requests.clone();
+    engine.register_fn("remove", move |entity: INT| {
+        requested.borrow_mut().push(Request::Remove { entity });
+    });
+
+    let requested = requests.clone();
+    engine.register_fn("connect", move |giver: INT, taker: INT, connector: &str| {
+        requested.borrow_mut().push(Request::Connect { giver, taker, connector: connector.to_string() });
+    });
+
+    let requested = requests.clone();
+    engine.register_fn("place_producer", move |x: INT, y: INT, recipe: Dynamic| -> Result<(), Box<EvalAltResult>> {
+        requested.borrow_mut().push(Request::PlaceProducer { x, y, recipe: optional_name(recipe)? });
+        Ok(())
+    });
+}
+
+/// Loading
+impl ScriptHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compile a script and run its top level, collecting the content it declares.
+    pub fn load(&mut self, name: &str, source: &str) -> Result<(), ScriptError> {
+        self.load_in(None, name, source)
+    }
+
+    fn load_in(&mut self, namespace: Option<Namespace>, name: &str, source: &str) -> Result<(), ScriptError> {
+        let error = |message: String| ScriptError { script: name.to_string(), message };
+
+        let mut scope = Scope::new();
+        let ran = self.engine
+        .compile(source)
+        .map_err(|parse_error| error(parse_error.to_string()))
+        .and_then(|ast| match self.engine.run_ast_with_scope(&mut scope, &ast) {
+            Ok(()) => Ok(ast),
+            Err(eval_error) => Err(error(eval_error.to_string())),
+        });
+
+        // Content is only declared at the top level, and a script that fails declares nothing.
+        let declared = std::mem::take(&mut *self.declarations.borrow_mut());
+        self.requests.borrow_mut().clear();
+        let ast = ran?;
+
+        let index = self.scripts.len();
+        self.pending.extend(declared.into_iter().map(|declaration| (index, declaration)));
+        self.scripts.push(Script { name: name.to_string(), namespace, ast, scope, state: Dynamic::from_map(Map::new()) });
+        Ok(())
+    }
+
+    /// Add the content declared by the scripts loaded since the last call.
+    ///
+    /// On error, the declarations after the one that failed are dropped.
+    pub fn declare_content(&mut self, content: &mut Content) -> Result<(), ScriptError> {
+        for (index, declaration) in std::mem::take(&mut self.pending) {
+            let script = &self.scripts[index];
+            let names = Names(script.namespace.as_ref());
+            declare(declaration, content, &names, &mut self.overrides).map_err(|message| ScriptError { script: script.name.clone(), message })?;
+        }
+
+        Ok(())
+    }
+
+    /// Load the scripts of every mod in order, adding the content they declare.
+    ///
+    /// Errors name the script as `<mod id>/<file name>`.
+    pub fn load_mods(&mut self, order: &LoadOrder, content: &mut Content) -> Result<(), ScriptError> {
+        for loaded in order.mods() {
+            let id = &loaded.manifest.id;
+            let requires = order
+            .mods()
+            .iter()
+            .map(|other| other.manifest.id.clone())
+            .filter(|other| order.requires(id, other))
+            .collect();
+
+            for (file, source) in &loaded.scripts {
+                let namespace = Namespace { id: id.clone(), requires: BTreeSet::clone(&requires) };
+                self.load_in(Some(namespace), &format!("{}/{}", id, file), source)?;
+                self.declare_content(content)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The mod a script is from, and the mods it depends on.
+struct Namespace {
+    id: String,
+    requires: BTreeSet<String>,
+}
+
+/// Where a declaration goes.
+enum Target<T> {
+    New(String),
+    Override(Handle<T>, String),
+}
+
+impl<T> Target<T> {
+    fn name(&self) -> &str {
+        match self {
+            Target::New(name) | Target::Override(_, name) => name,
+        }
+    }
+
+    /// Localization key for the content, e.g. `item-bronze-age-bronze` for `bronze-age:bronze` and `item-copper` for the game's `copper`.
+    fn key(&self, prefix: &str) -> LocalString {
+        LocalString::from_str(&format!("{}-{}", prefix, self.name().replace(mods::SEPARATOR, "-")))
+    }
+
+    fn put(self, table: &mut Table<T>, value: T) -> Result<(), String> {
+        match self {
+            Target::New(name) => {
+                table.try_insert(value, name).map_err(|NameTaken(name)| format!("`{}` is declared already", name))?;
+            },
+            Target::Override(handle, _) => {
+                table.replace(handle, value);
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// How the names in a script map to names in the tables, see [`crate::mods`].
+struct Names<'a>(Option<&'a Namespace>);
+
+impl Names<'_> {
+    fn find<T>(&self, table: &Table<T>, name: &str, what: &str) -> Result<Handle<T>, String> {
+        let found = match (self.0, mods::split(name)) {
+            (_, (Some(mods::BASE), rest)) => table.find_handle_from_name(rest),
+            (None, _) => table.find_handle_from_name(name),
+            (Some(namespace), (Some(owner), _)) if owner != namespace.id && !namespace.requires.contains(owner) => {
+                return Err(format!("`{}` isn't a dependency of `{}`", owner, namespace.id));
+            },
+            (Some(_), (Some(_), _)) => table.find_handle_from_name(name),
+            (Some(namespace), (None, rest)) => table
+            .find_handle_from_name(&mods::qualified(&namespace.id, rest))
+            .or_else(|| table.find_handle_from_name(rest)),
+        };
+
+        found.ok_or_else(|| format!("unknown {} `{}`", what, name))
+    }
+
+    /// Content declared by scripts outside of mods keeps its name.
+    fn target<T>(&self, table: &Table<T>, name: &str, what: &'static str, overrides: &mut Overrides) -> Result<Target<T>, String> {
+        let namespace = match self.0 {
+            Some(namespace) => namespace,
+            None => return Ok(Target::New(mods::stored_name(name).to_string())),
+        };
+
+        let (owner, rest) = mods::split(name);
+        if rest.is_empty() || rest.contains(mods::SEPARATOR) {
+            return Err(format!("`{}` isn't a name", name));
+        }
+
+        match owner {
+            Some(owner) if owner != namespace.id => {
+                let handle = self.find(table, name, what)?;
+                let overridden = overrides.entry((what, name.to_string())).or_insert_with(|| namespace.id.clone());
+                if *overridden != namespace.id && !namespace.requires.contains(overridden) {
+                    return Err(format!("`{}` is overridden by both `{}` and `{}`", name, overridden, namespace.id));
+                }
+
+                *overridden = namespace.id.clone();
+                Ok(Target::Override(handle, mods::stored_name(name).to_string()))
+            },
+
+            _ => Ok(Target::New(mods::qualified(&namespace.id, rest))),
+        }
+    }
+}
+
+fn quantity(value: INT, what: &str) -> Result<u16, String> {
+    u16::try_from(value)
+    .ok()
+    .filter(|&value| value > 0)
+    .ok_or_else(|| format!("{} has to be between 1 and {}", what, u16::MAX))
+}
+
+fn declare(declaration: Declaration, content: &mut Content, names: &Names, overrides: &mut Overrides) -> Result<(), String> {
+    match declaration {
+        Declaration::Item { name } => {
+            let target = names.target(content.items, &name, "item", overrides)?;
+            let item = ItemKindBuilder::new().with_name(target.key("item")).build();
+            target.put(content.items, item)?;
+        },
+
+        Declaration::Tag { name, items } => {
+            let target = names.target(content.tags, &name, "tag", overrides)?;
+            let mut tag = ItemTagBuilder::new().with_name(target.key("tag"));
+            for item in &items {
+                tag = tag.with_item(names.find(content.items, item, "item")?);
+            }
+            target.put(content.tags, tag.build())?;
+        },
+
+        Declaration::Recipe { name, time, inputs, outputs, mining } => {
+            let target = names.target(content.recipes, &name, "recipe", overrides)?;
+
+            let input_items = inputs
+            .iter()
+            .map(|(input, count)| {
+                let item: ItemFilter = match input.strip_prefix('#') {
+                    Some(tag) => names.find(content.tags, tag, "tag")?.into(),
+                    None => names.find(content.items, input, "item")?.into(),
+                };
+                Ok(RecipeInput { item, quantity: quantity(*count, "input quantity")?, wear: None })
+            })
+            .collect::<Result<_, String>>()?;
+
+            let output = outputs
+            .iter()
+            .map(|(output, count)| Ok(RecipeOutput { item: names.find(content.items, output, "item")?, quantity: quantity(*count, "output quantity")?, data: ItemData::default() }))
+            .collect::<Result<_, String>>()?;
+
+            let recipe = RecipeKind {
+                name: target.key("recipe"),
+                input_items,
+                output,
+                time: quantity(time, "recipe time")?,
+                quality: QualityRule::default(),
+                mining,
+            };
+            target.put(content.recipes, recipe)?;
+        },
+
+        Declaration::Connector { name, hand_size, pickup_timeout } => {
+            let target = names.target(content.connectors, &name, "connector", overrides)?;
+            let connector = ConnectorKindBuilder::new()
+            .with_name(target.key("connector"))
+            .with_hand_size(quantity(hand_size, "connector hand")?)
+            .with_pickup_timeout(u16::try_from(pickup_timeout).map_err(|_| "connector timeout is out of range".to_string())?)
+            .build();
+            target.put(content.connectors, connector)?;
+        },
+    }
+
+    Ok(())
+}
+
+/// Events
+impl ScriptHost {
+    /// Tell the scripts what happened on the tick just simulated, returning the commands they ask for.
+    ///
+    /// `events` are those drained from the factory since the last call, and
+    /// `crafts` the crafts of this tick, see [`Factory::tick_with_stats`].
+    /// A script that fails asks for nothing, but doesn't keep the others from
+    /// running.
+    pub fn run_events(&mut self, factory: &Factory, events: &[FactoryEvent], crafts: &ProductionStats, prototypes: &Prototypes) -> Reactions {
+        let placed: Vec<INT> = events
+        .iter()
+        .filter_map(|event| match event {
+            FactoryEvent::ProducerPlaced(id) | FactoryEvent::ConnectorPlaced(id) => Some(id.to_raw() as INT),
+            _ => None,
+        })
+        .collect();
+
+        let crafted: Vec<(INT, String, u64)> = crafts
+        .producer_recipes()
+        .map(|(id, recipe, count)| (id.to_raw() as INT, prototypes.recipes.name(&recipe).to_string(), count))
+        .collect();
+
+        let mut reactions = Reactions::default();
+
+        for index in 0..self.scripts.len() {
+            let called = self.call_events(index, &placed, &crafted, factory.current_tick());
+
+            // Content is only declared at the top level, and a script that fails asks for nothing.
+            self.declarations.borrow_mut().clear();
+            let requests = std::mem::take(&mut *self.requests.borrow_mut());
+            if let Err(error) = called {
+                reactions.errors.push(error);
+                continue;
+            }
+
+            let script = &self.scripts[index];
+            let names = Names(script.namespace.as_ref());
+            for request in requests {
+                match command(request, prototypes, &names) {
+                    Ok(command) => reactions.commands.push(command),
+                    Err(message) => reactions.errors.push(ScriptError { script: script.name.clone(), message }),
+                }
+            }
+        }
+
+        reactions
+    }
+
+    fn call_events(&mut self, index: usize, placed: &[INT], crafted: &[(INT, String, u64)], tick: u64) -> Result<(), ScriptError> {
+        for &entity in placed {
+            self.call(index, "on_placed", (entity,))?;
+        }
+
+        for (producer, recipe, count) in crafted {
+            for _ in 0..*count {
+                self.call(index, "on_craft", (*producer, recipe.clone()))?;
+            }
+        }
+
+        self.call(index, "on_tick", (tick as INT,))
+    }
+
+    /// Call an event function, if the script defines it.
+    fn call(&mut self, index: usize, function: &str, args: impl FuncArgs + ArgCount) -> Result<(), ScriptError> {
+        let script = &mut self.scripts[index];
+
+        let defined = script.ast
+        .iter_functions()
+        .any(|defined| defined.name == function && defined.params.len() == args.count());
+
+        if !defined {
+            return Ok(());
+        }
+
+        let options = CallFnOptions::new()
+        .eval_ast(false)
+        .bind_this_ptr(&mut script.state);
+
+        self.engine
+        .call_fn_with_options::<Dynamic>(options, &mut script.scope, &script.ast, function, args)
+        .map(drop)
+        .map_err(|error| ScriptError { script: script.name.clone(), message: format!("in `{}`: {}", function, error) })
+    }
+}
+
+/// Number of arguments, to tell whether a script defines an event function.
+trait ArgCount {
+    fn count(&self) -> usize;
+}
+
+impl<A> ArgCount for (A,) {
+    fn count(&self) -> usize {
+        1
+    }
+}
+
+impl<A, B> ArgCount for (A, B) {
+    fn count(&self) -> usize {
+        2
+    }
+}
+
+fn entity(id: INT) -> Result<EntityId, String> {
+    u32::try_from(id).map(EntityId::from_raw).map_err(|_| format!("there is no entity {}", id))
+}
+
+fn command(request: Request, prototypes: &Prototypes, names: &Names) -> Result<Command, String> {
+    let recipe = |name: Option<String>| name.map(|name| names.find(prototypes.recipes, &name, "recipe")).transpose();
+
+    Ok(match request {
+        Request::SetRecipe { producer, recipe: name } => Command::SetRecipe { producer: entity(producer)?, recipe: recipe(name)? },
+        Request::Remove { entity: id } => Command::RemoveEntity { entity: entity(id)? },
+        Request::Connect { giver, taker, connector } => Command::Connect {
+            id: None,
+            giver: entity(giver)?,
+            taker: entity(taker)?,
+            kind: names.find(prototypes.connectors, &connector, "connector")?,
+        },
+        Request::PlaceProducer { x, y, recipe: name } => {
+            let coordinate = |value: INT| i32::try_from(value).map(Fixed::from_int).map_err(|_| format!("{} is off the map", value));
+            Command::PlaceProducer { id: None, position: Position::new(coordinate(x)?, coordinate(y)?), rotation: Rotation::North, recipe: recipe(name)? }
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{kinds::TechnologyKind, mods::{Dependency, Mod, ModManifest}};
+
+    const SMELTING: &str = r##"
+        item("copper");
+        item("tin");
+        item("bronze");
+        tag("metal", ["copper", "tin", "bronze"]);
+        recipe("ore", #{ time: 2, outputs: [["copper", 3], ["tin", 1]] });
+        recipe("bronze", #{ time: 4, inputs: [["copper", 3], ["tin", 1]], outputs: [["bronze", 4]] });
+        recipe("destroy-metal", #{ time: 1, inputs: [["#metal", 1]] });
+        connector("stack-arm", #{ hand: 4, timeout: 20 });
+
+        // Turn the first producer placed into an ore source, and retool it to bronze once its output is full.
+        fn on_placed(entity) {
+            if this.source == () {
+                this.source = entity;
+                set_recipe(entity, "ore");
+            }
+        }
+
+        fn on_craft(producer, recipe) {
+            if recipe == "ore" {
+                this.crafts = (this.crafts ?? 0) + 1;
+                if this.crafts == 2 {
+                    set_recipe(producer, "bronze");
+                }
+            }
+        }
+    "##;
+
+    #[test]
+    fn scripts_declare_content_and_react_to_events() {
+        let (mut items, mut tags, mut recipes, mut connectors) = (Table::new(), Table::new(), Table::new(), Table::new());
+        let technologies = Table::<TechnologyKind>::new();
+
+        let mut host = ScriptHost::new();
+        host.load("smelting.rhai", SMELTING).unwrap();
+        host.declare_content(&mut Content { items: &mut items, tags: &mut tags, recipes: &mut recipes, connectors: &mut connectors }).unwrap();
+
+        let destroy = recipes.get_handle_from_name("destroy-metal");
+        assert!(matches!(recipes[destroy].input_items[0].item, ItemFilter::Tag(_)));
+        assert_eq!(4, connectors[connectors.get_handle_from_name("stack-arm")].hand_size);
+
+        let prototypes = Prototypes { recipes: &recipes, connectors: &connectors, tags: &tags, technologies: &technologies };
+        let mut factory = Factory::new();
+        factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(60.0, 15.0), rotation: Rotation::North, recipe: None }, &prototypes).unwrap();
+
+        let mut retooled = false;
+        for _ in 0..20 {
+            let mut crafts = ProductionStats::new();
+            factory.tick_with_stats(&prototypes, &mut crafts);
+
+            let events: Vec<_> = factory.drain_events().collect();
+            let reactions = host.run_events(&factory, &events, &crafts, &prototypes);
+            assert_eq!(Vec::<ScriptError>::new(), reactions.errors);
+            for command in reactions.commands {
+                retooled |= matches!(command, Command::SetRecipe { recipe: Some(recipe), .. } if recipe == recipes.get_handle_from_name("bronze"));
+                factory.apply(&command, &prototypes).unwrap();
+            }
+        }
+
+        assert!(retooled);
+        let producer = factory.producers().next().unwrap().1;
+        assert_eq!(Some(recipes.get_handle_from_name("bronze")), producer.producer.recipe());
+    }
+
+    #[test]
+    fn crafts_are_of_the_recipe_crafted() {
+        let (mut items, mut tags, mut recipes, mut connectors) = (Table::new(), Table::new(), Table::new(), Table::new());
+        let technologies = Table::<TechnologyKind>::new();
+
+        let mut host = ScriptHost::new();
+        host.load("smelting.rhai", SMELTING).unwrap();
+        host.load("echo.rhai", "fn on_craft(producer, recipe) { set_recipe(producer, recipe); }").unwrap();
+        host.declare_content(&mut Content { items: &mut items, tags: &mut tags, recipes: &mut recipes, connectors: &mut connectors }).unwrap();
+
+        let prototypes = Prototypes { recipes: &recipes, connectors: &connectors, tags: &tags, technologies: &technologies };
+        let (ore, bronze) = (recipes.get_handle_from_name("ore"), recipes.get_handle_from_name("bronze"));
+        let mut factory = Factory::new();
+        factory.apply(&Command::PlaceProducer { id: None, position: Position::from_f32(60.0, 15.0), rotation: Rotation::North, recipe: Some(ore) }, &prototypes).unwrap();
+        let producer = factory.producers().next().unwrap().0;
+
+        let mut crafts = ProductionStats::new();
+        while crafts.producer_crafts(producer) == 0 {
+            factory.tick_with_stats(&prototypes, &mut crafts);
+        }
+
+        // Retooled on the tick the craft finished, before the scripts hear of it.
+        factory.apply(&Command::SetRecipe { producer, recipe: Some(bronze) }, &prototypes).unwrap();
+        let reactions = host.run_events(&factory, &[], &crafts, &prototypes);
+        assert_eq!(vec![Command::SetRecipe { producer, recipe: Some(ore) }], reactions.commands);
+    }
+
+    #[test]
+    fn errors_name_the_script_and_runaway_scripts_stop() {
+        let (mut items, mut tags, mut recipes, mut connectors) = (Table::new(), Table::new(), Table::new(), Table::new());
+        let mut content = Content { items: &mut items, tags: &mut tags, recipes: &mut recipes, connectors: &mut connectors };
+
+        let mut host = ScriptHost::new();
+        host.load("broken.rhai", r#"recipe("bronze", #{ time: 4, inputs: [["tin", 1]] });"#).unwrap();
+        assert_eq!("broken.rhai: unknown item `tin`", host.declare_content(&mut content).unwrap_err().to_string());
+
+        // Declarations are checked against the script that made them, even when declared together.
+        let mut host = ScriptHost::new();
+        host.load("tag.rhai", r#"tag("metal", ["tin"]);"#).unwrap();
+        host.load("items.rhai", r#"item("copper");"#).unwrap();
+        assert_eq!("tag.rhai: unknown item `tin`", host.declare_content(&mut content).unwrap_err().to_string());
+
+        // Scripts that fail don't keep the others from running.
+        let mut host = ScriptHostBuilder::new().with_max_operations(1000).build();
+        host.load("before.rhai", "fn on_tick(tick) { remove(1); }").unwrap();
+        host.load("loop.rhai", "fn on_tick(tick) { loop {} }").unwrap();
+        host.load("typo.rhai", r#"fn on_tick(tick) { set_recipe(2, "tpyo"); }"#).unwrap();
+        host.load("after.rhai", "fn on_tick(tick) { remove(3); }").unwrap();
+
+        let (recipes, connectors, tags, technologies) = (Table::new(), Table::new(), Table::new(), Table::new());
+        let prototypes = Prototypes { recipes: &recipes, connectors: &connectors, tags: &tags, technologies: &technologies };
+        let reactions = host.run_events(&Factory::new(), &[], &ProductionStats::new(), &prototypes);
+        assert_eq!(vec![Command::RemoveEntity { entity: EntityId::from_raw(1) }, Command::RemoveEntity { entity: EntityId::from_raw(3) }], reactions.commands);
+
+        let errors: Vec<_> = reactions.errors.iter().map(|error| error.script.as_str()).collect();
+        assert_eq!(vec!["loop.rhai", "typo.rhai"], errors);
+        assert!(reactions.errors[0].message.starts_with("in `on_tick`"));
+        assert_eq!("unknown recipe `tpyo`", reactions.errors[1].message);
+    }
+
+    #[test]
+    fn failed_scripts_leave_nothing_behind() {
+        let (mut items, mut tags, mut recipes, mut connectors) = (Table::new(), Table::new(), Table::new(), Table::new());
+        let mut content = Content { items: &mut items, tags: &mut tags, recipes: &mut recipes, connectors: &mut connectors };
+
+        let mut host = ScriptHost::new();
+        assert!(host.load("half.rhai", r#"item("half"); remove(0); throw "stop";"#).is_err());
+        assert!(host.load("typo.rhai", r#"item("typo"); item(;"#).is_err());
+        host.load("whole.rhai", r#"item("whole");"#).unwrap();
+        host.declare_content(&mut content).unwrap();
+        assert_eq!(vec!["whole"], items.iter().map(|(handle, _)| items.name(&handle).to_string()).collect::<Vec<_>>());
+
+        // Fails the first tick after asking for a removal, then asks for nothing.
+        host.load("flaky.rhai", r#"
+            fn on_tick(tick) {
+                if this.failed == () {
+                    this.failed = true;
+                    remove(0);
+                    throw "stop";
+                }
+            }
+        "#).unwrap();
+
+        let (recipes, connectors, tags, technologies) = (Table::new(), Table::new(), Table::new(), Table::new());
+        let prototypes = Prototypes { recipes: &recipes, connectors: &connectors, tags: &tags, technologies: &technologies };
+        let factory = Factory::new();
+        assert_eq!(1, host.run_events(&factory, &[], &ProductionStats::new(), &prototypes).errors.len());
+        assert_eq!(Reactions::default(), host.run_events(&factory, &[], &ProductionStats::new(), &prototypes));
+    }
+
+    fn installed(manifest: &str, script: &str) -> Mod {
+        Mod { manifest: ModManifest::parse("test", manifest).unwrap(), scripts: vec![("content.rhai".to_string(), script.to_string())] }
+    }
+
+    #[test]
+    fn mods_declare_into_their_namespace_and_override_their_dependencies() {
+        let (mut items, mut tags, mut recipes, mut connectors) = (Table::new(), Table::new(), Table::new(), Table::new());
+        let mut content = Content { items: &mut items, tags: &mut tags, recipes: &mut recipes, connectors: &mut connectors };
+
+        let mut host = ScriptHost::new();
+        host.load("game.rhai", r#"item("copper"); recipe("melt", #{ time: 10, inputs: [["copper", 1]] });"#).unwrap();
+        host.declare_content(&mut content).unwrap();
+
+        let order = LoadOrder::new(vec![
+            installed("id bronze-age\nversion 1\ndepends metals", r#"
+                item("bronze");
+                recipe("metals:ingot", #{ time: 5, inputs: [["metals:tin", 1], ["copper", 1]], outputs: [["bronze", 2]] });
+                recipe("base:melt", #{ time: 2, inputs: [["base:copper", 1], ["bronze", 1]] });
+            "#),
+            installed("id metals\nversion 1", r#"
+                item("tin");
+                recipe("ingot", #{ time: 10, inputs: [["copper", 1]], outputs: [["tin", 1]] });
+            "#),
+        ]).unwrap();
+        host.load_mods(&order, &mut content).unwrap();
+
+        let (tin, bronze) = (items.get_handle_from_name("metals:tin"), items.get_handle_from_name("bronze-age:bronze"));
+        assert_eq!(3, items.len());
+
+        // Overriding keeps the handle, so content referring to it stays valid.
+        let ingot = recipes.get_handle_from_name("metals:ingot");
+        assert_eq!(2, recipes.len());
+        assert_eq!(5, recipes[ingot].time);
+        assert_eq!(ItemFilter::Item(tin), recipes[ingot].input_items[0].item);
+        assert_eq!(bronze, recipes[ingot].output[0].item);
+
+        // The game's content is overridden like a dependency's, and keeps its name.
+        let melt = recipes.get_handle_from_name("melt");
+        assert_eq!(2, recipes[melt].time);
+        assert_eq!(LocalString::from_str("recipe-melt"), recipes[melt].name);
+        assert_eq!(ItemFilter::Item(bronze), recipes[melt].input_items[1].item);
+    }
+
+    #[test]
+    fn mods_only_refer_to_and_override_their_dependencies() {
+        let load = |mods: Vec<Mod>| {
+            let (mut items, mut tags, mut recipes, mut connectors) = (Table::new(), Table::new(), Table::new(), Table::new());
+            let mut content = Content { items: &mut items, tags: &mut tags, recipes: &mut recipes, connectors: &mut connectors };
+
+            let mut host = ScriptHost::new();
+            host.load("game.rhai", r#"item("iron");"#).unwrap();
+            host.declare_content(&mut content).unwrap();
+            host.load_mods(&LoadOrder::new(mods).unwrap(), &mut content).map_err(|error| error.to_string())
+        };
+
+        let metals = || installed("id metals\nversion 1", r#"item("tin");"#);
+        let smaller_tin = |id: &str| installed(&format!("id {}\nversion 1\ndepends metals", id), r#"item("metals:tin");"#);
+
+        assert_eq!(Err("tools/content.rhai: `metals` isn't a dependency of `tools`".to_string()), load(vec![
+            metals(),
+            installed("id tools\nversion 1", r#"tag("tools", ["metals:tin"]);"#),
+        ]));
+
+        assert_eq!(Err("metals/content.rhai: `metals:tin` is declared already".to_string()), load(vec![
+            installed("id metals\nversion 1", r#"item("tin"); item("metals:tin");"#),
+        ]));
+
+        assert_eq!(Err("b/content.rhai: `metals:tin` is overridden by both `a` and `b`".to_string()), load(vec![metals(), smaller_tin("a"), smaller_tin("b")]));
+
+        let heavier_iron = |id: &str| installed(&format!("id {}\nversion 1", id), r#"item("base:iron");"#);
+        assert_eq!(Err("b/content.rhai: `base:iron` is overridden by both `a` and `b`".to_string()), load(vec![heavier_iron("a"), heavier_iron("b")]));
+
+        // Depending on the other mod settles which override wins.
+        let mut overrides = smaller_tin("b");
+        overrides.manifest.dependencies.push(Dependency { id: "a".to_string(), version: None });
+        assert_eq!(Ok(()), load(vec![metals(), smaller_tin("a"), overrides]));
+    }
+}
@@ -0,0 +1,295 @@
+//! Finding producers by where they are.
+//!
+//! The factory keeps a [`SpatialIndex`] of the bounds of its producers, so
+//! frontends can find what is under the cursor, inside a selection box or
+//! within a connector's reach without going through every producer.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    factory::{EntityId, Position},
+    fixed::Fixed,
+};
+
+/// An axis-aligned rectangle. Contains its minimum edges but not its maximum ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub min: Position,
+    pub max: Position,
+}
+
+impl Rect {
+    pub fn new(min: Position, max: Position) -> Self {
+        Self { min, max }
+    }
+
+    /// The rectangle with two opposite corners, in any order, e.g. where a selection box was started and ended.
+    pub fn from_corners(a: Position, b: Position) -> Self {
+        Self::new(
+            Position::new(a.x.min(b.x), a.y.min(b.y)),
+            Position::new(a.x.max(b.x), a.y.max(b.y)),
+        )
+    }
+
+    /// The rectangle reaching `half_extents` from `center` in every direction.
+    pub fn around(center: Position, half_extents: Position) -> Self {
+        Self::new(center - half_extents, center + half_extents)
+    }
+
+    pub fn contains(&self, point: Position) -> bool {
+        (self.min.x..self.max.x).contains(&point.x)
+        && (self.min.y..self.max.y).contains(&point.y)
+    }
+
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.min.x < other.max.x && other.min.x < self.max.x
+        && self.min.y < other.max.y && other.min.y < self.max.y
+    }
+
+    /// Distance from the point to the closest point of the rectangle, zero inside it.
+    pub fn distance_to(&self, point: Position) -> Fixed {
+        let gap = |value: Fixed, min: Fixed, max: Fixed| (min - value).max(value - max).max(Fixed::ZERO);
+        Fixed::hypot(gap(point.x, self.min.x, self.max.x), gap(point.y, self.min.y, self.max.y))
+    }
+}
+
+/// A spatial hash: the map is split into square cells, each listing the entities overlapping it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpatialIndex {
+    bounds: BTreeMap<EntityId, Rect>,
+    cells: BTreeMap<(i32, i32), BTreeSet<EntityId>>,
+
+    /// Occupied cells in each row, to know how far out there are cells to search.
+    rows: BTreeMap<i32, usize>,
+}
+
+impl SpatialIndex {
+    /// Width and height of a cell.
+    pub const CELL_SIZE: Fixed = Fixed::from_int(240);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn cell(coordinate: Fixed) -> i32 {
+        coordinate.to_bits().div_euclid(Self::CELL_SIZE.to_bits()) as i32
+    }
+
+    /// The cells a rectangle overlaps.
+    fn cells_of(rect: &Rect) -> impl Iterator<Item = (i32, i32)> {
+        let (min_x, min_y) = (Self::cell(rect.min.x), Self::cell(rect.min.y));
+        let (max_x, max_y) = (Self::cell(rect.max.x), Self::cell(rect.max.y));
+
+        (min_y..=max_y).flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+    }
+
+    /// Add an entity, or move it if it is in the index already.
+    pub(crate) fn insert(&mut self, id: EntityId, bounds: Rect) {
+        self.remove(id);
+
+        for cell in Self::cells_of(&bounds) {
+            let entities = self.cells.entry(cell).or_default();
+            if entities.is_empty() {
+                *self.rows.entry(cell.1).or_default() += 1;
+            }
+            entities.insert(id);
+        }
+        self.bounds.insert(id, bounds);
+    }
+
+    pub(crate) fn remove(&mut self, id: EntityId) {
+        let bounds = match self.bounds.remove(&id) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+
+        for cell in Self::cells_of(&bounds) {
+            if let Some(entities) = self.cells.get_mut(&cell) {
+                entities.remove(&id);
+                if entities.is_empty() {
+                    self.cells.remove(&cell);
+                    self.remove_from_row(cell.1);
+                }
+            }
+        }
+    }
+
+    fn remove_from_row(&mut self, row: i32) {
+        if let Some(cells) = self.rows.get_mut(&row) {
+            *cells -= 1;
+            if *cells == 0 {
+                self.rows.remove(&row);
+            }
+        }
+    }
+
+    /// The smallest and largest column and row of the occupied cells, or `None` if there are none.
+    fn occupied(&self) -> Option<((i32, i32), (i32, i32))> {
+        // Cells are ordered by column first, so the first and last are in the outermost columns.
+        let (min_x, max_x) = (self.cells.keys().next()?.0, self.cells.keys().next_back()?.0);
+        let (min_y, max_y) = (*self.rows.keys().next()?, *self.rows.keys().next_back()?);
+
+        Some(((min_x, min_y), (max_x, max_y)))
+    }
+
+    /// The cells `ring` cells away from `origin` that are within `min..=max`.
+    fn ring_cells(origin: (i32, i32), ring: i32, min: (i32, i32), max: (i32, i32)) -> impl Iterator<Item = (i32, i32)> {
+        let (x, y) = origin;
+        let (top_and_bottom, left_and_right) = match ring {
+            0 => (vec![y], vec![]),
+            _ => (vec![y - ring, y + ring], vec![x - ring, x + ring]),
+        };
+
+        let columns = (x - ring).max(min.0)..=(x + ring).min(max.0);
+        let rows = (y - ring + 1).max(min.1)..=(y + ring - 1).min(max.1);
+
+        let horizontal = top_and_bottom
+        .into_iter()
+        .filter(move |row| (min.1..=max.1).contains(row))
+        .flat_map(move |row| columns.clone().map(move |column| (column, row)));
+
+        let vertical = left_and_right
+        .into_iter()
+        .filter(move |column| (min.0..=max.0).contains(column))
+        .flat_map(move |column| rows.clone().map(move |row| (column, row)));
+
+        horizontal.chain(vertical)
+    }
+
+    pub fn bounds(&self, id: EntityId) -> Option<Rect> {
+        self.bounds.get(&id).copied()
+    }
+
+    /// Entities whose bounds contain the point, ordered by id.
+    pub fn query_point(&self, point: Position) -> Vec<EntityId> {
+        self.cells
+        .get(&(Self::cell(point.x), Self::cell(point.y)))
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|id| self.bounds[id].contains(point))
+        .collect()
+    }
+
+    /// Entities whose bounds overlap the rectangle, ordered by id.
+    pub fn query_rect(&self, rect: &Rect) -> Vec<EntityId> {
+        let found: BTreeSet<EntityId> = Self::cells_of(rect)
+        .filter_map(|cell| self.cells.get(&cell))
+        .flatten()
+        .copied()
+        .filter(|id| self.bounds[id].intersects(rect))
+        .collect();
+
+        found.into_iter().collect()
+    }
+
+    /// The entity closest to the point and no further than `max_distance` from it, with its distance.
+    ///
+    /// Of entities at the same distance, the one with the lowest id is picked.
+    pub fn nearest(&self, point: Position, max_distance: Fixed) -> Option<(EntityId, Fixed)> {
+        let origin = (Self::cell(point.x), Self::cell(point.y));
+        let (min, max) = self.occupied()?;
+        let last_ring = (origin.0 - min.0).max(max.0 - origin.0).max(origin.1 - min.1).max(max.1 - origin.1);
+        let mut best: Option<(EntityId, Fixed)> = None;
+
+        // Cells are searched in rings around the point's cell, as far as there are occupied cells. Everything in ring `ring` is at least `ring - 1` cells away.
+        for ring in 0..=last_ring {
+            let closest_possible = Fixed::from_bits(Self::CELL_SIZE.to_bits() * (ring as i64 - 1).max(0));
+            if closest_possible > max_distance || best.map(|(_, distance)| distance <= closest_possible).unwrap_or(false) {
+                break;
+            }
+
+            for cell in Self::ring_cells(origin, ring, min, max) {
+                let entities = match self.cells.get(&cell) {
+                    Some(entities) => entities,
+                    None => continue,
+                };
+
+                for &id in entities {
+                    let distance = self.bounds[&id].distance_to(point);
+                    let closer = match best {
+                        Some((best_id, best_distance)) => (distance, id) < (best_distance, best_id),
+                        None => true,
+                    };
+
+                    if distance <= max_distance && closer {
+                        best = Some((id, distance));
+                    }
+                }
+            }
+        }
+
+        best
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn square(x: f32, y: f32) -> Rect {
+        Rect::around(Position::from_f32(x, y), Position::from_f32(10.0, 10.0))
+    }
+
+    #[test]
+    fn finds_entities_by_point_rectangle_and_distance() {
+        let mut index = SpatialIndex::new();
+        let (a, b, c) = (EntityId::from_raw(0), EntityId::from_raw(1), EntityId::from_raw(2));
+        index.insert(a, square(0.0, 0.0));
+        index.insert(b, square(235.0, 0.0));
+        index.insert(c, square(-1000.0, 500.0));
+
+        assert_eq!(vec![a], index.query_point(Position::from_f32(-10.0, 9.5)));
+        assert!(index.query_point(Position::from_f32(10.0, 0.0)).is_empty());
+        assert_eq!(vec![b], index.query_point(Position::from_f32(242.0, 0.0)));
+
+        let selection = Rect::from_corners(Position::from_f32(300.0, -5.0), Position::from_f32(5.0, 5.0));
+        assert_eq!(vec![a, b], index.query_rect(&selection));
+
+        assert_eq!(Some((b, Fixed::from_int(5))), index.nearest(Position::from_f32(220.0, 0.0), Fixed::from_int(50)));
+        assert_eq!(None, index.nearest(Position::from_f32(-900.0, 300.0), Fixed::from_int(100)));
+        assert_eq!(Some(c), index.nearest(Position::from_f32(-900.0, 300.0), Fixed::from_int(1000)).map(|(id, _)| id));
+
+        // Moving and removing keep the cells in sync.
+        index.insert(b, square(-1000.0, 515.0));
+        assert!(index.query_point(Position::from_f32(242.0, 0.0)).is_empty());
+        assert_eq!(vec![b, c], index.query_point(Position::from_f32(-1000.0, 507.0)));
+
+        index.remove(c);
+        assert_eq!(vec![b], index.query_point(Position::from_f32(-1000.0, 507.0)));
+        assert!(index.cells.values().all(|entities| !entities.contains(&c)));
+    }
+
+    #[test]
+    fn nearest_only_searches_out_to_the_occupied_cells() {
+        let mut index = SpatialIndex::new();
+        let far = Fixed::from_int(i32::MAX);
+        assert_eq!(None, index.nearest(Position::from_f32(0.0, 0.0), far));
+
+        let (a, b) = (EntityId::from_raw(0), EntityId::from_raw(1));
+        index.insert(a, square(0.0, 0.0));
+        index.insert(b, square(5000.0, -3000.0));
+        assert_eq!(Some(a), index.nearest(Position::from_f32(-100_000.0, 0.0), far).map(|(id, _)| id));
+
+        // Same as looking at every entity.
+        for i in 0..40 {
+            index.insert(EntityId::from_raw(i + 2), square((i * 397 % 3000) as f32 - 1500.0, (i * 677 % 2000) as f32 - 1000.0));
+        }
+        for i in 0..100 {
+            let point = Position::from_f32((i * 131 % 4000) as f32 - 2000.0, (i * 89 % 3000) as f32 - 1500.0);
+            let brute_force = index.bounds
+            .iter()
+            .map(|(&id, bounds)| (bounds.distance_to(point), id))
+            .filter(|&(distance, _)| distance <= Fixed::from_int(400))
+            .min()
+            .map(|(distance, id)| (id, distance));
+            assert_eq!(brute_force, index.nearest(point, Fixed::from_int(400)));
+        }
+
+        for i in 0..42 {
+            index.remove(EntityId::from_raw(i));
+        }
+        assert!(index.rows.is_empty());
+        assert_eq!(None, index.nearest(Position::from_f32(0.0, 0.0), far));
+    }
+}
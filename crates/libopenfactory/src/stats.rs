@@ -0,0 +1,88 @@
+//! Counting what a factory makes, e.g. to compare designs.
+//!
+//! Ticking with [`Factory::tick_with_stats`](crate::factory::Factory::tick_with_stats)
+//! records every craft that finishes. What was made and used up follows from
+//! the recipes of those crafts.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    factory::EntityId,
+    item_stack::ItemFilter,
+    kinds::{ItemKind, RecipeKind},
+    registry::{Handle, Table},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProductionStats {
+    /// Ticks recorded.
+    pub ticks: u64,
+    by_recipe: BTreeMap<Handle<RecipeKind>, u64>,
+    by_producer: BTreeMap<EntityId, BTreeMap<Handle<RecipeKind>, u64>>,
+}
+
+impl ProductionStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_craft(&mut self, producer: EntityId, recipe: Handle<RecipeKind>) {
+        *self.by_recipe.entry(recipe).or_default() += 1;
+        *self.by_producer.entry(producer).or_default().entry(recipe).or_default() += 1;
+    }
+
+    /// Crafts finished of the recipe.
+    pub fn crafts(&self, recipe: Handle<RecipeKind>) -> u64 {
+        self.by_recipe.get(&recipe).copied().unwrap_or_default()
+    }
+
+    /// Crafts finished by the producer.
+    pub fn producer_crafts(&self, producer: EntityId) -> u64 {
+        self.by_producer.get(&producer).map(|recipes| recipes.values().sum()).unwrap_or_default()
+    }
+
+    /// Recipes with at least one finished craft and their number of crafts, ordered by handle.
+    pub fn recipes(&self) -> impl Iterator<Item = (Handle<RecipeKind>, u64)> + '_ {
+        self.by_recipe.iter().map(|(&recipe, &crafts)| (recipe, crafts))
+    }
+
+    /// Producers with at least one finished craft and their number of crafts, ordered by id.
+    pub fn producers(&self) -> impl Iterator<Item = (EntityId, u64)> + '_ {
+        self.by_producer.iter().map(|(&producer, recipes)| (producer, recipes.values().sum()))
+    }
+
+    /// Crafts finished by each producer of each recipe, ordered by producer and then recipe handle.
+    ///
+    /// The recipe is the one crafted, even if the producer has another one by now.
+    pub fn producer_recipes(&self) -> impl Iterator<Item = (EntityId, Handle<RecipeKind>, u64)> + '_ {
+        self.by_producer
+        .iter()
+        .flat_map(|(&producer, recipes)| recipes.iter().map(move |(&recipe, &crafts)| (producer, recipe, crafts)))
+    }
+
+    /// Items made by the finished crafts.
+    pub fn produced(&self, recipes: &Table<RecipeKind>) -> BTreeMap<Handle<ItemKind>, u64> {
+        let mut produced = BTreeMap::new();
+
+        for (recipe, crafts) in self.recipes() {
+            for output in &recipes[recipe].output {
+                *produced.entry(output.item).or_default() += crafts * output.quantity as u64;
+            }
+        }
+
+        produced
+    }
+
+    /// Items used up by the finished crafts, by the recipe input they went into. Worn tools aren't used up.
+    pub fn consumed(&self, recipes: &Table<RecipeKind>) -> BTreeMap<ItemFilter, u64> {
+        let mut consumed = BTreeMap::new();
+
+        for (recipe, crafts) in self.recipes() {
+            for input in recipes[recipe].input_items.iter().filter(|input| input.wear.is_none()) {
+                *consumed.entry(input.item).or_default() += crafts * input.quantity as u64;
+            }
+        }
+
+        consumed
+    }
+}
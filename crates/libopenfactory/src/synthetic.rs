@@ -0,0 +1,192 @@
+//! Generated factories for benchmarks and stress tests.
+//!
+//! A synthetic factory is a number of identical chains, one per row. Each
+//! chain is a line of producers: the first makes an item out of nothing, every
+//! next one turns the item before it into the next, and the last destroys what
+//! it gets, so the chain never backs up. Neighbours in a chain are linked by a
+//! number of connectors side by side.
+
+use crate::{
+    command::Command,
+    factory::{EntityId, Factory, Position, Prototypes},
+    item_stack::ItemData,
+    kinds::{ConnectorKind, ConnectorKindBuilder, ItemKind, ItemKindBuilder, ItemTag, QualityRule, RecipeInput, RecipeKind, RecipeOutput, TechnologyKind},
+    local_string::LocalString,
+    registry::Table,
+    stats::ProductionStats,
+    tile::Rotation,
+};
+
+/// Distance between the producers of a chain, and between chains.
+const COLUMN_WIDTH: f32 = 120.0;
+const ROW_HEIGHT: f32 = 30.0;
+
+pub struct SyntheticFactory {
+    pub items: Table<ItemKind>,
+    pub recipes: Table<RecipeKind>,
+    pub connectors: Table<ConnectorKind>,
+    pub tags: Table<ItemTag>,
+    pub technologies: Table<TechnologyKind>,
+    pub factory: Factory,
+}
+
+impl SyntheticFactory {
+    pub fn prototypes(&self) -> Prototypes<'_> {
+        Prototypes { recipes: &self.recipes, connectors: &self.connectors, tags: &self.tags, technologies: &self.technologies }
+    }
+
+    /// Simulate a tick, counting the crafts that finish.
+    pub fn tick(&mut self, stats: &mut ProductionStats) {
+        let prototypes = Prototypes { recipes: &self.recipes, connectors: &self.connectors, tags: &self.tags, technologies: &self.technologies };
+        self.factory.tick_with_stats(&prototypes, stats);
+    }
+}
+
+pub struct SyntheticFactoryBuilder {
+    chains: u32,
+    chain_length: u32,
+    connectors_per_link: u32,
+}
+
+impl SyntheticFactoryBuilder {
+    pub fn new() -> Self {
+        Self {
+            chains: 1,
+            chain_length: 3,
+            connectors_per_link: 1,
+        }
+    }
+
+    pub fn with_chains(mut self, chains: u32) -> Self {
+        self.chains = chains;
+        self
+    }
+
+    /// Producers, and so recipes, per chain.
+    pub fn with_chain_length(mut self, chain_length: u32) -> Self {
+        self.chain_length = chain_length;
+        self
+    }
+
+    /// Connectors between each pair of neighbours in a chain.
+    pub fn with_connectors_per_link(mut self, connectors_per_link: u32) -> Self {
+        self.connectors_per_link = connectors_per_link;
+        self
+    }
+
+    pub fn build(self) -> SyntheticFactory {
+        if self.chain_length == 0 {
+            panic!("Synthetic Factory Builder built with chains of no producers");
+        }
+
+        let mut items = Table::new();
+        let mut recipes = Table::new();
+        let mut connectors = Table::new();
+
+        let item_handles: Vec<_> = (1..self.chain_length)
+        .map(|step| {
+            let name = format!("item-{}", step);
+            items.insert(ItemKindBuilder::new().with_name(LocalString::from_str(&name)).build(), name)
+        })
+        .collect();
+
+        // Times differ from step to step, so crafts don't all finish on the same ticks.
+        let recipe_handles: Vec<_> = (0..self.chain_length as usize)
+        .map(|step| {
+            let name = format!("step-{}", step);
+            let recipe = RecipeKind {
+                name: LocalString::from_str(&name),
+                input_items: step
+                .checked_sub(1)
+                .map(|previous| RecipeInput { item: item_handles[previous].into(), quantity: 1, wear: None })
+                .into_iter()
+                .collect(),
+                output: item_handles
+                .get(step)
+                .map(|&item| RecipeOutput { item, quantity: 1, data: ItemData::default() })
+                .into_iter()
+                .collect(),
+                time: 10 + 5 * (step % 4) as crate::Time,
+                quality: QualityRule::default(),
+                mining: false,
+            };
+            recipes.insert(recipe, name)
+        })
+        .collect();
+
+        let connector = connectors.insert(ConnectorKindBuilder::new().with_name(LocalString::from_str("connector")).build(), "connector".to_string());
+
+        let mut synthetic = SyntheticFactory {
+            items,
+            recipes,
+            connectors,
+            tags: Table::new(),
+            technologies: Table::new(),
+            factory: Factory::new(),
+        };
+
+        let prototypes = Prototypes { recipes: &synthetic.recipes, connectors: &synthetic.connectors, tags: &synthetic.tags, technologies: &synthetic.technologies };
+        let factory = &mut synthetic.factory;
+
+        for chain in 0..self.chains {
+            let mut previous: Option<EntityId> = None;
+
+            for (step, &recipe) in recipe_handles.iter().enumerate() {
+                let position = Position::from_f32(60.0 + COLUMN_WIDTH * step as f32, 15.0 + ROW_HEIGHT * chain as f32);
+                factory
+                .apply(&Command::PlaceProducer { id: None, position, rotation: Rotation::North, recipe: Some(recipe) }, &prototypes)
+                .expect("chains don't overlap");
+
+                let producer = factory
+                .producer_at(position)
+                .expect("producer was just placed");
+
+                if let Some(giver) = previous {
+                    for _ in 0..self.connectors_per_link {
+                        factory
+                        .apply(&Command::Connect { id: None, giver, taker: producer, kind: connector }, &prototypes)
+                        .expect("neighbours can be connected");
+                    }
+                }
+
+                previous = Some(producer);
+            }
+        }
+
+        // Nobody is watching the events.
+        factory.drain_events().for_each(drop);
+        synthetic
+    }
+}
+
+impl Default for SyntheticFactoryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chains_run_to_the_end() {
+        let mut synthetic = SyntheticFactoryBuilder::new()
+        .with_chains(3)
+        .with_chain_length(4)
+        .with_connectors_per_link(2)
+        .build();
+
+        assert_eq!(12, synthetic.factory.producers().count());
+        assert_eq!(18, synthetic.factory.connectors().count());
+
+        let mut stats = ProductionStats::new();
+        for _ in 0..1000 {
+            synthetic.tick(&mut stats);
+        }
+
+        let last = synthetic.recipes.get_handle_from_name("step-3");
+        assert!(stats.crafts(last) > 0);
+        assert!(synthetic.recipes[last].output.is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+//! Shared setup for tests.
+
+use crate::{
+    factory::Prototypes,
+    item_stack::ItemData,
+    kinds::{ConnectorKind, ConnectorKindBuilder, ItemKind, ItemKindBuilder, ItemTag, QualityRule, RecipeInput, RecipeKind, RecipeOutput, TechnologyKind},
+    local_string::LocalString,
+    registry::{Handle, Table},
+};
+
+/// A generator feeding a consumer, and a connector to link them.
+pub(crate) struct Fixture {
+    pub(crate) recipes: Table<RecipeKind>,
+    pub(crate) connectors: Table<ConnectorKind>,
+    pub(crate) tags: Table<ItemTag>,
+    pub(crate) technologies: Table<TechnologyKind>,
+    pub(crate) generate: Handle<RecipeKind>,
+    pub(crate) consume: Handle<RecipeKind>,
+    pub(crate) connector: Handle<ConnectorKind>,
+}
+
+impl Fixture {
+    pub(crate) fn new() -> Self {
+        let mut items = Table::<ItemKind>::new();
+        let item = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("item")).build(), "item".to_string());
+
+        let mut recipes = Table::new();
+        let generate = recipes.insert(RecipeKind {
+            name: LocalString::from_str("generate"),
+            input_items: vec![],
+            output: vec![RecipeOutput { item, quantity: 1, data: ItemData::default() }],
+            time: 2,
+            quality: QualityRule::default(),
+            mining: false,
+        }, "generate".to_string());
+        let consume = recipes.insert(RecipeKind {
+            name: LocalString::from_str("consume"),
+            input_items: vec![RecipeInput { item: item.into(), quantity: 1, wear: None }],
+            output: vec![],
+            time: 100,
+            quality: QualityRule::default(),
+            mining: false,
+        }, "consume".to_string());
+
+        let mut connectors = Table::new();
+        let connector = connectors.insert(ConnectorKindBuilder::new().with_name(LocalString::from_str("connector")).build(), "connector".to_string());
+
+        Self { recipes, connectors, tags: Table::new(), technologies: Table::new(), generate, consume, connector }
+    }
+
+    pub(crate) fn prototypes(&self) -> Prototypes<'_> {
+        Prototypes { recipes: &self.recipes, connectors: &self.connectors, tags: &self.tags, technologies: &self.technologies }
+    }
+}
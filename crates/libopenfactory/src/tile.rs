@@ -0,0 +1,249 @@
+//! The grid of square tiles the factory map is divided into.
+//!
+//! Producers cover a [`Footprint`] of tiles, and no two producers can cover
+//! the same tile. Connectors run between producers and don't cover any.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    checksum::{Checksum, StateHasher},
+    codec::{Decode, DecodeError, Encode},
+    factory::{EntityId, Position},
+    fixed::Fixed,
+};
+
+/// Width and height of a tile.
+pub const TILE_SIZE: Fixed = Fixed::from_int(30);
+
+/// A tile, counted in tiles from the one whose corner is at the origin.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TilePosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl TilePosition {
+    pub fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    /// The tile a point is on.
+    pub fn containing(position: Position) -> Self {
+        let tile = |coordinate: Fixed| coordinate.to_bits().div_euclid(TILE_SIZE.to_bits()) as i32;
+        Self::new(tile(position.x), tile(position.y))
+    }
+
+    /// The point in the middle of the tile.
+    pub fn center(self) -> Position {
+        let center = |tile: i32| Fixed::from_bits(tile as i64 * TILE_SIZE.to_bits() + TILE_SIZE.to_bits() / 2);
+        Position::new(center(self.x), center(self.y))
+    }
+}
+
+/// The tiles something placed on the map covers, in tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Footprint {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Footprint {
+    pub const fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+
+    /// Number of tiles covered.
+    pub fn area(self) -> u32 {
+        self.width * self.height
+    }
+
+    /// Half the width and height in world units.
+    pub fn half_extents(self) -> Position {
+        let half = |tiles: u32| Fixed::from_bits(TILE_SIZE.to_bits() * tiles as i64 / 2);
+        Position::new(half(self.width), half(self.height))
+    }
+
+    /// The footprint turned by the rotation.
+    pub fn rotated(self, rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::North | Rotation::South => self,
+            Rotation::East | Rotation::West => Self::new(self.height, self.width),
+        }
+    }
+
+    /// Offset from the center of the footprint to the center of the tile in its corner with the lowest coordinates.
+    fn corner_offset(self) -> Position {
+        let offset = |tiles: u32| Fixed::from_bits(TILE_SIZE.to_bits() * (tiles as i64 - 1) / 2);
+        Position::new(offset(self.width), offset(self.height))
+    }
+
+    fn corner(self, position: Position) -> TilePosition {
+        TilePosition::containing(position - self.corner_offset())
+    }
+
+    /// The tiles covered when centered on `position`, row by row.
+    ///
+    /// These are the tiles whose centers are under the footprint.
+    pub fn tiles(self, position: Position) -> impl Iterator<Item = TilePosition> {
+        let corner = self.corner(position);
+        let Footprint { width, height } = self;
+
+        (0..height as i32).flat_map(move |y| (0..width as i32).map(move |x| TilePosition::new(corner.x + x, corner.y + y)))
+    }
+
+    /// The position near `position` where the footprint lines up with the tiles it covers.
+    pub fn snap(self, position: Position) -> Position {
+        self.corner(position).center() + self.corner_offset()
+    }
+}
+
+/// Which way something placed on the map faces, in quarter turns clockwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Rotation {
+    #[default]
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Rotation {
+    pub const ALL: [Rotation; 4] = [Rotation::North, Rotation::East, Rotation::South, Rotation::West];
+
+    /// A quarter turn further clockwise.
+    pub fn clockwise(self) -> Self {
+        Self::ALL[(self.quarter_turns() as usize + 1) % 4]
+    }
+
+    pub fn quarter_turns(self) -> u8 {
+        self as u8
+    }
+
+    /// This rotation turned further by another.
+    pub fn turned(self, by: Rotation) -> Self {
+        Self::ALL[((self.quarter_turns() + by.quarter_turns()) % 4) as usize]
+    }
+
+    /// Turn an offset from the center of something facing north so it faces this way.
+    pub fn rotate(self, offset: Position) -> Position {
+        match self {
+            Rotation::North => offset,
+            Rotation::East => Position::new(offset.y, -offset.x),
+            Rotation::South => Position::new(-offset.x, -offset.y),
+            Rotation::West => Position::new(-offset.y, offset.x),
+        }
+    }
+}
+
+/// Which entity covers each tile.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TileGrid {
+    occupants: BTreeMap<TilePosition, EntityId>,
+}
+
+impl TileGrid {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn occupant(&self, tile: TilePosition) -> Option<EntityId> {
+        self.occupants.get(&tile).copied()
+    }
+
+    /// The first entity other than `ignored` covering any of the tiles.
+    pub fn first_occupant(&self, tiles: impl IntoIterator<Item = TilePosition>, ignored: Option<EntityId>) -> Option<EntityId> {
+        tiles
+        .into_iter()
+        .filter_map(|tile| self.occupant(tile))
+        .find(|&occupant| Some(occupant) != ignored)
+    }
+
+    /// Cover the tiles with the entity. Returns the first entity that covered one of them already, leaving the grid unchanged.
+    pub(crate) fn occupy(&mut self, tiles: &[TilePosition], id: EntityId) -> Result<(), EntityId> {
+        if let Some(occupant) = self.first_occupant(tiles.iter().copied(), None) {
+            return Err(occupant);
+        }
+
+        self.occupants.extend(tiles.iter().map(|&tile| (tile, id)));
+        Ok(())
+    }
+
+    /// Uncover the tiles covered by the entity.
+    pub(crate) fn vacate(&mut self, tiles: &[TilePosition], id: EntityId) {
+        for tile in tiles {
+            if self.occupant(*tile) == Some(id) {
+                self.occupants.remove(tile);
+            }
+        }
+    }
+}
+
+impl Checksum for Rotation {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u8(self.quarter_turns());
+    }
+}
+
+impl Checksum for TilePosition {
+    fn checksum(&self, hasher: &mut StateHasher) {
+        hasher.write_u32(self.x as u32);
+        hasher.write_u32(self.y as u32);
+    }
+}
+
+impl Encode for TilePosition {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.x.encode(out);
+        self.y.encode(out);
+    }
+}
+
+impl Decode for TilePosition {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(TilePosition::new(i32::decode(input)?, i32::decode(input)?))
+    }
+}
+
+impl Encode for Rotation {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.quarter_turns().encode(out);
+    }
+}
+
+impl Decode for Rotation {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            turns @ 0..=3 => Ok(Rotation::ALL[turns as usize]),
+            tag => Err(DecodeError::InvalidTag { tag, type_name: "Rotation" }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn footprints_cover_the_tiles_under_them() {
+        assert_eq!(TilePosition::new(-1, 0), TilePosition::containing(Position::from_f32(-0.5, 29.9)));
+        assert_eq!(Position::from_f32(45.0, -15.0), TilePosition::new(1, -1).center());
+
+        let tiles: Vec<_> = Footprint::new(4, 1).tiles(Position::from_f32(60.0, 15.0)).collect();
+        assert_eq!((0..4).map(|x| TilePosition::new(x, 0)).collect::<Vec<_>>(), tiles);
+
+        let tiles: Vec<_> = Footprint::new(1, 1).tiles(TilePosition::new(3, -2).center()).collect();
+        assert_eq!(vec![TilePosition::new(3, -2)], tiles);
+
+        let turned = Footprint::new(4, 1).rotated(Rotation::East);
+        assert_eq!(Position::from_f32(15.0, 60.0), turned.snap(Position::from_f32(20.0, 50.0)));
+        assert_eq!(4, turned.tiles(Position::from_f32(15.0, 60.0)).filter(|tile| tile.x == 0).count());
+    }
+
+    #[test]
+    fn rotations_turn_clockwise() {
+        let offset = Position::from_f32(-45.0, 0.0);
+        assert_eq!(Position::from_f32(0.0, 45.0), Rotation::East.rotate(offset));
+        assert_eq!(Position::from_f32(45.0, 0.0), Rotation::South.rotate(offset));
+        assert_eq!(Rotation::North, Rotation::West.clockwise());
+    }
+}
@@ -0,0 +1,88 @@
+# German.
+
+locale-changed = Sprache: Deutsch
+
+## Toolbar
+
+action-connect = Verbinden
+action-stack-connect = Stapelweise
+action-copper = Kupfer
+action-tin = Zinn
+action-bronze = Bronze
+action-trash = Müll
+action-lab = Labor
+action-move = Verschieben
+action-remove = Entfernen
+action-copy = Kopieren
+action-paste = Einfügen
+
+## Speed
+
+tick-pause = Pause
+tick-step = Schritt
+tick-slower = Langsamer
+tick-faster = Schneller
+tick-paused = Pausiert
+tick-speed = Tempo { $speed }×
+tick-behind = { $ticks ->
+    [one] Ein Tick im Rückstand
+   *[other] { $ticks } Ticks im Rückstand
+}
+
+## Items
+
+item-iron-plate = Eisenplatte
+item-iron-pipe = Eisenrohr
+item-copper = Kupfer
+item-tin = Zinn
+item-bronze = Bronze
+
+## Quality
+
+quality-normal = Normal
+quality-good = Gut
+quality-excellent = Exzellent
+
+## Tags
+
+tag-metal = Metall
+
+## Recipes
+
+producer-no-recipe = Kein Rezept
+recipe-mine-copper = { item-copper } abbauen
+recipe-mine-tin = { item-tin } abbauen
+recipe-bronze = { item-bronze }
+recipe-destroy-metal = { tag-metal } vernichten
+recipe-iron-pipe = { item-iron-pipe }
+recipe-research-metallurgy = { tech-metallurgy } erforschen
+
+## Connectors
+
+connector = Verbinder
+connector-stack = Stapelverbinder
+
+## Map
+
+deposit-remaining = noch { $amount }
+items-returned = Bisher zurückerhalten: { $items }
+
+## Blueprints
+
+blueprint-copied = { $count ->
+    [one] Einen Produzenten kopiert. Mit --blueprint und diesem Text lässt er sich anderswo einfügen:
+   *[other] { $count } Produzenten kopiert. Mit --blueprint und diesem Text lassen sie sich anderswo einfügen:
+}
+
+## Research
+
+tech-metallurgy = Metallurgie
+research-started = Erforsche { $technology }
+research-completed = { $technology } erforscht
+
+## Replays
+
+replay-position = { $seconds ->
+    [one] Eine Sekunde in der Wiedergabe
+   *[other] { $seconds } Sekunden in der Wiedergabe
+}
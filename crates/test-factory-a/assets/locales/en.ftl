@@ -0,0 +1,88 @@
+# English. This is the fallback for messages missing from other languages.
+
+locale-changed = Language: English
+
+## Toolbar
+
+action-connect = Connect
+action-stack-connect = Stack Connect
+action-copper = Copper
+action-tin = Tin
+action-bronze = Bronze
+action-trash = Trash
+action-lab = Lab
+action-move = Move
+action-remove = Remove
+action-copy = Copy
+action-paste = Paste
+
+## Speed
+
+tick-pause = Pause
+tick-step = Step
+tick-slower = Slower
+tick-faster = Faster
+tick-paused = Paused
+tick-speed = Speed { $speed }×
+tick-behind = { $ticks ->
+    [one] One tick behind
+   *[other] { $ticks } ticks behind
+}
+
+## Items
+
+item-iron-plate = Iron Plate
+item-iron-pipe = Iron Pipe
+item-copper = Copper
+item-tin = Tin
+item-bronze = Bronze
+
+## Quality
+
+quality-normal = Normal
+quality-good = Good
+quality-excellent = Excellent
+
+## Tags
+
+tag-metal = Metal
+
+## Recipes
+
+producer-no-recipe = No Recipe
+recipe-mine-copper = Mine { item-copper }
+recipe-mine-tin = Mine { item-tin }
+recipe-bronze = { item-bronze }
+recipe-destroy-metal = Destroy { tag-metal }
+recipe-iron-pipe = { item-iron-pipe }
+recipe-research-metallurgy = Research { tech-metallurgy }
+
+## Connectors
+
+connector = Connector
+connector-stack = Stack Connector
+
+## Map
+
+deposit-remaining = { $amount } left
+items-returned = Returned so far: { $items }
+
+## Blueprints
+
+blueprint-copied = { $count ->
+    [one] Copied one producer. Start with --blueprint and this to paste it elsewhere:
+   *[other] Copied { $count } producers. Start with --blueprint and this to paste them elsewhere:
+}
+
+## Research
+
+tech-metallurgy = Metallurgy
+research-started = Researching { $technology }
+research-completed = Researched { $technology }
+
+## Replays
+
+replay-position = { $seconds ->
+    [one] One second into the replay
+   *[other] { $seconds } seconds into the replay
+}
@@ -0,0 +1,172 @@
+//! Box-selecting producers into a blueprint, and pasting it elsewhere.
+//!
+//! Copying prints the blueprint as text to share. Start the game with
+//! `--blueprint <text>` to paste one that was shared.
+
+use bevy::prelude::*;
+use open_factory::blueprint::Blueprint;
+use open_factory::command::Command;
+use open_factory::factory::{Factory, Position};
+use open_factory::history::History;
+use open_factory::kinds::{ConnectorKind, RecipeKind};
+use open_factory::local_string::LocalString;
+use open_factory::localization::Localizer;
+use open_factory::registry::Table;
+use open_factory::spatial::Rect;
+use open_factory::tile::TilePosition;
+
+use crate::camera::MousePositionInWorld;
+use crate::factory::PlacementRotation;
+use crate::session::Session;
+use crate::ui::Action;
+use crate::Colors;
+
+pub struct BlueprintPlugin;
+
+impl Plugin for BlueprintPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+        .init_resource::<Clipboard>()
+        .insert_resource::<Option<SelectionStart>>(None)
+        .add_startup_system(spawn_selection_box.system())
+        .add_system(copy_system.system())
+        .add_system(paste_system.system())
+        ;
+    }
+}
+
+/// The blueprint Paste places.
+struct Clipboard(Option<Blueprint>);
+
+impl FromWorld for Clipboard {
+    fn from_world(world: &mut World) -> Self {
+        let text = match crate::flag_value("--blueprint") {
+            Some(text) => text,
+            None => return Clipboard(None),
+        };
+
+        let recipes = world.get_resource::<Table<RecipeKind>>().expect("database is loaded before blueprints");
+        let connectors = world.get_resource::<Table<ConnectorKind>>().expect("database is loaded before blueprints");
+
+        match Blueprint::from_text(&text, recipes, connectors) {
+            Ok(blueprint) => Clipboard(Some(blueprint)),
+            Err(error) => {
+                eprintln!("Can't read the blueprint: {}", error);
+                Clipboard(None)
+            },
+        }
+    }
+}
+
+/// Where the mouse was pressed to start a selection box.
+struct SelectionStart(Vec2);
+
+/// Marks the sprite showing the selection box.
+struct SelectionBox;
+
+fn spawn_selection_box(mut commands: Commands, colors: Res<Colors>) {
+    commands
+    .spawn_bundle(SpriteBundle {
+        material: colors.ghost_valid.clone(),
+        visible: Visible { is_visible: false, is_transparent: true },
+        ..Default::default()
+    })
+    .insert(SelectionBox);
+}
+
+/// While copying, dragging a box copies the producers in it and the connectors between them.
+fn copy_system(
+    action: Res<Action>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    mouse_position: Res<Option<MousePositionInWorld>>,
+    factory: Res<Factory>,
+    recipes: Res<Table<RecipeKind>>,
+    connectors: Res<Table<ConnectorKind>>,
+    localizer: Res<Localizer>,
+    mut clipboard: ResMut<Clipboard>,
+    mut selection_start: ResMut<Option<SelectionStart>>,
+    mut box_query: Query<(&mut Transform, &mut Sprite, &mut Visible), With<SelectionBox>>,
+) {
+    let (mut transform, mut sprite, mut visible) = match box_query.single_mut() {
+        Ok(selection_box) => selection_box,
+        Err(_) => return,
+    };
+
+    let cursor = match (*action, &*mouse_position) {
+        (Action::CopyBlueprint, Some(mouse_position)) => Vec2::new(mouse_position.x, mouse_position.y),
+        _ => {
+            *selection_start = None;
+            visible.is_visible = false;
+            return;
+        },
+    };
+
+    if mouse_button_input.just_pressed(MouseButton::Left) {
+        *selection_start = Some(SelectionStart(cursor));
+    }
+
+    let start = match *selection_start {
+        Some(SelectionStart(start)) => start,
+        None => return,
+    };
+
+    // Above the producers, so it shows what gets copied.
+    transform.translation = ((start + cursor) / 2.0).extend(6.0);
+    sprite.size = (cursor - start).abs();
+    visible.is_visible = true;
+
+    if mouse_button_input.just_released(MouseButton::Left) {
+        *selection_start = None;
+        visible.is_visible = false;
+
+        let area = Rect::from_corners(Position::from_f32(start.x, start.y), Position::from_f32(cursor.x, cursor.y));
+        let blueprint = Blueprint::copy(&factory, &area);
+        if blueprint.is_empty() {
+            eprintln!("Nothing to copy here!");
+            return;
+        }
+
+        println!("{}", localizer.localize(&LocalString::from_str("blueprint-copied").with_arg("count", blueprint.producers.len() as u32)));
+        println!("{}", blueprint.to_text(&recipes, &connectors));
+        clipboard.0 = Some(blueprint);
+    }
+}
+
+/// While pasting, clicking places the copied blueprint, turned the way producers are placed.
+fn paste_system(
+    action: Res<Action>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    mouse_position: Res<Option<MousePositionInWorld>>,
+    rotation: Res<PlacementRotation>,
+    clipboard: Res<Clipboard>,
+    session: Option<ResMut<Session>>,
+    history: Option<ResMut<History>>,
+) {
+    if *action != Action::PasteBlueprint || !mouse_button_input.just_released(MouseButton::Left) {
+        return;
+    }
+
+    // Without a session, a replay is playing and can't be edited.
+    let (mut session, mut history) = match (session, history) {
+        (Some(session), Some(history)) => (session, history),
+        _ => return,
+    };
+
+    let mouse_position = if let Some(ref mouse_position) = *mouse_position {
+        mouse_position
+    } else {
+        return;
+    };
+
+    let blueprint = match clipboard.0 {
+        Some(ref blueprint) => blueprint.clone(),
+        None => {
+            eprintln!("Copy something first, or start with --blueprint!");
+            return;
+        },
+    };
+
+    // Blueprints line up with the tiles when placed on the center of one.
+    let position = TilePosition::containing(Position::from_f32(mouse_position.x, mouse_position.y)).center();
+    session.issue(history.issue(Command::PlaceBlueprint { blueprint, position, rotation: rotation.0 }));
+}
@@ -17,12 +17,6 @@ impl Plugin for CameraPlugin {
 struct MainCamera;
 pub struct MousePositionInWorld(Vec2);
 
-impl MousePositionInWorld {
-    pub fn transform(&self) -> Transform {
-        Transform::from_xyz(self.x, self.y, 0.0)
-    }
-}
-
 impl Deref for MousePositionInWorld {
     type Target = Vec2;
 
@@ -1,60 +1,149 @@
 use bevy::prelude::*;
-use open_factory::{kinds::{ItemKind, ItemKindBuilder, RecipeInput, RecipeKind, RecipeOutput}, local_string::LocalString, registry::Table};
+use open_factory::{deposit::{Deposit, Deposits}, item_stack::ItemData, kinds::{ConnectorKind, ConnectorKindBuilder, ItemKind, ItemKindBuilder, ItemTag, ItemTagBuilder, QualityRule, RecipeInput, RecipeKind, RecipeOutput, TechnologyEffect, TechnologyKind, TechnologyKindBuilder}, local_string::LocalString, registry::{Handle, Table}, tile::TilePosition};
 
 pub struct DatabasePlugin;
 
+/// Fills the prototype tables while the app is built, so later plugins can use them, e.g. to make the map.
 impl Plugin for DatabasePlugin {
     fn build(&self, app: &mut bevy::prelude::AppBuilder) {
+        let mut items = Table::<ItemKind>::new();
+        let mut recipes = Table::<RecipeKind>::new();
+        let mut connectors = Table::<ConnectorKind>::new();
+        let mut tags = Table::<ItemTag>::new();
+        let mut technologies = Table::<TechnologyKind>::new();
+        populate(&mut items, &mut recipes, &mut connectors, &mut tags, &mut technologies);
+
         app
-        .insert_resource(Table::<ItemKind>::new())
-        .insert_resource(Table::<RecipeKind>::new())
-        .add_startup_system_to_stage(StartupStage::Startup, setup.system())
+        .insert_resource(items)
+        .insert_resource(recipes)
+        .insert_resource(connectors)
+        .insert_resource(tags)
+        .insert_resource(technologies)
         ;
     }
 }
 
-fn setup(
-    mut items: ResMut<Table<ItemKind>>,
-    mut recipes: ResMut<Table<RecipeKind>>,
+/// Fill the tables with the game's prototypes.
+///
+/// Kept apart from the plugin so the tables can be built without an app, e.g. to play replays headlessly.
+pub fn populate(
+    items: &mut Table<ItemKind>,
+    recipes: &mut Table<RecipeKind>,
+    connectors: &mut Table<ConnectorKind>,
+    tags: &mut Table<ItemTag>,
+    technologies: &mut Table<TechnologyKind>,
 ) {
-    let iron_plate = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("iron-plate")).build(), "iron-plate".to_string());
-    let iron_pipe = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("iron-pipe")).build(), "iron-pipe".to_string());
-    let copper = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("copper")).build(), "copper".to_string());
-    let tin = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("tin")).build(), "tin".to_string());
-    let bronze = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("bronze")).build(), "bronze".to_string());
+    let iron_plate = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("item-iron-plate")).build(), "iron-plate".to_string());
+    let iron_pipe = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("item-iron-pipe")).build(), "iron-pipe".to_string());
+    let copper = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("item-copper")).build(), "copper".to_string());
+    let tin = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("item-tin")).build(), "tin".to_string());
+    let bronze = items.insert(ItemKindBuilder::new().with_name(LocalString::from_str("item-bronze")).build(), "bronze".to_string());
+
+    let metal = tags.insert(
+        ItemTagBuilder::new()
+        .with_name(LocalString::from_str("tag-metal"))
+        .with_item(iron_plate)
+        .with_item(copper)
+        .with_item(tin)
+        .with_item(bronze)
+        .build(),
+
+        "metal".to_string(),
+    );
 
     recipes.insert(RecipeKind {
-        name: LocalString::from_str("recipe:generate-copper"),
+        name: LocalString::from_str("recipe-mine-copper"),
         input_items: vec![],
-        output: vec![RecipeOutput { item: copper, quantity: 1 }],
+        output: vec![RecipeOutput { item: copper, quantity: 1, data: ItemData::default() }],
         time: 20,
-    }, "generate-copper".to_string());
+        quality: QualityRule::default(),
+        mining: true,
+    }, "mine-copper".to_string());
 
     recipes.insert(RecipeKind {
-        name: LocalString::from_str("recipe:generate-tin"),
+        name: LocalString::from_str("recipe-mine-tin"),
         input_items: vec![],
-        output: vec![RecipeOutput { item: tin, quantity: 1 }],
+        output: vec![RecipeOutput { item: tin, quantity: 1, data: ItemData::default() }],
         time: 20,
-    }, "generate-tin".to_string());
+        quality: QualityRule::default(),
+        mining: true,
+    }, "mine-tin".to_string());
 
-    recipes.insert(RecipeKind {
-        name: LocalString::from_str("recipe:bronze"),
-        input_items: vec![RecipeInput { item: copper, quantity: 2 }, RecipeInput { item: tin, quantity: 1 }],
-        output: vec![RecipeOutput { item: bronze, quantity: 3 }],
+    let make_bronze = recipes.insert(RecipeKind {
+        name: LocalString::from_str("recipe-bronze"),
+        input_items: vec![RecipeInput { item: copper.into(), quantity: 2, wear: None }, RecipeInput { item: tin.into(), quantity: 1, wear: None }],
+        output: vec![RecipeOutput { item: bronze, quantity: 3, data: ItemData::default() }],
         time: 20,
+        quality: QualityRule { from_inputs: true, upgrade_chance: 100 },
+        mining: false,
     }, "bronze".to_string());
 
     recipes.insert(RecipeKind {
-        name: LocalString::from_str("recipe:destroy-bronze"),
-        input_items: vec![RecipeInput { item: bronze, quantity: 1 }],
+        name: LocalString::from_str("recipe-destroy-metal"),
+        input_items: vec![RecipeInput { item: metal.into(), quantity: 1, wear: None }],
         output: vec![],
         time: 5,
-    }, "destroy-bronze".to_string());
+        quality: QualityRule::default(),
+        mining: false,
+    }, "destroy-metal".to_string());
 
     recipes.insert(RecipeKind {
-        name: LocalString::from_str("recipe:iron-pipe"),
-        input_items: vec![RecipeInput { item: iron_plate, quantity: 1 }],
-        output: vec![RecipeOutput { item: iron_pipe, quantity: 1 }],
+        name: LocalString::from_str("recipe-iron-pipe"),
+        input_items: vec![RecipeInput { item: iron_plate.into(), quantity: 1, wear: None }],
+        output: vec![RecipeOutput { item: iron_pipe, quantity: 1, data: ItemData::default() }],
         time: 20,
+        quality: QualityRule::default(),
+        mining: false,
     }, "iron-pipe".to_string());
+
+    let research_metallurgy = recipes.insert(RecipeKind {
+        name: LocalString::from_str("recipe-research-metallurgy"),
+        input_items: vec![RecipeInput { item: copper.into(), quantity: 1, wear: None }, RecipeInput { item: tin.into(), quantity: 1, wear: None }],
+        output: vec![],
+        time: 40,
+        quality: QualityRule::default(),
+        mining: false,
+    }, "research-metallurgy".to_string());
+
+    technologies.insert(
+        TechnologyKindBuilder::new()
+        .with_name(LocalString::from_str("tech-metallurgy"))
+        .with_lab_recipe(research_metallurgy)
+        .with_units(10)
+        .with_effect(TechnologyEffect::UnlockRecipe(make_bronze))
+        .with_effect(TechnologyEffect::ProductionSpeed(250))
+        .build(),
+
+        "metallurgy".to_string(),
+    );
+
+    connectors.insert(ConnectorKindBuilder::new().with_name(LocalString::from_str("connector")).build(), "connector".to_string());
+    connectors.insert(
+        ConnectorKindBuilder::new()
+        .with_name(LocalString::from_str("connector-stack"))
+        .with_hand_size(4)
+        .with_pickup_timeout(40)
+        .build(),
+
+        "stack-connector".to_string(),
+    );
+}
+
+/// The ore patches of a new map.
+pub fn deposits(items: &Table<ItemKind>) -> Deposits {
+    let mut deposits = Deposits::new();
+    add_patch(&mut deposits, items.get_handle_from_name("copper"), TilePosition::new(-8, 5), 4, 300);
+    add_patch(&mut deposits, items.get_handle_from_name("tin"), TilePosition::new(6, -6), 3, 200);
+    deposits
+}
+
+/// A square patch of ore that is richest in the middle.
+fn add_patch(deposits: &mut Deposits, item: Handle<ItemKind>, center: TilePosition, radius: i32, amount: u32) {
+    for y in -radius..=radius {
+        for x in -radius..=radius {
+            let distance = x.abs().max(y.abs()) as u32;
+            let tile = TilePosition::new(center.x + x, center.y + y);
+            deposits.insert(tile, Deposit::new(item, amount * (radius as u32 + 1 - distance) / (radius as u32 + 1)));
+        }
+    }
 }
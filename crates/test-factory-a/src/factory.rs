@@ -1,16 +1,23 @@
-use bevy::math::Vec3Swizzles;
+use std::collections::HashMap;
+
 use bevy::prelude::*;
 use open_factory::item_stack::ItemStackQuanity;
 use open_factory::registry::Table;
 use open_factory::producer::{Producer, ProductionStatus};
-use open_factory::kinds::RecipeKind;
-use open_factory::connector::{Connector, ConnectorStatus};
+use open_factory::kinds::{ConnectorKind, ItemKind, RecipeKind, TechnologyKind};
+use open_factory::factory::{EntityId, Factory, FactoryEvent, PlacedProducer, Position};
+use open_factory::command::Command;
+use open_factory::tile::{Footprint, Rotation, TilePosition, TILE_SIZE};
 
 use crate::ui::Action;
 use crate::mouse_interaction::{Extents, MouseInteraction};
 use crate::camera::MousePositionInWorld;
+use crate::session::Session;
+use crate::localization::LocalizedText;
+use open_factory::local_string::LocalString;
+use open_factory::history::History;
+use open_factory::localization::Localizer;
 use crate::Colors;
-use crate::tick::Tick;
 
 pub struct FactoryProducerPlugin;
 
@@ -18,10 +25,19 @@ impl Plugin for FactoryProducerPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app
         .insert_resource::<Option<PartialConnector>>(None)
-        .add_system(producer_tick_system.system())
+        .insert_resource::<Option<Drag>>(None)
+        .insert_resource(FactoryView::default())
+        .insert_resource(DepositView::default())
+        .insert_resource(PlacementRotation::default())
+        .add_startup_system(spawn_ghost.system())
+        .add_system(factory_view_system.system())
+        .add_system(deposit_view_system.system())
+        .add_system(rotate_placement_system.system())
+        .add_system(move_system.system())
+        .add_system(ghost_system.system())
         .add_system(producer_color_system.system())
         .add_system(click_system.system())
-        .add_system(connector_transfer_system.system())
+        .add_system(connector_line_system.system())
         .add_system(producer_entry_exit_color_system.system())
         .add_system(producer_io_count_text_system.system())
         ;
@@ -30,30 +46,79 @@ impl Plugin for FactoryProducerPlugin {
 
 #[derive(Debug)]
 struct PartialConnector {
-    giver: Entity,
-    giver_position: GlobalTransform,
+    giver: EntityId,
+}
+
+/// A producer being dragged somewhere else, and which way it will face there.
+#[derive(Debug)]
+struct Drag {
+    entity: EntityId,
+    rotation: Rotation,
+}
+
+/// The sprite entity showing each entity of the factory.
+#[derive(Default)]
+pub struct FactoryView {
+    entities: HashMap<EntityId, Entity>,
+}
+
+impl FactoryView {
+    pub fn entity(&self, id: EntityId) -> Option<Entity> {
+        self.entities.get(&id).copied()
+    }
+}
+
+/// The sprite showing each tile with a deposit.
+#[derive(Default)]
+struct DepositView {
+    tiles: HashMap<TilePosition, Entity>,
 }
 
+/// Which way producers and blueprints are placed. Q turns it.
+#[derive(Default)]
+pub struct PlacementRotation(pub Rotation);
+
+/// Marks the preview of the producer about to be placed.
+struct Ghost;
+
+/// The factory entity a sprite shows.
+struct FactoryEntity(EntityId);
+
+/// Marks the sprite of a producer.
+struct ProducerView;
+
 // This is one massive system because prototype.
 // It would probably be more editable if it wasn't a single massive function.
 //
 // For what it's worth, what it does is check if the mouse is in a window,
 // and if it is, if a button is released. If the left click is released,
 // it compares against the Action defined in `ui.rs` and does the logic for
-// that. For most actions, it just places a producer in the world tied to
-// a specific recipe. For `Connect`, check the comment near it's match arm.
+// that. For most actions, it issues a command to place a producer in the world
+// tied to a specific recipe. For `Connect`, check the comment near it's match arm.
+//
+// Nothing is spawned here. The commands go through the session and the sprites
+// are spawned by `factory_view_system` once the factory applies them.
 fn click_system(
-    mut commands: Commands,
     action: Res<Action>,
     mouse_button_input: Res<Input<MouseButton>>,
     mouse_position: Res<Option<MousePositionInWorld>>,
+    rotation: Res<PlacementRotation>,
+    factory: Res<Factory>,
     recipes: Res<Table<RecipeKind>>,
+    connector_kinds: Res<Table<ConnectorKind>>,
     colors: Res<Colors>,
-    font: Res<crate::GameFont>,
+    session: Option<ResMut<Session>>,
+    history: Option<ResMut<History>>,
     mut partial_connector: ResMut<Option<PartialConnector>>,
-    mut connector_giver_query: Query<(Entity, &MouseInteraction, &GlobalTransform, &mut ConnectorGiver, &mut Handle<ColorMaterial>)>,
-    mut connector_taker_query: Query<(Entity, &MouseInteraction, &GlobalTransform, &mut ConnectorTaker,)>,
+    mut connector_giver_query: Query<(&MouseInteraction, &ConnectorGiver, &mut Handle<ColorMaterial>)>,
+    connector_taker_query: Query<(&MouseInteraction, &ConnectorTaker,)>,
 ) {
+    // Without a session, a replay is playing and can't be edited.
+    let (mut session, mut history) = match (session, history) {
+        (Some(session), Some(history)) => (session, history),
+        _ => return,
+    };
+
     let mouse_position = if let Some(ref mouse_position) = *mouse_position {
         mouse_position
     } else {
@@ -62,13 +127,13 @@ fn click_system(
 
     if mouse_button_input.just_released(MouseButton::Left) {
         match *action {
-            Action::Connect => {
+            Action::Connect | Action::StackConnect => {
                 match partial_connector.take() {
                     // Has not previously clicked on a giver.
                     None => {
                         let possibly_clicked_giver = connector_giver_query
                         .iter_mut()
-                        .filter(|(_, mouse_interaction, _, _, _,)| {
+                        .filter(|(mouse_interaction, _, _,)| {
                             **mouse_interaction == MouseInteraction::Hovered
                         })
                         .next();
@@ -79,25 +144,25 @@ fn click_system(
                                 return;
                             },
 
-                            Some((entity, _, position, _, mut color)) => {
+                            Some((_, &ConnectorGiver(giver), mut color)) => {
                                 *color = colors.grey.clone();
-                                *partial_connector = Some(PartialConnector { giver: entity, giver_position: *position });
+                                *partial_connector = Some(PartialConnector { giver });
                             }
                         }
                     },
 
                     // Has previously clicked on a giver.
-                    Some(PartialConnector { giver, giver_position: giver_transform }) => {
+                    Some(PartialConnector { giver }) => {
                         let possibly_clicked_taker = connector_taker_query
-                        .iter_mut()
-                        .filter(|(_, mouse_interaction, _, _,)| {
+                        .iter()
+                        .filter(|(mouse_interaction, _,)| {
                             **mouse_interaction == MouseInteraction::Hovered
                         })
                         .next();
 
-                        let (taker, taker_transform) = 
-                        if let Some((entity, &MouseInteraction::Hovered, position, _)) = possibly_clicked_taker {
-                            (entity, position)
+                        let taker = 
+                        if let Some((&MouseInteraction::Hovered, &ConnectorTaker(taker))) = possibly_clicked_taker {
+                            taker
                         } else {
                             eprintln!("Not hovering over a production entry!");
                             return;
@@ -105,69 +170,56 @@ fn click_system(
 
                         // Set all connector givers to white, unconditionally.
                         // Yes, this is wasteful.
-                        connector_giver_query.iter_mut().for_each(|(_, _, _, _, mut color,)| {
+                        connector_giver_query.iter_mut().for_each(|(_, _, mut color,)| {
                             *color = colors.white.clone();
                         });
 
-                        let connector_vector = taker_transform.translation.xy() - giver_transform.translation.xy();
-                        let connector_length = dbg!(connector_vector.length());
-                        let angle = connector_vector.angle_between(Vec2::X);
-                        let center = (giver_transform.translation + taker_transform.translation) / 2.0;
-
-                        let connector = Connector::new(connector_length);
+                        let connector_kind = match *action {
+                            Action::StackConnect => "stack-connector",
+                            _ => "connector",
+                        };
 
-                        // The position is the center point between the 
-                        let mut connector_position = Transform::from_translation(center);
-                        connector_position.rotate(Quat::from_rotation_z(-angle));
+                        session.issue(history.issue(Command::Connect {
+                            id: None,
+                            giver,
+                            taker,
+                            kind: connector_kinds.get_handle_from_name(connector_kind),
+                        }));
 
-                        let connector_entity = commands
-                        .spawn_bundle(SpriteBundle {
-                            transform: connector_position,
-                            material: colors.black.clone(),
-                            sprite: Sprite::new(Vec2::new(connector_length, 3.0)),
-                            ..Default::default()
-                        })
-                        .insert(connector)
-                        .with_children(|parent| {
-                            parent.spawn_bundle(SpriteBundle {
-                                transform: Transform::from_xyz(-connector_length / 2.0, 0.0, 4.0),
-                                material: colors.blue.clone(),
-                                sprite: Sprite::new(Vec2::new(3.0, 16.0)),
-                                ..Default::default()
-                            })
-                            .insert(ConnectorLine)
-                            ;
-                        })
-                        .id();
-
-                        connector_taker_query.get_component_mut::<ConnectorTaker>(taker).unwrap().takers.push(connector_entity);
-                        connector_giver_query.get_component_mut::<ConnectorGiver>(giver).unwrap().givers.push(connector_entity);
                         *partial_connector = Default::default();
                     },
                 }
             },
-            Action::Copper => {
-                let (recipe, handle) = recipes.get_ref_and_handle_from_name("generate-copper");
-                let producer = Producer::new_with_recipe(recipe, handle);
-                spawn_producer(&mut commands, &*colors, mouse_position.transform(), producer, font.0.clone(), "Copper")
-            },
+            Action::Copper | Action::Tin | Action::Bronze | Action::Trash | Action::Lab => {
+                let recipe = recipes.get_handle_from_name(action.recipe().expect("action places a producer"));
+                let rotation = rotation.0;
+                let position = snapped_position(mouse_position, rotation);
+
+                // The factory would reject these too, but this saves a round trip through the session.
+                if let Some(blocker) = factory.producer_blocked_by(position, rotation, None) {
+                    eprintln!("Producer {} is in the way!", blocker);
+                    return;
+                }
 
-            Action::Tin => {
-                let (recipe, handle) = recipes.get_ref_and_handle_from_name("generate-tin");
-                let producer = Producer::new_with_recipe(recipe, handle);
-                spawn_producer(&mut commands, &*colors, mouse_position.transform(), producer, font.0.clone(), "Tin")
-            },
+                if !factory.has_deposits_for(&recipes[recipe], position, rotation) {
+                    eprintln!("Miners have to be placed on their ore!");
+                    return;
+                }
 
-            Action::Bronze => {
-                let (recipe, handle) = recipes.get_ref_and_handle_from_name("bronze");
-                let producer = Producer::new_with_recipe(recipe, handle);
-                spawn_producer(&mut commands, &*colors, mouse_position.transform(), producer, font.0.clone(), "Bronze")
+                session.issue(history.issue(Command::PlaceProducer { id: None, position, rotation, recipe: Some(recipe) }));
             },
-
-            Action::Trash => {
-                let (recipe, handle) = recipes.get_ref_and_handle_from_name("destroy-bronze");
-                let producer = Producer::new_with_recipe(recipe, handle);
-                spawn_producer(&mut commands, &*colors, mouse_position.transform(), producer, font.0.clone(), "Delete Bronze")
+            // Dragging is handled by `move_system`, blueprints by the blueprint plugin.
+            Action::Move | Action::CopyBlueprint | Action::PasteBlueprint => (),
+            Action::Remove => {
+                let point = Vec2::new(mouse_position.x, mouse_position.y);
+                let entity = factory
+                .producer_at(Position::from_f32(point.x, point.y))
+                .or_else(|| connector_at(&factory, point));
+
+                match entity {
+                    Some(entity) => session.issue(history.issue(Command::RemoveEntity { entity })),
+                    None => eprintln!("Nothing to remove here!"),
+                }
             },
         }
 
@@ -178,32 +230,413 @@ fn click_system(
 
             // Set all connector exits to white, unconditionally.
             // Yes, this is wasteful.
-            connector_giver_query.iter_mut().for_each(|(_, _, _, _, mut color,)| {
+            connector_giver_query.iter_mut().for_each(|(_, _, mut color,)| {
                 *color = colors.white.clone();
             });
         }
     }
 }
 
+/// The connector whose line passes closest to the point, if any passes close enough to click.
+fn connector_at(factory: &Factory, point: Vec2) -> Option<EntityId> {
+    const REACH: f32 = 8.0;
+
+    factory
+    .connectors()
+    .map(|(id, placed)| {
+        let giver_port = to_vec2(factory.producer(placed.giver).expect("giver exists").output_port());
+        let taker_port = to_vec2(factory.producer(placed.taker).expect("taker exists").input_port());
+
+        let line = taker_port - giver_port;
+        let along = ((point - giver_port).dot(line) / line.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+        (id, point.distance(giver_port + line * along))
+    })
+    .filter(|&(_, distance)| distance <= REACH)
+    .min_by(|(_, a), (_, b)| a.partial_cmp(b).expect("distances are not NaN"))
+    .map(|(id, _)| id)
+}
+
+/// Where a producer placed at the cursor goes, lined up with the tiles.
+fn snapped_position(mouse_position: &MousePositionInWorld, rotation: Rotation) -> Position {
+    Factory::PRODUCER_FOOTPRINT.rotated(rotation).snap(Position::from_f32(mouse_position.x, mouse_position.y))
+}
+
+/// Size of a footprint in world units.
+fn footprint_size(footprint: Footprint) -> Vec2 {
+    Vec2::new(footprint.width as f32, footprint.height as f32) * TILE_SIZE.to_f32()
+}
+
+fn rotation_quat(rotation: Rotation) -> Quat {
+    Quat::from_rotation_z(-(rotation.quarter_turns() as f32) * std::f32::consts::FRAC_PI_2)
+}
+
+fn producer_transform(placed: &PlacedProducer) -> Transform {
+    Transform {
+        translation: to_vec2(placed.position).extend(0.0),
+        rotation: rotation_quat(placed.rotation),
+        ..Default::default()
+    }
+}
+
+fn rotate_placement_system(action: Res<Action>, keys: Res<Input<KeyCode>>, mut rotation: ResMut<PlacementRotation>) {
+    if (action.recipe().is_some() || *action == Action::PasteBlueprint) && keys.just_pressed(KeyCode::Q) {
+        rotation.0 = rotation.0.clockwise();
+    }
+}
+
+/// While moving, drags producers to where the mouse is released. Q turns the dragged producer, or the one under the cursor where it stands.
+fn move_system(
+    action: Res<Action>,
+    mouse_button_input: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mouse_position: Res<Option<MousePositionInWorld>>,
+    factory: Res<Factory>,
+    recipes: Res<Table<RecipeKind>>,
+    session: Option<ResMut<Session>>,
+    history: Option<ResMut<History>>,
+    mut drag: ResMut<Option<Drag>>,
+) {
+    let (mut session, mut history) = match (session, history) {
+        (Some(session), Some(history)) => (session, history),
+        _ => return,
+    };
+
+    if *action != Action::Move {
+        *drag = None;
+        return;
+    }
+
+    let mouse_position = if let Some(ref mouse_position) = *mouse_position {
+        mouse_position
+    } else {
+        return;
+    };
+    let cursor = Position::from_f32(mouse_position.x, mouse_position.y);
+
+    if mouse_button_input.just_pressed(MouseButton::Left) {
+        *drag = factory
+        .producer_at(cursor)
+        .map(|entity| Drag { entity, rotation: factory.producer(entity).expect("producer exists").rotation });
+    }
+
+    if keys.just_pressed(KeyCode::Q) {
+        match &mut *drag {
+            Some(drag) => drag.rotation = drag.rotation.clockwise(),
+
+            None => if let Some(entity) = factory.producer_at(cursor) {
+                let placed = factory.producer(entity).expect("producer exists");
+                let rotation = placed.rotation.clockwise();
+                let position = Factory::PRODUCER_FOOTPRINT.rotated(rotation).snap(placed.position);
+                try_move(&factory, &recipes, &mut session, &mut history, entity, position, rotation);
+            },
+        }
+    }
+
+    if mouse_button_input.just_released(MouseButton::Left) {
+        if let Some(Drag { entity, rotation }) = drag.take() {
+            let position = snapped_position(mouse_position, rotation);
+            try_move(&factory, &recipes, &mut session, &mut history, entity, position, rotation);
+        }
+    }
+}
+
+/// Issue a move, unless it changes nothing or the factory would reject it anyway.
+fn try_move(factory: &Factory, recipes: &Table<RecipeKind>, session: &mut Session, history: &mut History, entity: EntityId, position: Position, rotation: Rotation) {
+    let placed = match factory.producer(entity) {
+        Some(placed) => placed,
+        None => return,
+    };
+
+    if placed.position == position && placed.rotation == rotation {
+        return;
+    }
+
+    if let Some(blocker) = factory.producer_blocked_by(position, rotation, Some(entity)) {
+        eprintln!("Producer {} is in the way!", blocker);
+        return;
+    }
+
+    if let Some(recipe) = placed.producer.recipe() {
+        if !factory.has_deposits_for(&recipes[recipe], position, rotation) {
+            eprintln!("Miners have to stay on their ore!");
+            return;
+        }
+    }
+
+    session.issue(history.issue(Command::MoveEntity { entity, position, rotation }));
+}
+
+fn spawn_ghost(mut commands: Commands, colors: Res<Colors>) {
+    commands
+    .spawn_bundle(SpriteBundle {
+        material: colors.ghost_valid.clone(),
+        sprite: Sprite::new(footprint_size(Factory::PRODUCER_FOOTPRINT)),
+        visible: Visible { is_visible: false, is_transparent: true },
+        ..Default::default()
+    })
+    .insert(Ghost);
+}
+
+/// Shows where the producer about to be placed or moved goes, green if it can go there and red if it can't.
+fn ghost_system(
+    action: Res<Action>,
+    mouse_position: Res<Option<MousePositionInWorld>>,
+    rotation: Res<PlacementRotation>,
+    drag: Res<Option<Drag>>,
+    factory: Res<Factory>,
+    recipes: Res<Table<RecipeKind>>,
+    colors: Res<Colors>,
+    mut ghost_query: Query<(&mut Transform, &mut Handle<ColorMaterial>, &mut Visible), With<Ghost>>,
+) {
+    let (mut transform, mut material, mut visible) = match ghost_query.single_mut() {
+        Ok(ghost) => ghost,
+        Err(_) => return,
+    };
+
+    let (mouse_position, rotation, recipe, moving) = match (&*mouse_position, &*drag, action.recipe()) {
+        (Some(mouse_position), Some(drag), _) => {
+            let recipe = factory.producer(drag.entity).and_then(|placed| placed.producer.recipe());
+            (mouse_position, drag.rotation, recipe, Some(drag.entity))
+        },
+        (Some(mouse_position), None, Some(recipe)) => (mouse_position, rotation.0, Some(recipes.get_handle_from_name(recipe)), None),
+        _ => {
+            visible.is_visible = false;
+            return;
+        },
+    };
+
+    let position = snapped_position(mouse_position, rotation);
+    let fits = factory.producer_blocked_by(position, rotation, moving).is_none()
+    && recipe.map(|recipe| factory.has_deposits_for(&recipes[recipe], position, rotation)).unwrap_or(true);
+
+    // Above the producers, so it shows what it overlaps.
+    *transform = Transform {
+        translation: to_vec2(position).extend(5.0),
+        rotation: rotation_quat(rotation),
+        ..Default::default()
+    };
+    *material = if fits { colors.ghost_valid.clone() } else { colors.ghost_invalid.clone() };
+    visible.is_visible = true;
+}
+
+fn to_vec2(position: Position) -> Vec2 {
+    Vec2::new(position.x.to_f32(), position.y.to_f32())
+}
+
+/// Spawns and updates sprites for whatever the factory placed or changed.
+fn factory_view_system(
+    mut commands: Commands,
+    mut factory: ResMut<Factory>,
+    mut view: ResMut<FactoryView>,
+    recipes: Res<Table<RecipeKind>>,
+    technologies: Res<Table<TechnologyKind>>,
+    items: Res<Table<ItemKind>>,
+    localizer: Res<Localizer>,
+    colors: Res<Colors>,
+    font: Res<crate::GameFont>,
+    mut label_query: Query<(&mut LocalizedText, &Parent), With<ProducerLabel>>,
+) {
+    let events = factory.drain_events().collect::<Vec<_>>();
+
+    for event in events {
+        match event {
+            FactoryEvent::ProducerPlaced(id) => {
+                let placed = match factory.producer(id) {
+                    Some(placed) => placed,
+                    // Removed again before the view caught up.
+                    None => continue,
+                };
+
+                despawn_view(&mut commands, &mut view, id);
+                let entity = spawn_producer(&mut commands, &*colors, id, placed, font.0.clone(), producer_label(&recipes, &placed.producer));
+                view.entities.insert(id, entity);
+            },
+
+            FactoryEvent::ConnectorPlaced(id) => {
+                if factory.connector(id).is_none() {
+                    continue;
+                }
+
+                despawn_view(&mut commands, &mut view, id);
+                let entity = spawn_connector(&mut commands, &*colors, &factory, id);
+                view.entities.insert(id, entity);
+            },
+
+            FactoryEvent::RecipeChanged(id) => {
+                let (entity, placed) = match (view.entities.get(&id), factory.producer(id)) {
+                    (Some(&entity), Some(placed)) => (entity, placed),
+                    _ => continue,
+                };
+
+                for (mut label, parent) in label_query.iter_mut() {
+                    if **parent == entity {
+                        label.0 = producer_label(&recipes, &placed.producer);
+                    }
+                }
+            },
+
+            FactoryEvent::ProducerRemoved(id) | FactoryEvent::ConnectorRemoved(id) => {
+                despawn_view(&mut commands, &mut view, id);
+            },
+
+            FactoryEvent::EntityMoved(id) => {
+                if let Some(placed) = factory.producer(id) {
+                    if let Some(&entity) = view.entities.get(&id) {
+                        commands
+                        .entity(entity)
+                        .insert(producer_transform(placed))
+                        .insert(Extents(footprint_size(Factory::PRODUCER_FOOTPRINT.rotated(placed.rotation)) / 2.0));
+                    }
+                } else if factory.connector(id).is_some() {
+                    // The connector's length and angle changed, so it is simpler to draw it again.
+                    despawn_view(&mut commands, &mut view, id);
+                    let entity = spawn_connector(&mut commands, &*colors, &factory, id);
+                    view.entities.insert(id, entity);
+                }
+            },
+
+            FactoryEvent::ResearchChanged => {
+                if let Some(technology) = factory.research().current() {
+                    let name = localizer.localize(&technologies[technology].name);
+                    println!("{}", localizer.localize(&LocalString::from_str("research-started").with_arg("technology", name)));
+                }
+            },
+
+            FactoryEvent::ResearchCompleted(technology) => {
+                let name = localizer.localize(&technologies[technology].name);
+                println!("{}", localizer.localize(&LocalString::from_str("research-completed").with_arg("technology", name)));
+            },
+
+            // There is no inventory to show them in yet, so list everything returned so far.
+            FactoryEvent::ItemsReturned(_) => {
+                let returned = factory
+                .returned_items()
+                .iter()
+                .map(|stack| format!("{} {}", stack.quantity, localizer.localize(items[stack.item].name())))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+                println!("{}", localizer.localize(&LocalString::from_str("items-returned").with_arg("items", returned)));
+            },
+        }
+    }
+}
+
+/// Shows the ore on the map, removing tiles as they are mined out.
+fn deposit_view_system(
+    mut commands: Commands,
+    factory: Res<Factory>,
+    items: Res<Table<ItemKind>>,
+    colors: Res<Colors>,
+    mut view: ResMut<DepositView>,
+) {
+    let deposits = factory.deposits();
+
+    view.tiles.retain(|&tile, &mut entity| {
+        let exists = deposits.get(tile).is_some();
+        if !exists {
+            commands.entity(entity).despawn();
+        }
+        exists
+    });
+
+    for (tile, deposit) in deposits.iter() {
+        if view.tiles.contains_key(&tile) {
+            continue;
+        }
+
+        let material = match items.name(&deposit.item) {
+            "copper" => colors.copper.clone(),
+            "tin" => colors.tin.clone(),
+            _ => colors.grey.clone(),
+        };
+
+        // Below the producers. The 2D camera doesn't see past z = -0.1.
+        let size = TILE_SIZE.to_f32() - 2.0;
+        let entity = commands
+        .spawn_bundle(SpriteBundle {
+            material,
+            transform: Transform::from_translation(to_vec2(tile.center()).extend(-0.05)),
+            sprite: Sprite::new(Vec2::new(size, size)),
+            ..Default::default()
+        })
+        .id();
+
+        view.tiles.insert(tile, entity);
+    }
+}
+
+fn despawn_view(commands: &mut Commands, view: &mut FactoryView, id: EntityId) {
+    if let Some(entity) = view.entities.remove(&id) {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn producer_label(recipes: &Table<RecipeKind>, producer: &Producer) -> LocalString {
+    match producer.recipe() {
+        Some(recipe) => recipes[recipe].name.clone(),
+        None => LocalString::from_str("producer-no-recipe"),
+    }
+}
+
+fn spawn_connector(commands: &mut Commands, colors: &Colors, factory: &Factory, id: EntityId) -> Entity {
+    let placed = factory.connector(id).expect("placed connector exists");
+    let giver_port = to_vec2(factory.producer(placed.giver).expect("giver exists").output_port());
+    let taker_port = to_vec2(factory.producer(placed.taker).expect("taker exists").input_port());
+
+    let connector_vector = taker_port - giver_port;
+    let connector_length = placed.connector.length().to_f32();
+    let angle = connector_vector.angle_between(Vec2::X);
+    let center = (giver_port + taker_port) / 2.0;
+
+    // The position is the center point between the ports, above the producers.
+    let mut connector_position = Transform::from_translation(center.extend(2.0));
+    connector_position.rotate(Quat::from_rotation_z(-angle));
+
+    commands
+    .spawn_bundle(SpriteBundle {
+        transform: connector_position,
+        material: colors.black.clone(),
+        sprite: Sprite::new(Vec2::new(connector_length, 3.0)),
+        ..Default::default()
+    })
+    .insert(FactoryEntity(id))
+    .with_children(|parent| {
+        parent.spawn_bundle(SpriteBundle {
+            transform: Transform::from_xyz(-connector_length / 2.0, 0.0, 4.0),
+            material: colors.blue.clone(),
+            sprite: Sprite::new(Vec2::new(3.0, 16.0)),
+            ..Default::default()
+        })
+        .insert(ConnectorLine)
+        ;
+    })
+    .id()
+}
+
 struct ProducerIOCountText;
 
-fn spawn_producer(commands: &mut Commands, colors: &Colors, location: Transform, producer: Producer, font: Handle<Font>, label: &str) {
+struct ProducerLabel;
+
+fn spawn_producer(commands: &mut Commands, colors: &Colors, id: EntityId, placed: &PlacedProducer, font: Handle<Font>, label: LocalString) -> Entity {
+    let producer = &placed.producer;
     let takes_input = producer.takes_input();
     let gives_output = producer.gives_output();
 
     commands
     .spawn_bundle(SpriteBundle {
         material: colors.red.clone(),
-        transform: location,
-        sprite: Sprite::new(Vec2::new(120.0, 30.0)),
+        transform: producer_transform(placed),
+        sprite: Sprite::new(footprint_size(Factory::PRODUCER_FOOTPRINT)),
         ..Default::default()
     })
-    .insert(Extents(Vec2::new(60.0, 15.0)))
+    // Hovering works on the unturned bounds, so those have to be turned here.
+    .insert(Extents(footprint_size(Factory::PRODUCER_FOOTPRINT.rotated(placed.rotation)) / 2.0))
     .insert(MouseInteraction::default())
     .with_children(|parent| {
         parent.spawn_bundle(Text2dBundle {
             text: Text::with_section(
-                label.to_string(), 
+                String::new(),
                 TextStyle {
                     font: font.clone(),
                     font_size: 12.0,
@@ -218,9 +651,11 @@ fn spawn_producer(commands: &mut Commands, colors: &Colors, location: Transform,
             transform: Transform::from_xyz(-35.0, -10.0, 1.0),
 
             ..Default::default()
-        });
+        })
+        .insert(ProducerLabel)
+        .insert(LocalizedText(label));
 
-        let (input_text, output_text) = producer_counts(&producer);
+        let (input_text, output_text) = producer_counts(producer);
 
         parent.spawn_bundle(Text2dBundle {
             text: Text {
@@ -269,7 +704,7 @@ fn spawn_producer(commands: &mut Commands, colors: &Colors, location: Transform,
                 sprite: Sprite::new(Vec2::new(15.0, 15.0)),
                 ..Default::default()
             })
-            .insert(ConnectorTaker::new())
+            .insert(ConnectorTaker(id))
             .insert(Extents(Vec2::new(15.0, 15.0)))
             .insert(MouseInteraction::default())
             .with_children(|parent| {
@@ -289,7 +724,7 @@ fn spawn_producer(commands: &mut Commands, colors: &Colors, location: Transform,
                 sprite: Sprite::new(Vec2::new(15.0, 15.0)),
                 ..Default::default()
             })
-            .insert(ConnectorGiver::new())
+            .insert(ConnectorGiver(id))
             .insert(Extents(Vec2::new(15.0, 15.0)))
             .insert(MouseInteraction::default())
             .with_children(|parent| {
@@ -302,28 +737,22 @@ fn spawn_producer(commands: &mut Commands, colors: &Colors, location: Transform,
             ;
         }
     })
-    .insert(producer)
-    ;
-}
-
-fn producer_tick_system(
-    tick: Res<Tick>,
-    mut query: Query<&mut Producer>,
-    recipes: Res<Table<RecipeKind>>,
-) {
-    if **tick {
-        for mut producer in query.iter_mut() {
-            producer.attempt_to_start_production(&recipes);
-            producer.tick(&recipes);
-        }
-    }
+    .insert(FactoryEntity(id))
+    .insert(ProducerView)
+    .id()
 }
 
 fn producer_color_system(
     colors: Res<Colors>,
-    mut query: Query<(&Producer, &mut Handle<ColorMaterial>,)>,
+    factory: Res<Factory>,
+    mut query: Query<(&FactoryEntity, &mut Handle<ColorMaterial>,), With<ProducerView>>,
 ) {
-    for (producer, mut color) in query.iter_mut() {
+    for (&FactoryEntity(id), mut color) in query.iter_mut() {
+        let producer = match factory.producer(id) {
+            Some(placed) => &placed.producer,
+            None => continue,
+        };
+
         *color = match producer.status() {
             ProductionStatus::Idle => colors.red.clone(),
             ProductionStatus::Producing => colors.green.clone(),
@@ -363,12 +792,24 @@ fn producer_counts(producer: &Producer) -> (String, String) {
 }
 
 fn producer_io_count_text_system(
-    producer_query: Query<&Producer>,
+    factory: Res<Factory>,
+    recipes: Res<Table<RecipeKind>>,
+    localizer: Res<Localizer>,
+    producer_query: Query<&FactoryEntity, With<ProducerView>>,
     mut producer_text_query: Query<(&mut Text, &Parent), With<ProducerIOCountText>>,
 ) {
     for (mut text, &parent) in producer_text_query.iter_mut() {
-        let producer: &Producer = producer_query.get_component::<Producer>(*parent).expect("producer must exist");
-        let (input_text, output_text) = producer_counts(producer);
+        let &FactoryEntity(id) = producer_query.get(*parent).expect("producer view must exist");
+        let producer = match factory.producer(id) {
+            Some(placed) => &placed.producer,
+            None => continue,
+        };
+        let (input_text, mut output_text) = producer_counts(producer);
+
+        if let Some(amount) = factory.deposit_remaining(id, &recipes) {
+            let remaining = LocalString::from_str("deposit-remaining").with_arg("amount", amount as i64);
+            output_text = format!("{} ({})", output_text, localizer.localize(&remaining));
+        }
 
         text.sections[0].value = input_text;
         text.sections[2].value = output_text;
@@ -396,94 +837,27 @@ fn producer_entry_exit_color_system(
     }
 }
 
-fn connector_transfer_system(
-    tick: Res<Tick>,
-    mut producer_query: Query<(&mut Producer,)>,
-    mut connector_query: Query<(&mut Connector,)>,
+/// Moves the line on each connector to where the connector's hand is.
+fn connector_line_system(
+    factory: Res<Factory>,
+    connector_query: Query<&FactoryEntity>,
     mut connector_line_query: Query<(&mut Transform, &Parent), With<ConnectorLine>>,
-    giver_query: Query<(&ConnectorGiver, &Parent)>,
-    taker_query: Query<(&ConnectorTaker, &Parent)>,
 ) {
-    if **tick {
-        for (giver, parent) in giver_query.iter() {
-            for &connector_entity in giver.givers.iter() {
-                let mut connector = match connector_query.get_component_mut::<Connector>(connector_entity) {
-                    Ok(connector) => connector,
-                    // Possible because there's no system sync.
-                    Err(_) => continue,
-                };
-
-                if connector.status() == ConnectorStatus::WaitingOnInput {
-                    let mut producer = producer_query.get_component_mut::<Producer>(**parent).expect("Producer entity exists");
-
-                    if producer.has_output() {
-                        let item_stack = producer.take_single_item();
-
-                        // We know at this point that the connector can take an item stack and that it doesn't return one.
-                        // As such, we discard the return value.
-                        let _ = connector.insert_stack(item_stack);
-                    }
-                }
-            }
-
-            for (taker, parent) in taker_query.iter() {
-                for &connector_entity in taker.takers.iter() {
-                    let mut connector = match connector_query.get_component_mut::<Connector>(connector_entity) {
-                        Ok(connector) => connector,
-                        // Possible because the connector is added one frame after the handle is
-                        // given to the ConnectorGiver/ConnectorTaker.
-                        Err(_) => continue,
-                    };
-    
-                    if connector.status() == ConnectorStatus::WaitingOnOutput {
-                        let mut producer = producer_query.get_component_mut::<Producer>(**parent).expect("Producer entity exists");
-    
-                        if producer.takes_input() {
-                            let result = producer.try_insert_ingredient(connector.take_stack());
-                            if let Some(stack) = result.get_item_stack() {
-                                let _ = connector.insert_stack(stack);
-                            }
-                        }
-                    }
-                }
-            }
-
-            for (mut connector,) in connector_query.iter_mut() {
-                connector.tick();
-            }
-    
-            for (mut transform, parent) in connector_line_query.iter_mut() {
-                let connector = connector_query.get_component::<Connector>(**parent).expect("parent has to be a connector");
-                transform.translation.x = connector.position() - (connector.length() / 2.0);
-            }
-        }
+    for (mut transform, parent) in connector_line_query.iter_mut() {
+        let &FactoryEntity(id) = connector_query.get(**parent).expect("parent has to be a connector");
+        let connector = match factory.connector(id) {
+            Some(placed) => &placed.connector,
+            None => continue,
+        };
+
+        transform.translation.x = connector.position().to_f32() - (connector.length().to_f32() / 2.0);
     }
 }
 
-/// An entity that gives input to a connector.
-struct ConnectorGiver {
-    givers: Vec<Entity>,
-}
+/// The output port of a producer. Connectors start here.
+struct ConnectorGiver(EntityId);
 
-impl ConnectorGiver {
-    fn new() -> Self {
-        Self {
-            givers: vec![],
-        }
-    }
-}
-
-/// An entity that takes output from a connector.
-struct ConnectorTaker {
-    takers: Vec<Entity>,
-}
-
-impl ConnectorTaker {
-    fn new() -> Self {
-        Self {
-            takers: vec![],
-        }
-    }
-}
+/// The input port of a producer. Connectors end here.
+struct ConnectorTaker(EntityId);
 
-struct ConnectorLine;
\ No newline at end of file
+struct ConnectorLine;
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use open_factory::local_string::LocalString;
+use open_factory::localization::{Catalog, Localizer};
+
+/// Locale used for messages missing from the current one.
+const DEFAULT_LOCALE: &str = "en";
+
+/// Loads the catalogs in `assets/locales`, one `<locale>.ftl` file per locale.
+///
+/// Starts in the locale given with `--locale <locale>`. L switches to the next locale.
+pub struct LocalizationPlugin;
+
+impl Plugin for LocalizationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+        .insert_resource(load_localizer())
+        .add_system(switch_locale_system.system())
+        .add_system(localized_text_system.system())
+        ;
+    }
+}
+
+/// Shows a `LocalString` in the first section of an entity's `Text`, in the current locale.
+pub struct LocalizedText(pub LocalString);
+
+/// The same directory bevy loads assets from.
+fn locales_dir() -> PathBuf {
+    let root = std::env::var_os("CARGO_MANIFEST_DIR")
+    .map(PathBuf::from)
+    .or_else(|| std::env::current_exe().ok().and_then(|exe| exe.parent().map(PathBuf::from)))
+    .unwrap_or_default();
+
+    root.join("assets").join("locales")
+}
+
+pub fn load_localizer() -> Localizer {
+    let mut localizer = Localizer::new(DEFAULT_LOCALE);
+    let dir = locales_dir();
+
+    let mut paths = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect::<Vec<_>>(),
+        Err(error) => {
+            eprintln!("Can't read locales from {}: {}", dir.display(), error);
+            vec![]
+        },
+    };
+    paths.sort();
+
+    for path in paths {
+        let locale = match (path.extension(), path.file_stem()) {
+            (Some(extension), Some(stem)) if extension == "ftl" => stem.to_string_lossy().into_owned(),
+            _ => continue,
+        };
+
+        let catalog = std::fs::read_to_string(&path)
+        .map_err(|error| error.to_string())
+        .and_then(|source| Catalog::parse(&locale, &source).map_err(|error| error.to_string()));
+
+        match catalog {
+            Ok(catalog) => localizer.add_catalog(catalog),
+            Err(error) => eprintln!("Skipping {}: {}", path.display(), error),
+        }
+    }
+
+    if let Some(locale) = crate::flag_value("--locale") {
+        localizer.set_locale(&locale);
+    }
+
+    localizer
+}
+
+fn switch_locale_system(
+    keys: Res<Input<KeyCode>>,
+    mut localizer: ResMut<Localizer>,
+) {
+    if !keys.just_pressed(KeyCode::L) {
+        return;
+    }
+
+    let locales = localizer.locales().map(str::to_string).collect::<Vec<_>>();
+    if locales.is_empty() {
+        return;
+    }
+
+    let next = locales
+    .iter()
+    .position(|locale| locale == localizer.locale())
+    .map(|current| (current + 1) % locales.len())
+    .unwrap_or(0);
+
+    localizer.set_locale(&locales[next]);
+    println!("{}", localizer.localize(&LocalString::from_str("locale-changed")));
+}
+
+/// Updates texts whose string changed, or all of them when the locale changed.
+fn localized_text_system(
+    localizer: Res<Localizer>,
+    mut text_query: Query<(&LocalizedText, &mut Text)>,
+    changed_query: Query<Entity, Changed<LocalizedText>>,
+) {
+    if localizer.is_changed() {
+        for (LocalizedText(string), mut text) in text_query.iter_mut() {
+            text.sections[0].value = localizer.localize(string);
+        }
+    } else {
+        for entity in changed_query.iter() {
+            if let Ok((LocalizedText(string), mut text)) = text_query.get_mut(entity) {
+                text.sections[0].value = localizer.localize(string);
+            }
+        }
+    }
+}
@@ -6,6 +6,11 @@ mod factory;
 mod mouse_interaction;
 mod tick;
 mod database;
+mod session;
+mod replay;
+mod localization;
+mod blueprint;
+
 struct Colors {
     green: Handle<ColorMaterial>,
     yellow: Handle<ColorMaterial>,
@@ -14,6 +19,10 @@ struct Colors {
     grey: Handle<ColorMaterial>,
     black: Handle<ColorMaterial>,
     blue: Handle<ColorMaterial>,
+    copper: Handle<ColorMaterial>,
+    tin: Handle<ColorMaterial>,
+    ghost_valid: Handle<ColorMaterial>,
+    ghost_invalid: Handle<ColorMaterial>,
 }
 
 struct GameFont(Handle<Font>);
@@ -26,17 +35,50 @@ fn setup_handles(mut commands: Commands, mut materials: ResMut<Assets<ColorMater
     let grey = materials.add(Color::rgb(0.8, 0.8, 0.8).into());
     let black = materials.add(Color::rgb(0.0, 0.0, 0.0).into());
     let blue = materials.add(Color::rgb(0.11764705882352941, 0.5372549019607843, 0.7019607843137254).into());
+    let copper = materials.add(Color::rgb(0.45, 0.27, 0.12).into());
+    let tin = materials.add(Color::rgb(0.35, 0.38, 0.42).into());
+    let ghost_valid = materials.add(Color::rgba(0.0, 0.8, 0.0, 0.4).into());
+    let ghost_invalid = materials.add(Color::rgba(0.8, 0.0, 0.0, 0.4).into());
 
     let font = GameFont(asset_server.load::<Font, _>("fonts/FiraSans-Bold.ttf"));
 
     commands.insert_resource(Colors {
-        green, yellow, red, white, grey, black, blue,
+        green, yellow, red, white, grey, black, blue, copper, tin, ghost_valid, ghost_invalid,
     });
     commands.insert_resource(font)
 }
 
+/// The argument following a flag, e.g. the file in `--replay <file>`.
+fn flag_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.find(|arg| arg == flag)?;
+    args.next()
+}
+
+/// Threads to tick the factory on, given with `--threads <n>`.
+fn tick_threads() -> usize {
+    flag_value("--threads")
+    .map(|threads| threads.parse().expect("--threads needs a number"))
+    .unwrap_or(1)
+}
+
 fn main() {
-    App::build()
+    let replay_path = flag_value("--replay");
+
+    if let Some(ref path) = replay_path {
+        if std::env::args().any(|arg| arg == "--headless") {
+            if let Err(error) = replay::run_headless(path) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+
+            return;
+        }
+    }
+
+    let mut app = App::build();
+
+    app
     .insert_resource(WindowDescriptor {
         title: "Test Factory".to_string(),
         ..Default::default()
@@ -45,9 +87,19 @@ fn main() {
     .add_startup_system_to_stage(StartupStage::PreStartup, setup_handles.system())
     .add_system(mouse_interaction::update_interaction_system.system())
     .add_plugin(database::DatabasePlugin)
+    .add_plugin(localization::LocalizationPlugin)
     .add_plugin(camera::CameraPlugin)
     .add_plugin(tick::TickPlugin)
+    ;
+
+    match replay_path {
+        Some(path) => app.add_plugin(replay::ReplayPlugin { path }),
+        None => app.add_plugin(session::SessionPlugin),
+    };
+
+    app
     .add_plugin(factory::FactoryProducerPlugin)
+    .add_plugin(blueprint::BlueprintPlugin)
     .add_plugin(ui::UiPlugin)
     .add_system(bevy::input::system::exit_on_esc_system.system())
     .run();
@@ -1,6 +1,8 @@
 use bevy::prelude::*;
+use open_factory::factory::{Factory, Position};
 
 use crate::camera::MousePositionInWorld;
+use crate::factory::FactoryView;
 
 pub struct Extents(pub Vec2);
 
@@ -41,29 +43,48 @@ impl Default for MouseInteraction {
     }
 }
 
+/// Hovers the producer under the cursor and its ports.
+///
+/// Only those can be hovered, so the factory's spatial index is used to find
+/// them instead of checking every entity with `Extents`.
 pub fn update_interaction_system(
     mouse_position_in_world: Res<Option<MousePositionInWorld>>,
+    factory: Res<Factory>,
+    view: Res<FactoryView>,
+    mut hovered: Local<Vec<Entity>>,
+    children_query: Query<&Children>,
     mut query: Query<(
         &Extents,
         &GlobalTransform,
         &mut MouseInteraction,
     )>,
 ) {
+    for entity in hovered.drain(..) {
+        if let Ok((_, _, mut interaction)) = query.get_mut(entity) {
+            interaction.set(MouseInteraction::None);
+        }
+    }
+
     let mouse_position_in_world = match *mouse_position_in_world {
         Some(ref mouse_position_in_world) => mouse_position_in_world,
-        None => {
-            for (_, _, mut interaction) in query.iter_mut() {
-                interaction.set(MouseInteraction::None);
-            }
-            return;
-        }
+        None => return,
     };
 
-    for (extents, global_transform, mut interaction) in query.iter_mut() {
-        if point_in_area(global_transform, extents, mouse_position_in_world) {
-            interaction.set(MouseInteraction::Hovered);
-        } else {
-            interaction.set(MouseInteraction::None);
+    let producer = factory
+    .producer_at(Position::from_f32(mouse_position_in_world.x, mouse_position_in_world.y))
+    .and_then(|id| view.entity(id));
+
+    let ports = producer
+    .and_then(|producer| children_query.get(producer).ok())
+    .into_iter()
+    .flat_map(|children| children.iter().copied());
+
+    for entity in producer.into_iter().chain(ports) {
+        if let Ok((extents, global_transform, mut interaction)) = query.get_mut(entity) {
+            if point_in_area(global_transform, extents, mouse_position_in_world) {
+                interaction.set(MouseInteraction::Hovered);
+                hovered.push(entity);
+            }
         }
     }
-}
\ No newline at end of file
+}
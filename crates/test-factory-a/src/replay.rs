@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+use open_factory::checksum::checksum;
+use open_factory::factory::{Factory, Prototypes};
+use open_factory::kinds::{ConnectorKind, ItemKind, ItemTag, RecipeKind, TechnologyKind};
+use open_factory::local_string::LocalString;
+use open_factory::localization::Localizer;
+use open_factory::registry::Table;
+use open_factory::replay::{Replay, ReplayPlayer};
+
+use crate::tick::Tick;
+
+/// Ticks skipped by seeking, ten seconds.
+const SEEK_TICKS: u64 = 200;
+
+/// Plays back a replay given with `--replay <file>` instead of running a session.
+///
+/// Pausing and the speed are up to the [`TickController`](crate::tick::TickController).
+/// Left and Right seek ten seconds and Home goes back to the start.
+pub struct ReplayPlugin {
+    pub path: String,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let replay = read_replay(&self.path).expect("Can read the replay");
+        let mut factory = Factory::new();
+        factory.set_threads(crate::tick_threads());
+        let player = ReplayPlayer::new(replay, &mut factory);
+
+        app
+        .insert_resource(factory)
+        .insert_resource(player)
+        .add_system(playback_system.system())
+        ;
+    }
+}
+
+fn read_replay(path: &str) -> Result<Replay, Box<dyn std::error::Error>> {
+    let bytes = std::fs::read(path)?;
+    Ok(Replay::from_bytes(&bytes)?)
+}
+
+/// Plays a replay as fast as possible without opening a window.
+pub fn run_headless(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let replay = read_replay(path)?;
+
+    let mut items = Table::<ItemKind>::new();
+    let mut recipes = Table::<RecipeKind>::new();
+    let mut connectors = Table::<ConnectorKind>::new();
+    let mut tags = Table::<ItemTag>::new();
+    let mut technologies = Table::<TechnologyKind>::new();
+    crate::database::populate(&mut items, &mut recipes, &mut connectors, &mut tags, &mut technologies);
+    let prototypes = Prototypes { recipes: &recipes, connectors: &connectors, tags: &tags, technologies: &technologies };
+
+    let mut factory = Factory::new();
+    factory.set_threads(crate::tick_threads());
+    let mut player = ReplayPlayer::new(replay, &mut factory);
+    let end_tick = player.replay().end_tick();
+    player.seek(&mut factory, end_tick, &prototypes)?;
+
+    println!("Played to tick {} with checksum {:016x}", factory.current_tick(), checksum(&factory));
+    Ok(())
+}
+
+fn playback_system(
+    tick: Res<Tick>,
+    keys: Res<Input<KeyCode>>,
+    mut player: ResMut<ReplayPlayer>,
+    mut factory: ResMut<Factory>,
+    localizer: Res<Localizer>,
+    recipes: Res<Table<RecipeKind>>,
+    connectors: Res<Table<ConnectorKind>>,
+    tags: Res<Table<ItemTag>>,
+    technologies: Res<Table<TechnologyKind>>,
+) {
+    let prototypes = Prototypes { recipes: &*recipes, connectors: &*connectors, tags: &*tags, technologies: &*technologies };
+
+    let current_tick = factory.current_tick();
+    let seek_to = if keys.just_pressed(KeyCode::Left) {
+        Some(current_tick.saturating_sub(SEEK_TICKS))
+    } else if keys.just_pressed(KeyCode::Right) {
+        Some(current_tick + SEEK_TICKS)
+    } else if keys.just_pressed(KeyCode::Home) {
+        Some(0)
+    } else {
+        None
+    };
+
+    if let Some(seek_to) = seek_to {
+        if let Err(error) = player.seek(&mut *factory, seek_to, &prototypes) {
+            eprintln!("{}", error);
+        }
+        let seconds = factory.current_tick() / open_factory::TICKS_PER_SECOND as u64;
+        println!("{}", localizer.localize(&LocalString::from_str("replay-position").with_arg("seconds", seconds as i64)));
+    }
+
+    for _ in 0..**tick {
+        match player.step(&mut *factory, &prototypes) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(error) => {
+                eprintln!("{}", error);
+                break;
+            },
+        }
+    }
+}
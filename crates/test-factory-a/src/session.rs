@@ -0,0 +1,210 @@
+use std::fs::File;
+use std::io::BufWriter;
+
+use bevy::prelude::*;
+use open_factory::checksum::checksum;
+use open_factory::command::Command;
+use open_factory::factory::{Factory, Prototypes};
+use open_factory::history::History;
+use open_factory::kinds::{ConnectorKind, ItemKind, ItemTag, RecipeKind, TechnologyKind};
+use open_factory::lockstep::{LockstepSession, LoopbackTransport, TcpTransport, Transport};
+use open_factory::registry::Table;
+use open_factory::replay::{Record, ReplayWriter};
+
+use crate::tick::Tick;
+
+/// Ticks between issuing a command and it being applied.
+const INPUT_DELAY: u64 = 3;
+
+/// Ticks between checksums in a recording.
+const RECORDED_CHECKSUM_INTERVAL: u64 = 20;
+
+pub type Session = LockstepSession<Box<dyn Transport + Send + Sync>>;
+
+/// Records the session when given `--record <file>`.
+type Recorder = Option<ReplayWriter<BufWriter<File>>>;
+
+/// Runs the factory through a lockstep session.
+///
+/// By default, the session only has the local player. Pass `--host <port> <players>`
+/// to wait for other players to join, or `--join <address>` to join a hosted game.
+/// Add `--record <file>` to record a replay of the session.
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let items = app.world().get_resource::<Table<ItemKind>>().expect("DatabasePlugin is added before the session");
+        let mut factory = Factory::with_deposits(crate::database::deposits(items));
+        factory.set_threads(crate::tick_threads());
+        let recorder = start_recording(&factory);
+
+        app
+        .insert_resource(factory)
+        .insert_resource(recorder)
+        .insert_resource(start_session())
+        .insert_resource(History::new())
+        .add_system(session_tick_system.system())
+        .add_system(undo_system.system())
+        .add_system(research_system.system())
+        ;
+    }
+}
+
+fn start_session() -> Session {
+    let args = std::env::args().collect::<Vec<_>>();
+
+    match args.get(1).map(String::as_str) {
+        Some("--host") => {
+            let port = args.get(2).and_then(|port| port.parse::<u16>().ok()).expect("--host needs a port");
+            let players = args.get(3).map(|players| players.parse::<u8>().expect("players must be a number")).unwrap_or(2);
+            assert!(players > 1, "Hosting needs at least two players");
+
+            let host = TcpTransport::listen(("0.0.0.0", port)).expect("Can listen on the port");
+            println!("Waiting for {} players to join on port {}", players - 1, port);
+            let transport = host.accept(players - 1).expect("Players can join");
+
+            Session::new(Box::new(transport), 0, players, INPUT_DELAY)
+        },
+
+        Some("--join") => {
+            let address = args.get(2).expect("--join needs an address");
+            let transport = TcpTransport::join(address.as_str()).expect("Can join the host");
+            let (player, players) = (transport.local_player(), transport.player_count());
+            println!("Joined as player {} of {}", player, players);
+
+            Session::new(Box::new(transport), player, players, INPUT_DELAY)
+        },
+
+        _ => {
+            let transport = LoopbackTransport::connect(1).remove(0);
+            Session::new(Box::new(transport), 0, 1, INPUT_DELAY)
+        },
+    }
+}
+
+fn start_recording(factory: &Factory) -> Recorder {
+    let path = crate::flag_value("--record")?;
+    let file = File::create(&path).expect("Can create the replay file");
+    let writer = ReplayWriter::new(BufWriter::new(file), factory).expect("Can write the replay file");
+    println!("Recording to {}", path);
+
+    Some(writer)
+}
+
+fn record(recorder: &mut Recorder, record: &Record) {
+    if let Some(writer) = recorder {
+        if let Err(error) = writer.write(record) {
+            eprintln!("Stopped recording: {}", error);
+            *recorder = None;
+        }
+    }
+}
+
+fn session_tick_system(
+    tick: Res<Tick>,
+    mut session: ResMut<Session>,
+    mut history: ResMut<History>,
+    mut recorder: ResMut<Recorder>,
+    mut factory: ResMut<Factory>,
+    recipes: Res<Table<RecipeKind>>,
+    connectors: Res<Table<ConnectorKind>>,
+    tags: Res<Table<ItemTag>>,
+    technologies: Res<Table<TechnologyKind>>,
+) {
+    let prototypes = Prototypes { recipes: &*recipes, connectors: &*connectors, tags: &*tags, technologies: &*technologies };
+
+    for _ in 0..**tick {
+        let tick = factory.current_tick();
+
+        // A tick can be simulated and still fail, e.g. on a desync or sending its checksum.
+        let result = session.advance(&mut *factory, &prototypes);
+        let advanced = factory.current_tick() > tick;
+
+        let local_player = session.local_player();
+
+        for outcome in session.drain_outcomes() {
+            if outcome.player == local_player {
+                history.record_outcome(&outcome.result);
+            }
+
+            record(&mut *recorder, &Record::Command { tick, player: outcome.player, command: outcome.command.clone() });
+
+            if let Err(error) = outcome.result {
+                eprintln!("Player {}'s command {:?} was rejected: {}", outcome.player, outcome.command, error);
+            }
+        }
+
+        if advanced && tick.is_multiple_of(RECORDED_CHECKSUM_INTERVAL) {
+            record(&mut *recorder, &Record::Checksum { tick, checksum: checksum(&*factory) });
+        }
+
+        match result {
+            Ok(true) => {},
+
+            // Still waiting on other players, the remaining ticks would wait too.
+            Ok(false) => break,
+
+            Err(error) => {
+                eprintln!("{}", error);
+                break;
+            },
+        }
+    }
+
+    // Flush after ticking so a crash doesn't lose the end of the recording.
+    if let (Some(writer), true) = (&mut *recorder, **tick > 0) {
+        if let Err(error) = writer.flush() {
+            eprintln!("Stopped recording: {}", error);
+            *recorder = None;
+        }
+    }
+}
+
+/// Ctrl+Z undoes the local player's last command. Ctrl+Y or Ctrl+Shift+Z redoes it.
+fn undo_system(
+    keys: Res<Input<KeyCode>>,
+    mut session: ResMut<Session>,
+    mut history: ResMut<History>,
+) {
+    let control = keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl);
+    let shift = keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift);
+
+    if !control {
+        return;
+    }
+
+    let command = if keys.just_pressed(KeyCode::Y) || (shift && keys.just_pressed(KeyCode::Z)) {
+        history.redo()
+    } else if keys.just_pressed(KeyCode::Z) {
+        history.undo()
+    } else {
+        return;
+    };
+
+    if let Some(command) = command {
+        session.issue(command);
+    }
+}
+
+/// R starts researching the first technology that can be researched.
+fn research_system(
+    keys: Res<Input<KeyCode>>,
+    factory: Res<Factory>,
+    technologies: Res<Table<TechnologyKind>>,
+    mut session: ResMut<Session>,
+    mut history: ResMut<History>,
+) {
+    if !keys.just_pressed(KeyCode::R) {
+        return;
+    }
+
+    let technology = technologies
+    .iter()
+    .map(|(handle, _)| handle)
+    .find(|&handle| factory.research().can_research(handle, &technologies));
+
+    match technology {
+        Some(technology) => session.issue(history.issue(Command::SetResearch { technology: Some(technology) })),
+        None => println!("Nothing left to research"),
+    }
+}
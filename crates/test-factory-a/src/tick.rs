@@ -1,38 +1,141 @@
 use bevy::prelude::*;
+use open_factory::clock::{TickClock, TickClockBuilder};
+use open_factory::local_string::LocalString;
 
+/// Slowest and fastest speeds, as powers of two.
+const MIN_SPEED: i32 = -3;
+const MAX_SPEED: i32 = 4;
+
+/// Frame rate at which the fastest speed still keeps up. Ticks per frame are capped to what it takes.
+const MIN_FRAME_RATE: u32 = 20;
+
+/// Space pauses, Up and Down double and halve the speed, and Period runs a
+/// single tick, pausing first if needed.
+///
+/// Runs 20 ticks per second at normal speed, or as many as given with `--tick-rate <n>`.
 pub struct TickPlugin;
 
 impl Plugin for TickPlugin {
     fn build(&self, app: &mut AppBuilder) {
+        let rate = crate::flag_value("--tick-rate")
+        .map(|rate| rate.parse().expect("--tick-rate needs a number of ticks per second"))
+        .unwrap_or(open_factory::TICKS_PER_SECOND);
+
+        let max_ticks_per_frame = ((rate << MAX_SPEED) / MIN_FRAME_RATE).max(1);
+
         app
-        .insert_resource(TickTimer(Timer::from_seconds(1.0 / 20.0, true)))
-        .insert_resource(Tick(false))
-        .add_system(update_tick_system.system())
+        .insert_resource(TickClockBuilder::new().with_rate(rate).with_max_ticks_per_update(max_ticks_per_frame).build())
+        .insert_resource(TickController::default())
+        .insert_resource(Tick::default())
+        .add_system(tick_hotkey_system.system().before("update_tick"))
+        .add_system(update_tick_system.system().label("update_tick"))
         ;
     }
 }
 
-struct TickTimer(Timer);
-
-/// Whether or not a logic tick has occured.
+/// The number of logic ticks to run this frame.
 ///
-/// Occurs every 1/20th of a second.
+/// At normal speed, one tick occurs every 1/20th of a second. Frames that take
+/// longer run the ticks they missed.
 ///
-/// Use `**tick` to get to a bool.
-pub struct Tick(bool);
+/// Use `**tick` to get to the count.
+#[derive(Default)]
+pub struct Tick {
+    ran: u32,
+
+    /// Ticks owed but left for later frames, because catching up takes too many for one frame.
+    behind: u32,
+}
 
 impl std::ops::Deref for Tick {
-    type Target = bool;
+    type Target = u32;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.ran
+    }
+}
+
+/// Pause, speed and single steps.
+#[derive(Default)]
+pub struct TickController {
+    paused: bool,
+
+    /// Speed as a power of two.
+    speed: i32,
+
+    /// Run one logic tick on the next frame.
+    step: bool,
+}
+
+impl TickController {
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(MAX_SPEED);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = (self.speed - 1).max(MIN_SPEED);
+    }
+
+    /// Pause, and run a single tick.
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.step = true;
+    }
+
+    /// Describes the state, e.g. "Speed 4×", "Paused" or "12 ticks behind".
+    pub fn label(&self, tick: &Tick) -> LocalString {
+        if self.paused {
+            return LocalString::from_str("tick-paused");
+        }
+
+        if tick.behind > 0 {
+            return LocalString::from_str("tick-behind").with_arg("ticks", tick.behind as i64);
+        }
+
+        let speed = if self.speed >= 0 { (1 << self.speed).to_string() } else { format!("1/{}", 1 << -self.speed) };
+        LocalString::from_str("tick-speed").with_arg("speed", speed)
+    }
+}
+
+fn tick_hotkey_system(
+    keys: Res<Input<KeyCode>>,
+    mut controller: ResMut<TickController>,
+) {
+    if keys.just_pressed(KeyCode::Space) {
+        controller.toggle_pause();
+    }
+
+    if keys.just_pressed(KeyCode::Up) {
+        controller.faster();
+    }
+
+    if keys.just_pressed(KeyCode::Down) {
+        controller.slower();
+    }
+
+    if keys.just_pressed(KeyCode::Period) {
+        controller.step();
     }
 }
 
 fn update_tick_system(
     time: Res<Time>,
-    mut tick_timer: ResMut<TickTimer>,
+    mut clock: ResMut<TickClock>,
+    mut controller: ResMut<TickController>,
     mut tick: ResMut<Tick>,
 ) {
-    *tick = Tick(tick_timer.0.tick(time.delta()).just_finished());
-}
\ No newline at end of file
+    *tick = if std::mem::take(&mut controller.step) {
+        Tick { ran: 1, behind: 0 }
+    } else if controller.paused {
+        // Don't catch up on the time spent paused.
+        clock.reset();
+        Tick::default()
+    } else {
+        let update = clock.update(time.delta().mul_f64(2f64.powi(controller.speed)));
+        Tick { ran: update.ticks, behind: update.behind }
+    };
+}
@@ -1,4 +1,8 @@
 use bevy::prelude::*;
+use open_factory::local_string::LocalString;
+
+use crate::localization::LocalizedText;
+use crate::tick::{Tick, TickController};
 
 pub struct UiPlugin;
 
@@ -8,7 +12,10 @@ impl Plugin for UiPlugin {
         .init_resource::<ButtonMaterials>()
         .insert_resource(Action::default())
         .add_startup_system(setup.system())
+        .add_startup_system(setup_tick_controls.system())
         .add_system(button_system.system())
+        .add_system(tick_button_system.system())
+        .add_system(tick_label_system.system())
         ;
     }
 }
@@ -41,10 +48,16 @@ struct ButtonMaterials {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Action {
     Connect,
+    StackConnect,
     Copper,
     Tin,
     Bronze,
     Trash,
+    Lab,
+    Move,
+    Remove,
+    CopyBlueprint,
+    PasteBlueprint,
 }
 
 impl Default for Action {
@@ -60,12 +73,69 @@ impl std::fmt::Display for Action {
 }
 
 impl Action {
-    pub fn iter_variants() -> <[Action; 5] as IntoIterator>::IntoIter {
+    /// What the action's button says.
+    pub fn label(self) -> LocalString {
+        LocalString::from_str(match self {
+            Action::Connect => "action-connect",
+            Action::StackConnect => "action-stack-connect",
+            Action::Copper => "action-copper",
+            Action::Tin => "action-tin",
+            Action::Bronze => "action-bronze",
+            Action::Trash => "action-trash",
+            Action::Lab => "action-lab",
+            Action::Move => "action-move",
+            Action::Remove => "action-remove",
+            Action::CopyBlueprint => "action-copy",
+            Action::PasteBlueprint => "action-paste",
+        })
+    }
+
+    /// Name of the recipe of the producer the action places, if it places one.
+    pub fn recipe(self) -> Option<&'static str> {
+        match self {
+            Action::Connect | Action::StackConnect | Action::Move | Action::Remove | Action::CopyBlueprint | Action::PasteBlueprint => None,
+            Action::Copper => Some("mine-copper"),
+            Action::Tin => Some("mine-tin"),
+            Action::Bronze => Some("bronze"),
+            Action::Trash => Some("destroy-metal"),
+            Action::Lab => Some("research-metallurgy"),
+        }
+    }
+
+    pub fn iter_variants() -> <[Action; 11] as IntoIterator>::IntoIter {
         use Action::*;
-        IntoIterator::into_iter([Connect, Copper, Tin, Bronze, Trash])
+        IntoIterator::into_iter([Connect, StackConnect, Copper, Tin, Bronze, Trash, Lab, Move, Remove, CopyBlueprint, PasteBlueprint])
     }
 }
 
+/// Buttons for the [`TickController`], in the top right corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TickButton {
+    Pause,
+    Step,
+    Slower,
+    Faster,
+}
+
+impl TickButton {
+    fn label(self) -> LocalString {
+        LocalString::from_str(match self {
+            TickButton::Pause => "tick-pause",
+            TickButton::Step => "tick-step",
+            TickButton::Slower => "tick-slower",
+            TickButton::Faster => "tick-faster",
+        })
+    }
+
+    fn iter_variants() -> <[TickButton; 4] as IntoIterator>::IntoIter {
+        use TickButton::*;
+        IntoIterator::into_iter([Pause, Step, Slower, Faster])
+    }
+}
+
+/// Shows the speed, that the game is paused, or how far behind it is.
+struct TickLabel;
+
 impl FromWorld for ButtonMaterials {
     fn from_world(world: &mut World) -> Self {
         let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
@@ -113,7 +183,7 @@ fn setup(
             .with_children(|parent| {
                 parent.spawn_bundle(TextBundle {
                     text: Text::with_section(
-                        action.to_string(),
+                        String::new(),
                         TextStyle {
                             font: font.0.clone(),
                             font_size: 40.0,
@@ -123,6 +193,7 @@ fn setup(
                     ),
                     ..Default::default()
                 })
+                .insert(LocalizedText(action.label()))
                 ;
             })
             .insert(action)
@@ -197,4 +268,113 @@ fn button_system(
         *material = button_materials.normal.clone();
         previous_active_button.0 = active_button.0;
     }
-}
\ No newline at end of file
+}
+fn setup_tick_controls(
+    mut commands: Commands,
+    font: Res<crate::GameFont>,
+    button_materials: Res<ButtonMaterials>,
+    controller: Res<TickController>,
+    tick: Res<Tick>,
+) {
+    let text_style = TextStyle {
+        font: font.0.clone(),
+        font_size: 30.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+    .spawn_bundle(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect { top: Val::Px(10.0), right: Val::Px(10.0), ..Default::default() },
+            align_items: AlignItems::Center,
+            flex_direction: FlexDirection::Row,
+            ..Default::default()
+        },
+        material: button_materials.background.clone(),
+        ..Default::default()
+    })
+    .with_children(|parent| {
+        parent.spawn_bundle(TextBundle {
+            style: Style {
+                margin: Rect::all(Val::Px(10.0)),
+                ..Default::default()
+            },
+            text: Text::with_section(String::new(), text_style.clone(), Default::default()),
+            ..Default::default()
+        })
+        .insert(LocalizedText(controller.label(&tick)))
+        .insert(TickLabel)
+        ;
+
+        for button in TickButton::iter_variants() {
+            parent.spawn_bundle(ButtonBundle {
+                style: Style {
+                    size: Size::new(Val::Px(110.0), Val::Px(45.0)),
+                    margin: Rect::all(Val::Px(5.0)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                material: button_materials.normal.clone(),
+                ..Default::default()
+            })
+            .with_children(|parent| {
+                parent.spawn_bundle(TextBundle {
+                    text: Text::with_section(String::new(), text_style.clone(), Default::default()),
+                    ..Default::default()
+                })
+                .insert(LocalizedText(button.label()))
+                ;
+            })
+            .insert(button)
+            ;
+        }
+    });
+}
+
+fn tick_button_system(
+    button_materials: Res<ButtonMaterials>,
+    mut controller: ResMut<TickController>,
+    mut interaction_query: Query<
+        (&Interaction, &mut Handle<ColorMaterial>, &TickButton),
+        (Changed<Interaction>, With<Button>),
+    >,
+) {
+    for (interaction, mut material, button) in interaction_query.iter_mut() {
+        match *interaction {
+            Interaction::Clicked => {
+                match button {
+                    TickButton::Pause => controller.toggle_pause(),
+                    TickButton::Step => controller.step(),
+                    TickButton::Slower => controller.slower(),
+                    TickButton::Faster => controller.faster(),
+                }
+                *material = button_materials.active.clone();
+            },
+
+            Interaction::Hovered => {
+                *material = button_materials.hovered.clone();
+            },
+
+            Interaction::None => {
+                *material = button_materials.normal.clone();
+            },
+        }
+    }
+}
+
+fn tick_label_system(
+    controller: Res<TickController>,
+    tick: Res<Tick>,
+    mut label_query: Query<&mut LocalizedText, With<TickLabel>>,
+) {
+    let label = controller.label(&tick);
+
+    for mut text in label_query.iter_mut() {
+        // Only touch the text when it changes, so it isn't localized again every frame.
+        if text.0 != label {
+            text.0 = label.clone();
+        }
+    }
+}